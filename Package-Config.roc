@@ -1,9 +1,9 @@
 platform "roc-lang/rbt"
-    requires {} { init : Rbt.Rbt }
+    requires {} { init : Rbt.Config -> Rbt.Rbt }
     exposes [Rbt]
     packages {}
     imports [Rbt]
     provides [initForHost]
 
-initForHost : Rbt.Rbt
-initForHost = init
+initForHost : Rbt.Config -> Rbt.Rbt
+initForHost = \config -> init config
@@ -1,25 +1,30 @@
 interface Rbt
-    exposes [Rbt, init, Job, job, Command, exec, Tool, tool, systemTool, projectFiles, fromJob, Input, sourceFile, withFilename]
+    exposes [Rbt, init, compose, Config, define, Job, job, expectFailure, allowHostPaths, rerunOnMissingOutputs, withPassthroughEnv, withResource, withIncrementalState, withCheckpointDir, withShards, withMatrix, runAfter, thenRun, withPersistentWorker, stamp, OutputFilter, withOutputFilter, withResponseFile, withOutputFromStdout, withWritableOutput, InputStrategy, withInputStrategy, JobKind, withKind, Visibility, withVisibility, Network, withNetwork, withHost, withDnsServer, Limit, withLimit, Retention, withRetention, Command, exec, Tool, tool, systemTool, pinnedTool, projectFiles, fromJob, fromResolver, fromArchive, Input, sourceFile, withFilename]
     imports []
 
 # TODO: these are all out of order due to https://github.com/rtfeldman/roc/issues/1642. Once that's fixed, they should rearrange into the order in `exposes`
-# TODO: how are we gonna get tools from Jobs? Maybe Tool, Command, and Job
-# need to live in a single union and have private aliases outwards?  I'd like
-# to have this look like:
-#
-#     Tool : [ Tool { name : Str, fromJob: Maybe Job } ]
-#
-# Or maybe:
-#
-#     Tool : [ SystemTool { name : Str }, FromJob { name : Str, job : Job } ]
-#
 SystemToolPayload : { name : Str }
-Tool := [SystemTool SystemToolPayload]
+FromJobPayload : { job : Job, name : Str }
+PinnedToolPayload : { path : Str, sha256 : Str }
+Tool := [
+    SystemTool SystemToolPayload,
+    FromJob FromJobPayload,
+    PinnedTool PinnedToolPayload,
+]
 
 systemTool : Str -> Tool
 systemTool = \name ->
     @Tool (SystemTool { name })
 
+# Run the executable at an absolute path outside the build, like a toolchain
+# your team installs on every machine, but only if its SHA-256 hash is the one
+# given here. rbt checks before each job that uses it runs, and fails the job
+# if it's different. The hash is part of the job's key, so changing it runs
+# every job that uses the tool again.
+pinnedTool : Str, Str -> Tool
+pinnedTool = \path, sha256 ->
+    @Tool (PinnedTool { path, sha256 })
+
 Command := { tool : Tool, args : List Str }
 
 exec : Tool, List Str -> Command
@@ -37,6 +42,8 @@ withFilename = \@FileMapping { source }, dest -> @FileMapping { source, dest }
 Input := [
     FromProjectSource (List FileMapping),
     FromJob Job (List FileMapping),
+    FromResolver Str Str (List FileMapping),
+    FromArchive Str Str (List FileMapping),
 ]
 
 # Add the given file to the job's workspace (the working directory where the
@@ -48,18 +55,63 @@ projectFiles = \mappings -> @Input (FromProjectSource mappings)
 fromJob : Job, List FileMapping -> Input
 fromJob = \otherJob, mappings -> @Input (FromJob otherJob mappings)
 
+# Add files fetched by a resolver plugin (an executable named
+# `rbt-resolver-<name>` on your PATH) to the current job's workspace. The spec
+# is JSON that gets passed along to the plugin. See
+# docs/adrs/012-input-resolvers.md for how plugins work.
+fromResolver : Str, Str, List FileMapping -> Input
+fromResolver = \name, spec, mappings -> @Input (FromResolver name spec mappings)
+
+# Add files from an archive (.tar, .tar.gz, .tgz, or .tar.zst) to the current
+# job's workspace. rbt downloads the archive, checks it against the SHA-256
+# hash, and unpacks it once. After that, builds use the unpacked files without
+# going to the network at all.
+fromArchive : { url : Str, sha256 : Str }, List FileMapping -> Input
+fromArchive = \{ url, sha256 }, mappings ->
+    @Input (FromArchive url sha256 mappings)
+
 Job := [
     Job
         {
+            after : List Job,
             command : Command,
             # eventually we want this to be `List Input` but there's a bug.
             # see https://github.com/roc-lang/roc/issues/4077
             inputs : List [
                 FromProjectSource (List FileMapping),
                 FromJob Job (List FileMapping),
+                FromResolver Str Str (List FileMapping),
+                FromArchive Str Str (List FileMapping),
             ],
             outputs : List Str,
+            limits : List { limit : Limit, value : U64 },
+            matrix : List { name : Str, values : List Str },
+            outputFilters : List { output : Str, filter : OutputFilter },
+            # empty if stdout only goes to the log
+            outputFromStdout : Str,
             env : Dict Str Str,
+            incrementalState : List Str,
+            # empty if the job doesn't checkpoint
+            checkpointDir : Str,
+            passthroughEnv : List Str,
+            hosts : List { name : Str, address : Str },
+            dnsServers : List Str,
+            resources : List Str,
+            # empty if the job doesn't use one
+            responseFile : Str,
+            thenRun : List Command,
+            writableOutputs : List Str,
+            retention : { kind : RetentionKind, days : U32 },
+            shards : U32,
+            allowHostPaths : Bool,
+            expectFailure : Bool,
+            inputStrategy : InputStrategy,
+            kind : JobKind,
+            network : Network,
+            persistentWorker : Bool,
+            rerunOnMissingOutputs : Bool,
+            stamp : Bool,
+            visibility : Visibility,
         },
 ]
 
@@ -69,14 +121,303 @@ job : { command : Command, inputs : List Input, outputs : List Str, env : Dict S
 job = \{ command, inputs, outputs, env } ->
     unwrappedInputs = List.map inputs (\@Input input -> input)
 
-    @Job (Job { command, inputs: unwrappedInputs, outputs, env })
+    @Job (Job { after: [], command, inputs: unwrappedInputs, outputs, limits: [], matrix: [], outputFilters: [], outputFromStdout: "", env, incrementalState: [], checkpointDir: "", passthroughEnv: [], hosts: [], dnsServers: [], resources: [], responseFile: "", thenRun: [], writableOutputs: [], retention: { kind: Default, days: 0 }, shards: 1, allowHostPaths: Bool.false, expectFailure: Bool.false, inputStrategy: Symlink, kind: Build, network: Allowed, persistentWorker: Bool.false, rerunOnMissingOutputs: Bool.false, stamp: Bool.false, visibility: Public })
+
+# Mark a job as expected to fail, for example a test checking that a compiler
+# rejects bad input. rbt will treat a non-zero exit code as success (and a
+# zero exit code as a failure) and capture the command's stdout and stderr
+# into files named `stdout` and `stderr` so you can list them in `outputs`.
+expectFailure : Job -> Job
+expectFailure = \@Job (Job fields) -> @Job (Job { fields & expectFailure: Bool.true })
+
+# Let a job mention paths that only exist on this machine (like
+# `/home/alice/...` or the project's absolute path) in its command or
+# environment without a warning. Jobs like that usually can't share cache hits
+# with other machines, so only use this for jobs that never run anywhere else.
+allowHostPaths : Job -> Job
+allowHostPaths = \@Job (Job fields) -> @Job (Job { fields & allowHostPaths: Bool.true })
+
+# If files have gone missing from this job's outputs in the store (usually
+# because someone deleted them by hand), run it again instead of failing the
+# jobs that use them. rbt only tries once per build: if the files are still
+# missing afterwards, the build fails. This isn't part of the job's key.
+rerunOnMissingOutputs : Job -> Job
+rerunOnMissingOutputs = \@Job (Job fields) -> @Job (Job { fields & rerunOnMissingOutputs: Bool.true })
+
+# Let a job see some of the environment rbt was run with, like `SSL_CERT_FILE`
+# or `LANG`, on top of its own `env`. Everything else is cleared. The names are
+# part of the job's key, but the values aren't (unless rbt runs with
+# `--hash-passthrough-env`), so a job can be a cache hit on a machine where
+# they're different. Only pass through variables that don't change what the
+# job produces.
+withPassthroughEnv : Job, List Str -> Job
+withPassthroughEnv = \@Job (Job fields), names -> @Job (Job { fields & passthroughEnv: List.concat fields.passthroughEnv names })
+
+# Make a job wait for a system resource (like a fixed port or `/dev/kvm`)
+# before it runs. Resources declared with `rbt --resource NAME=CAPACITY` can be
+# shared by that many jobs at once, and the job learns which unit it got from
+# an environment variable like `RBT_RESOURCE_PORT`. Any other resource is held
+# by one job at a time. Resources don't affect caching, so jobs should produce
+# the same outputs no matter which unit they get.
+withResource : Job, Str -> Job
+withResource = \@Job (Job fields), resource -> @Job (Job { fields & resources: List.append fields.resources resource })
+
+# Keep a directory in the job's workspace around between runs, for tools like
+# `tsc --incremental` that are much faster when they can see their previous
+# state. This is impure on purpose: what's in the directory doesn't affect
+# caching, so make sure the tool can cope with anything it finds there. Outputs
+# can't live inside the directory. `rbt clean --incremental` resets them all.
+withIncrementalState : Job, Str -> Job
+withIncrementalState = \@Job (Job fields), dir -> @Job (Job { fields & incrementalState: List.append fields.incrementalState dir })
+
+# Let a long job (like processing a big dataset) pick up where it left off if
+# it gets interrupted. rbt keeps whatever the job writes to this directory
+# when a run is cancelled or fails, and gives it back to the next run with the
+# same final key, so the job can look for its latest checkpoint there and
+# resume from it. Changing the job or its inputs starts from an empty
+# directory, and it's removed once the job succeeds. Outputs can't live
+# inside it. `rbt clean --checkpoints` removes them all.
+withCheckpointDir : Job, Str -> Job
+withCheckpointDir = \@Job (Job fields), dir -> @Job (Job { fields & checkpointDir: dir })
+
+# Split a job (usually a big test suite) into this many copies that run in
+# parallel. Each copy learns which slice of the work is its own from the
+# `RBT_SHARD_INDEX` (starting at 0) and `RBT_SHARD_TOTAL` environment
+# variables, and is cached on its own, so changing one test only re-runs the
+# shard it landed in. Other jobs can't take files from a sharded job.
+withShards : Job, U32 -> Job
+withShards = \@Job (Job fields), shards -> @Job (Job { fields & shards })
+
+# Run a copy of the job for each of the given values, like building the same
+# thing for several architectures. Each copy gets its value in the environment
+# variable with the given name, which has to be letters, digits, and
+# underscores, not starting with a digit. Adding another matrix multiplies the
+# copies, one for each combination of values. rbt makes the copies, so a big
+# matrix doesn't mean building a big list of jobs in Roc. Each copy is cached
+# by its own values, so adding a value doesn't re-run the others. Like with
+# `withShards`, building the job builds every copy, and other jobs can't take
+# files from it.
+withMatrix : Job, Str, List Str -> Job
+withMatrix = \@Job (Job fields), name, values -> @Job (Job { fields & matrix: List.append fields.matrix { name, values } })
+
+# Make a job wait for another job to finish without taking any files from it,
+# like running database migrations before the tests that need them. Changing
+# the other job won't make this one run again; it only affects the order.
+runAfter : Job, Job -> Job
+runAfter = \@Job (Job fields), other -> @Job (Job { fields & after: List.append fields.after other })
+
+# Run another command in the job's workspace once the ones before it have
+# succeeded, instead of chaining them with `bash -c "a && b && c"`. Commands run
+# in the order you add them, with the same environment, and the job fails as
+# soon as one of them does. The response file (if any) only goes to the first
+# command. Jobs with more than one command can't use a persistent worker, be
+# expected to fail, or take an output from stdout.
+thenRun : Job, Command -> Job
+thenRun = \@Job (Job fields), @Command command -> @Job (Job { fields & thenRun: List.append fields.thenRun command })
+
+# Run a job in a long-lived copy of its tool instead of starting a new process,
+# for tools like the JVM that take longer to start than to do the work. The
+# tool has to speak rbt's worker protocol (see docs/adrs/013-persistent-workers.md.)
+# Workers are shared between jobs with the same tool and environment, so the
+# tool must not let one job's state leak into the next one's outputs.
+withPersistentWorker : Job -> Job
+withPersistentWorker = \@Job (Job fields) -> @Job (Job { fields & persistentWorker: Bool.true })
+
+# Give a job the build status (like the git SHA or build time) to stamp into
+# its outputs, usually a version string in a binary. The job finds `KEY value`
+# files at `$RBT_STABLE_STATUS` and `$RBT_VOLATILE_STATUS`, filled in by
+# `rbt --status-command`. Changing a stable key (one starting with `STABLE_`)
+# runs the job again, but volatile keys don't, so stamp in the final link step
+# rather than somewhere everything else depends on.
+stamp : Job -> Job
+stamp = \@Job (Job fields) -> @Job (Job { fields & stamp: Bool.true })
+
+# Clean-ups rbt can run on an output after the job succeeds, before storing it:
+#
+# - `Strip` removes debug info with the system's `strip -S`.
+# - `NormalizeArchive` zeroes the timestamps, owners, and modes in an `ar`
+#   archive, like `ar D`.
+# - `ZeroZipTimestamps` sets every timestamp in a zip file (or jar) to
+#   1980-01-01.
+OutputFilter : [Strip, NormalizeArchive, ZeroZipTimestamps]
+
+# Run a filter on one of the job's outputs so that what's stored doesn't
+# depend on when or where the job ran. Filters on the same output run in the
+# order you add them, and which filters a job uses is part of its key.
+withOutputFilter : Job, Str, OutputFilter -> Job
+withOutputFilter = \@Job (Job fields), output, filter -> @Job (Job { fields & outputFilters: List.append fields.outputFilters { output, filter } })
+
+# For jobs with too many inputs to list on the command line (like linking
+# thousands of objects), have rbt write the workspace path of every input to a
+# file with this name, one per line, and add `@name` to the end of the
+# command's arguments. Most compilers and linkers read arguments from a file
+# given this way.
+withResponseFile : Job, Str -> Job
+withResponseFile = \@Job (Job fields), responseFile -> @Job (Job { fields & responseFile })
 
-Rbt := { default : Job }
+# Write everything the command prints to stdout into a file with this name,
+# and store it as one of the job's outputs (you don't need to list it in
+# `outputs` too.) This is for jobs that would otherwise be `bash -c "foo > out"`.
+# stdout still shows up in the log as usual.
+withOutputFromStdout : Job, Str -> Job
+withOutputFromStdout = \@Job (Job fields), outputFromStdout -> @Job (Job { fields & outputFromStdout })
+
+# Give jobs that use this output (or the files in it, if it's a directory) a
+# writable copy instead of a link to the read-only original in the store. Use
+# this for things consumers have to change in place, like a SQLite database
+# used as a test fixture. Copying is slower than linking, so only ask for it
+# when you need it.
+withWritableOutput : Job, Str -> Job
+withWritableOutput = \@Job (Job fields), output -> @Job (Job { fields & writableOutputs: List.append fields.writableOutputs output })
+
+# How a job's inputs get into its workspace. Jobs get symlinks by default.
+InputStrategy : [Copy, Hardlink, Symlink]
+
+# Ask for inputs to be hardlinked or copied into the workspace instead of
+# symlinked, for tools (like some compilers and file watchers) that follow
+# symlinks out of the workspace or don't follow them at all. Hardlinks fall
+# back to copies across filesystems. Copying is slowest, so only ask for it
+# when a tool needs it. This is part of the job's key.
+withInputStrategy : Job, InputStrategy -> Job
+withInputStrategy = \@Job (Job fields), inputStrategy -> @Job (Job { fields & inputStrategy })
+
+# Whether a job builds something or tests it. Jobs build by default.
+JobKind : [Build, Test]
+
+# Mark a job as a test (or back as a build.) `rbt test` runs only the tests
+# the targets depend on, and reports each one as passed, failed, or a cached
+# pass: like any other job, a test that passed is a cache hit until its key
+# changes, so unchanged tests don't run again. This isn't part of the job's
+# key, so marking a job as a test doesn't make it run again either.
+withKind : Job, JobKind -> Job
+withKind = \@Job (Job fields), kind -> @Job (Job { fields & kind })
+
+# Where a job's outputs can go besides the machine that built them. Jobs are
+# public by default.
+Visibility : [Local, Team, Public]
+
+# Keep a job's outputs from leaving the machine, for things like licensed
+# SDKs or outputs with secrets baked in. `Local` outputs are never uploaded
+# to a remote cache or exported with `--export`; `Team` outputs go to remote
+# caches but aren't exported. Jobs that take files from a job get its
+# visibility too (if it's narrower than their own), since their outputs
+# could contain its outputs. This isn't part of the job's key.
+withVisibility : Job, Visibility -> Job
+withVisibility = \@Job (Job fields), visibility -> @Job (Job { fields & visibility })
+
+# Whether a job's command can use the network. Jobs can use it by default.
+Network : [Allowed, Forbidden]
+
+# Keep a job off the network (or let it back on.) Jobs are supposed to get
+# everything they need from their inputs, so forbidding the network makes one
+# that quietly downloads things fail instead of depending on whatever the
+# server said that day. On Linux the command runs in its own network namespace,
+# and on macOS under `sandbox-exec`; other platforms can't run these jobs yet.
+# This is part of the job's key.
+withNetwork : Job, Network -> Job
+withNetwork = \@Job (Job fields), network -> @Job (Job { fields & network })
+
+# Make `name` resolve to `address` (an IP address) for this job, as if it were
+# in `/etc/hosts`. Test jobs can use this to reach local services by stable
+# names. Only Linux supports this so far: the command runs in its own mount
+# namespace with a hosts file rbt writes for it. This is part of the job's key.
+withHost : Job, Str, Str -> Job
+withHost = \@Job (Job fields), name, address ->
+    @Job (Job { fields & hosts: List.append fields.hosts { name, address } })
+
+# Send this job's DNS lookups to `server` (an IP address) instead of the ones
+# the machine is configured with. Servers are tried in the order they were
+# added. Like `withHost`, this only works on Linux so far, and it's part of
+# the job's key.
+withDnsServer : Job, Str -> Job
+withDnsServer = \@Job (Job fields), server ->
+    @Job (Job { fields & dnsServers: List.append fields.dnsServers server })
+
+# Resource limits (like `ulimit`) a job can ask for:
+#
+# - `OpenFiles` is how many files the job can have open at once.
+# - `StackSize` is the size of the main thread's stack, in bytes.
+Limit : [OpenFiles, StackSize]
+
+# Run the job with a resource limit set to this value instead of whatever the
+# shell that started rbt had. Limits are part of the job's key. If the machine
+# won't allow the value (it's above the hard limit and rbt isn't running as
+# root) the job fails before it starts and says which limit was the problem.
+withLimit : Job, Limit, U64 -> Job
+withLimit = \@Job (Job fields), limit, value -> @Job (Job { fields & limits: List.append fields.limits { limit, value } })
+
+# How long `rbt gc` should keep a job's output after it was last used, instead
+# of the `--unused-for-days` default:
+#
+# - `KeepDays days` keeps it for that many days.
+# - `KeepForever` never removes it.
+# - `Ephemeral` removes it the next time GC runs, unless it was used that day.
+Retention : [KeepDays U32, KeepForever, Ephemeral]
+
+# the same thing, but in a shape that's easy to hand to the host
+RetentionKind : [Default, Days, Forever, Ephemeral]
+
+# Ask for the job's output to be kept longer (release builds) or shorter
+# (scratch test outputs) than usual. This isn't part of the job's key, and the
+# most recent build that used the output decides how long it's kept. Operators
+# can ignore these with `rbt gc --ignore-retention`.
+withRetention : Job, Retention -> Job
+withRetention = \@Job (Job fields), retention ->
+    stored =
+        when retention is
+            KeepDays days -> { kind: Days, days }
+            KeepForever -> { kind: Forever, days: 0 }
+            Ephemeral -> { kind: Ephemeral, days: 0 }
+
+    @Job (Job { fields & retention: stored })
+
+Rbt := { default : Job, targets : Dict Str Job }
+
+# Build-time configuration from `rbt --define NAME=VALUE`, so that one build
+# definition can describe (for example) both debug and release builds. rbt
+# hands this to your `init`.
+Config := List { name : Str, value : Str }
+
+# Look up a value passed with `--define`, or use the given default if there
+# wasn't one. Defines only reach jobs through what you build with them (the
+# command, env, inputs, and so on) so a job's cache key changes exactly when a
+# define it actually uses does.
+define : Config, Str, Str -> Str
+define = \@Config defines, name, default ->
+    when List.findFirst defines (\def -> def.name == name) is
+        Ok def -> def.value
+        Err NotFound -> default
+
+# `default` is what `rbt` builds. Give jobs names in `targets` to build them
+# with `rbt build NAME...` instead.
+init : { default : Job, targets ? Dict Str Job } -> Rbt
+init = \{ default, targets ? Dict.empty } -> @Rbt { default, targets }
+
+# Put several build definitions together, for repos where each package
+# describes its own jobs (in a module that gives back an `Rbt`, like `init`
+# does.) A package's default job is the target named after it, and its other
+# targets get its name in front, like `rbt build server/tests`. Jobs that come
+# out the same in more than one package (like a tool they all build) are the
+# same job to rbt, so it only runs once.
+#
+# Paths are not relative to each package: rbt doesn't know where a package
+# lives, so every path in every package is relative to the root of the repo,
+# the same as with `init`. A job in a `server` package that compiles
+# `server/main.c` has to say `server/main.c`, not `main.c`.
+compose : { default : Job, packages : Dict Str Rbt } -> Rbt
+compose = \{ default, packages } ->
+    targets =
+        Dict.walk packages Dict.empty \all, name, @Rbt package ->
+            Dict.walk package.targets (Dict.insert all name package.default) \withPackage, target, targetJob ->
+                Dict.insert withPackage "\(name)/\(target)" targetJob
 
-init : { default : Job } -> Rbt
-init = \rbt -> @Rbt rbt
+    @Rbt { default, targets }
 
+# Run an executable that another job built, like a compiler built from
+# source. The name is the executable's path in the job's outputs. rbt runs
+# it from the store (so files next to it, like a compiler's runtime library,
+# come along) and puts its directory at the front of the `PATH`. Changing the
+# job that builds the tool makes every job that uses it run again.
 tool : Job, Str -> Tool
-tool = \_, _ ->
-    # FromJob { name, job }
-    @Tool (SystemTool { name: "TODO" })
+tool = \toolJob, name ->
+    @Tool (FromJob { job: toolJob, name })
@@ -0,0 +1,115 @@
+use crate::ui;
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+/// How many of the slowest executed jobs the summary lists by name.
+const SLOWEST_JOBS: usize = 10;
+
+/// Where a build's time went, collected by the coordinator as it goes. The
+/// CLI prints it as a summary after each build, and it serializes so
+/// reports can include it too.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildStats {
+    /// Every job in the build, including ones that never got to run.
+    pub jobs: usize,
+    pub cache_hits: usize,
+
+    /// Cache hits we had to download from the remote cache first.
+    pub downloads: usize,
+    pub failed: usize,
+
+    /// Jobs that ran and worked, by ID, with how long each took.
+    pub executed: Vec<(String, Duration)>,
+
+    /// Reading and hashing input files, and calculating final keys.
+    pub hashing: Duration,
+
+    /// Running jobs, added up across jobs that ran at the same time (so it
+    /// can be longer than the build.)
+    pub execution: Duration,
+
+    /// Moving job outputs from workspaces into the store.
+    pub store_moves: Duration,
+}
+
+impl Display for BuildStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<12} {:>8}", "jobs", self.jobs)?;
+        writeln!(f, "{:<12} {:>8}", "cache hits", self.cache_hits)?;
+        if self.downloads > 0 {
+            writeln!(f, "{:<12} {:>8}", "downloaded", self.downloads)?;
+        }
+        writeln!(f, "{:<12} {:>8}", "executed", self.executed.len())?;
+        if self.failed > 0 {
+            writeln!(f, "{:<12} {:>8}", "failed", self.failed)?;
+        }
+
+        if !self.executed.is_empty() {
+            let mut slowest: Vec<&(String, Duration)> = self.executed.iter().collect();
+            slowest.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then(a_id.cmp(b_id)));
+
+            let width = slowest
+                .iter()
+                .take(SLOWEST_JOBS)
+                .map(|(id, _)| id.len())
+                .max()
+                .unwrap_or(0)
+                .max(12);
+
+            writeln!(f)?;
+            for (id, duration) in slowest.iter().take(SLOWEST_JOBS) {
+                writeln!(f, "{:<width$} {:>8}", id, ui::elapsed(*duration))?;
+            }
+            if slowest.len() > SLOWEST_JOBS {
+                writeln!(f, "...and {} more", slowest.len() - SLOWEST_JOBS)?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<12} {:>8}", "hashing", ui::elapsed(self.hashing))?;
+        writeln!(f, "{:<12} {:>8}", "execution", ui::elapsed(self.execution))?;
+        write!(
+            f,
+            "{:<12} {:>8}",
+            "store moves",
+            ui::elapsed(self.store_moves)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lists_slowest_jobs_first() {
+        let stats = BuildStats {
+            jobs: 4,
+            cache_hits: 2,
+            downloads: 0,
+            failed: 0,
+            executed: vec![
+                ("cc-a".to_string(), Duration::from_millis(1500)),
+                ("ld-b".to_string(), Duration::from_secs(90)),
+            ],
+            hashing: Duration::from_millis(300),
+            execution: Duration::from_millis(91500),
+            store_moves: Duration::from_millis(200),
+        };
+
+        assert_eq!(
+            "jobs                4\n\
+             cache hits          2\n\
+             executed            2\n\
+             \n\
+             ld-b            1m30s\n\
+             cc-a             1.5s\n\
+             \n\
+             hashing          0.3s\n\
+             execution       1m31s\n\
+             store moves      0.2s",
+            stats.to_string()
+        );
+    }
+}
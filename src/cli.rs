@@ -1,14 +1,45 @@
 use crate::coordinator;
+use crate::daemon;
+use crate::db::{self, Db};
+use crate::events::JobInfo;
+use crate::export::Export;
+use crate::flake_check;
 use crate::glue;
+use crate::in_flight::InFlight;
+use crate::job;
+use crate::journal;
+use crate::lint;
+use crate::log_sink::{self, Sinks};
+use crate::logs;
+use crate::ninja;
+use crate::out_link::OutLink;
+use crate::path_meta_key;
+use crate::pause::Pauser;
+use crate::profile::{self, Profile};
+use crate::prune;
+use crate::remote_cache::RemoteCache;
+use crate::report::{self, Report};
+use crate::resolver;
+use crate::resources::{self, Resources};
+use crate::snapshot::Snapshot;
 use crate::store::Store;
+use crate::tools;
+use crate::trace;
+use crate::ui;
 use anyhow::{Context, Result};
 use clap::Parser;
 use core::mem::MaybeUninit;
 use path_absolutize::Absolutize;
+use roc_std::RocList;
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use tokio::runtime;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
@@ -20,47 +51,886 @@ pub struct Cli {
     #[clap(long)]
     print_root_output_paths: bool,
 
+    /// Send this build (or `stats`) to the `rbt daemon` running in the
+    /// root dir instead of running it here. The daemon logs the build; we
+    /// only say whether it worked. Only one process can have the database
+    /// open, so this is also how to ask about it while a build is running.
+    #[clap(long)]
+    daemon: bool,
+
     /// How many worker threads should we spawn? If unset, we'll calculate a
     /// reasonable number based on the host. If set manually, must be greater
     /// than zero.
     #[clap(long, short('j'))]
     max_local_jobs: Option<NonZeroUsize>,
 
+    /// Refuse to build if the build definition has more jobs than this.
+    /// These limits are there so a definition with a runaway recursive
+    /// function fails with an error instead of using up all the memory on
+    /// a shared build machine. The default is a million.
+    #[clap(long, value_name = "JOBS")]
+    max_jobs: Option<usize>,
+
+    /// Refuse to build if there's a chain of jobs depending on each other
+    /// that's longer than this. The default is ten thousand.
+    #[clap(long, value_name = "DEPTH")]
+    max_graph_depth: Option<usize>,
+
+    /// Refuse to build if a single job takes more input files than this.
+    /// The default is a quarter million.
+    #[clap(long, value_name = "FILES")]
+    max_inputs_per_job: Option<usize>,
+
+    /// When pausing the build with `kill -TSTP`, also stop jobs that are
+    /// already running instead of letting them finish in the background.
+    /// (Ctrl-Z in a terminal always stops them, since they're in rbt's
+    /// process group.)
+    #[clap(long)]
+    pause_children: bool,
+
+    /// Declare a system resource that jobs can ask for, either as a number
+    /// of interchangeable slots (`kvm=4`) or a range of numbers to hand out
+    /// (`port=8000-8099`.) Jobs get the unit they were given in an
+    /// environment variable like `RBT_RESOURCE_PORT`. Resources that aren't
+    /// declared can be held by one job at a time.
+    #[clap(long = "resource", value_name = "NAME=CAPACITY")]
+    resources: Vec<resources::Declaration>,
+
+    /// Build with the settings in this profile from `rbt.json`, like
+    /// `--profile ci`. Flags given here win over the profile's settings.
+    #[clap(long, value_name = "NAME")]
+    profile: Option<String>,
+
     #[clap(long, default_value = "trace")]
-    pub log_level: log::LevelFilter,
+    pub log_level: LevelFilter,
+
+    /// If this version of rbt calculates job keys differently than the one
+    /// that last used the root dir, drop the old cache associations instead
+    /// of refusing to build. See docs/internals/changing-job-keys.md.
+    #[clap(long)]
+    migrate_keys: bool,
+
+    /// Take over parts of the database that an older version of rbt wrote
+    /// and could otherwise keep using. Until then, this version only reads
+    /// them (or refuses to run, if it can't build without writing.) Only do
+    /// this once nobody using this root dir needs the older version.
+    #[clap(long)]
+    upgrade_db: bool,
+
+    /// Copy every project file the build reads into a snapshot before
+    /// starting, and build only from those copies. Edits made while the
+    /// build runs won't leak into it, and the snapshot's identity is written
+    /// to `provenance.json` in the root dir afterwards. Meant for release
+    /// builds; it costs a full read of every input.
+    #[clap(long)]
+    snapshot_inputs: bool,
+
+    /// When a job fails, save its environment variables, the path and
+    /// version of its tool, a listing of its workspace, and its resource
+    /// limits to a tarball under `diagnostics` in the root dir. Handy for
+    /// attaching to bug reports when a job only fails on one machine.
+    #[clap(long)]
+    capture_diagnostics: bool,
+
+    /// After a successful build, symlink each root job's output here.
+    /// `{NAME}` is replaced with the job's environment variable `NAME`, and
+    /// `{key}`, `{hash}`, and `{shard}` with the job's key, the hash of its
+    /// output, and its shard number. For example:
+    /// `--out-link 'dist/app-{VERSION}-{TARGET}'`. Links from earlier builds
+    /// are replaced in one step, so scripts following them never find them
+    /// missing.
+    #[clap(long, value_name = "TEMPLATE")]
+    out_link: Option<String>,
+
+    /// After a successful build, write the root jobs' outputs to an archive
+    /// at this path, each in a directory named after its job's key. The
+    /// format comes from the extension: `.tar`, `.tar.gz`, or `.tar.zst`.
+    /// Only public jobs can be exported (see `withVisibility`.)
+    #[clap(long, value_name = "PATH")]
+    export: Option<PathBuf>,
+
+    /// How hard to compress `--export` archives: 0 to 9 for gzip, or 1 to
+    /// 22 for zstd. Defaults to each format's usual level.
+    #[clap(long, value_name = "LEVEL", requires = "export")]
+    export_level: Option<i32>,
+
+    /// Pass a configuration value to the build definition, which can read it
+    /// with `Rbt.define`. Use this to get debug and release builds (or
+    /// feature flags) out of the same definition. If a name is given more
+    /// than once, the last value wins.
+    #[clap(long = "define", value_name = "NAME=VALUE", value_parser = parse_define)]
+    defines: Vec<(String, String)>,
+
+    /// How many seconds a persistent worker (see `Rbt.withPersistentWorker`)
+    /// gets to finish one job before we stop it and fail the job.
+    #[clap(long, value_name = "SECONDS", default_value_t = coordinator::DEFAULT_WORKER_TIMEOUT.as_secs())]
+    worker_timeout: u64,
+
+    /// When a job fails, keep building everything that doesn't depend on it.
+    /// By default, we stop the jobs that are running and give up right away.
+    /// Either way, the jobs that didn't get built are listed at the end.
+    #[clap(long)]
+    keep_going: bool,
+
+    /// Give up on the build if it takes longer than this, like `90s`, `10m`
+    /// or `2h`: stop the jobs that are running, say which jobs finished and
+    /// which were cut short, and exit with status 124 (instead of the usual
+    /// 1) so CI can tell a slow build from a broken one.
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// When a job fails, show the chain of dependencies from each target
+    /// you asked for down to the failure, like `app <- bundle <- codegen
+    /// FAILED`, so you can tell which targets it affects.
+    #[clap(long)]
+    verbose_failures: bool,
+
+    /// Make jobs' passthrough environment variables (see
+    /// `withPassthroughEnv`) part of their cache keys, so they run again
+    /// when the values change. By default only the names count, so
+    /// machines with different values can share cache hits.
+    #[clap(long)]
+    hash_passthrough_env: bool,
+
+    /// Fail jobs that leave files in their workspace that aren't inputs or
+    /// declared outputs. Those files never make it into the store, so
+    /// anything relying on them is a dependency bug waiting to happen. By
+    /// default we only warn about them. Scratch files belong in `$TMPDIR`,
+    /// which rbt points outside the workspace and never checks.
+    #[clap(long)]
+    strict_outputs: bool,
+
+    /// Run every job in one of a few reused workspaces whose paths are all
+    /// the same length (`workspaces/pool-0000/build` and so on), instead of
+    /// one named after the job's key. Tools that put their working
+    /// directory (or `$PWD`) in their output then produce the same thing
+    /// every time, and bugs that depend on how long paths are stop coming
+    /// and going. The paths still start with the root dir, so point
+    /// `--root-dir` at the same place on every machine for outputs to match
+    /// across machines too.
+    #[clap(long)]
+    stable_paths: bool,
+
+    /// Also send job output here, for CI systems that collect logs in their
+    /// own place: `file:PATH` to append to a file, `stdout`, `syslog`, or
+    /// `command:COMMAND` to pipe it into a shell command (like a log
+    /// shipper) started once per build. Each line goes out as JSON with the
+    /// job's `key`, the `stream` (`stdout` or `stderr`), and the `line`
+    /// itself, except for syslog, which gets plain messages. Can be given
+    /// more than once.
+    #[clap(long = "log-sink", value_name = "SINK")]
+    log_sinks: Vec<log_sink::Spec>,
+
+    /// Run each job under `strace` and warn about files it read from the
+    /// project without declaring them as inputs (like a header found
+    /// through an include path nobody listed.) Only works on Linux, needs
+    /// `strace` on the PATH, and makes jobs a good deal slower, so it's
+    /// meant for tracking down dependency bugs rather than every build.
+    #[clap(long)]
+    check_inputs: bool,
+
+    /// Run each job under `strace`, like `--check-inputs`, and warn about
+    /// inputs it never read. Every input is part of a job's cache key, so
+    /// these make it run again whenever they change, for nothing. They're
+    /// listed in `--report json` as `unusedInputs` too. Tools that find
+    /// their inputs some other way than their paths in the workspace can
+    /// make inputs look unused when they aren't, so check before removing
+    /// them.
+    #[clap(long)]
+    warn_unused_inputs: bool,
+
+    /// Hash the contents of every store item the build would use again, and
+    /// check they still match the hash the item is named after. Items that
+    /// don't (because of disk corruption or someone editing them by hand)
+    /// get moved to `quarantine` in the store and their jobs run again.
+    /// Reads every cached output, so it's slower than a normal build.
+    #[clap(long)]
+    verify_store: bool,
+
+    /// Every ten seconds, log why each job that isn't running yet is
+    /// waiting: for unfinished dependencies (listed), for a free slot under
+    /// `--max-local-jobs`, or for a resource other jobs are holding. Useful
+    /// for figuring out why a build isn't using the whole machine.
+    #[clap(long)]
+    explain_schedule: bool,
+
+    /// How to show the build while it runs. `fancy` keeps a status block
+    /// of running jobs, queue depth, and cache hits up to date in place, and
+    /// only shows warnings and errors from rbt itself. Job output is always
+    /// saved in the logs directory either way.
+    #[clap(long, value_enum, value_name = "MODE", default_value = "auto")]
+    progress: ui::Mode,
+
+    /// After the build (whether or not it worked), write out what happened
+    /// to each job: its key and command, whether it was a cache hit, how
+    /// long it ran, where its output is in the store, and why it failed if
+    /// it did. This goes to stdout unless you give `--report-path`.
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+
+    /// Check this HTTP cache for the output of jobs we'd otherwise have to
+    /// run, before running them. Useful for sharing build results between
+    /// CI machines.
+    #[clap(long, value_name = "URL", env = "RBT_REMOTE_CACHE")]
+    remote_cache: Option<String>,
+
+    /// Upload the output of jobs we run to `--remote-cache`.
+    #[clap(long, requires = "remote_cache")]
+    remote_cache_upload: bool,
+
+    /// Instead of building, write out the graph of jobs and the
+    /// dependencies between them: `dot` for Graphviz (try `rbt --emit-graph
+    /// dot | dot -Tsvg > graph.svg`) or `json` for other tools. Jobs that
+    /// were in the last build get how it went for them (cached, rebuilt,
+    /// failed, or skipped) and how long they took, and the critical path
+    /// (the chain of jobs that decided how long the build took) is marked.
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    emit_graph: Option<GraphFormat>,
+
+    /// Render `--emit-graph dot` to an SVG here with Graphviz, instead of
+    /// printing it. Needs `dot` on the PATH.
+    #[clap(long, value_name = "PATH", requires = "emit_graph")]
+    render: Option<PathBuf>,
+
+    /// Where to write the `--report`.
+    #[clap(long, value_name = "PATH", requires = "report")]
+    report_path: Option<PathBuf>,
+
+    /// Run this program at the start of each build that has stamped jobs
+    /// (see `stamp` in Rbt.roc) and give them what it prints: `KEY value`
+    /// lines, like `GIT_SHA 3fa2c81e`. Keys starting with `STABLE_` re-run
+    /// stamped jobs when they change, and others don't.
+    #[clap(long, value_name = "PATH")]
+    status_command: Option<PathBuf>,
+
+    /// Record how long each build phase and each job's steps (workspace
+    /// setup, running the command, moving outputs into the store) took, and
+    /// write it here in Chrome's trace format when rbt exits. Open it in
+    /// `chrome://tracing` or https://ui.perfetto.dev to see where a build
+    /// spends its time.
+    #[clap(long, value_name = "PATH")]
+    trace_file: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Where we look for the project config (see `profile::Config`), relative
+/// to the directory rbt runs in.
+const PROJECT_CONFIG: &str = "rbt.json";
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ReportFormat {
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Build the named targets (see `targets` in `Rbt.init`) instead of the
+    /// default job. This is what running `rbt` without a command does, with
+    /// no targets.
+    Build {
+        /// Which targets to build. Builds the default job if none are given.
+        /// You can also give a job's ID from the logs (like `cc-3fa2c81e`) or
+        /// its key, or enough of the start of the key to tell it apart, to
+        /// build just that job and what it depends on.
+        #[clap(value_name = "TARGET")]
+        targets: Vec<String>,
+    },
+
+    /// Remove state that rbt keeps around between builds
+    Clean {
+        /// Remove the incremental state directories that jobs use to pick up
+        /// where their last run left off. Those jobs will start from scratch
+        /// the next time they run.
+        #[clap(long)]
+        incremental: bool,
+
+        /// Remove checkpoints that interrupted or failed jobs left to resume
+        /// from (see `withCheckpointDir`.) Those jobs will start from
+        /// scratch the next time they run.
+        #[clap(long)]
+        checkpoints: bool,
+    },
+
+    /// Look after rbt's database
+    Db {
+        #[clap(subcommand)]
+        command: DbCommand,
+    },
+
+    /// Convert a Ninja build file into an rbt build definition (printed to
+    /// stdout) so you can try rbt on a project without rewriting its build
+    /// first. Anything that couldn't be converted is listed at the top.
+    ImportNinja {
+        /// Usually `build.ninja`
+        path: PathBuf,
+    },
+
+    /// Remove store items that no build has used in a while, along with the
+    /// cache entries that point at them and any resolved inputs that no
+    /// build has asked for
+    Gc {
+        /// Remove items that haven't been used (as a cache hit or as an input
+        /// to another job) in this many days.
+        #[clap(long, default_value = "30")]
+        unused_for_days: u64,
+
+        /// Use `--unused-for-days` for every item, even ones whose jobs
+        /// asked to be kept longer (or shorter) with `withRetention`.
+        #[clap(long)]
+        ignore_retention: bool,
+    },
+
+    /// Show how much space rbt's state takes up
+    Stats,
+
+    /// Show what a job wrote the last time it ran: its stdout on stdout and
+    /// its stderr on stderr, in the order it wrote them. This only reads
+    /// the logs directory, so it works while a build (or the daemon) is
+    /// running, too.
+    Logs {
+        /// A job's ID (like `cc-3fa2c81e`), its base key (which its output
+        /// is prefixed with during builds) or its final key, or enough of
+        /// the start of a key to tell it apart
+        #[clap(value_name = "JOB")]
+        job: String,
+
+        /// Keep showing lines as the job writes them, until it finishes
+        #[clap(long, short, conflicts_with = "until")]
+        follow: bool,
+
+        /// Only show the last N lines
+        #[clap(long, value_name = "N")]
+        tail: Option<usize>,
+
+        /// Only show lines written at most this long ago, like `90s`,
+        /// `10m`, `2h` or `1d`
+        #[clap(long, value_name = "AGO", value_parser = parse_duration)]
+        since: Option<Duration>,
+
+        /// Only show lines written at least this long ago
+        #[clap(long, value_name = "AGO", value_parser = parse_duration)]
+        until: Option<Duration>,
+    },
+
+    /// Run the tests (jobs marked with `withKind Test`) among the targets
+    /// and what they depend on, then say how each one went. A test that
+    /// passed is a cache hit until its key changes, so unchanged tests are
+    /// reported as cached passes instead of running again. One failing test
+    /// doesn't stop the others, like with `--keep-going`.
+    Test {
+        /// Which targets to look for tests in. Looks in the default job if
+        /// none are given.
+        #[clap(value_name = "TARGET")]
+        targets: Vec<String>,
+    },
+
+    /// Download what the remote cache has for the targets and what they
+    /// depend on into the local store, without running any jobs, then say
+    /// what would still have to build. Handy before going offline, or for
+    /// warming up a CI runner. A job's cache key depends on its
+    /// dependencies' outputs, so nothing that depends on a job that has to
+    /// build can be looked up until it has.
+    Prefetch {
+        /// Which targets to prefetch. Prefetches the default job if none
+        /// are given.
+        #[clap(value_name = "TARGET")]
+        targets: Vec<String>,
+    },
+
+    /// Compare two build reports (from `--report json`) and print what got
+    /// worse or better as Markdown, for posting on a pull request: new
+    /// failures, jobs that ran instead of being cache hits, jobs whose
+    /// duration changed, and the change in cache hit rate. Jobs are
+    /// matched up by key.
+    CompareReports {
+        /// Usually from the main branch
+        before: PathBuf,
+
+        /// Usually from the pull request
+        after: PathBuf,
+
+        /// Only list duration changes of at least this many milliseconds.
+        #[clap(long, default_value = "1000")]
+        threshold_ms: u64,
+    },
+
+    /// Build everything a target needs, then start an interactive shell
+    /// (`$SHELL`) in its workspace, with its inputs in place and its
+    /// environment set, instead of running its command. Handy for working
+    /// out a failing command by hand before changing the build definition.
+    Shell {
+        /// A target name, or a job's ID or key, like `rbt build` takes
+        #[clap(value_name = "TARGET")]
+        target: String,
+    },
+
+    /// Print what went into the cache keys of a target's jobs: first
+    /// everything about the job itself (its command, environment, inputs
+    /// and outputs) that makes up its base key, then the hashes of its
+    /// input files and its dependencies' outputs that make up its final
+    /// key. When a job rebuilds and you don't know why, diff this from
+    /// before and after. Nothing runs, but what the target depends on has
+    /// to be in the store (or the remote cache) for its final key to be
+    /// known.
+    Explain {
+        /// A target name, or a job's ID or key, like `rbt build` takes
+        #[clap(value_name = "TARGET")]
+        target: String,
+    },
+
+    /// Build everything a target needs, then run it over and over with the
+    /// same inputs, each time in a fresh workspace, and say how often it
+    /// failed. A job that only fails sometimes is flaky, and this is for
+    /// finding out how flaky before (and after) trying to fix it. Each
+    /// run's logs are kept for `rbt logs`, and each check is remembered so
+    /// later checks of the same job can be compared with it. Nothing the
+    /// runs output goes into the store.
+    FlakeCheck {
+        /// A target name, or a job's ID or key, like `rbt build` takes
+        #[clap(value_name = "TARGET")]
+        target: String,
+
+        /// How many times to run the job.
+        #[clap(long, default_value = "20")]
+        runs: usize,
+    },
+
+    /// Load the build definition and check every job in it, without
+    /// building anything. Bad paths and outputs that overlap inputs are
+    /// errors; paths that only exist on this machine (which keep other
+    /// machines from getting cache hits) and system tools that aren't on
+    /// the PATH their jobs would run with are listed as warnings.
+    Check,
+
+    /// Keep rbt's database open and run builds sent with `--daemon` until
+    /// stopped. Builds skip opening the database, and most of the file
+    /// hashes they look up are already in memory, which makes builds where
+    /// little changed a lot faster on big projects. Builds from different
+    /// clients run side by side, and a job one of them is already running
+    /// doesn't run again for another, which waits for its output. The build
+    /// program is part of rbt, so restart the daemon after changing it.
+    /// `rbt --daemon stats` gets answered right away, even in the middle of
+    /// a build. While it's idle, it also removes workspaces and temporary
+    /// store directories that builds which were killed left behind, and it
+    /// stops once nobody has asked it for anything in a while. Only works on
+    /// Unix-like systems so far.
+    Daemon {
+        #[clap(subcommand)]
+        command: Option<DaemonCommand>,
+
+        /// Remove leftover workspaces and temporary store directories once
+        /// they haven't changed in this many hours. 0 turns this off.
+        #[clap(long, value_name = "HOURS", default_value = "24")]
+        prune_after_hours: u64,
+
+        /// Stop once nobody has asked for anything in this many minutes. 0
+        /// keeps the daemon running until it's stopped.
+        #[clap(long, value_name = "MINUTES", default_value = "0")]
+        idle_timeout_minutes: u64,
+
+        /// Like `--idle-timeout-minutes`, but while the machine is running
+        /// on battery (which we can only tell on Linux so far.) 0 turns this
+        /// off.
+        #[clap(long, value_name = "MINUTES", default_value = "30")]
+        battery_idle_timeout_minutes: u64,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum DaemonCommand {
+    /// Ask the running daemon what it's doing, and how much CPU time and
+    /// memory it has used.
+    Status,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum DbCommand {
+    /// Rewrite the database without the space sled keeps for old values.
+    /// The database can grow to several times the size of what's in it on
+    /// projects that build often, and this shrinks it back down. Builds
+    /// can't run at the same time, so it's a good thing to do after
+    /// `rbt gc`, from the same cron job or CI schedule.
+    Compact,
+}
+
+/// Sizes on disk, in the biggest unit that keeps them above 1.
+fn bytes(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+
+    let mut scaled = size as f64 / 1024.0;
+    let mut unit = 0;
+    while scaled >= 1024.0 && unit < UNITS.len() - 1 {
+        scaled /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", scaled, UNITS[unit])
+}
+
+/// Parse a length of time, like `90s`, `10m`, `2h` or `1d`.
+fn parse_duration(duration: &str) -> Result<Duration> {
+    let (number, unit) = duration.split_at(
+        duration
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(duration.len()),
+    );
+
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("`{}` should start with a number", duration))?;
+
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => anyhow::bail!("`{}` should end with `s`, `m`, `h` or `d`", duration),
+    };
+
+    Ok(Duration::from_secs(number.saturating_mul(unit_secs)))
+}
+
+fn parse_define(define: &str) -> Result<(String, String)> {
+    let (name, value) = define
+        .split_once('=')
+        .context("defines should look like `NAME=VALUE`")?;
+
+    if name.is_empty() {
+        anyhow::bail!("define names can't be empty")
+    }
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Have Graphviz lay out `dot` and write it to `path` as an SVG.
+fn render_svg(dot: &str, path: &Path) -> Result<()> {
+    let mut child = std::process::Command::new("dot")
+        .arg("-Tsvg")
+        .arg("-o")
+        .arg(path)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .context("could not run Graphviz's `dot`. Is Graphviz installed?")?;
+
+    std::io::Write::write_all(
+        child
+            .stdin
+            .as_mut()
+            .context("could not get stdin for `dot`")?,
+        dot.as_bytes(),
+    )
+    .context("could not send the graph to `dot`")?;
+
+    // closing stdin lets `dot` know the graph is done
+    drop(child.stdin.take());
+    let status = child.wait().context("could not wait for `dot`")?;
+    if !status.success() {
+        anyhow::bail!("`dot` failed with {}", status)
+    }
+
+    Ok(())
 }
 
 impl Cli {
+    /// Send log messages to stderr, and spans to `--trace-file` if we got
+    /// one. The trace needs to be finished once we're done.
+    pub fn init_logging(&self) -> Result<Option<trace::ChromeTrace>> {
+        // log lines would scroll the status block out from under us
+        let level = if self.progress.is_fancy() {
+            self.log_level.min(LevelFilter::WARN)
+        } else {
+            self.log_level
+        };
+
+        let stderr = tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_filter(
+                Targets::new()
+                    .with_default(level)
+                    .with_target("sled", level.min(LevelFilter::INFO)),
+            );
+
+        let (chrome, trace) = match &self.trace_file {
+            Some(path) => {
+                let (layer, trace) = trace::chrome(path.clone());
+                (Some(layer), Some(trace))
+            }
+            None => (None, None),
+        };
+
+        tracing_subscriber::registry()
+            .with(stderr)
+            .with(chrome)
+            .try_init()
+            .context("could not set up logging")?;
+
+        Ok(trace)
+    }
+
     pub fn run(&self) -> Result<()> {
-        let rbt = Self::load();
+        let targets = match &self.command {
+            Some(Command::Clean {
+                incremental,
+                checkpoints,
+            }) => return self.clean(*incremental, *checkpoints),
+            Some(Command::Gc {
+                unused_for_days,
+                ignore_retention,
+            }) => return self.gc(*unused_for_days, *ignore_retention),
+            Some(Command::ImportNinja { path }) => return self.import_ninja(path),
+            Some(Command::Logs {
+                job,
+                follow,
+                tail,
+                since,
+                until,
+            }) => return self.logs(job, *follow, *tail, *since, *until),
+            Some(Command::Db {
+                command: DbCommand::Compact,
+            }) => return self.compact_db(),
+            Some(Command::Build { .. } | Command::Stats) | None if self.daemon => {
+                return self.send_to_daemon()
+            }
+            Some(Command::Stats) => return self.stats(),
+            Some(Command::CompareReports {
+                before,
+                after,
+                threshold_ms,
+            }) => return Self::compare_reports(before, after, *threshold_ms),
+            Some(Command::Check) => return self.check(),
+            Some(Command::Daemon {
+                command: Some(DaemonCommand::Status),
+                ..
+            }) => return self.send_to_daemon(),
+            Some(Command::Daemon {
+                command: None,
+                prune_after_hours,
+                idle_timeout_minutes,
+                battery_idle_timeout_minutes,
+            }) => {
+                let minutes = |minutes: u64| {
+                    (minutes > 0).then(|| Duration::from_secs(minutes.saturating_mul(60)))
+                };
+
+                return self.serve(
+                    Duration::from_secs(prune_after_hours.saturating_mul(60 * 60)),
+                    daemon::IdleTimeout {
+                        always: minutes(*idle_timeout_minutes),
+                        on_battery: minutes(*battery_idle_timeout_minutes),
+                    },
+                );
+            }
+            Some(Command::Shell { target }) => {
+                let db = self.open_db().context("could not open rbt's database")?;
+                return self.shell(target, &db);
+            }
+            Some(Command::Explain { target }) => {
+                let db = self.open_db().context("could not open rbt's database")?;
+                return self.explain(target, &db);
+            }
+            Some(Command::FlakeCheck { target, runs }) => {
+                let db = self.open_db().context("could not open rbt's database")?;
+                return self.flake_check(target, *runs, &db);
+            }
+            Some(Command::Test { targets }) => {
+                let db = self.open_db().context("could not open rbt's database")?;
+                return self.build(targets, &db, true, None);
+            }
+            Some(Command::Prefetch { targets }) => {
+                let db = self.open_db().context("could not open rbt's database")?;
+                return self.prefetch(targets, &db);
+            }
+            Some(Command::Build { targets }) => targets.as_slice(),
+            None => &[],
+        };
 
         let db = self.open_db().context("could not open rbt's database")?;
+        self.build(targets, &db, false, None)
+    }
 
-        let store = Store::new(
-            db.open_tree("store")
-                .context("could not open the store database")?,
-            self.root_dir()?.join("store"),
-        )
-        .context("could not open store")?;
+    /// Build `targets`, or only the tests among them and what they depend
+    /// on if `tests_only` is set. Builds sharing `in_flight` don't run the
+    /// same job at the same time.
+    fn build(
+        &self,
+        targets: &[String],
+        db: &Db,
+        tests_only: bool,
+        in_flight: Option<&InFlight>,
+    ) -> Result<()> {
+        let profile = self.profile()?;
+        if let Some(name) = &self.profile {
+            tracing::info!("using the `{}` profile", name);
+        }
 
-        let mut builder = coordinator::Builder::new(
-            store,
-            db.open_tree("file_hashes")
-                .context("could not open file hashes database")?,
-            self.root_dir()?.join("workspaces"),
-            self.max_local_jobs()?,
-        );
-        builder.add_root(&rbt.default);
+        let defines = self.defines(&profile);
+        if !defines.is_empty() {
+            tracing::info!(
+                "building with {}",
+                defines
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            );
+        }
+
+        let export = match &self.export {
+            Some(path) => Some(Export::new(path.clone(), self.export_level)?),
+            None => None,
+        };
+
+        let rbt = Self::load(&defines);
+
+        self.check_key_format(db)
+            .context("could not check the job key format")?;
+
+        let last_build_started = self
+            .swap_build_started(db)
+            .context("could not record when this build started")?;
+
+        let mut builder = self.coordinator_builder(&rbt, targets, db, &profile)?;
+        builder.last_build_started(last_build_started);
+        if let Some(in_flight) = in_flight {
+            builder.in_flight(in_flight.clone());
+        }
 
+        let log_sinks = Sinks::open(&self.log_sinks).context("could not open log sinks")?;
+        builder.log_sinks(log_sinks.clone());
+        if tests_only {
+            builder.keep_going(true);
+        }
+
+        let fancy = self.progress.is_fancy();
         let mut coordinator = builder
             .build()
             .context("could not initialize coordinator")?;
 
+        let tests = if tests_only {
+            if coordinator.keep_only_tests() == 0 {
+                anyhow::bail!("there aren't any tests in these targets or what they depend on. Mark jobs as tests with `withKind`.")
+            }
+
+            Some(
+                coordinator
+                    .roots()
+                    .iter()
+                    .map(|root| coordinator.job(root).map(JobInfo::from))
+                    .collect::<Option<Vec<JobInfo>>>()
+                    .context("could not get a test job")?,
+            )
+        } else {
+            None
+        };
+
+        // better to find out before building everything
+        if export.is_some() {
+            for root in coordinator.roots() {
+                let job = coordinator.job(root).context("could not get root job")?;
+                if !job.visibility.allows_export() {
+                    anyhow::bail!(
+                        "{} is {}, so I can't export its outputs (see `withVisibility`.) Jobs that take files from local or team jobs get their visibility too.",
+                        job,
+                        job.visibility
+                    )
+                }
+            }
+        }
+
+        if let Some(format) = self.emit_graph {
+            let mut graph = coordinator.graph();
+            if let Some(last_build) = Report::read(&self.root_dir()?.join(report::LAST_BUILD_FILE))
+                .context("could not read the last build's report")?
+            {
+                graph.annotate(&last_build);
+            }
+
+            match (format, &self.render) {
+                (GraphFormat::Dot, Some(path)) => render_svg(&graph.to_dot(), path)
+                    .with_context(|| format!("could not render graph to `{}`", path.display()))?,
+                (GraphFormat::Json, Some(_)) => {
+                    anyhow::bail!("`--render` only works with `--emit-graph dot`")
+                }
+                (GraphFormat::Dot, None) => print!("{}", graph.to_dot()),
+                (GraphFormat::Json, None) => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&graph).context("could not serialize graph")?
+                ),
+            }
+
+            return Ok(());
+        }
+
         let runtime = self.async_runtime()?;
 
-        runtime
-            .block_on(coordinator.run())
-            .context("failed to run jobs")?;
+        // we always keep the last build's report around for `--emit-graph`
+        let report = runtime.spawn(Report::collect(coordinator.subscribe()));
+        let journal = runtime.spawn(journal::record(
+            self.root_dir()?.join(journal::FILE),
+            coordinator.subscribe(),
+        ));
+
+        let progress = fancy.then(|| runtime.spawn(ui::fancy(coordinator.subscribe())));
+
+        let result = runtime.block_on(coordinator.run());
+        log_sinks
+            .finish()
+            .context("could not finish sending job output to log sinks")?;
+
+        // let the status block draw the end of the build before anything
+        // else writes to the terminal.
+        if let Some(progress) = progress {
+            runtime
+                .block_on(progress)
+                .context("could not join progress display")?;
+        }
+        eprintln!("\n{}", coordinator.stats());
+
+        // the journal is for other tools to watch, so a problem writing it
+        // shouldn't fail the build.
+        if let Err(problem) = runtime
+            .block_on(journal)
+            .context("could not join journal writer")?
+        {
+            tracing::warn!("could not keep the build journal: {:?}", problem);
+        }
+
+        // failed builds are the ones people most want reports for, so this
+        // has to happen before we bail.
+        let mut report = runtime
+            .block_on(report)
+            .context("could not collect report")?;
+        report.profile = self.profile.clone();
+
+        let last_build = self.root_dir()?.join(report::LAST_BUILD_FILE);
+        std::fs::write(
+            &last_build,
+            serde_json::to_vec(&report).context("could not serialize report")?,
+        )
+        .with_context(|| format!("could not write `{}`", last_build.display()))?;
+
+        if let Some(format) = self.report {
+            self.write_report(format, &report)
+                .context("could not write report")?;
+        }
+
+        if let Some(tests) = &tests {
+            println!("{}", report.test_results(tests));
+        }
+
+        result.context("failed to run jobs")?;
 
         if self.print_root_output_paths {
             for root in coordinator.roots() {
@@ -75,17 +945,945 @@ impl Cli {
             }
         }
 
+        if let Some(template) = &self.out_link {
+            let roots = coordinator
+                .roots()
+                .iter()
+                .map(|root| {
+                    Ok((
+                        coordinator.job(root).context("could not get root job")?,
+                        coordinator
+                            .store_path(root)
+                            .context("could not get store path for root")?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            OutLink::new(template.clone())
+                .link_all(roots)
+                .context("could not link outputs")?;
+        }
+
+        if let Some(export) = export {
+            let outputs = coordinator
+                .roots()
+                .iter()
+                .map(|root| {
+                    Ok((
+                        root.to_string(),
+                        coordinator
+                            .store_path(root)
+                            .context("could not get store path for root")?
+                            .path()
+                            .clone(),
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            export.write(&outputs).context("could not export outputs")?;
+        }
+
+        if let Some(snapshot) = coordinator.snapshot() {
+            self.write_provenance(snapshot, &coordinator, &defines)
+                .context("could not write provenance")?;
+        }
+
+        Ok(())
+    }
+
+    /// A coordinator for `targets`, set up the way our flags and `profile`
+    /// say, for anything that runs jobs.
+    fn coordinator_builder<'a>(
+        &self,
+        rbt: &'a glue::Rbt,
+        targets: &[String],
+        db: &Db,
+        profile: &Profile,
+    ) -> Result<coordinator::Builder<'a>> {
+        let mut store = self.open_store(db)?;
+        if let Some(url) = &self.remote_cache {
+            store.use_remote(RemoteCache::new(url, self.remote_cache_upload));
+        } else if let Some(url) = &profile.remote_cache {
+            store.use_remote(RemoteCache::new(url, profile.remote_cache_upload));
+        }
+
+        // file hashes are only a cache, so we can build without saving them
+        // while an older rbt still needs them the way it wrote them.
+        let (file_hashes, file_hashes_access) = db.tree_with_access(db::Tree::FileHashes)?;
+
+        let mut builder = coordinator::Builder::new(
+            store,
+            file_hashes,
+            self.root_dir()?.into_owned(),
+            self.max_local_jobs(profile)?,
+            Resources::new(&self.resources),
+            Pauser::new(self.root_dir()?.join("paused.json"), self.pause_children),
+        );
+        Self::add_roots(&mut builder, rbt, targets)?;
+        builder.snapshot_inputs(self.snapshot_inputs || profile.snapshot_inputs);
+        builder.capture_diagnostics(self.capture_diagnostics || profile.capture_diagnostics);
+        builder.salt(profile.salt.clone());
+        builder.status_command(
+            self.status_command
+                .clone()
+                .or_else(|| profile.status_command.clone()),
+        );
+        builder.worker_timeout(Duration::from_secs(self.worker_timeout));
+        builder.explain_schedule(self.explain_schedule);
+        builder.keep_going(self.keep_going);
+        builder.timeout(self.timeout);
+        builder.verbose_failures(self.verbose_failures);
+        builder.hash_passthrough_env(self.hash_passthrough_env);
+        builder.graph_limits(self.graph_limits());
+        builder.strict_outputs(self.strict_outputs || profile.strict_outputs);
+        builder.stable_paths(self.stable_paths || profile.stable_paths);
+        builder.check_inputs(self.check_inputs);
+        builder.warn_unused_inputs(self.warn_unused_inputs);
+        builder.verify_store(self.verify_store || profile.verify_store);
+        builder.show_job_output(!self.progress.is_fancy());
+        builder.remember_file_hashes(file_hashes_access == db::Access::ReadWrite);
+
+        Ok(builder)
+    }
+
+    /// Download what the remote cache has for `targets` without running
+    /// anything, then say what would still have to build.
+    fn prefetch(&self, targets: &[String], db: &Db) -> Result<()> {
+        let profile = self.profile()?;
+        if self.remote_cache.is_none() && profile.remote_cache.is_none() {
+            anyhow::bail!("there's nowhere to prefetch from. Give a `--remote-cache`, or use a profile that has one.")
+        }
+
+        let rbt = Self::load(&self.defines(&profile));
+
+        self.check_key_format(db)
+            .context("could not check the job key format")?;
+
+        // this hashes input files just like a build, so it needs to know
+        // the same things about when the last one was.
+        let last_build_started = self
+            .swap_build_started(db)
+            .context("could not record when this build started")?;
+
+        let mut builder = self.coordinator_builder(&rbt, targets, db, &profile)?;
+        builder.last_build_started(last_build_started);
+        builder.fetch_only(true);
+
+        let mut coordinator = builder
+            .build()
+            .context("could not initialize coordinator")?;
+
+        let runtime = self.async_runtime()?;
+        let progress = self
+            .progress
+            .is_fancy()
+            .then(|| runtime.spawn(ui::fancy(coordinator.subscribe())));
+
+        let result = runtime.block_on(coordinator.run());
+        if let Some(progress) = progress {
+            runtime
+                .block_on(progress)
+                .context("could not join progress display")?;
+        }
+        result.context("could not prefetch")?;
+
+        let stats = coordinator.stats();
+        println!(
+            "downloaded {} items; {} more were already in the store",
+            stats.downloads,
+            stats.cache_hits - stats.downloads
+        );
+
+        let (to_run, blocked) = coordinator.unbuilt();
+        if to_run.is_empty() {
+            println!("everything is ready, so nothing would need to build");
+            return Ok(());
+        }
+
+        println!(
+            "{} jobs aren't in any cache, so they'd have to build:",
+            to_run.len()
+        );
+        for job in to_run {
+            println!("    {}", job);
+        }
+        if !blocked.is_empty() {
+            println!(
+                "{} more depend on those, so they may have to build too",
+                blocked.len()
+            );
+        }
+
         Ok(())
     }
 
-    pub fn load() -> glue::Rbt {
+    /// Build what `target` needs, then start an interactive shell in its
+    /// workspace with its environment instead of running its command.
+    fn shell(&self, target: &str, db: &Db) -> Result<()> {
+        let profile = self.profile()?;
+        let rbt = Self::load(&self.defines(&profile));
+
+        self.check_key_format(db)
+            .context("could not check the job key format")?;
+
+        let coordinator = self
+            .coordinator_builder(&rbt, &[target.to_string()], db, &profile)?
+            .build()
+            .context("could not initialize coordinator")?;
+
+        let runtime = self.async_runtime()?;
+        let progress = self
+            .progress
+            .is_fancy()
+            .then(|| runtime.spawn(ui::fancy(coordinator.subscribe())));
+
+        let (job, workspace, mut env) = runtime.block_on(coordinator.shell())?;
+        if let Some(progress) = progress {
+            runtime
+                .block_on(progress)
+                .context("could not join progress display")?;
+        }
+
+        // without these, the shell can't find the job's tool the way rbt
+        // would, or draw itself properly.
+        for name in ["PATH", "TERM"] {
+            if let (false, Ok(value)) = (env.contains_key(name), std::env::var(name)) {
+                env.insert(name.to_string(), value);
+            }
+        }
+
+        let mut words = vec![job.command.tool().to_string()];
+        words.extend(job.command.args().iter().cloned());
+        if let Some(response_file) = &job.response_file {
+            words.push(format!("@{}", response_file.display()));
+        }
+
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+        eprintln!(
+            "Starting {} in the workspace for {}. The job would run:\n\n    {}\n\nThe workspace is removed when the shell exits, and nothing in it goes into the store.",
+            shell,
+            job,
+            ninja::shell_join(&words),
+        );
+
+        // the shell's exit code is just whatever was run last, so there's
+        // nothing to report.
+        std::process::Command::new(&shell)
+            .current_dir(&workspace)
+            .env_clear()
+            .envs(&env)
+            .status()
+            .with_context(|| format!("could not start `{}`", shell))?;
+
+        Ok(())
+    }
+
+    /// Print what went into the keys of each of `target`'s jobs.
+    fn explain(&self, target: &str, db: &Db) -> Result<()> {
+        let profile = self.profile()?;
+        let rbt = Self::load(&self.defines(&profile));
+
+        self.check_key_format(db)
+            .context("could not check the job key format")?;
+
+        // final keys depend on what's in the store, and we don't want to run
+        // anything to find out, so this is a prefetch in all but name.
+        let mut builder = self.coordinator_builder(&rbt, &[target.to_string()], db, &profile)?;
+        builder.fetch_only(true);
+
+        let mut coordinator = builder
+            .build()
+            .context("could not initialize coordinator")?;
+
+        let runtime = self.async_runtime()?;
+        runtime
+            .block_on(coordinator.run())
+            .context("could not look up dependency outputs")?;
+
+        let explanations = coordinator
+            .roots()
+            .iter()
+            .map(|key| coordinator.explain(key))
+            .collect::<Result<Vec<_>>>()?;
+        for (index, explanation) in explanations.iter().enumerate() {
+            if index > 0 {
+                println!();
+            }
+            println!("{}", explanation);
+        }
+
+        Ok(())
+    }
+
+    /// Run `target` `runs` times with the same inputs, and report and
+    /// remember how many of them failed.
+    fn flake_check(&self, target: &str, runs: usize, db: &Db) -> Result<()> {
+        if runs == 0 {
+            anyhow::bail!("a flake check needs at least one run")
+        }
+
+        let profile = self.profile()?;
+        let rbt = Self::load(&self.defines(&profile));
+
+        self.check_key_format(db)
+            .context("could not check the job key format")?;
+
+        let history = flake_check::History::new(db.tree(db::Tree::FlakeChecks)?);
+
+        let coordinator = self
+            .coordinator_builder(&rbt, &[target.to_string()], db, &profile)?
+            .build()
+            .context("could not initialize coordinator")?;
+
+        let runtime = self.async_runtime()?;
+        let progress = self
+            .progress
+            .is_fancy()
+            .then(|| runtime.spawn(ui::fancy(coordinator.subscribe())));
+
+        let (job, check) = runtime.block_on(coordinator.flake_check(runs))?;
+        if let Some(progress) = progress {
+            runtime
+                .block_on(progress)
+                .context("could not join progress display")?;
+        }
+
+        let previous = history
+            .for_job(&job.base_key)
+            .context("could not look up earlier flake checks")?
+            .pop();
+        history
+            .record(&job.base_key, &check)
+            .context("could not remember this flake check")?;
+
+        println!("{}", check);
+        if let Some(previous) = previous {
+            println!(
+                "the last check failed {} of {} runs ({:.0}%)",
+                previous.failures(),
+                previous.runs.len(),
+                previous.flake_rate() * 100.0
+            );
+        }
+
+        if check.failures() > 0 {
+            anyhow::bail!("{} failed {} of {} runs", job, check.failures(), runs)
+        }
+
+        Ok(())
+    }
+
+    fn compare_reports(before: &Path, after: &Path, threshold_ms: u64) -> Result<()> {
+        let read = |path: &Path| {
+            Report::read(path)?.with_context(|| format!("`{}` doesn't exist", path.display()))
+        };
+        let before = read(before)?;
+        let after = read(after)?;
+
+        print!(
+            "{}",
+            report::Comparison::new(&before, &after, Duration::from_millis(threshold_ms))
+                .to_markdown()
+        );
+
+        Ok(())
+    }
+
+    fn write_report(&self, format: ReportFormat, report: &Report) -> Result<()> {
+        let rendered = match format {
+            ReportFormat::Json => {
+                serde_json::to_string_pretty(report).context("could not serialize report")?
+            }
+        };
+
+        match &self.report_path {
+            Some(path) => std::fs::write(path, rendered)
+                .with_context(|| format!("could not write `{}`", path.display())),
+            None => {
+                println!("{}", rendered);
+                Ok(())
+            }
+        }
+    }
+
+    /// Record exactly which inputs went into the outputs of a snapshotted
+    /// build, so a release can be traced back to the files it came from.
+    fn write_provenance(
+        &self,
+        snapshot: &Snapshot,
+        coordinator: &coordinator::Coordinator,
+        defines: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let outputs = coordinator
+            .roots()
+            .iter()
+            .map(|root| {
+                coordinator
+                    .store_path(root)
+                    .map(|item| item.path().display().to_string())
+                    .context("could not get store path for root")
+            })
+            .collect::<Result<Vec<String>>>()?;
+
+        let provenance = serde_json::json!({
+            "snapshot": snapshot.id().to_hex().to_string(),
+            "inputs": snapshot
+                .files()
+                .iter()
+                .map(|(path, hash)| (path.display().to_string(), hash.to_hex().to_string()))
+                .collect::<BTreeMap<String, String>>(),
+            "outputs": outputs,
+            "defines": defines,
+        });
+
+        let path = self.root_dir()?.join("provenance.json");
+        std::fs::write(
+            &path,
+            serde_json::to_vec_pretty(&provenance).context("could not serialize provenance")?,
+        )
+        .with_context(|| format!("could not write `{}`", path.display()))?;
+
+        tracing::info!("built from snapshot {}", snapshot.id());
+        Ok(())
+    }
+
+    fn import_ninja(&self, path: &Path) -> Result<()> {
+        let import = ninja::import(path)
+            .with_context(|| format!("could not import `{}`", path.display()))?;
+
+        for problem in &import.problems {
+            tracing::warn!("{}", problem);
+        }
+
+        print!("{}", import.definition);
+        Ok(())
+    }
+
+    /// Record that a build is starting now, and get when the last one
+    /// started.
+    fn swap_build_started(&self, db: &Db) -> Result<Option<SystemTime>> {
+        let last = db
+            .meta_u64(db::Meta::LastBuildStarted)?
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+
+        // if the clock is before 1970, we have bigger problems than caching
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        db.set_meta_u64(db::Meta::LastBuildStarted, now.as_secs())?;
+
+        Ok(last)
+    }
+
+    fn open_store(&self, db: &Db) -> Result<Store> {
+        Store::new(
+            db.tree(db::Tree::Store)?,
+            db.tree(db::Tree::StoreAccess)?,
+            db.tree(db::Tree::StoreRetention)?,
+            db.tree(db::Tree::StoreIntents)?,
+            db.tree(db::Tree::StoreFileTypes)?,
+            self.root_dir()?.join("store"),
+        )
+        .context("could not open store")
+    }
+
+    fn gc(&self, unused_for_days: u64, ignore_retention: bool) -> Result<()> {
+        let db = self.open_db().context("could not open rbt's database")?;
+        let mut store = self.open_store(&db)?;
+
+        let removed = store
+            .collect_garbage(unused_for_days, ignore_retention)
+            .context("could not collect garbage")?;
+
+        tracing::info!(
+            "removed {} store items that were unused for more than {} days",
+            removed,
+            unused_for_days
+        );
+
+        // resolved inputs don't have retention settings, since they come
+        // from outside the build rather than from a job.
+        let resolved = resolver::collect_garbage(
+            &self.root_dir()?.join("resolved"),
+            Duration::from_secs(unused_for_days * 24 * 60 * 60),
+        )
+        .context("could not collect resolved inputs")?;
+
+        tracing::info!(
+            "removed {} resolved inputs that were unused for more than {} days",
+            resolved,
+            unused_for_days
+        );
+
+        // file hashes are a cache, so old ones don't need any of the care
+        // store items do. We only keep ones for directories that still exist.
+        let pruned =
+            path_meta_key::prune_missing_subtrees(&db.tree(db::Tree::FileHashes)?, |path| {
+                path.exists()
+            })
+            .context("could not remove old file hashes")?;
+        let retired = db.drop_retired_trees()?;
+
+        tracing::info!(
+            "removed {} file hashes for directories that are gone{}",
+            pruned,
+            if retired > 0 {
+                " and file hashes left by older versions of rbt"
+            } else {
+                ""
+            }
+        );
+
+        Ok(())
+    }
+
+    fn compact_db(&self) -> Result<()> {
+        let compaction = Db::compact(&self.root_dir()?.join("db"))
+            .context("could not compact rbt's database")?;
+
+        tracing::info!(
+            "compacted the database from {} to {}",
+            bytes(compaction.before),
+            bytes(compaction.after)
+        );
+
+        Ok(())
+    }
+
+    fn logs(
+        &self,
+        job: &str,
+        follow: bool,
+        tail: Option<usize>,
+        since: Option<Duration>,
+        until: Option<Duration>,
+    ) -> Result<()> {
+        const FOLLOW_EVERY: Duration = Duration::from_millis(200);
+
+        let now = SystemTime::now();
+        let ago = |ago: Duration| now.checked_sub(ago).unwrap_or(SystemTime::UNIX_EPOCH);
+
+        let mut log = logs::SavedLog::find(&self.root_dir()?.join("logs"), job)?;
+        tracing::debug!("showing logs from `{}`", log.dir().display());
+
+        let mut which = logs::Lines {
+            since: since.map(ago),
+            until: until.map(ago),
+            last: tail,
+        };
+        loop {
+            for line in log.read(which)? {
+                logs::print(&line).context("could not show log line")?;
+            }
+
+            if !follow || log.finished() {
+                return Ok(());
+            }
+
+            // after the first read, every new line is one we want
+            which = logs::Lines::default();
+            std::thread::sleep(FOLLOW_EVERY);
+        }
+    }
+
+    fn stats(&self) -> Result<()> {
+        let db = self.open_db().context("could not open rbt's database")?;
+        print!("{}", Self::stats_text(&db, None)?);
+
+        Ok(())
+    }
+
+    /// What `rbt stats` prints. It only reads, so the daemon can answer it
+    /// in the middle of a build.
+    fn stats_text(db: &Db, pruned: Option<&prune::Pruned>) -> Result<String> {
+        let stats = db.stats().context("could not get database stats")?;
+
+        let mut out = format!("database: {} on disk\n", bytes(stats.size_on_disk));
+        for (tree, entries) in stats.entries {
+            out.push_str(&format!("  {}: {} entries\n", tree.name(), entries));
+        }
+
+        if let Some(pruned) = pruned {
+            let (dirs, reclaimed) = pruned.totals();
+            out.push_str(&format!(
+                "pruned: {} leftover directories, {}\n",
+                dirs,
+                bytes(reclaimed)
+            ));
+        }
+
+        Ok(out)
+    }
+
+    fn check(&self) -> Result<()> {
+        let profile = self.profile()?;
+        let rbt = Self::load(&self.defines(&profile));
+
+        let mut glue_job_to_key = HashMap::new();
+        let mut jobs: BTreeMap<job::Key<job::Base>, job::Job> = BTreeMap::new();
+        for glue_job in job::dependency_order(
+            std::iter::once(&rbt.default).chain(rbt.targets.iter_values()),
+            &self.graph_limits(),
+        )
+        .context("the build definition's job graph is too big")?
+        {
+            let job = job::Job::from_glue(glue_job, &glue_job_to_key)
+                .context("found a problem with a job")?;
+            glue_job_to_key.insert(job::GlueRef(glue_job), job.base_key);
+            jobs.insert(job.base_key, job);
+        }
+        job::disambiguate(jobs.values_mut());
+
+        let host_paths = lint::HostPaths::from_env();
+        let mut warnings = 0;
+        for job in jobs.values() {
+            for problem in host_paths.check(job) {
+                println!("warning: {}: {}", job.id, problem);
+                warnings += 1;
+            }
+
+            // the message says which job it's about
+            for command in job.commands() {
+                if let Err(missing) = tools::check(job, command, &BTreeMap::new()) {
+                    println!("warning: {}", missing);
+                    warnings += 1;
+                }
+            }
+        }
+
+        println!("checked {} jobs: {} warnings", jobs.len(), warnings);
+        Ok(())
+    }
+
+    /// Hold the database open and build whatever `--daemon` clients ask
+    /// for, until nobody has for `idle_timeout`. Requests get our root dir,
+    /// and log the way we do, no matter what they were run with. Between
+    /// builds, a background thread prunes what killed builds left behind
+    /// once it's older than `prune_after`.
+    #[cfg(unix)]
+    fn serve(&self, prune_after: Duration, idle_timeout: daemon::IdleTimeout) -> Result<()> {
+        let db = self.open_db().context("could not open rbt's database")?;
+        let daemon = daemon::Daemon::bind(&self.root_dir()?, idle_timeout)?;
+        let cwd = std::env::current_dir().context("could not get the current directory")?;
+
+        // builds from different clients run side by side, sharing what
+        // they're running so that nobody runs the same job twice. They only
+        // have to wait for the pruner, which takes the lock for itself.
+        // Queries only read, and sled doesn't make readers wait for
+        // writers, so they go right ahead.
+        let building = std::sync::RwLock::new(());
+        let pruned = prune::Pruned::default();
+        let stopping = std::sync::atomic::AtomicBool::new(false);
+        let root_dir = self.root_dir()?;
+
+        tracing::info!("waiting for builds in `{}`", cwd.display());
+        std::thread::scope(|scope| {
+            let pruner = (!prune_after.is_zero()).then(|| {
+                let (root_dir, building, pruned, stopping) =
+                    (&root_dir, &building, &pruned, &stopping);
+                scope.spawn(move || {
+                    Self::prune_while_idle(root_dir, prune_after, building, pruned, stopping)
+                })
+            });
+
+            let result = (|| loop {
+                let Some(connection) = daemon.accept()? else {
+                    tracing::info!("nobody has asked for anything in a while, so I'm stopping");
+                    return Ok(());
+                };
+                let (db, cwd, building, pruned, daemon) = (&db, &cwd, &building, &pruned, &daemon);
+
+                scope.spawn(move || {
+                    connection
+                        .answer(|request| self.answer(request, cwd, db, building, pruned, daemon))
+                });
+            })();
+
+            // the scope waits for every thread, so the pruner has to know
+            // to stop before we can return.
+            stopping.store(true, std::sync::atomic::Ordering::Relaxed);
+            if let Some(pruner) = pruner {
+                pruner.thread().unpark();
+            }
+            result
+        })
+    }
+
+    /// Every so often, remove leftover workspaces and temporary store
+    /// directories older than `prune_after`, as long as nothing is
+    /// building. A build that starts while we're pruning waits for us. If
+    /// a build is running when we wake up, we skip a turn instead of
+    /// waiting, since what it leaves behind won't be old enough anyway.
+    #[cfg(unix)]
+    fn prune_while_idle(
+        root_dir: &Path,
+        prune_after: Duration,
+        building: &std::sync::RwLock<()>,
+        pruned: &prune::Pruned,
+        stopping: &std::sync::atomic::AtomicBool,
+    ) {
+        const PRUNE_EVERY: Duration = Duration::from_secs(60 * 60);
+
+        // pruning can always wait, so let builds and clients go first.
+        // SAFETY: nice only changes this thread's scheduling priority.
+        unsafe { libc::nice(10) };
+
+        while !stopping.load(std::sync::atomic::Ordering::Relaxed) {
+            // like `answer`, a poisoned lock still means nobody's building
+            let turn = match building.try_write() {
+                Ok(turn) => Some(turn),
+                Err(std::sync::TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+                Err(std::sync::TryLockError::WouldBlock) => None,
+            };
+
+            // `serve` unparks us when it's time to stop. Waking up early
+            // for any other reason only means we prune a little sooner.
+            let Some(turn) = turn else {
+                std::thread::park_timeout(PRUNE_EVERY);
+                continue;
+            };
+
+            match prune::stale_dirs(root_dir, prune_after) {
+                Ok((0, _)) => tracing::debug!("nothing to prune"),
+                Ok((dirs, reclaimed)) => {
+                    pruned.add(dirs, reclaimed);
+                    tracing::info!(
+                        "pruned {} leftover directories, reclaiming {}",
+                        dirs,
+                        bytes(reclaimed)
+                    );
+                }
+                Err(problem) => tracing::warn!("could not prune: {:?}", problem),
+            }
+
+            drop(turn);
+            std::thread::park_timeout(PRUNE_EVERY);
+        }
+    }
+
+    /// Run a request sent to the daemon, returning what it printed.
+    #[cfg(unix)]
+    fn answer(
+        &self,
+        request: daemon::Request,
+        cwd: &Path,
+        db: &Db,
+        building: &std::sync::RwLock<()>,
+        pruned: &prune::Pruned,
+        daemon: &daemon::Daemon,
+    ) -> Result<String> {
+        if request.cwd != cwd {
+            anyhow::bail!(
+                "the daemon builds in `{}`, but this build is in `{}`",
+                cwd.display(),
+                request.cwd.display()
+            )
+        }
+
+        let mut cli = Cli::try_parse_from(std::iter::once("rbt".to_string()).chain(request.args))
+            .context("could not parse arguments")?;
+        cli.root_dir = self.root_dir.clone();
+        cli.progress = ui::Mode::Plain;
+
+        let targets = match &cli.command {
+            Some(Command::Stats) => return Self::stats_text(db, Some(pruned)),
+            Some(Command::Daemon {
+                command: Some(DaemonCommand::Status),
+                ..
+            }) => return Ok(Self::daemon_status_text(&daemon.status())),
+            Some(Command::Build { targets }) => targets.as_slice(),
+            None => &[],
+            Some(_) => anyhow::bail!("the daemon only runs builds, `stats`, and `daemon status`"),
+        };
+
+        // a build that panicked can't have left anything behind that the
+        // lock protects, since it doesn't protect anything but the turn.
+        let _turn = building
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        tracing::info!("building for a client");
+        let result = cli.build(targets, db, false, Some(daemon.in_flight()));
+        if let Err(problem) = &result {
+            tracing::error!("{:?}", problem);
+        }
+
+        result.map(|()| String::new())
+    }
+
+    /// What `rbt daemon status` prints.
+    #[cfg(unix)]
+    fn daemon_status_text(status: &daemon::Status) -> String {
+        let minutes = |timeout: Option<Duration>| match timeout {
+            Some(timeout) => format!("{} minutes", timeout.as_secs() / 60),
+            None => "never".to_string(),
+        };
+
+        // the request asking for this counts too
+        let building = if status.answering > 1 {
+            format!("answering {} other requests", status.answering - 1)
+        } else {
+            "idle".to_string()
+        };
+
+        format!(
+            "pid {}, up for {:.0?}, {}\n\
+             answered {} requests\n\
+             stops when idle for: {} ({} on battery){}\n\
+             cpu time: {:.1?}\n\
+             peak memory: {}\n",
+            status.pid,
+            status.uptime,
+            building,
+            status.answered,
+            minutes(status.idle_timeout.always),
+            minutes(status.idle_timeout.on_battery),
+            if status.on_battery {
+                ", on battery now"
+            } else {
+                ""
+            },
+            status.cpu_time,
+            bytes(status.peak_memory),
+        )
+    }
+
+    #[cfg(not(unix))]
+    fn serve(&self, _prune_after: Duration, _idle_timeout: daemon::IdleTimeout) -> Result<()> {
+        anyhow::bail!("the daemon only runs on Unix-like systems so far")
+    }
+
+    fn send_to_daemon(&self) -> Result<()> {
+        let request = daemon::Request {
+            args: std::env::args()
+                .skip(1)
+                .filter(|arg| arg != "--daemon")
+                .collect(),
+            cwd: std::env::current_dir().context("could not get the current directory")?,
+        };
+
+        let output =
+            daemon::send(&self.root_dir()?, &request).context("the daemon could not answer")?;
+        print!("{}", output);
+
+        Ok(())
+    }
+
+    fn clean(&self, incremental: bool, checkpoints: bool) -> Result<()> {
+        if !incremental && !checkpoints {
+            anyhow::bail!(
+                "I don't know what to clean! Try `rbt clean --incremental` or `rbt clean --checkpoints`."
+            )
+        }
+
+        for (clean, dir, what) in [
+            (incremental, "incremental", "incremental state"),
+            (checkpoints, "checkpoints", "checkpoints"),
+        ] {
+            let root = self.root_dir()?.join(dir);
+            if clean && root.exists() {
+                tracing::info!("removing {}", what);
+                std::fs::remove_dir_all(&root)
+                    .with_context(|| format!("could not remove `{}`", root.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load(defines: &BTreeMap<String, String>) -> glue::Rbt {
+        let config: RocList<glue::Define> = defines
+            .iter()
+            .map(|(name, value)| glue::Define {
+                name: name.as_str().into(),
+                value: value.as_str().into(),
+            })
+            .collect();
+
         unsafe {
             let mut input = MaybeUninit::uninit();
-            roc_init(input.as_mut_ptr());
+            roc_init(input.as_mut_ptr(), &config);
             input.assume_init()
         }
     }
 
+    /// The configuration we were given with `--define`, with later values
+    /// for the same name replacing earlier ones.
+    fn defines(&self, profile: &Profile) -> BTreeMap<String, String> {
+        let mut defines = profile.defines.clone();
+        defines.extend(self.defines.iter().cloned());
+
+        defines
+    }
+
+    /// Add the targets (or jobs) someone asked for on the command line, or
+    /// the default job if they didn't ask for any.
+    fn add_roots<'a>(
+        builder: &mut coordinator::Builder<'a>,
+        rbt: &'a glue::Rbt,
+        targets: &[String],
+    ) -> Result<()> {
+        if targets.is_empty() {
+            builder.add_root(&rbt.default);
+            return Ok(());
+        }
+
+        let mut by_id = false;
+        for target in targets {
+            if let Some((_, job)) = rbt.targets.iter().find(|(name, _)| name.as_str() == target) {
+                builder.add_root(job);
+                continue;
+            }
+
+            // the coordinator can only tell whether a job has this ID once
+            // it's seen every job, so we leave that part up to it.
+            if job::looks_like_id(target) {
+                builder.add_root_by_id(target.clone());
+                by_id = true;
+                continue;
+            }
+
+            let mut available: Vec<&str> =
+                rbt.targets.iter_keys().map(|name| name.as_str()).collect();
+            available.sort_unstable();
+
+            if available.is_empty() {
+                anyhow::bail!(
+                    "there's no target named `{}`. This build definition doesn't have any targets; add some with `targets` in `Rbt.init`.",
+                    target
+                )
+            }
+
+            anyhow::bail!(
+                "there's no target named `{}`. Try one of these: {}",
+                target,
+                available
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        }
+
+        if by_id {
+            builder.search(&rbt.default);
+            for job in rbt.targets.iter_values() {
+                builder.search(job);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn profile(&self) -> Result<Profile> {
+        match &self.profile {
+            Some(name) => Ok(profile::Config::load(Path::new(PROJECT_CONFIG))?
+                .profile(name)?
+                .clone()),
+            None => Ok(Profile::default()),
+        }
+    }
+
     pub fn async_runtime(&self) -> Result<runtime::Runtime> {
         let mut builder = runtime::Builder::new_multi_thread();
         builder.enable_io();
@@ -93,16 +1891,70 @@ impl Cli {
         builder.build().context("failed to build async runtime")
     }
 
-    pub fn open_db(&self) -> Result<sled::Db> {
-        sled::Config::default()
-            .path(self.root_dir()?.join("db"))
-            .mode(sled::Mode::HighThroughput)
-            .open()
-            .context("could not open sled database")
+    pub fn open_db(&self) -> Result<Db> {
+        let mut db = Db::open(&self.root_dir()?.join("db"))?;
+        if self.upgrade_db {
+            db.allow_upgrades();
+        }
+
+        Ok(db)
+    }
+
+    /// Make sure the keys in the database were calculated the same way we're
+    /// going to calculate them now. If they weren't, nothing would ever match
+    /// and the old associations would just pile up.
+    fn check_key_format(&self, db: &Db) -> Result<()> {
+        let associations = db.tree(db::Tree::Store)?;
+
+        let stored = match db.meta_u64(db::Meta::KeyFormatVersion)? {
+            Some(version) => version,
+
+            // databases from before we tracked the version have format 0.
+            // If there's nothing in the store, though, it's a new database
+            // and it doesn't matter.
+            None if associations.is_empty() => job::KEY_FORMAT_VERSION,
+            None => 0,
+        };
+
+        if stored != job::KEY_FORMAT_VERSION {
+            if !self.migrate_keys {
+                anyhow::bail!(
+                    "`{}` was last used by a version of rbt that calculates job keys differently (format {}, but I use format {}.) Run me again with `--migrate-keys` to drop the old cache associations and continue.",
+                    self.root_dir()?.display(),
+                    stored,
+                    job::KEY_FORMAT_VERSION,
+                );
+            }
+
+            // Keys are hashes, so there's no way to translate them from one
+            // format to another. All we can do is drop the associations. The
+            // store itself is content-addressed, though, so jobs that produce
+            // the same output as before will find their items already there.
+            tracing::info!(
+                "migrating job keys from format {} to format {}",
+                stored,
+                job::KEY_FORMAT_VERSION
+            );
+            associations
+                .clear()
+                .context("could not clear old cache associations")?;
+        }
+
+        db.set_meta_u64(db::Meta::KeyFormatVersion, job::KEY_FORMAT_VERSION)
+    }
+
+    fn graph_limits(&self) -> job::GraphLimits {
+        let default = job::GraphLimits::default();
+
+        job::GraphLimits {
+            jobs: self.max_jobs.unwrap_or(default.jobs),
+            depth: self.max_graph_depth.unwrap_or(default.depth),
+            inputs_per_job: self.max_inputs_per_job.unwrap_or(default.inputs_per_job),
+        }
     }
 
-    fn max_local_jobs(&self) -> Result<NonZeroUsize> {
-        if let Some(explicit) = self.max_local_jobs {
+    fn max_local_jobs(&self, profile: &Profile) -> Result<NonZeroUsize> {
+        if let Some(explicit) = self.max_local_jobs.or(profile.max_local_jobs) {
             return Ok(explicit);
         }
 
@@ -119,5 +1971,5 @@ impl Cli {
 
 extern "C" {
     #[link_name = "roc__initForHost_1_exposed_generic"]
-    fn roc_init(init: *mut crate::glue::Rbt);
+    fn roc_init(init: *mut crate::glue::Rbt, config: &RocList<crate::glue::Define>);
 }
@@ -1,43 +1,134 @@
+use crate::build_stats::BuildStats;
+use crate::diagnostics::Capture;
+use crate::events::{Bus, Event, JobInfo};
+use crate::explain::Explanation;
+use crate::flake_check::FlakeCheck;
 use crate::glue;
+use crate::graph::Graph;
+use crate::in_flight::{self, InFlight};
 use crate::job::{self, Job};
-use crate::path_meta_key::PathMetaKey;
-use crate::runner::RunnerBuilder;
+use crate::lint;
+use crate::log_sink::Sinks;
+use crate::logs::Logs;
+use crate::path_meta_key::{PathMetaKey, Strategies};
+use crate::pause::{Pauser, QueueState};
+use crate::progress;
+use crate::resolver;
+use crate::resources::Resources;
+use crate::runner::{Finished, RunnerBuilder};
+use crate::snapshot::Snapshot;
+use crate::status::Status;
 use crate::store::{self, Store};
+use crate::ui;
 use crate::workspace::Workspace;
 use anyhow::{Context, Result};
 use core::convert::TryInto;
 use futures::stream::{FuturesUnordered, StreamExt};
-use std::collections::{HashMap, HashSet};
-use std::fs::{self, File};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::future::Future;
 use std::io::Read;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{broadcast, mpsc};
 use tokio::task::JoinHandle;
+use tracing::Instrument;
 use xxhash_rust::xxh3::Xxh3Builder;
 
+pub const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How `Coordinator::run` fails when the build took longer than its
+/// `timeout` (and nothing else went wrong), so the CLI can tell.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the build took longer than {}", ui::elapsed(self.0))
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
 pub struct Builder<'roc> {
     store: Store,
     roots: Vec<&'roc glue::Job>,
+    search: Vec<&'roc glue::Job>,
+    queries: Vec<String>,
     meta_to_hash: sled::Tree,
-    workspace_root: PathBuf,
+    root_dir: PathBuf,
     max_local_jobs: NonZeroUsize,
+    resources: Resources,
+    pauser: Pauser,
+    snapshot_inputs: bool,
+    capture_diagnostics: bool,
+    worker_timeout: Duration,
+    explain_schedule: bool,
+    remember_file_hashes: bool,
+    salt: Option<String>,
+    status_command: Option<PathBuf>,
+    last_build_started: Option<SystemTime>,
+    keep_going: bool,
+    timeout: Option<Duration>,
+    verbose_failures: bool,
+    hash_passthrough_env: bool,
+    graph_limits: job::GraphLimits,
+    show_job_output: bool,
+    strict_outputs: bool,
+    check_inputs: bool,
+    warn_unused_inputs: bool,
+    stable_paths: bool,
+    verify_store: bool,
+    fetch_only: bool,
+    log_sinks: Sinks,
+    in_flight: InFlight,
 }
 
 impl<'roc> Builder<'roc> {
     pub fn new(
         store: Store,
         meta_to_hash: sled::Tree,
-        workspace_root: PathBuf,
+        root_dir: PathBuf,
         max_local_jobs: NonZeroUsize,
+        resources: Resources,
+        pauser: Pauser,
     ) -> Self {
         Builder {
             store,
             meta_to_hash,
-            workspace_root,
+            root_dir,
             max_local_jobs,
+            resources,
+            pauser,
+            snapshot_inputs: false,
+            capture_diagnostics: false,
+            worker_timeout: DEFAULT_WORKER_TIMEOUT,
+            explain_schedule: false,
+            remember_file_hashes: true,
+            salt: None,
+            status_command: None,
+            last_build_started: None,
+            keep_going: false,
+            timeout: None,
+            verbose_failures: false,
+            hash_passthrough_env: false,
+            graph_limits: job::GraphLimits::default(),
+            show_job_output: true,
+            strict_outputs: false,
+            check_inputs: false,
+            warn_unused_inputs: false,
+            stable_paths: false,
+            verify_store: false,
+            fetch_only: false,
+            log_sinks: Sinks::default(),
+            in_flight: InFlight::default(),
 
             // it's very likely we'll have at least one root
             roots: Vec::with_capacity(1),
+            search: Vec::new(),
+            queries: Vec::new(),
         }
     }
 
@@ -45,40 +136,209 @@ impl<'roc> Builder<'roc> {
         self.roots.push(job);
     }
 
+    /// Build the job that `query` (a job ID or key, see `job::resolve`)
+    /// refers to. We look for it among the roots and the jobs given to
+    /// `search`, and everything they depend on.
+    pub fn add_root_by_id(&mut self, query: String) {
+        self.queries.push(query);
+    }
+
+    /// Look for `add_root_by_id` jobs under `job` too, without building it
+    /// unless it's needed.
+    pub fn search(&mut self, job: &'roc glue::Job) {
+        self.search.push(job);
+    }
+
+    /// Copy every declared project file into a snapshot before building, and
+    /// only ever let jobs see the copies.
+    pub fn snapshot_inputs(&mut self, snapshot_inputs: bool) {
+        self.snapshot_inputs = snapshot_inputs;
+    }
+
+    /// Save a bundle describing each failed job's environment, for sharing
+    /// in bug reports.
+    pub fn capture_diagnostics(&mut self, capture_diagnostics: bool) {
+        self.capture_diagnostics = capture_diagnostics;
+    }
+
+    /// How long a persistent worker gets to finish a request before we give
+    /// up on it.
+    pub fn worker_timeout(&mut self, worker_timeout: Duration) {
+        self.worker_timeout = worker_timeout;
+    }
+
+    /// Every so often, log why each job that isn't running yet is waiting.
+    pub fn explain_schedule(&mut self, explain_schedule: bool) {
+        self.explain_schedule = explain_schedule;
+    }
+
+    /// Save the hashes of input files we had to read, so the next build
+    /// doesn't have to. We only turn this off when an older version of rbt
+    /// still needs the database the way it wrote it.
+    pub fn remember_file_hashes(&mut self, remember_file_hashes: bool) {
+        self.remember_file_hashes = remember_file_hashes;
+    }
+
+    /// Mix `salt` into every job's final key, so this build only shares
+    /// cache entries with builds that used the same salt.
+    pub fn salt(&mut self, salt: Option<String>) {
+        self.salt = salt;
+    }
+
+    /// Run this program each build to get status for stamped jobs. Stamped
+    /// jobs still get the build time without one.
+    pub fn status_command(&mut self, status_command: Option<PathBuf>) {
+        self.status_command = status_command;
+    }
+
+    /// When the last build in this root dir started, so we can tell if the
+    /// clock jumped backwards since then.
+    pub fn last_build_started(&mut self, last_build_started: Option<SystemTime>) {
+        self.last_build_started = last_build_started;
+    }
+
+    /// When a job fails, keep building everything that doesn't depend on it
+    /// instead of stopping the jobs that are running and giving up.
+    pub fn keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
+    }
+
+    /// Give up if running the build takes longer than this, even with
+    /// `keep_going`. Time spent paused doesn't count. `run` fails with
+    /// `TimedOut` when that happens.
+    pub fn timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// When a job fails, show how each requested target depends on it, so
+    /// it's clear which targets the failure affects.
+    pub fn verbose_failures(&mut self, verbose_failures: bool) {
+        self.verbose_failures = verbose_failures;
+    }
+
+    /// Refuse job graphs bigger than these instead of trying to build them.
+    pub fn graph_limits(&mut self, graph_limits: job::GraphLimits) {
+        self.graph_limits = graph_limits;
+    }
+
+    /// Mix the values of jobs' passthrough environment variables into
+    /// their final keys, so they run again when the values change.
+    pub fn hash_passthrough_env(&mut self, hash_passthrough_env: bool) {
+        self.hash_passthrough_env = hash_passthrough_env;
+    }
+
+    /// Echo job output to our own stdout and stderr as well as the logs.
+    /// Progress displays that draw on the terminal turn this off.
+    pub fn show_job_output(&mut self, show_job_output: bool) {
+        self.show_job_output = show_job_output;
+    }
+
+    /// Fail jobs that leave files in their workspace that aren't in their
+    /// outputs. By default we only warn about them.
+    pub fn strict_outputs(&mut self, strict_outputs: bool) {
+        self.strict_outputs = strict_outputs;
+    }
+
+    /// Trace the files each job reads (Linux-only, with `strace`) and warn
+    /// about project files it used without declaring them as inputs.
+    pub fn check_inputs(&mut self, check_inputs: bool) {
+        self.check_inputs = check_inputs;
+    }
+
+    /// Trace the files each job reads, like `check_inputs`, and warn about
+    /// inputs it never read. They go in the report too.
+    pub fn warn_unused_inputs(&mut self, warn_unused_inputs: bool) {
+        self.warn_unused_inputs = warn_unused_inputs;
+    }
+
+    /// Run jobs in reused workspaces whose paths are all the same length,
+    /// for tools that put their working directory in their output.
+    pub fn stable_paths(&mut self, stable_paths: bool) {
+        self.stable_paths = stable_paths;
+    }
+
+    /// Hash cache hits' store items again before using them, and run the
+    /// job again instead if the contents don't match.
+    pub fn verify_store(&mut self, verify_store: bool) {
+        self.verify_store = verify_store;
+    }
+
+    /// Get whatever outputs we can from the store and the remote cache, but
+    /// don't run anything. Jobs whose outputs we can't get, and everything
+    /// that depends on them, are left unbuilt (see `Coordinator::unbuilt`.)
+    pub fn fetch_only(&mut self, fetch_only: bool) {
+        self.fetch_only = fetch_only;
+    }
+
+    /// Send job output to these as well as the logs directory.
+    pub fn log_sinks(&mut self, log_sinks: Sinks) {
+        self.log_sinks = log_sinks;
+    }
+
+    /// Check with (and tell) other builds sharing `in_flight` before
+    /// running a job, so that we wait for them instead of running a job
+    /// they already are.
+    pub fn in_flight(&mut self, in_flight: InFlight) {
+        self.in_flight = in_flight;
+    }
+
     pub fn build(self) -> Result<Coordinator> {
-        // Here's the overview of what we're about to do: for each file in
-        // each target job, we're going to look at metadata for that file and
-        // use that metadata to look up the file's hash (if we don't have it
-        // already, we'll read the file and calculate it.) We'll use all those
-        // hashes to construct a mapping from path->hash that coordinator can
-        // use to determine which jobs need to be run or skipped once the build
-        // is running.
+        // Here's the overview of what we're about to do: we convert every
+        // job we were given (and everything they depend on) into our own
+        // jobs, with base keys, and work out which ones block which. Then we
+        // run resolvers and collect build status, if any job needs them.
+        //
+        // Input files get hashed once the build starts (see `Coordinator::
+        // run`), on another thread, so the first jobs can start before we've
+        // looked at every file in a big project. The hashes go in a mapping
+        // from path->hash that the coordinator uses to determine which jobs
+        // need to be run or skipped.
         //
         // For more higher-level explanation of what we're going for, refer
         // to docs/internals/how-we-determine-when-to-run-jobs.md.
 
-        // We assume that there will be at least some overlap in inputs (i.e. at
-        // least two targets needing the same file.) That assumption means that
-        // it makes sense to deduplicate them to avoid duplicating filesystem
-        // operations.
-        let mut input_files: HashSet<PathBuf> = HashSet::new();
-        for glue_job in &self.roots {
-            for input in &glue_job.as_Job().inputs {
-                if input.discriminant() == glue::discriminant_U1::FromProjectSource {
-                    for glue::FileMapping { source, .. } in unsafe { input.as_FromProjectSource() }
-                    {
-                        input_files.insert(job::sanitize_file_path(source)?);
-                    }
-                }
-            }
-        }
+        // runners announce progress on the same bus as everything else
+        let events = Bus::new();
+
+        let mut logs = Logs::new(self.root_dir.join("logs"), self.show_job_output);
+        logs.send_to(self.log_sinks);
 
         let mut coordinator = Coordinator {
             store: self.store,
             roots: Vec::with_capacity(self.roots.len()),
             max_local_jobs: self.max_local_jobs.get(),
-
-            path_to_hash: HashMap::with_capacity(input_files.len()),
+            resources: self.resources,
+            pauser: self.pauser,
+            explain_schedule: self.explain_schedule,
+            salt: self.salt,
+            keep_going: self.keep_going,
+            timeout: self.timeout,
+            verbose_failures: self.verbose_failures,
+            verify_store: self.verify_store,
+            fetch_only: self.fetch_only,
+            host_env: self.hash_passthrough_env.then(|| {
+                std::env::vars_os()
+                    .filter_map(|(name, value)| {
+                        Some((name.into_string().ok()?, value.into_string().ok()?))
+                    })
+                    .collect()
+            }),
+            stopping: false,
+
+            path_to_hash: HashMap::default(),
+            // a snapshot hashes everything up front, so there's nothing
+            // left to hash while building from one.
+            file_hashes: (!self.snapshot_inputs).then(|| FileHashes {
+                meta_to_hash: self.meta_to_hash,
+                last_build_started: self.last_build_started,
+                remember: self.remember_file_hashes,
+            }),
+            hashes: None,
+            resolved_root: self.root_dir.join("resolved"),
+            unresolved: BTreeSet::new(),
+            spec_to_resolved: HashMap::default(),
+            snapshot: None,
+            status: None,
             job_to_content_hash: HashMap::with_capacity(self.roots.len()),
             final_keys: HashMap::with_capacity(self.roots.len()),
 
@@ -86,191 +346,377 @@ impl<'roc> Builder<'roc> {
             // each of which will have at least one leaf node.
             jobs: HashMap::with_capacity(self.roots.len()),
             blocked: HashMap::default(),
+            reruns: HashMap::default(),
+            shard_groups: HashMap::default(),
 
             ready: Vec::with_capacity(self.roots.len()),
+            unhashed: Vec::new(),
+            waiting: Vec::new(),
+            not_fetched: Vec::new(),
             running: FuturesUnordered::new(),
+            started: HashMap::default(),
+            in_flight: self.in_flight,
+            claims: HashMap::default(),
+            attached: FuturesUnordered::new(),
+            stats: BuildStats::default(),
+            events: events.clone(),
 
             // TODO: clean up bits of state
-            runner_builder: RunnerBuilder::new(self.workspace_root.clone()),
+            runner_builder: RunnerBuilder::new(
+                &self.root_dir,
+                self.max_local_jobs.get(),
+                self.capture_diagnostics
+                    .then(|| Capture::new(self.root_dir.join("diagnostics"))),
+                self.worker_timeout,
+                logs,
+                events,
+            ),
         };
 
-        /////////////////////////////////////////////
-        // Phase 1: check which files have changed //
-        /////////////////////////////////////////////
-
-        let mut path_to_meta: HashMap<PathBuf, PathMetaKey> =
-            HashMap::with_capacity(input_files.len());
+        coordinator
+            .runner_builder
+            .strict_outputs(self.strict_outputs);
+        coordinator.runner_builder.check_inputs(self.check_inputs);
+        coordinator
+            .runner_builder
+            .warn_unused_inputs(self.warn_unused_inputs);
+        coordinator.runner_builder.stable_paths(self.stable_paths);
+
+        ///////////////////////////////////////////////////////////////////
+        // Phase 1: convert jobs and work out which ones can start first //
+        ///////////////////////////////////////////////////////////////////
+
+        let planning = tracing::info_span!("planning").entered();
+
+        // to build a graph, we need the base keys for all jobs, and a job's
+        // key depends on the keys of the jobs it takes files from. So we
+        // convert jobs in topological order: every job after everything it
+        // depends on.
+        let to_convert = job::dependency_order(
+            self.roots.iter().chain(&self.search).copied(),
+            &self.graph_limits,
+        )
+        .context("the build definition's job graph is too big")?;
+
+        let mut glue_to_job_key: HashMap<job::GlueRef, job::Key<job::Base>, Xxh3Builder> =
+            HashMap::with_capacity_and_hasher(to_convert.len(), Xxh3Builder::new());
+
+        for glue_job in to_convert {
+            let mut job = job::Job::from_glue(glue_job, &glue_to_job_key)
+                .context("could not convert glue job into actual job")?;
+            let key = job.base_key;
+            glue_to_job_key.insert(job::GlueRef(glue_job), key);
 
-        // TODO: perf hint for later: we could be doing this in parallel
-        // using rayon
-        for input_file in input_files {
-            // TODO: collect errors instead of bailing immediately
-            let meta = input_file.metadata().with_context(|| {
-                format!("could not read metadata for `{}`", input_file.display())
-            })?;
+            // equal jobs that Roc built separately aren't the same glue job,
+            // but they are the same job to us.
+            if coordinator.jobs.contains_key(&key) || coordinator.shard_groups.contains_key(&key) {
+                tracing::trace!("already converted job {}", key);
+                continue;
+            }
 
-            if meta.is_dir() {
-                anyhow::bail!(
-                    "One of your jobs specifies `{}` as a dependency. It's a directory, but I can only handle files.",
-                    input_file.display(),
-                )
-            };
+            // Order-only dependencies block a job just like the jobs it
+            // takes files from do. They just don't show up in its key.
+            let mut blockers = HashSet::new();
+            for dep_key in job.input_jobs.keys().chain(job.after.iter()).copied() {
+                match coordinator.shard_groups.get(&dep_key) {
+                    // shards all produce files with the same names, so
+                    // there's no single set of outputs to hand to a
+                    // dependent.
+                    Some(_) if job.input_jobs.contains_key(&dep_key) => anyhow::bail!(
+                        "{} takes files from a sharded or matrix job, but those can't be used as inputs",
+                        job
+                    ),
+                    Some(group) => blockers.extend(group.shards.iter().copied()),
+                    None => {
+                        blockers.insert(dep_key);
+                    }
+                }
+            }
 
-            let cache_key = meta.try_into().with_context(|| {
-                format!(
-                    "could not calculate a cache key for `{}`",
-                    input_file.display()
-                )
-            })?;
+            // catch typos in file names now, instead of when the job is
+            // about to run (possibly after building everything else.) Tools
+            // from other jobs are files from them too.
+            let wanted =
+                job.input_jobs
+                    .iter()
+                    .flat_map(|(dep_key, files)| {
+                        files
+                            .iter()
+                            .map(move |file| (*dep_key, file.source.as_path()))
+                    })
+                    .chain(job.commands().filter_map(|command| {
+                        Some((command.tool_job()?, Path::new(command.tool())))
+                    }));
+
+            for (dep_key, path) in wanted {
+                let producer = match coordinator.jobs.get(&dep_key) {
+                    Some(producer) => producer,
+                    None => continue, // a sharded or matrix job, which we refused above
+                };
+
+                if !producer.produces(path) {
+                    let outputs: Vec<String> = producer
+                        .outputs
+                        .iter()
+                        .map(|output| format!("`{}`", output.display()))
+                        .collect();
+
+                    anyhow::bail!(
+                        "{} takes `{}` from {}, but that isn't one of its outputs (it has {})",
+                        job,
+                        path.display(),
+                        producer,
+                        if outputs.is_empty() {
+                            "none".to_string()
+                        } else {
+                            outputs.join(", ")
+                        },
+                    );
+                }
+            }
 
-            path_to_meta.insert(input_file, cache_key);
-        }
+            // our outputs could contain what we took from our dependencies,
+            // so they can't go anywhere the dependencies' outputs couldn't.
+            let narrowest = job
+                .input_jobs
+                .keys()
+                .filter_map(|dep_key| coordinator.jobs.get(dep_key))
+                .min_by_key(|dep| dep.visibility);
+            if let Some(dep) = narrowest.filter(|dep| dep.visibility < job.visibility) {
+                tracing::debug!(
+                    "{} takes files from {}, so it's {} too",
+                    job,
+                    dep,
+                    dep.visibility
+                );
+                job.visibility = dep.visibility;
+            }
 
-        //////////////////////////////////////////////////////////////////
-        // Phase 2: get hashes for metadata keys we haven't seen before //
-        //////////////////////////////////////////////////////////////////
-        let mut hasher = blake3::Hasher::new();
+            // matrix copies and shards stand in for the job they came from
+            // as one group, which is what dependents and targets refer to.
+            let unwrapped = glue_job.as_Job();
+            let jobs = if unwrapped.shards > 1 || !unwrapped.matrix.is_empty() {
+                let description = job.to_string();
+                let mut parts = Vec::new();
+                for variant in job.into_variants(&unwrapped.matrix)? {
+                    if unwrapped.shards > 1 {
+                        parts.extend(variant.into_shards(unwrapped.shards));
+                    } else {
+                        parts.push(variant);
+                    }
+                }
+                coordinator
+                    .shard_groups
+                    .insert(key, ShardGroup::new(description, &parts));
 
-        for (path, cache_key) in path_to_meta.iter() {
-            let key = cache_key.to_db_key();
-            if let Some(value) = self
-                .meta_to_hash
-                .get(key)
-                .context("could not read file hash from database")?
-            {
-                let bytes: [u8; 32] = value
-                    .as_ref()
-                    .try_into()
-                    .context("value was not exactly 32 bytes")?;
+                parts
+            } else {
+                vec![job]
+            };
 
-                coordinator
-                    .path_to_hash
-                    .insert(path.to_path_buf(), blake3::Hash::from(bytes));
+            for job in jobs {
+                if blockers.is_empty() {
+                    coordinator.ready.push(job.base_key);
+                } else {
+                    coordinator.blocked.insert(job.base_key, blockers.clone());
+                }
 
-                continue;
+                coordinator.jobs.insert(job.base_key, job);
             }
+        }
 
-            let mut file = File::open(path)
-                .with_context(|| format!("couldn't open `{}` for hashing.", path.display()))?;
+        drop(planning);
+
+        ////////////////////////////////////////////////////////////////
+        // Phase 2: run resolver plugins for inputs we can't see into //
+        ////////////////////////////////////////////////////////////////
+
+        // Resolvers only tell us what they produced by running, so we have
+        // to run each one once per build. Like input files, we deduplicate
+        // specs first so that jobs sharing an input share a single fetch.
+        // Plugins and downloads can take a while, so they run once the
+        // build starts (see `resolve_inputs`.) We collect them here rather
+        // than there so that jobs `shell` takes out of the build still get
+        // theirs.
+        coordinator.unresolved = coordinator
+            .jobs
+            .values()
+            .flat_map(|job| job.input_resolvers.keys())
+            .cloned()
+            .collect();
 
-            hasher.reset();
+        // we couldn't track which roots were needed before because we didn't
+        // have the keys for those jobs. Now that we do, take a minute to
+        // populate the roots vec (which up until now has had the right capacity
+        // but no items.)
+        for root in self.roots {
+            let key = *glue_to_job_key
+                .get(&job::GlueRef(root))
+                .context("could not key for root job")?;
 
-            // The docs for Blake3 say that a 16 KiB buffer is the most
-            // efficient (for SIMD reasons)
-            let mut buf = [0; 16 * 1024];
-            loop {
-                let bytes = file.read(&mut buf)?;
-                if bytes == 0 {
-                    break;
-                }
-                hasher.update(&buf[0..bytes]);
+            match coordinator.shard_groups.get(&key) {
+                Some(group) => coordinator.roots.extend_from_slice(&group.shards),
+                None => coordinator.roots.push(key),
             }
+        }
 
-            let hash = hasher.finalize();
+        // IDs are only unique among the jobs we know about, so we can't give
+        // them out (or look jobs up by them) until now.
+        job::disambiguate(coordinator.jobs.values_mut());
+
+        // jobs that mention paths only this machine has will never get
+        // cache hits anywhere else, which is easy to miss until CI is slow.
+        let host_paths = lint::HostPaths::from_env();
+        let mut linted: Vec<&Job> = coordinator.jobs.values().collect();
+        linted.sort_by_key(|job| job.base_key);
+        for job in linted {
+            for problem in host_paths.check(job) {
+                tracing::warn!(
+                    "{}: {}. Other machines won't get cache hits for it (use `allowHostPaths` if that's fine.)",
+                    job.id,
+                    problem
+                );
+            }
+        }
 
-            log::debug!("hash of `{}` was {}", path.display(), hash);
-            log::trace!("bytes of hash: {:?}", hash.as_bytes());
-            self.meta_to_hash
-                .insert(key, hash.as_bytes())
-                .context("could not write file hash to database")?;
+        for query in &self.queries {
+            let key = job::resolve(coordinator.jobs.values(), query)?.base_key;
+            coordinator.roots.push(key);
+        }
 
-            coordinator.path_to_hash.insert(path.to_path_buf(), hash);
+        if !self.search.is_empty() {
+            coordinator.keep_only_roots_and_dependencies();
         }
 
-        ///////////////////////////////////////////////////////////////////////////
-        // Phase 3: get the hahes to determine what jobs we actually need to run //
-        ///////////////////////////////////////////////////////////////////////////
+        // When we're building from a snapshot, the hashes of the copies are
+        // the only ones that matter, so we skip the metadata cache entirely
+        // and have every hash before we start.
+        if self.snapshot_inputs {
+            let input_files = coordinator.input_files_by_readiness();
+            let _hashing = tracing::info_span!("hashing", files = input_files.len()).entered();
 
-        // to build a graph, we need the base keys for all jobs. This can't be
-        // a depth-first search, however, because that would mean processing
-        // dependent jobs before their dependencies. We can't do that because
-        // then we would have unresolved base keys and we'd have to defer until
-        // we got them or temporarily suffer incomplete information in the
-        // graph.
-        //
-        // Ideally, we'd look at the leaf nodes first, then the things that
-        // depend on them, etc. In other words, a depth-first search starting
-        // at the leaves instead of the roots. Lucky for us, that's easy to do:
-        // just write down the jobs we see as we do a depth first search, then
-        // walk that list in the opposite direction.
-        //
-        // `to_descend_into` tracks the depth-first search part of this scheme,
-        // and `to_convert` tracks the dependencies in root-to-leaf order.
-        let mut to_descend_into = self.roots.clone();
-        let mut to_convert = Vec::with_capacity(self.roots.len());
+            let snapshot = Snapshot::take(&self.root_dir.join("snapshots"), &input_files)
+                .context("could not snapshot input files")?;
+            tracing::info!("building from input snapshot {}", snapshot.id());
 
-        let mut glue_to_job_key: HashMap<&glue::Job, job::Key<job::Base>, Xxh3Builder> =
-            HashMap::with_capacity_and_hasher(self.roots.len(), Xxh3Builder::new());
+            coordinator.path_to_hash = snapshot
+                .files()
+                .iter()
+                .map(|(path, hash)| (path.clone(), *hash))
+                .collect();
+            coordinator.snapshot = Some(snapshot);
+        }
 
-        let mut job_deps: HashMap<&glue::Job, HashSet<&glue::Job, Xxh3Builder>, Xxh3Builder> =
-            HashMap::with_hasher(Xxh3Builder::new());
+        // status commands usually shell out to git, so we only run them when
+        // some job in this build is going to look.
+        if coordinator.jobs.values().any(|job| job.stamp) {
+            let status = Status::collect(
+                self.status_command.as_deref(),
+                &self.root_dir.join("status"),
+            )
+            .context("could not collect build status")?;
 
-        while let Some(next_glue_job) = to_descend_into.pop() {
-            next_glue_job
-                .as_Job()
-                .inputs
-                .iter()
-                .filter(|item| item.discriminant() == glue::discriminant_U1::FromJob)
-                .for_each(|item| {
-                    let job = unsafe { item.as_FromJob() }.0;
+            coordinator.runner_builder.stamp_with(status.clone());
+            coordinator.status = Some(status);
+        }
 
-                    let entry = job_deps.entry(next_glue_job);
-                    entry
-                        .or_insert_with(|| HashSet::with_capacity_and_hasher(1, Xxh3Builder::new()))
-                        .insert(job);
+        Ok(coordinator)
+    }
+}
 
-                    to_descend_into.push(job);
-                });
+type DoneMsg = (job::Key<job::Base>, Finished);
 
-            to_convert.push(next_glue_job);
-        }
+/// What a running job's task hands back: the job's ID, so we know which job
+/// it was whether or not it worked, and its workspace if it did.
+type RunResult = (job::Key<job::Base>, Result<Finished>);
 
-        while let Some(glue_job) = to_convert.pop() {
-            // multiple jobs can depend on the same job, but we only need to
-            // convert each job once.
-            if let Some(key) = glue_to_job_key.get(glue_job) {
-                log::trace!("already converted job {}", key);
-                continue;
-            }
+/// What we need to hash input files once the build starts (see
+/// `spawn_hashing`.)
+#[derive(Debug)]
+struct FileHashes {
+    meta_to_hash: sled::Tree,
+    last_build_started: Option<SystemTime>,
 
-            let job = job::Job::from_glue(glue_job, &glue_to_job_key)
-                .context("could not convert glue job into actual job")?;
+    /// Whether to save new hashes (see `--upgrade-db`.)
+    remember: bool,
+}
 
-            if let Some(deps) = job_deps.get(glue_job) {
-                let blockers = coordinator.blocked.entry(job.base_key).or_default();
+/// One batch of input file hashes from `spawn_hashing`.
+#[derive(Debug)]
+struct Hashed {
+    hashes: Vec<(PathBuf, blake3::Hash)>,
+    took: Duration,
+}
 
-                for dep in deps {
-                    blockers.insert(
-                        *glue_to_job_key
-                            .get(dep)
-                            .context("could not get job key for a glue job. This is probably an internal ordering bug and should be reported!")?
-                    );
-                }
-            } else {
-                coordinator.ready.push(job.base_key);
-            }
+/// When a job started, and the span its steps are traced under (so that
+/// setting up, running, and storing it show up together.)
+#[derive(Debug)]
+struct Started {
+    at: Instant,
+    span: tracing::Span,
+}
 
-            glue_to_job_key.insert(glue_job, job.base_key);
-            coordinator.jobs.insert(job.base_key, job);
+/// Progress of all the shards (or matrix copies) of one job.
+#[derive(Debug)]
+struct ShardGroup {
+    description: String,
+    shards: Vec<job::Key<job::Base>>,
+    cached: u32,
+    ran: u32,
+}
+
+impl ShardGroup {
+    fn new(description: String, shards: &[Job]) -> Self {
+        ShardGroup {
+            description,
+            shards: shards.iter().map(|shard| shard.base_key).collect(),
+            cached: 0,
+            ran: 0,
         }
+    }
 
-        // we couldn't track which roots were needed before because we didn't
-        // have the keys for those jobs. Now that we do, take a minute to
-        // populate the roots vec (which up until now has had the right capacity
-        // but no items.)
-        for root in self.roots {
-            coordinator.roots.push(
-                *glue_to_job_key
-                    .get(root)
-                    .context("could not key for root job")?,
-            )
+    fn finished(&mut self, cached: bool) {
+        if cached {
+            self.cached += 1;
+        } else {
+            self.ran += 1;
         }
 
-        Ok(coordinator)
+        if (self.cached + self.ran) as usize == self.shards.len() {
+            tracing::info!(
+                "finished all {} parts of {} ({} ran, {} from cache)",
+                self.shards.len(),
+                self.description,
+                self.ran,
+                self.cached,
+            );
+        }
     }
 }
 
-type DoneMsg = (job::Key<job::Base>, Option<Workspace>);
+/// How many times we'll re-run a job because files went missing from its
+/// store item before giving up. If the files are still gone after re-running,
+/// something is wrong with the job itself rather than with the store.
+const MAX_RERUNS_FOR_MISSING_OUTPUTS: usize = 1;
+
+/// Calculating a final key is quick, so it's not worth starting a thread for
+/// fewer than this many.
+const MIN_FINAL_KEYS_PER_THREAD: usize = 64;
+
+/// Reading metadata is even quicker than calculating a final key.
+const MIN_FILES_PER_THREAD: usize = 256;
+
+/// Hashing a file is slow enough to spread out even a few of them, but not so
+/// slow that it's worth a thread each.
+const MIN_HASHES_PER_THREAD: usize = 8;
+
+/// How many files we hash before handing the hashes over. Smaller batches
+/// let the first jobs start sooner; bigger ones mean fewer trips around the
+/// coordinator loop and fewer database writes.
+const FILES_PER_HASH_BATCH: usize = 1024;
+
+/// How often `--explain-schedule` says what everything is waiting on.
+const EXPLAIN_SCHEDULE_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Debug)]
 pub struct Coordinator {
@@ -279,9 +725,34 @@ pub struct Coordinator {
 
     roots: Vec<job::Key<job::Base>>,
     max_local_jobs: usize,
+    resources: Resources,
+    pauser: Pauser,
+    explain_schedule: bool,
+    salt: Option<String>,
+    keep_going: bool,
+    timeout: Option<Duration>,
+    verbose_failures: bool,
+    verify_store: bool,
+    fetch_only: bool,
+
+    // our own environment, if passthrough values go into final keys
+    host_env: Option<HashMap<String, String>>,
 
     // caches
     path_to_hash: HashMap<PathBuf, blake3::Hash>,
+
+    // input files get hashed on another thread while we build, so jobs
+    // whose files are hashed can start before the rest are. These are
+    // what it needs (until it starts) and where the hashes come back
+    // (until it's done.)
+    file_hashes: Option<FileHashes>,
+    hashes: Option<mpsc::UnboundedReceiver<Result<Hashed>>>,
+    // specs jobs take files from, until `run` resolves them
+    resolved_root: PathBuf,
+    unresolved: BTreeSet<resolver::Spec>,
+    spec_to_resolved: HashMap<resolver::Spec, resolver::Resolved>,
+    snapshot: Option<Snapshot>,
+    status: Option<Status>,
     final_keys: HashMap<job::Key<job::Base>, job::Key<job::Final>>,
 
     // note:  this mapping is only safe to use in the context of a single
@@ -292,196 +763,2110 @@ pub struct Coordinator {
     // which jobs should run when?
     jobs: HashMap<job::Key<job::Base>, Job>,
     blocked: HashMap<job::Key<job::Base>, HashSet<job::Key<job::Base>>>,
+    reruns: HashMap<job::Key<job::Base>, usize>,
+    shard_groups: HashMap<job::Key<job::Base>, ShardGroup>,
 
     // what's the state of the coordinator while running?
     ready: Vec<job::Key<job::Base>>,
-    running: FuturesUnordered<JoinHandle<Result<DoneMsg>>>,
+    // jobs that are otherwise ready but whose input files are still being
+    // hashed
+    unhashed: Vec<job::Key<job::Base>>,
+    // jobs that are otherwise ready but need resources other jobs are holding
+    waiting: Vec<job::Key<job::Base>>,
+    // jobs that would have run if we weren't only fetching
+    not_fetched: Vec<job::Key<job::Base>>,
+    running: FuturesUnordered<JoinHandle<RunResult>>,
+    started: HashMap<job::Key<job::Base>, Started>,
+
+    // jobs we're running that other builds might be waiting on, and jobs
+    // other builds are running that we're waiting on (see `InFlight`.)
+    in_flight: InFlight,
+    claims: HashMap<job::Key<job::Base>, in_flight::Running>,
+    attached: FuturesUnordered<Pin<Box<dyn Future<Output = job::Key<job::Base>> + Send + Sync>>>,
+
+    // set once a job fails (unless we're keeping going) or we run out of
+    // time, so we stop starting new ones
+    stopping: bool,
+
+    // where the time went, for the summary after the build
+    stats: BuildStats,
+
+    // what happened to each job, for `--report` and anything else that
+    // wants to know
+    events: Bus,
 }
 
-impl<'roc> Coordinator {
+impl Coordinator {
     /// Run the build from start to finish.
     pub async fn run(&mut self) -> Result<()> {
-        log::trace!("scheduling immediately-available jobs");
+        self.resolve_inputs().await?;
+
+        self.stats.jobs = self.jobs.len();
+        let mut jobs: Vec<JobInfo> = self.jobs.values().map(JobInfo::from).collect();
+        jobs.sort_by(|a, b| a.id.cmp(&b.id));
+        self.events.publish(Event::BuildStarted { jobs });
+
+        // we wait until now so we only hash files for jobs that are still
+        // part of the build (see `keep_only_tests` and `shell`.)
+        if let Some(file_hashes) = self.file_hashes.take() {
+            self.hashes = Some(spawn_hashing(
+                self.input_files_by_readiness(),
+                file_hashes,
+                self.max_local_jobs,
+            ));
+        }
+
+        let result = self.run_jobs().await;
+
+        self.events.publish(Event::BuildFinished {
+            succeeded: result.is_ok(),
+        });
+
+        result
+    }
+
+    /// Run the resolver plugins (or fetch the archives) that jobs take
+    /// files from. We do them one at a time, since two resolving to the
+    /// same hash would race to move their files into the cache.
+    async fn resolve_inputs(&mut self) -> Result<()> {
+        for spec in std::mem::take(&mut self.unresolved) {
+            // TODO: collect errors instead of bailing immediately
+            let resolved = spec
+                .resolve(&self.resolved_root)
+                .instrument(tracing::info_span!("resolving", resolver = %spec.resolver))
+                .await
+                .with_context(|| format!("could not resolve {}", spec))?;
+
+            tracing::debug!("resolved {} to {}", spec, resolved.hash());
+            self.spec_to_resolved.insert(spec, resolved);
+        }
+
+        Ok(())
+    }
+
+    async fn run_jobs(&mut self) -> Result<()> {
+        tracing::trace!("scheduling immediately-available jobs");
         self.schedule()
             .await
             .context("could not start immediately-ready jobs")?;
 
-        let mut failed = false;
+        let mut failed = HashSet::new();
+        let mut lost_track = false;
+        let mut hashing_failed = false;
+        let mut timed_out = false;
+
+        let mut deadline = self
+            .timeout
+            .map(|timeout| Box::pin(tokio::time::sleep(timeout)));
+
+        self.pauser
+            .listen()
+            .context("could not listen for pause requests")?;
+
+        // the first tick of an interval happens right away, but there's
+        // nothing interesting to explain until jobs have had a chance to run.
+        let mut explain = self.explain_schedule.then(|| {
+            tokio::time::interval_at(
+                tokio::time::Instant::now() + EXPLAIN_SCHEDULE_INTERVAL,
+                EXPLAIN_SCHEDULE_INTERVAL,
+            )
+        });
+
+        tracing::trace!("starting coordinator loop");
+        loop {
+            // jobs that are waiting for hashes count as work left to do,
+            // unless we've stopped starting jobs anyway.
+            if self.running.is_empty()
+                && self.attached.is_empty()
+                && (self.hashes.is_none() || self.stopping)
+            {
+                self.hashes = None;
+                break;
+            }
+
+            let join_res = tokio::select! {
+                join_res = self.running.next(), if !self.running.is_empty() => match join_res {
+                    Some(join_res) => join_res,
+                    None => break,
+                },
+                Some(id) = self.attached.next(), if !self.attached.is_empty() => {
+                    self.finish_attached(id)
+                        .await
+                        .context("could not finish job another build ran")?;
+                    continue;
+                }
+                hashed = next_hashes(&mut self.hashes) => {
+                    match hashed {
+                        Some(Ok(hashed)) => self.add_hashes(hashed),
+                        Some(Err(err)) => {
+                            tracing::error!("{:?}", err.context("could not hash input files"));
+                            hashing_failed = true;
+                            self.hashes = None;
+                            self.stop();
+                        }
+                        None => self.finish_hashing(),
+                    }
+
+                    self.schedule()
+                        .await
+                        .context("could not start jobs whose input files were hashed")?;
+                    continue;
+                }
+                () = self.pauser.requested() => {
+                    let paused_at = tokio::time::Instant::now();
+                    self.pause().context("could not pause")?;
+
+                    // time spent paused doesn't count against the timeout
+                    if let Some(sleep) = &mut deadline {
+                        let resumed = sleep.deadline() + paused_at.elapsed();
+                        sleep.as_mut().reset(resumed);
+                    }
+                    continue;
+                }
+                () = next_tick(&mut explain) => {
+                    self.explain_schedule().context("could not explain the schedule")?;
+                    continue;
+                }
+                () = next_deadline(&mut deadline) => {
+                    deadline = None;
+                    timed_out = true;
+                    self.time_out();
+                    continue;
+                }
+            };
 
-        log::trace!("starting coordinator loop");
-        while let Some(join_res) = self.running.next().await {
             match join_res {
-                Ok(Ok(done_msg)) => self
-                    .handle_done(done_msg)
+                Ok((id, Ok(finished))) => self
+                    .handle_done((id, finished))
                     .await
                     .context("could not finish job")?,
-                Ok(Err(err)) => {
-                    log::error!("{:?}", err.context("job failed"));
-                    failed = true
+                Ok((id, Err(err))) => {
+                    let err = err.context("job failed");
+
+                    let duration = self.elapsed(id);
+                    self.events.publish(Event::JobFailed {
+                        job: self.jobs.get(&id).context("had a bad job ID")?.into(),
+                        duration,
+                        error: format!("{:#}", err),
+                    });
+
+                    tracing::error!("{:?}", err);
+                    self.stats.failed += 1;
+                    failed.insert(id);
+
+                    // anyone waiting on us gets a turn at running it
+                    self.claims.remove(&id);
+                    self.stop();
                 }
+                // we did this ourselves in `stop`, and we'll report the job
+                // as skipped once everything has wound down.
+                Err(err) if err.is_cancelled() => (),
                 Err(err) => {
-                    log::error!(
+                    tracing::error!(
                         "{:?}",
                         anyhow::Error::new(err).context("could not join async task")
                     );
-                    failed = true
+                    lost_track = true;
+                    self.stop();
                 }
             }
         }
 
-        if failed {
-            anyhow::bail!("there was a failure while building; see logs for details")
-        } else {
-            Ok(())
+        // jobs we cancelled never finished, so nobody should wait on them
+        self.claims.clear();
+
+        let broken = !failed.is_empty() || lost_track || hashing_failed;
+        if !broken && !timed_out {
+            return Ok(());
         }
-    }
 
-    /// Start any outstanding work according to our scheduling rules. Right
-    /// now that just means that we won't ever be running more jobs than
-    /// `self.max_local_jobs`.
-    async fn schedule(&mut self) -> Result<()> {
-        let maximum_schedulable = self.max_local_jobs.saturating_sub(self.running.len());
+        let mut not_skipped = failed.clone();
+        if timed_out {
+            not_skipped.extend(self.report_cut_short()?);
+        }
 
-        // The intent here is to drain a certain number of items from
-        // `self.ready`. If the borrowing rules allowed it, we'd drain directly.
-        let mut ready_now = self
-            .ready
-            .split_off(self.ready.len() - maximum_schedulable.min(self.ready.len()));
+        self.report_skipped(&not_skipped, !broken)
+            .context("could not report skipped jobs")?;
 
-        log::debug!("scheduling {} jobs", ready_now.len());
-        for id in ready_now.drain(..) {
-            self.start(id)
-                .await
-                .context("could not start job from immediately-available set")?;
+        if self.verbose_failures && !failed.is_empty() {
+            self.report_failure_chains(&failed)
+                .context("could not report how targets depend on failures")?;
         }
 
-        Ok(())
+        // a job that actually failed is more worth knowing about than how
+        // long the build took.
+        if !broken {
+            return Err(TimedOut(self.timeout.unwrap_or_default()).into());
+        }
+
+        anyhow::bail!("there was a failure while building; see logs for details")
     }
 
-    /// Start and track a single job by ID.
-    async fn start(&mut self, id: job::Key<job::Base>) -> Result<()> {
-        let job = self.jobs.get(&id).context("had a bad job ID")?;
+    /// Stop the build after a failure: cancel the jobs that are running and
+    /// don't start any more. With `keep_going`, this does nothing, and the
+    /// build goes on until only jobs depending on the failure are left.
+    fn stop(&mut self) {
+        if self.keep_going || self.stopping {
+            return;
+        }
 
-        log::debug!("preparing to run job {}", job);
+        if !self.running.is_empty() {
+            tracing::info!(
+                "stopping {} running jobs since a job failed. Use `--keep-going` to build everything that doesn't depend on the failure instead.",
+                self.running.len()
+            );
+        }
 
-        let final_key = job
-            .final_key(&self.path_to_hash, &self.job_to_content_hash)
-            .context("could not calculate final cache key")?;
-        self.final_keys.insert(id, final_key);
+        self.abort_running();
+    }
 
-        // build (or don't) based on the final key!
-        let join_handle = match self
-            .store
-            .item_for_job(&final_key)
-            .context("could not get a store path for the current job")?
-        {
-            Some(item) => {
-                log::debug!("already had output of job {}; skipping", job);
-                self.job_to_content_hash.insert(job.base_key, item);
+    /// Stop the build because it's taken longer than `timeout`. Unlike
+    /// `stop`, this happens with `keep_going` too.
+    fn time_out(&mut self) {
+        tracing::error!(
+            "the build took longer than {}, so stopping {} running jobs",
+            ui::elapsed(self.timeout.unwrap_or_default()),
+            self.running.len()
+        );
 
-                tokio::spawn(async move { Ok((id, None)) })
-            }
-            None => {
-                // TODO:  this preparation step probably represents a
-                // bottleneck. In the current design, we need to be able to
-                // access `job_to_content_hash` to prepare the workspace. It's
-                // not send-safe, so we either need to copy only the keys we
-                // need for the current job or use some data structure that
-                // is sendable.
-                //
-                // Doing that would also mean that we could move preparation
-                // into the spawned task, which would remove the requirement
-                // that `start` be `async` (at least as of the writing of this
-                // comment.)
-                let runner = self
-                    .runner_builder
-                    .build(job, &self.job_to_content_hash)
-                    .await
-                    .context("could not prepare job to run")?;
+        self.abort_running();
+    }
 
-                tokio::spawn(async move {
-                    let workspace = runner.run().await.context("could not run job")?;
+    fn abort_running(&mut self) {
+        self.stopping = true;
+        for handle in self.running.iter() {
+            handle.abort();
+        }
 
-                    Ok((id, Some(workspace)))
-                })
-            }
-        };
+        // jobs other builds are running keep going without us
+        self.attached.clear();
+    }
 
-        self.running.push(join_handle);
+    /// Tell everyone about the jobs that were still running when we ran out
+    /// of time, and how far the build got. Returns the jobs we cut short.
+    fn report_cut_short(&mut self) -> Result<HashSet<job::Key<job::Base>>> {
+        // jobs leave `started` when they finish, so what's left was running
+        let mut cut_short: Vec<(job::Key<job::Base>, Duration)> = self
+            .started
+            .drain()
+            .map(|(id, started)| (id, started.at.elapsed()))
+            .collect();
+        cut_short.sort_by_key(|(id, _)| *id);
+
+        let mut names = Vec::with_capacity(cut_short.len());
+        for (id, duration) in &cut_short {
+            let job = self.jobs.get(id).context("had a bad job ID")?;
+            self.events.publish(Event::JobCutShort {
+                job: job.into(),
+                duration: *duration,
+            });
+            names.push(job.to_string());
+        }
 
-        Ok(())
+        tracing::warn!(
+            "finished {} of {} jobs before running out of time",
+            self.job_to_content_hash.len(),
+            self.jobs.len()
+        );
+        if !names.is_empty() {
+            tracing::warn!(
+                "cut short {} running jobs: {}",
+                names.len(),
+                names.join(", ")
+            );
+        }
+
+        Ok(cut_short.into_iter().map(|(id, _)| id).collect())
     }
 
-    async fn handle_done(&mut self, msg: DoneMsg) -> Result<()> {
-        let (id, workspace_opt) = msg;
+    /// Tell everyone about the jobs that didn't finish because of a failure
+    /// (or, if `timed_out`, because we ran out of time.) Jobs in
+    /// `not_skipped` have been reported some other way already.
+    fn report_skipped(
+        &self,
+        not_skipped: &HashSet<job::Key<job::Base>>,
+        timed_out: bool,
+    ) -> Result<()> {
+        let mut skipped: Vec<&Job> = self
+            .jobs
+            .iter()
+            .filter(|(id, _)| {
+                !self.job_to_content_hash.contains_key(id) && !not_skipped.contains(id)
+            })
+            .map(|(_, job)| job)
+            .collect();
+
+        if skipped.is_empty() {
+            return Ok(());
+        }
 
-        let job = self.jobs.get(&id).context("had a bad job ID")?;
+        skipped.sort_by_key(|job| job.base_key);
+        for job in &skipped {
+            self.events
+                .publish(Event::JobSkipped { job: (*job).into() });
+        }
 
-        let final_key = self
-            .final_keys
-            .get(&id)
-            .context("could not retrieve final cache key; was it calculated in `start`?")?;
+        tracing::warn!(
+            "skipped {} jobs because {}: {}",
+            skipped.len(),
+            if timed_out {
+                "the build ran out of time"
+            } else {
+                "of the failure"
+            },
+            skipped
+                .iter()
+                .map(|job| job.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
 
-        if let Some(workspace) = workspace_opt {
-            self.check_nothing_was_in_home(workspace.home_dir())
-                .await
-                .context("could not check for leftover files in HOME")?;
+        Ok(())
+    }
 
-            self.job_to_content_hash.insert(
-                job.base_key,
-                self.store
-                    .store_from_workspace(*final_key, job, workspace)
-                    .await
-                    .context("could not store job output")?,
-            );
+    /// Show the chain of dependencies from each requested target down to
+    /// each failed job, like `app <- bundle <- codegen FAILED`. A target
+    /// can reach a failure more than one way; we show the shortest.
+    fn report_failure_chains(&self, failed: &HashSet<job::Key<job::Base>>) -> Result<()> {
+        // shards stand in for their group, both as targets and as
+        // dependencies.
+        let expand = |key: job::Key<job::Base>| match self.shard_groups.get(&key) {
+            Some(group) => group.shards.clone(),
+            None => vec![key],
         };
 
-        // Now that we're done running the job, we update our bookkeeping to
-        // figure out what running that job just unblocked.
-        let mut newly_unblocked = vec![]; // get around needing an async context in the loop below
+        let mut dependents: HashMap<job::Key<job::Base>, Vec<job::Key<job::Base>>> =
+            HashMap::default();
+        for (id, job) in &self.jobs {
+            for dep in job.input_jobs.keys().chain(job.after.iter()) {
+                for dep in expand(*dep) {
+                    dependents.entry(dep).or_default().push(*id);
+                }
+            }
+        }
 
-        self.blocked.retain(|blocked, blockers| {
-            let removed = blockers.remove(&id);
-            if !removed {
-                return true;
+        let roots: HashSet<job::Key<job::Base>> =
+            self.roots.iter().flat_map(|root| expand(*root)).collect();
+
+        let mut failed: Vec<&job::Key<job::Base>> = failed.iter().collect();
+        failed.sort();
+
+        for failure in failed {
+            let chains = failure_chains(*failure, &dependents, &roots);
+            if chains.is_empty() {
+                continue;
             }
 
-            let no_blockers_remaining = blockers.is_empty();
-            if no_blockers_remaining {
-                log::debug!("unblocked {}", blocked);
-                newly_unblocked.push(*blocked);
+            let mut lines = Vec::with_capacity(chains.len());
+            for chain in chains {
+                let mut names = Vec::with_capacity(chain.len());
+                for key in chain {
+                    names.push(
+                        self.jobs
+                            .get(&key)
+                            .context("had a bad job ID")?
+                            .id
+                            .to_string(),
+                    );
+                }
+
+                lines.push(format!("    {} FAILED", names.join(" <- ")));
             }
-            !no_blockers_remaining
-        });
 
-        for id in newly_unblocked.drain(..) {
-            self.ready.push(id)
+            tracing::error!(
+                "{} affects these targets:\n{}",
+                self.jobs.get(failure).context("had a bad job ID")?,
+                lines.join("\n")
+            );
         }
 
-        self.schedule().await.context("could not start new jobs")?;
-
         Ok(())
     }
 
-    pub fn roots(&self) -> &[job::Key<job::Base>] {
-        self.roots.as_ref()
-    }
+    /// Stop until someone tells us to resume. Nothing gets scheduled while
+    /// we're paused, since the whole process is stopped.
+    fn pause(&self) -> Result<()> {
+        let state = QueueState {
+            pid: std::process::id(),
+            ready: self
+                .ready
+                .iter()
+                .chain(self.unhashed.iter())
+                .chain(self.waiting.iter())
+                .map(|key| key.to_string())
+                .collect(),
+            blocked: self.blocked.keys().map(|key| key.to_string()).collect(),
+            running: self.running.len(),
+        };
 
-    pub fn store_path(&self, key: &job::Key<job::Base>) -> Option<&store::Item> {
-        self.job_to_content_hash.get(key)
+        self.pauser.pause(&state, self.runner_builder.children())
     }
 
-    async fn check_nothing_was_in_home(&self, home_dir: &Path) -> Result<()> {
-        for entry in fs::read_dir(home_dir)
-            .with_context(|| format!("could not read `{}`", home_dir.display()))?
+    /// Log the reason each job that isn't running yet is waiting, so people
+    /// can tell whether a slow build needs more `--max-local-jobs`, more
+    /// resources, or just has a long chain of dependencies.
+    fn explain_schedule(&self) -> Result<()> {
+        tracing::info!(
+            "{} of at most {} jobs running; {} ready, {} waiting for input files to be hashed, {} waiting for resources, {} waiting for dependencies",
+            self.running.len(),
+            self.max_local_jobs,
+            self.ready.len(),
+            self.unhashed.len(),
+            self.waiting.len(),
+            self.blocked.len(),
+        );
+
+        for id in &self.ready {
+            let job = self.jobs.get(id).context("had a bad job ID")?;
+
+            if self.running.len() >= self.max_local_jobs {
+                tracing::info!(
+                    "{} is ready, but {} jobs are already running (the limit)",
+                    job,
+                    self.running.len()
+                );
+            } else {
+                tracing::info!("{} is ready and will start next", job);
+            }
+        }
+
+        for id in &self.waiting {
+            let job = self.jobs.get(id).context("had a bad job ID")?;
+            let busy = self
+                .resources
+                .busy(&job.resources)
+                .context("could not check resources")?;
+
+            tracing::info!(
+                "{} is waiting for resources that other jobs are holding: {}",
+                job,
+                busy.join(", ")
+            );
+        }
+
+        for (id, blockers) in &self.blocked {
+            let job = self.jobs.get(id).context("had a bad job ID")?;
+            let blockers = blockers
+                .iter()
+                .map(|blocker| {
+                    self.jobs
+                        .get(blocker)
+                        .map(|blocker| blocker.to_string())
+                        .context("had a bad job ID")
+                })
+                .collect::<Result<Vec<String>>>()?;
+
+            tracing::info!(
+                "{} is waiting for {} unfinished {}: {}",
+                job,
+                blockers.len(),
+                if blockers.len() == 1 {
+                    "dependency"
+                } else {
+                    "dependencies"
+                },
+                blockers.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Start any outstanding work according to our scheduling rules. Right
+    /// now that just means that we won't ever be running more jobs than
+    /// `self.max_local_jobs`.
+    async fn schedule(&mut self) -> Result<()> {
+        if self.stopping {
+            return Ok(());
+        }
+
+        self.finish_cache_hits()
+            .await
+            .context("could not check the store for ready jobs")?;
+
+        // Starting a job can put more work in `self.ready` without adding
+        // anything to `self.running` (for example when we need to re-run a
+        // dependency first) so we keep going until we're either full or out
+        // of things to do.
+        while self.running.len() < self.max_local_jobs {
+            let id = match self.ready.pop() {
+                Some(id) => id,
+                None => break,
+            };
+
+            self.start(id)
+                .await
+                .context("could not start job from immediately-available set")?;
+        }
+
+        tracing::debug!("{} jobs running", self.running.len());
+        self.events.publish(Event::QueueChanged {
+            ready: self.ready.len(),
+            waiting: self.waiting.len(),
+            blocked: self.blocked.len(),
+            running: self.running.len(),
+        });
+
+        Ok(())
+    }
+
+    /// Calculate final keys for every ready job that doesn't have one yet
+    /// and check the store for all of them at once. Jobs whose output we
+    /// already have are finished right here instead of going through
+    /// `running`, so a build that's mostly cache hits isn't limited by how
+    /// fast we can go around the coordinator loop. Finishing a job can make
+    /// its dependents ready, so we keep going until a round has no hits.
+    async fn finish_cache_hits(&mut self) -> Result<()> {
+        loop {
+            // we can't calculate final keys without the hashes of the
+            // job's input files, so jobs wait for those first.
+            if self.hashes.is_some() {
+                let (hashed, unhashed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.ready)
+                    .into_iter()
+                    .partition(|id| self.inputs_hashed(id));
+                self.ready = hashed;
+                self.unhashed.extend(unhashed);
+            }
+
+            let unkeyed: Vec<job::Key<job::Base>> = self
+                .ready
+                .iter()
+                .filter(|id| !self.final_keys.contains_key(id))
+                .copied()
+                .collect();
+
+            if unkeyed.is_empty() {
+                return Ok(());
+            }
+
+            let keying_started = Instant::now();
+            let final_keys = self
+                .final_keys_for(&unkeyed)
+                .context("could not calculate final cache keys")?;
+            self.stats.hashing += keying_started.elapsed();
+
+            let items = self
+                .store
+                .items_for_jobs(&final_keys)
+                .context("could not get store paths for ready jobs")?;
+
+            let mut hits = HashSet::new();
+            for ((id, final_key), item) in unkeyed.into_iter().zip(final_keys).zip(items) {
+                self.final_keys.insert(id, final_key);
+
+                // a quarantined item is just a miss, so the job runs again
+                let item = match item {
+                    Some(item) if self.verify_store => {
+                        let job = self.jobs.get(&id).context("had a bad job ID")?;
+                        self.store
+                            .verify(&final_key, job, item)
+                            .await
+                            .with_context(|| format!("could not verify the output of {}", job))?
+                    }
+                    item => item,
+                };
+
+                if let Some(item) = item {
+                    tracing::debug!(
+                        "already had output of job {}; skipping",
+                        self.jobs.get(&id).context("had a bad job ID")?
+                    );
+                    self.finish_cached(id, item)?;
+                    hits.insert(id);
+                }
+            }
+
+            if hits.is_empty() {
+                return Ok(());
+            }
+
+            self.ready.retain(|id| !hits.contains(id));
+            for id in hits {
+                self.unblock_dependents(id);
+            }
+        }
+    }
+
+    /// Do we have hashes for every input file of `id`?
+    fn inputs_hashed(&self, id: &job::Key<job::Base>) -> bool {
+        self.jobs.get(id).is_none_or(|job| {
+            job.input_files
+                .iter()
+                .all(|file| self.path_to_hash.contains_key(&file.source))
+        })
+    }
+
+    /// Take a batch of hashes from `spawn_hashing`, and make the jobs that
+    /// were only waiting for them ready.
+    fn add_hashes(&mut self, hashed: Hashed) {
+        self.stats.hashing += hashed.took;
+        self.path_to_hash.extend(hashed.hashes);
+
+        let (hashed, unhashed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.unhashed)
+            .into_iter()
+            .partition(|id| self.inputs_hashed(id));
+        self.ready.extend(hashed);
+        self.unhashed = unhashed;
+    }
+
+    /// Every file has been hashed, so nothing has to wait for hashes any
+    /// more. (If a job's files somehow still aren't hashed, calculating its
+    /// final key will say so.)
+    fn finish_hashing(&mut self) {
+        self.hashes = None;
+        self.ready.append(&mut self.unhashed);
+    }
+
+    /// Every input file in the build, once each, with the files of jobs that
+    /// can start soonest first: jobs that are ready, then the jobs only
+    /// waiting on those, and so on.
+    fn input_files_by_readiness(&self) -> Vec<PathBuf> {
+        let mut ready: Vec<job::Key<job::Base>> = self.ready.clone();
+        ready.sort();
+
+        let mut placed: HashSet<job::Key<job::Base>> = ready.iter().copied().collect();
+        let mut order = ready;
+
+        let mut blocked: Vec<(&job::Key<job::Base>, &HashSet<job::Key<job::Base>>)> =
+            self.blocked.iter().collect();
+        blocked.sort_by_key(|(id, _)| **id);
+
+        while !blocked.is_empty() {
+            let (next, later): (Vec<_>, Vec<_>) = blocked
+                .into_iter()
+                .partition(|(_, blockers)| blockers.iter().all(|id| placed.contains(id)));
+
+            // only jobs blocked on something outside the build are left,
+            // which shouldn't happen, but they still need their files.
+            if next.is_empty() {
+                order.extend(later.iter().map(|(id, _)| **id));
+                break;
+            }
+
+            placed.extend(next.iter().map(|(id, _)| **id));
+            order.extend(next.iter().map(|(id, _)| **id));
+            blocked = later;
+        }
+
+        let mut seen = HashSet::new();
+        order
+            .iter()
+            .filter_map(|id| self.jobs.get(id))
+            .flat_map(|job| job.input_files.iter())
+            .filter(|file| seen.insert(&file.source))
+            .map(|file| file.source.clone())
+            .collect()
+    }
+
+    /// Calculate final keys for `ids`, spread across threads. The jobs have
+    /// to be ready, since we need the output hashes of their dependencies.
+    fn final_keys_for(&self, ids: &[job::Key<job::Base>]) -> Result<Vec<job::Key<job::Final>>> {
+        in_parallel(ids, self.max_local_jobs, MIN_FINAL_KEYS_PER_THREAD, |id| {
+            let job = self.jobs.get(id).context("had a bad job ID")?;
+
+            job.final_key(
+                &self.path_to_hash,
+                &self.job_to_content_hash,
+                &self.spec_to_resolved,
+                self.salt.as_deref(),
+                self.status.as_ref().map(Status::stable_hash),
+                self.host_env.as_ref(),
+            )
+            .with_context(|| format!("could not calculate final cache key for {}", job))
+        })
+    }
+
+    /// Start and track a single job by ID. We only get here for jobs that
+    /// `finish_cache_hits` couldn't find in the store.
+    async fn start(&mut self, id: job::Key<job::Base>) -> Result<()> {
+        if self
+            .fetch_remote(id)
+            .await
+            .context("could not check the remote cache")?
+        {
+            return Ok(());
+        }
+
+        let job = self.jobs.get(&id).context("had a bad job ID")?;
+
+        // its dependents stay blocked, since they can't have final keys
+        // until it has outputs.
+        if self.fetch_only {
+            tracing::debug!("{} isn't in any cache, so it would have to run", job);
+            self.not_fetched.push(id);
+            return Ok(());
+        }
+
+        tracing::debug!("preparing to run job {}", job);
+
+        let damaged = self
+            .damaged_dependencies(job)
+            .context("could not check dependency outputs")?;
+
+        if !damaged.is_empty() {
+            return self
+                .rerun_dependencies(id, damaged)
+                .context("could not re-run dependencies with missing outputs");
+        }
+
+        // linking dependency outputs into the workspace counts as using
+        // them, as far as GC is concerned.
+        for dep in job.input_jobs.keys() {
+            if let Some(item) = self.job_to_content_hash.get(dep) {
+                self.store
+                    .touch(item)
+                    .context("could not record store item access")?;
+            }
+        }
+
+        // another build sharing `in_flight` may be running this job already.
+        // If so, we wait for it instead of running the job a second time.
+        let final_key = self.final_keys.get(&id).context(
+            "could not retrieve final cache key; was it calculated before starting the job?",
+        )?;
+        let claim = match self.in_flight.claim(*final_key) {
+            in_flight::Claim::Run(claim) => claim,
+            in_flight::Claim::Wait(waiting) => {
+                tracing::debug!("another build is running {}, so I'm waiting for it", job);
+                self.attached.push(Box::pin(async move {
+                    waiting.finished().await;
+                    id
+                }));
+                return Ok(());
+            }
+        };
+
+        let allocation = match self
+            .resources
+            .try_acquire(&job.resources)
+            .with_context(|| format!("could not get resources for {}", job))?
+        {
+            Some(allocation) => allocation,
+            None => {
+                tracing::debug!("waiting for resources to run job {}", job);
+                self.waiting.push(id);
+                return Ok(());
+            }
+        };
+
+        // TODO:  this preparation step probably represents a bottleneck. In
+        // the current design, we need to be able to access
+        // `job_to_content_hash` to prepare the workspace. It's not
+        // send-safe, so we either need to copy only the keys we need for the
+        // current job or use some data structure that is sendable.
+        //
+        // Doing that would also mean that we could move preparation into the
+        // spawned task, which would remove the requirement that `start` be
+        // `async` (at least as of the writing of this comment.)
+        let span = tracing::info_span!("job", key = %id, job = %job);
+        let runner = self
+            .runner_builder
+            .build(
+                job,
+                self.final_keys.get(&id).context(
+                    "could not retrieve final cache key; was it calculated before starting the job?",
+                )?,
+                &self.job_to_content_hash,
+                &self.spec_to_resolved,
+                self.snapshot.as_ref(),
+                allocation,
+            )
+            .instrument(tracing::info_span!(parent: &span, "workspace setup"))
+            .await
+            .context("could not prepare job to run")?;
+
+        self.events.publish(Event::JobStarted { job: job.into() });
+
+        let execution = tracing::info_span!(parent: &span, "execution");
+        self.started.insert(
+            id,
+            Started {
+                at: Instant::now(),
+                span,
+            },
+        );
+        self.running.push(tokio::spawn(
+            async move { (id, runner.run().await.context("could not run job")) }
+                .instrument(execution),
+        ));
+        self.claims.insert(id, claim);
+
+        Ok(())
+    }
+
+    /// Try to get a job's output from the remote cache instead of running
+    /// it. If we can, the job is finished just like a local cache hit.
+    async fn fetch_remote(&mut self, id: job::Key<job::Base>) -> Result<bool> {
+        let job = self.jobs.get(&id).context("had a bad job ID")?;
+        let final_key = self.final_keys.get(&id).context(
+            "could not retrieve final cache key; was it calculated before starting the job?",
+        )?;
+
+        let item = match self.store.fetch_remote(*final_key, job).await? {
+            Some(item) => item,
+            None => return Ok(false),
+        };
+
+        tracing::debug!("got output of job {} from the remote cache", job);
+        self.finish_cached(id, item)?;
+        self.stats.downloads += 1;
+        self.unblock_dependents(id);
+
+        // anything we just unblocked needs a final key before it can start,
+        // and might be a cache hit itself.
+        self.finish_cache_hits()
+            .await
+            .context("could not check the store for newly-ready jobs")?;
+
+        Ok(true)
+    }
+
+    /// Finish a job whose output was already in the store (or a cache) as
+    /// a cache hit. Unblocking its dependents is up to the caller.
+    fn finish_cached(&mut self, id: job::Key<job::Base>, item: store::Item) -> Result<()> {
+        let job = self.jobs.get(&id).context("had a bad job ID")?;
+        self.store
+            .retain(&item, job.retention)
+            .context("could not record store item retention")?;
+        self.events.publish(Event::JobCached {
+            job: job.into(),
+            store_path: item.path().clone(),
+            output_types: self
+                .store
+                .file_types(&item)
+                .context("could not look up output file types")?,
+        });
+        self.job_to_content_hash.insert(id, item);
+        self.record_shard(id, true);
+        self.stats.cache_hits += 1;
+
+        Ok(())
+    }
+
+    /// Another build was running `id` when we went to start it, and now
+    /// it's done. If it worked, the output is in the store and we use it
+    /// like any other cache hit. If not, `id` gets back in line to run, and
+    /// whichever build starts it next runs it.
+    async fn finish_attached(&mut self, id: job::Key<job::Base>) -> Result<()> {
+        let final_key = self.final_keys.get(&id).context(
+            "could not retrieve final cache key; was it calculated before starting the job?",
+        )?;
+
+        match self
+            .store
+            .item_for_job(final_key)
+            .context("could not get the store path for a job another build ran")?
         {
+            Some(item) => {
+                tracing::debug!(
+                    "another build ran {}, so I'm using its output",
+                    self.jobs.get(&id).context("had a bad job ID")?
+                );
+                self.finish_cached(id, item)?;
+                self.unblock_dependents(id);
+            }
+            None => self.ready.push(id),
+        }
+
+        self.schedule().await.context("could not start new jobs")
+    }
+
+    async fn handle_done(&mut self, msg: DoneMsg) -> Result<()> {
+        let (
+            id,
+            Finished {
+                workspace,
+                unused_inputs,
+            },
+        ) = msg;
+        let span = self
+            .started
+            .get(&id)
+            .map_or_else(tracing::Span::none, |started| started.span.clone());
+        let duration = self.elapsed(id);
+
+        let job = self.jobs.get(&id).context("had a bad job ID")?;
+
+        let final_key = self.final_keys.get(&id).context(
+            "could not retrieve final cache key; was it calculated before starting the job?",
+        )?;
+
+        self.check_nothing_was_in_home(workspace.home_dir())
+            .await
+            .context("could not check for leftover files in HOME")?;
+
+        let moving_started = Instant::now();
+        let item = self
+            .store
+            .store_from_workspace(*final_key, job, workspace)
+            .instrument(tracing::info_span!(parent: &span, "store move"))
+            .await
+            .context("could not store job output")?;
+        self.stats.store_moves += moving_started.elapsed();
+
+        // other builds waiting on this job can take it from the store now
+        self.claims.remove(&id);
+        self.stats.execution += duration;
+        self.stats.executed.push((job.id.to_string(), duration));
+
+        self.store
+            .retain(&item, job.retention)
+            .context("could not record store item retention")?;
+        self.events.publish(Event::JobSucceeded {
+            job: job.into(),
+            duration,
+            store_path: item.path().clone(),
+            output_types: self
+                .store
+                .file_types(&item)
+                .context("could not look up output file types")?,
+            unused_inputs,
+        });
+        self.job_to_content_hash.insert(job.base_key, item);
+
+        self.record_shard(id, false);
+        self.unblock_dependents(id);
+
+        // the job that just finished may have been holding resources that
+        // waiting jobs need, so give them another shot. They go at the end
+        // of `ready` so they get first pick before anything new grabs them.
+        self.ready.append(&mut self.waiting);
+
+        self.schedule().await.context("could not start new jobs")?;
+
+        Ok(())
+    }
+
+    /// If `id` is one shard (or matrix copy) of a bigger job, count it as
+    /// done. Once every part is done, we report on the whole job at once,
+    /// since that's what people actually asked for.
+    fn record_shard(&mut self, id: job::Key<job::Base>, cached: bool) {
+        let group = match self.jobs.get(&id).and_then(|job| job.group) {
+            Some(group) => group,
+            None => return,
+        };
+
+        if let Some(group) = self.shard_groups.get_mut(&group) {
+            group.finished(cached);
+        }
+    }
+
+    /// Now that `id` is done, move any jobs that were only waiting on it
+    /// into `ready`.
+    fn unblock_dependents(&mut self, id: job::Key<job::Base>) {
+        let mut newly_unblocked = vec![];
+
+        self.blocked.retain(|blocked, blockers| {
+            let removed = blockers.remove(&id);
+            if !removed {
+                return true;
+            }
+
+            let no_blockers_remaining = blockers.is_empty();
+            if no_blockers_remaining {
+                tracing::debug!("unblocked {}", blocked);
+                newly_unblocked.push(*blocked);
+            }
+            !no_blockers_remaining
+        });
+
+        self.ready.append(&mut newly_unblocked);
+    }
+
+    /// Find dependencies of `job` whose store items are missing files that
+    /// they declared as outputs. This usually means someone has been deleting
+    /// things in the store by hand.
+    fn damaged_dependencies(&self, job: &Job) -> Result<Vec<job::Key<job::Base>>> {
+        let mut damaged = Vec::new();
+
+        for (dep, files) in &job.input_jobs {
+            let item = match self.job_to_content_hash.get(dep) {
+                Some(item) => item,
+                None => {
+                    // we're only ready to run once all our dependencies have
+                    // finished, so if there's no item here it's because some
+                    // other job noticed damage first and already asked for
+                    // this one to be re-run.
+                    damaged.push(*dep);
+                    continue;
+                }
+            };
+
+            // we checked that every file is one the dependency declared
+            // when we built the graph, so a missing one means the store
+            // lost it.
+            if files.iter().any(|file| !item.join(&file.source).exists()) {
+                damaged.push(*dep);
+            }
+        }
+
+        Ok(damaged)
+    }
+
+    /// Invalidate the store items for `damaged` dependencies and schedule
+    /// them to run again before `id`. Only dependencies that asked for this
+    /// with `rerunOnMissingOutputs` get re-run; any other damage fails the
+    /// build, since re-running a job can be expensive or have side effects
+    /// the build author didn't sign up for.
+    fn rerun_dependencies(
+        &mut self,
+        id: job::Key<job::Base>,
+        damaged: Vec<job::Key<job::Base>>,
+    ) -> Result<()> {
+        // the dependencies may not produce the same output this time, so
+        // we'll need a fresh final key once they're done.
+        self.final_keys.remove(&id);
+
+        for dep in damaged {
+            self.blocked.entry(id).or_default().insert(dep);
+
+            let item = match self.job_to_content_hash.remove(&dep) {
+                Some(item) => item,
+                None => continue, // already scheduled to re-run
+            };
+
+            let producer = self.jobs.get(&dep).context("had a bad job ID")?;
+
+            if !producer.rerun_on_missing_outputs {
+                anyhow::bail!(
+                    "some outputs of {} are missing from the store. Use `rerunOnMissingOutputs` on it to run it again when that happens.",
+                    producer
+                );
+            }
+
+            let reruns = self.reruns.entry(dep).or_default();
+            if *reruns >= MAX_RERUNS_FOR_MISSING_OUTPUTS {
+                anyhow::bail!(
+                    "files are still missing from the output of {} after re-running it",
+                    producer
+                );
+            }
+            *reruns += 1;
+
+            tracing::warn!(
+                "some outputs of {} are missing from the store, so I'm going to run it again",
+                producer
+            );
+
+            let final_key = self
+                .final_keys
+                .get(&dep)
+                .context("could not retrieve final cache key for dependency")?;
+
+            self.store
+                .invalidate(final_key)
+                .with_context(|| format!("could not invalidate store item {}", item))?;
+
+            self.ready.push(dep);
+        }
+
+        Ok(())
+    }
+
+    /// Build everything the root depends on, but not the root itself, and
+    /// set up its workspace for `rbt shell` (see `RunnerBuilder::shell`.)
+    pub async fn shell(mut self) -> Result<(Job, Workspace, BTreeMap<String, String>)> {
+        let job = self.build_only_dependencies("a shell").await?;
+
+        let (workspace, env) = self
+            .runner_builder
+            .shell(
+                &job,
+                &self.job_to_content_hash,
+                &self.spec_to_resolved,
+                self.snapshot.as_ref(),
+            )
+            .await
+            .with_context(|| format!("could not set up a shell for {}", job))?;
+
+        Ok((job, workspace, env))
+    }
+
+    /// Build everything the root depends on, then run the root `runs` times
+    /// in a row, each in a fresh workspace, for `rbt flake-check`. Every run
+    /// gets the same inputs, and nothing they output goes in the store.
+    pub async fn flake_check(mut self, runs: usize) -> Result<(Job, FlakeCheck)> {
+        let job = self.build_only_dependencies("a flake check").await?;
+        let mut check = FlakeCheck::new(&job);
+
+        for run in 0..runs {
+            let log_key = job.base_key.flake_run(check.started_ms, run);
+
+            // nothing else is running, so the only way not to get the
+            // resources is to ask for more than there are, which is an error.
+            let allocation = self
+                .resources
+                .try_acquire(&job.resources)
+                .with_context(|| format!("could not get resources for {}", job))?
+                .with_context(|| format!("resources for {} were still in use", job))?;
+
+            let runner = self
+                .runner_builder
+                .build(
+                    &job,
+                    &log_key,
+                    &self.job_to_content_hash,
+                    &self.spec_to_resolved,
+                    self.snapshot.as_ref(),
+                    allocation,
+                )
+                .await
+                .context("could not prepare job to run")?;
+
+            let started = Instant::now();
+            let result = runner.run().await;
+            let duration = started.elapsed();
+
+            // a failed run leaves its checkpoints behind to resume from,
+            // but no later run will.
+            self.runner_builder
+                .discard_checkpoints(&log_key)
+                .await
+                .with_context(|| format!("could not remove checkpoints for {}", job))?;
+
+            match &result {
+                Ok(_) => tracing::info!(
+                    "run {} of {} of {} passed in {}",
+                    run + 1,
+                    runs,
+                    job,
+                    ui::elapsed(duration)
+                ),
+                Err(err) => tracing::warn!(
+                    "run {} of {} of {} failed after {}: {:#}",
+                    run + 1,
+                    runs,
+                    job,
+                    ui::elapsed(duration),
+                    err
+                ),
+            }
+            check.record(&log_key, duration, result.as_ref().err());
+        }
+
+        Ok((job, check))
+    }
+
+    /// Take the root out of the build and build everything it depends on,
+    /// for tools that do something other than run it the usual way. `what`
+    /// is what the tool makes, for errors about roots that aren't one job.
+    async fn build_only_dependencies(&mut self, what: &str) -> Result<Job> {
+        let key = match self.roots.as_slice() {
+            [key] => *key,
+            roots => anyhow::bail!(
+                "{} can only be for one job, but this target is {} jobs (is it sharded?)",
+                what,
+                roots.len()
+            ),
+        };
+
+        let job = self.jobs.remove(&key).context("had a bad job ID")?;
+
+        // `blocked` has the shards of sharded dependencies, which the job's
+        // own list of inputs doesn't.
+        self.roots = self
+            .blocked
+            .get(&key)
+            .map(|blockers| blockers.iter().copied().collect())
+            .unwrap_or_default();
+        self.keep_only_roots_and_dependencies();
+
+        self.run()
+            .await
+            .with_context(|| format!("could not build what {} needs", job))?;
+
+        Ok(job)
+    }
+
+    /// What went into the keys of the job with `key`, for `rbt explain`.
+    /// Run a `fetch_only` build first, so we know as many of the hashes
+    /// that go into the final key as we can without running anything.
+    pub fn explain(&self, key: &job::Key<job::Base>) -> Result<Explanation> {
+        let job = self.jobs.get(key).context("had a bad job ID")?;
+        let name_of = |key: &job::Key<job::Base>| match self.jobs.get(key) {
+            Some(job) => job.id.to_string(),
+            None => key.to_string(),
+        };
+        let not_known = || "not known yet".to_string();
+
+        let mut explanation = Explanation::new(job, name_of);
+        explanation.final_key = self.final_keys.get(key).map(|key| key.to_string());
+
+        if let Some(salt) = &self.salt {
+            explanation.add_final("salt", salt.clone());
+        }
+        for file in &job.input_files {
+            let hash = self.path_to_hash.get(&file.source);
+            explanation.add_final(
+                "input",
+                format!(
+                    "{} {}",
+                    file.source.display(),
+                    hash.map_or_else(not_known, |hash| hash.to_string())
+                ),
+            );
+        }
+        for dep in job.input_jobs.keys() {
+            let item = self.job_to_content_hash.get(dep);
+            explanation.add_final(
+                "output of",
+                format!(
+                    "{} {}",
+                    name_of(dep),
+                    item.map_or_else(not_known, |item| item.hash().to_string())
+                ),
+            );
+        }
+        for spec in job.input_resolvers.keys() {
+            let resolved = self.spec_to_resolved.get(spec);
+            explanation.add_final(
+                "resolved",
+                format!(
+                    "{} {}",
+                    spec,
+                    resolved.map_or_else(not_known, |resolved| resolved.hash().to_string())
+                ),
+            );
+        }
+        if job.stamp {
+            explanation.add_final(
+                "stable status",
+                self.status
+                    .as_ref()
+                    .map_or_else(not_known, |status| status.stable_hash().to_string()),
+            );
+        }
+
+        // only whether the values changed matters here, and they're
+        // likelier than most things to be secret.
+        if let Some(host_env) = &self.host_env {
+            for name in &job.passthrough_env {
+                let value = match host_env.get(name) {
+                    Some(value) => blake3::hash(value.as_bytes()).to_hex()[..16].to_string(),
+                    None => "unset".to_string(),
+                };
+                explanation.add_final("passthrough", format!("{} {}", name, value));
+            }
+        }
+
+        Ok(explanation)
+    }
+
+    pub fn job(&self, key: &job::Key<job::Base>) -> Option<&Job> {
+        self.jobs.get(key)
+    }
+
+    pub fn roots(&self) -> &[job::Key<job::Base>] {
+        self.roots.as_ref()
+    }
+
+    /// After a `fetch_only` build, the jobs we couldn't get outputs for
+    /// (so they'd have to run) and the jobs that depend on those (so we
+    /// couldn't look theirs up.) Both are sorted by ID.
+    pub fn unbuilt(&self) -> (Vec<&Job>, Vec<&Job>) {
+        let by_id = |ids: Vec<&job::Key<job::Base>>| {
+            let mut jobs: Vec<&Job> = ids.into_iter().filter_map(|id| self.jobs.get(id)).collect();
+            jobs.sort_by_key(|job| job.id.to_string());
+            jobs
+        };
+
+        (
+            by_id(self.not_fetched.iter().collect()),
+            by_id(self.blocked.keys().collect()),
+        )
+    }
+
+    /// Where the time went in the build so far.
+    pub fn stats(&self) -> &BuildStats {
+        &self.stats
+    }
+
+    /// Make the test jobs among the roots and their dependencies the new
+    /// roots, for `rbt test`, and forget about everything they don't need.
+    /// Returns how many tests there are.
+    pub fn keep_only_tests(&mut self) -> usize {
+        let mut seen: HashSet<job::Key<job::Base>> = HashSet::with_capacity(self.jobs.len());
+        let mut to_visit = self.roots.clone();
+        let mut tests = Vec::new();
+
+        while let Some(id) = to_visit.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+
+            if let Some(blockers) = self.blocked.get(&id) {
+                to_visit.extend(blockers);
+            }
+            if let Some(job) = self.jobs.get(&id) {
+                if job.is_test {
+                    tests.push(id);
+                }
+                to_visit.extend(job.input_jobs.keys());
+                to_visit.extend(&job.after);
+            }
+        }
+
+        tests.sort();
+        self.roots = tests;
+        self.keep_only_roots_and_dependencies();
+
+        self.roots.len()
+    }
+
+    /// Forget about jobs that the roots don't need, so we don't build them.
+    fn keep_only_roots_and_dependencies(&mut self) {
+        let mut needed: HashSet<job::Key<job::Base>> = HashSet::with_capacity(self.jobs.len());
+        let mut to_visit = self.roots.clone();
+
+        while let Some(id) = to_visit.pop() {
+            if !needed.insert(id) {
+                continue;
+            }
+
+            // `blocked` has the shards of sharded dependencies, which the
+            // job's own list of inputs doesn't.
+            if let Some(blockers) = self.blocked.get(&id) {
+                to_visit.extend(blockers);
+            }
+            if let Some(job) = self.jobs.get(&id) {
+                to_visit.extend(job.input_jobs.keys());
+                to_visit.extend(&job.after);
+            }
+        }
+
+        self.jobs.retain(|id, _| needed.contains(id));
+        self.blocked.retain(|id, _| needed.contains(id));
+        self.ready.retain(|id| needed.contains(id));
+        self.unhashed.retain(|id| needed.contains(id));
+        self.shard_groups
+            .retain(|_, group| group.shards.iter().any(|shard| needed.contains(shard)));
+    }
+
+    /// Every job we know about and how they depend on each other.
+    pub fn graph(&self) -> Graph {
+        Graph::new(self.jobs.values(), &self.roots)
+    }
+
+    pub fn store_path(&self, key: &job::Key<job::Base>) -> Option<&store::Item> {
+        self.job_to_content_hash.get(key)
+    }
+
+    /// Listen for what happens during the build. Subscribe before calling
+    /// `run`, since events published earlier aren't kept around.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// How long it's been since we started the job, forgetting the start
+    /// time since the job is done.
+    fn elapsed(&mut self, id: job::Key<job::Base>) -> Duration {
+        self.started
+            .remove(&id)
+            .map(|started| started.at.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// The snapshot we built from, if we were asked to take one.
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    async fn check_nothing_was_in_home(&self, home_dir: &Path) -> Result<()> {
+        let mut entries = tokio::fs::read_dir(home_dir)
+            .await
+            .with_context(|| format!("could not read `{}`", home_dir.display()))?;
+
+        while let Some(entry) = entries.next_entry().await.context("could not read entry")? {
+            // we put this one there ourselves
+            if entry.file_name() == progress::FILE_NAME {
+                continue;
+            }
+
             // TODO: eventually, we'll collect these and report them per-job
-            log::warn!(
+            tracing::warn!(
                 "there was a leftover file in the home directory. (`{}`) Did your job write to $HOME?",
-                entry.context("could not read entry")?.path().display()
+                entry.path().display()
             );
         }
 
         Ok(())
     }
 }
+
+/// The shortest chain from each of `roots` that depends on `failed` down to
+/// it, through `dependents` (which jobs depend on each job.) Chains start
+/// with the root and end with `failed`, and come back in root order.
+fn failure_chains<K: Copy + Eq + std::hash::Hash + Ord>(
+    failed: K,
+    dependents: &HashMap<K, Vec<K>>,
+    roots: &HashSet<K>,
+) -> Vec<Vec<K>> {
+    // breadth-first, so the first way we reach a job is the shortest
+    let mut towards_failure: HashMap<K, K> = HashMap::default();
+    let mut queue = std::collections::VecDeque::from([failed]);
+    let mut reached_roots = Vec::new();
+
+    while let Some(key) = queue.pop_front() {
+        if roots.contains(&key) {
+            reached_roots.push(key);
+        }
+
+        for dependent in dependents.get(&key).into_iter().flatten() {
+            if *dependent != failed && !towards_failure.contains_key(dependent) {
+                towards_failure.insert(*dependent, key);
+                queue.push_back(*dependent);
+            }
+        }
+    }
+
+    reached_roots.sort();
+    reached_roots
+        .into_iter()
+        .map(|root| {
+            let mut chain = vec![root];
+            let mut current = root;
+            while let Some(next) = towards_failure.get(&current) {
+                chain.push(*next);
+                current = *next;
+            }
+            chain
+        })
+        .collect()
+}
+
+/// Wait for the next tick of `interval`, or forever if there isn't one.
+/// Call `f` on every item, splitting the work across up to `threads` threads
+/// with at least `min_per_thread` items each. Results come back in the same
+/// order as `items`.
+fn in_parallel<T, R, F>(items: &[T], threads: usize, min_per_thread: usize, f: F) -> Result<Vec<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R> + Sync,
+{
+    let chunk_size = items.len().div_ceil(threads).max(min_per_thread);
+    let f = &f;
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Result<Vec<R>>>()))
+            .collect();
+
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            results.extend(
+                handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("a worker thread panicked"))??,
+            );
+        }
+
+        Ok(results)
+    })
+}
+
+/// Hash `files` on another thread, in order, sending the hashes back a batch
+/// at a time so that jobs whose files come first can start before the rest
+/// are done. Stops after an error, or once nobody's listening.
+fn spawn_hashing(
+    files: Vec<PathBuf>,
+    file_hashes: FileHashes,
+    threads: usize,
+) -> mpsc::UnboundedReceiver<Result<Hashed>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let span = tracing::info_span!("hashing", files = files.len());
+
+    std::thread::spawn(move || {
+        let _hashing = span.entered();
+        let mut strategies = Strategies::new(file_hashes.last_build_started);
+        let mut warned_about_remembering = false;
+
+        for batch in files.chunks(FILES_PER_HASH_BATCH) {
+            let started = Instant::now();
+            let result = hash_batch(batch, &file_hashes, &mut strategies, threads);
+
+            let failed = result.is_err();
+            let result = result.map(|(hashes, hashed_any)| {
+                if hashed_any && !file_hashes.remember && !warned_about_remembering {
+                    tracing::info!("not saving file hashes, since an older version of rbt is still using them. See `--upgrade-db`.");
+                    warned_about_remembering = true;
+                }
+
+                Hashed {
+                    hashes,
+                    took: started.elapsed(),
+                }
+            });
+
+            if sender.send(result).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Get hashes for `files`: from the database if their metadata says they
+/// haven't changed, or by reading them if not. Also says whether we had to
+/// read any.
+fn hash_batch(
+    files: &[PathBuf],
+    file_hashes: &FileHashes,
+    strategies: &mut Strategies,
+    threads: usize,
+) -> Result<(Vec<(PathBuf, blake3::Hash)>, bool)> {
+    // Reading metadata is a syscall per file, which adds up in big
+    // projects, so we spread it across threads. Picking a strategy needs
+    // `&mut strategies` but is quick, so that part stays here.
+    let metas = in_parallel(files, threads, MIN_FILES_PER_THREAD, |input_file| {
+        // TODO: collect errors instead of bailing immediately
+        let meta = input_file
+            .metadata()
+            .with_context(|| format!("could not read metadata for `{}`", input_file.display()))?;
+
+        if meta.is_dir() {
+            anyhow::bail!(
+                "One of your jobs specifies `{}` as a dependency. It's a directory, but I can only handle files.",
+                input_file.display(),
+            )
+        };
+
+        Ok(meta)
+    })?;
+
+    let mut hashes = Vec::with_capacity(files.len());
+
+    // files we have to read, with the database key to remember the hash
+    // under (if we can trust the file's metadata enough to have one.) `None`
+    // means we can't trust the file's metadata at all and need to hash it
+    // every time.
+    let mut to_hash: Vec<(PathBuf, Option<Vec<u8>>)> = Vec::new();
+
+    for (path, meta) in files.iter().zip(metas) {
+        let strategy = strategies.for_path(path, &meta);
+        let cache_key = PathMetaKey::new(path, &meta, strategy)
+            .with_context(|| format!("could not calculate a cache key for `{}`", path.display()))?;
+
+        let key = cache_key
+            .as_ref()
+            .map(|cache_key| cache_key.to_db_key(path));
+        if let Some(value) = match key {
+            Some(ref key) => file_hashes
+                .meta_to_hash
+                .get(key)
+                .context("could not read file hash from database")?,
+            None => None,
+        } {
+            let bytes: [u8; 32] = value
+                .as_ref()
+                .try_into()
+                .context("value was not exactly 32 bytes")?;
+
+            hashes.push((path.clone(), blake3::Hash::from(bytes)));
+            continue;
+        }
+
+        to_hash.push((path.clone(), key));
+    }
+
+    let new_hashes = in_parallel(&to_hash, threads, MIN_HASHES_PER_THREAD, |(path, _)| {
+        hash_file(path)
+    })?;
+
+    // sled writes are much cheaper all at once than one at a time
+    let mut batch = sled::Batch::default();
+    let hashed_any = !to_hash.is_empty();
+    for ((path, key), hash) in to_hash.into_iter().zip(new_hashes) {
+        tracing::debug!("hash of `{}` was {}", path.display(), hash);
+        tracing::trace!("bytes of hash: {:?}", hash.as_bytes());
+        if let Some(key) = key {
+            batch.insert(key, hash.as_bytes());
+        }
+
+        hashes.push((path, hash));
+    }
+
+    if file_hashes.remember {
+        file_hashes
+            .meta_to_hash
+            .apply_batch(batch)
+            .context("could not write file hashes to database")?;
+    }
+
+    Ok((hashes, hashed_any))
+}
+
+fn hash_file(path: &Path) -> Result<blake3::Hash> {
+    let mut file = File::open(path)
+        .with_context(|| format!("couldn't open `{}` for hashing.", path.display()))?;
+
+    let mut hasher = blake3::Hasher::new();
+
+    // The docs for Blake3 say that a 16 KiB buffer is the most
+    // efficient (for SIMD reasons)
+    let mut buf = [0; 16 * 1024];
+    loop {
+        let bytes = file
+            .read(&mut buf)
+            .with_context(|| format!("couldn't read `{}` for hashing.", path.display()))?;
+        if bytes == 0 {
+            break;
+        }
+        hasher.update(&buf[0..bytes]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+/// Wait for the next batch of hashes from `spawn_hashing`, or `None` once
+/// it's done. Waits forever if it's not running.
+async fn next_hashes(
+    hashes: &mut Option<mpsc::UnboundedReceiver<Result<Hashed>>>,
+) -> Option<Result<Hashed>> {
+    match hashes {
+        Some(hashes) => hashes.recv().await,
+        None => futures::future::pending().await,
+    }
+}
+
+async fn next_deadline(deadline: &mut Option<Pin<Box<tokio::time::Sleep>>>) {
+    match deadline {
+        Some(sleep) => sleep.as_mut().await,
+        None => futures::future::pending().await,
+    }
+}
+
+async fn next_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_shortest_chains_from_roots_to_failures() {
+        let dependents = HashMap::from([
+            ("codegen", vec!["compile-worker", "types"]),
+            ("compile-worker", vec!["bundle"]),
+            ("types", vec!["docs", "bundle"]),
+            ("bundle", vec!["app"]),
+        ]);
+        let roots = HashSet::from(["app", "docs", "lint"]);
+
+        assert_eq!(
+            vec![
+                vec!["app", "bundle", "compile-worker", "codegen"],
+                vec!["docs", "types", "codegen"],
+            ],
+            failure_chains("codegen", &dependents, &roots)
+        );
+        assert_eq!(
+            vec![vec!["app", "bundle"]],
+            failure_chains("bundle", &dependents, &roots)
+        );
+        assert_eq!(
+            vec![vec!["app"]],
+            failure_chains("app", &dependents, &roots)
+        );
+    }
+
+    #[test]
+    fn in_parallel_keeps_order_and_errors() {
+        let numbers: Vec<usize> = (0..100).collect();
+
+        assert_eq!(
+            numbers.iter().map(|n| n * 2).collect::<Vec<_>>(),
+            in_parallel(&numbers, 4, 1, |n| Ok(n * 2)).unwrap()
+        );
+
+        assert!(in_parallel(&numbers, 4, 1, |n| if *n == 77 {
+            anyhow::bail!("no")
+        } else {
+            Ok(*n)
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn hashes_files_in_order_a_batch_at_a_time() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let files: Vec<PathBuf> = (0..FILES_PER_HASH_BATCH + 1)
+            .map(|n| {
+                let path = temp.path().join(n.to_string());
+                std::fs::write(&path, n.to_string()).unwrap();
+                path
+            })
+            .collect();
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let file_hashes = FileHashes {
+            meta_to_hash: db.open_tree("file_hashes").unwrap(),
+            last_build_started: None,
+            remember: true,
+        };
+
+        let mut hashes = spawn_hashing(files.clone(), file_hashes, 4);
+        let mut batches = Vec::new();
+        while let Some(batch) = hashes.recv().await {
+            batches.push(batch.unwrap().hashes);
+        }
+
+        assert_eq!(
+            vec![FILES_PER_HASH_BATCH, 1],
+            batches.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+
+        let hashed: HashMap<PathBuf, blake3::Hash> = batches.into_iter().flatten().collect();
+        let expected: HashMap<PathBuf, blake3::Hash> = files
+            .iter()
+            .map(|path| (path.clone(), hash_file(path).unwrap()))
+            .collect();
+        assert_eq!(expected, hashed);
+    }
+
+    #[tokio::test]
+    async fn stops_hashing_at_the_first_error() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let file_hashes = FileHashes {
+            meta_to_hash: db.open_tree("file_hashes").unwrap(),
+            last_build_started: None,
+            remember: true,
+        };
+
+        let mut hashes = spawn_hashing(vec![temp.path().join("missing")], file_hashes, 1);
+        assert!(hashes.recv().await.unwrap().is_err());
+        assert!(hashes.recv().await.is_none());
+    }
+
+    /// A job that runs `script` with `sh`, taking `inputs` and producing
+    /// `outputs`.
+    fn sh_job(script: &str, inputs: &[glue::U1], outputs: &[&str]) -> glue::Job {
+        use roc_std::{RocList, RocStr};
+
+        glue::Job::Job(glue::R1 {
+            inputs: RocList::from_slice(inputs),
+            outputs: outputs.iter().map(|output| RocStr::from(*output)).collect(),
+            ..glue::R1::for_test(job::system_command("sh", &["-c", script]))
+        })
+    }
+
+    /// A coordinator for `root`, keeping its store in `root_dir`. Give the
+    /// same `db` to see what earlier coordinators stored.
+    fn coordinator(root: &glue::Job, root_dir: &Path, db: &crate::db::Db) -> Coordinator {
+        builder(&[root], root_dir, db).build().unwrap()
+    }
+
+    /// Like `coordinator`, but for setting more options first.
+    fn builder<'roc>(
+        roots: &[&'roc glue::Job],
+        root_dir: &Path,
+        db: &crate::db::Db,
+    ) -> Builder<'roc> {
+        use crate::db::Tree;
+
+        let store = Store::new(
+            db.tree(Tree::Store).unwrap(),
+            db.tree(Tree::StoreAccess).unwrap(),
+            db.tree(Tree::StoreRetention).unwrap(),
+            db.tree(Tree::StoreIntents).unwrap(),
+            db.tree(Tree::StoreFileTypes).unwrap(),
+            root_dir.join("store"),
+        )
+        .unwrap();
+
+        let mut builder = Builder::new(
+            store,
+            db.tree(Tree::FileHashes).unwrap(),
+            root_dir.to_path_buf(),
+            NonZeroUsize::new(roots.len()).unwrap(),
+            Resources::new(&[]),
+            Pauser::new(root_dir.join("paused.json"), false),
+        );
+        builder.show_job_output(false);
+        for root in roots {
+            builder.add_root(root);
+        }
+        builder
+    }
+
+    fn timed_out(err: &anyhow::Error) -> bool {
+        err.chain().any(|err| err.is::<TimedOut>())
+    }
+
+    /// A job that counts its runs in `runs` and writes `out`, and one that
+    /// copies `out` from it. The first one re-runs when its outputs go
+    /// missing if `rerun` is set.
+    fn producer_and_consumer(runs: &Path, rerun: bool) -> (glue::Job, glue::Job) {
+        let script = format!("echo run >> '{}'; echo hi > out", runs.display());
+        let producer = glue::Job::Job(glue::R1 {
+            outputs: roc_std::RocList::from_slice(&["out".into()]),
+            rerunOnMissingOutputs: rerun,
+            ..glue::R1::for_test(job::system_command("sh", &["-c", &script]))
+        });
+        let consumer = sh_job(
+            "cat out > copy",
+            &[glue::U1::FromJob(
+                producer.clone(),
+                roc_std::RocList::from_slice(&[glue::FileMapping {
+                    source: "out".into(),
+                    dest: "out".into(),
+                }]),
+            )],
+            &["copy"],
+        );
+
+        (producer, consumer)
+    }
+
+    fn count_runs(runs: &Path) -> usize {
+        std::fs::read_to_string(runs).unwrap().lines().count()
+    }
+
+    #[tokio::test]
+    async fn reruns_producers_whose_outputs_went_missing() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = crate::db::Db::temporary();
+        let runs = temp.path().join("runs");
+        let (producer, consumer) = producer_and_consumer(&runs, true);
+
+        let mut building_producer = coordinator(&producer, temp.path(), &db);
+        building_producer.run().await.unwrap();
+        let item = building_producer
+            .store_path(&building_producer.roots()[0])
+            .unwrap()
+            .path()
+            .clone();
+        assert_eq!(1, count_runs(&runs));
+
+        delete_by_hand(&item.join("out"));
+
+        let mut building_consumer = coordinator(&consumer, temp.path(), &db);
+        building_consumer.run().await.unwrap();
+
+        assert_eq!(2, count_runs(&runs));
+        assert_eq!("hi\n", std::fs::read_to_string(item.join("out")).unwrap());
+
+        let root = building_consumer.roots()[0];
+        let copied = building_consumer.store_path(&root).unwrap().join("copy");
+        assert_eq!("hi\n", std::fs::read_to_string(copied).unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_reruns_producers_that_asked_for_it() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = crate::db::Db::temporary();
+        let runs = temp.path().join("runs");
+        let (producer, consumer) = producer_and_consumer(&runs, false);
+
+        let mut building_producer = coordinator(&producer, temp.path(), &db);
+        building_producer.run().await.unwrap();
+        let item = building_producer
+            .store_path(&building_producer.roots()[0])
+            .unwrap()
+            .path()
+            .clone();
+        delete_by_hand(&item.join("out"));
+
+        let mut building_consumer = coordinator(&consumer, temp.path(), &db);
+        let err = building_consumer.run().await.unwrap_err();
+
+        assert!(
+            format!("{:?}", err).contains("rerunOnMissingOutputs"),
+            "{:?}",
+            err
+        );
+        assert_eq!(1, count_runs(&runs));
+    }
+
+    /// Build `roots` side by side, one coordinator each, on their own
+    /// threads and runtimes like the daemon does, sharing a database and
+    /// what they're running.
+    fn build_side_by_side(
+        roots: &[&glue::Job],
+        root_dir: &Path,
+        db: &crate::db::Db,
+    ) -> Vec<(Result<()>, BuildStats)> {
+        let in_flight = InFlight::default();
+
+        std::thread::scope(|scope| {
+            let builds: Vec<_> = roots
+                .iter()
+                .map(|root| {
+                    let mut builder = builder(&[root], root_dir, db);
+                    builder.in_flight(in_flight.clone());
+                    let mut coordinator = builder.build().unwrap();
+
+                    scope.spawn(move || {
+                        let runtime = tokio::runtime::Runtime::new().unwrap();
+                        let result = runtime.block_on(coordinator.run());
+                        (result, coordinator.stats().clone())
+                    })
+                })
+                .collect();
+
+            builds
+                .into_iter()
+                .map(|build| build.join().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn attaches_to_jobs_another_build_is_running() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = crate::db::Db::temporary();
+        let runs = temp.path().join("runs");
+
+        // slow enough that the second build goes to start it while the
+        // first is still running it
+        let script = format!("echo run >> '{}'; sleep 1; echo hi > out", runs.display());
+        let producer = sh_job(&script, &[], &["out"]);
+        let from_producer = || {
+            glue::U1::FromJob(
+                producer.clone(),
+                roc_std::RocList::from_slice(&[glue::FileMapping {
+                    source: "out".into(),
+                    dest: "out".into(),
+                }]),
+            )
+        };
+        let copier = sh_job("cat out > copy", &[from_producer()], &["copy"]);
+        let counter = sh_job("wc -c < out > count", &[from_producer()], &["count"]);
+
+        let builds = build_side_by_side(&[&copier, &counter], temp.path(), &db);
+
+        for (result, _) in &builds {
+            result.as_ref().unwrap();
+        }
+        assert_eq!(1, count_runs(&runs));
+        assert_eq!(
+            1,
+            builds
+                .iter()
+                .map(|(_, stats)| stats.cache_hits)
+                .sum::<usize>(),
+            "one build should have taken the producer's output from the other"
+        );
+    }
+
+    #[test]
+    fn runs_jobs_itself_when_the_build_it_attached_to_fails() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = crate::db::Db::temporary();
+        let runs = temp.path().join("runs");
+
+        // fails the first time, and works after that
+        let script = format!(
+            "echo run >> '{runs}'; sleep 1; test $(wc -l < '{runs}') -gt 1 || exit 1; echo hi > out",
+            runs = runs.display()
+        );
+        let producer = sh_job(&script, &[], &["out"]);
+        let other_producer = producer.clone();
+
+        let builds = build_side_by_side(&[&producer, &other_producer], temp.path(), &db);
+
+        assert_eq!(
+            1,
+            builds.iter().filter(|(result, _)| result.is_err()).count(),
+            "only the build that ran the job first should have failed"
+        );
+        assert_eq!(2, count_runs(&runs));
+    }
+
+    /// Delete `file` from a store item, the way someone tidying up by hand
+    /// might.
+    fn delete_by_hand(file: &Path) {
+        let item = file.parent().unwrap();
+        let mut perms = std::fs::metadata(item).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        std::fs::set_permissions(item, perms).unwrap();
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_outputs_go_missing_after_a_rerun() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = crate::db::Db::temporary();
+        let (_, consumer) = producer_and_consumer(&temp.path().join("runs"), true);
+
+        let mut coordinator = coordinator(&consumer, temp.path(), &db);
+        coordinator.run().await.unwrap();
+
+        let root = coordinator.roots()[0];
+        let dep = *coordinator
+            .job(&root)
+            .unwrap()
+            .input_jobs
+            .keys()
+            .next()
+            .unwrap();
+        let final_key = coordinator.final_keys[&dep];
+
+        // the first miss re-runs the producer...
+        let rerun = coordinator.store.item_for_job(&final_key).unwrap().unwrap();
+        coordinator.rerun_dependencies(root, vec![dep]).unwrap();
+        assert!(coordinator.ready.contains(&dep));
+
+        // ...but if its outputs are still missing once it's done, we don't
+        // try again.
+        coordinator.job_to_content_hash.insert(dep, rerun);
+        let err = coordinator.rerun_dependencies(root, vec![dep]).unwrap_err();
+        assert!(err.to_string().contains("still missing"), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn times_out_by_cutting_running_jobs_short() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = crate::db::Db::temporary();
+        let slow = sh_job("sleep 30; echo hi > out", &[], &["out"]);
+
+        let mut builder = builder(&[&slow], temp.path(), &db);
+        builder.timeout(Some(Duration::from_millis(200)));
+        let mut coordinator = builder.build().unwrap();
+        let mut events = coordinator.subscribe();
+
+        let started = Instant::now();
+        let err = coordinator.run().await.unwrap_err();
+
+        assert!(timed_out(&err), "{:?}", err);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let mut cut_short = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let Event::JobCutShort { job, .. } = event {
+                cut_short.push(job.key);
+            }
+        }
+        assert_eq!(vec![coordinator.roots()[0].to_string()], cut_short);
+    }
+
+    #[tokio::test]
+    async fn failures_win_over_timing_out() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let db = crate::db::Db::temporary();
+        let slow = sh_job("sleep 30; echo hi > out", &[], &["out"]);
+        let failing = sh_job("exit 1", &[], &["out"]);
+
+        // keep going, so the failure doesn't stop the slow job before we
+        // run out of time
+        let mut builder = builder(&[&slow, &failing], temp.path(), &db);
+        builder.timeout(Some(Duration::from_millis(500)));
+        builder.keep_going(true);
+        let mut coordinator = builder.build().unwrap();
+
+        let err = coordinator.run().await.unwrap_err();
+
+        assert!(!timed_out(&err), "{:?}", err);
+        assert!(err.to_string().contains("failure"), "{:?}", err);
+    }
+}
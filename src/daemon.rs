@@ -0,0 +1,600 @@
+#[cfg(unix)]
+use crate::in_flight::InFlight;
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Where in the root dir `rbt daemon` listens for builds.
+pub const SOCKET: &str = "daemon.sock";
+
+/// A build someone asked the daemon for: the arguments they ran rbt with,
+/// and where they ran it (which has to be where the daemon is, since jobs
+/// read project files relative to it.)
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Request {
+    pub args: Vec<String>,
+    pub cwd: PathBuf,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Response {
+    /// What a query printed, for the client to print instead.
+    #[serde(default)]
+    output: String,
+
+    /// What went wrong, with all its context, if anything did.
+    error: Option<String>,
+}
+
+/// How long the daemon waits for requests before it stops, so one that
+/// nobody's using gives back its memory (and the database.) Laptops on
+/// battery can have a shorter timeout than everyone else.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdleTimeout {
+    /// `None` waits forever.
+    pub always: Option<Duration>,
+    pub on_battery: Option<Duration>,
+}
+
+#[cfg(unix)]
+impl IdleTimeout {
+    /// How often we look at the power supply when only the battery timeout
+    /// is set. Linux can tell us about changes through udev, but that's a
+    /// lot of machinery for something that only matters minutes later.
+    const POWER_CHECK_EVERY: Duration = Duration::from_secs(5 * 60);
+
+    /// The timeout that applies right now, and how long until we should
+    /// look again in case that changes.
+    fn current(&self) -> (Option<Duration>, Option<Duration>) {
+        match self.on_battery {
+            Some(on_battery) if on_battery_in(Path::new(POWER_SUPPLIES)) => {
+                let timeout = self
+                    .always
+                    .map_or(on_battery, |always| always.min(on_battery));
+                (Some(timeout), Some(Self::POWER_CHECK_EVERY))
+            }
+            Some(_) => (self.always, Some(Self::POWER_CHECK_EVERY)),
+            None => (self.always, None),
+        }
+    }
+}
+
+/// Where Linux lists power supplies. Other platforms don't have it, so they
+/// never look like they're on battery.
+#[cfg(unix)]
+const POWER_SUPPLIES: &str = "/sys/class/power_supply";
+
+/// Whether the machine is running on battery: it has one, and none of its
+/// mains supplies (the charger) are plugged in. Desktops and servers have
+/// no battery, so they never are.
+#[cfg(unix)]
+fn on_battery_in(supplies: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(supplies) else {
+        return false;
+    };
+
+    let read = |path: PathBuf| std::fs::read_to_string(path).unwrap_or_default();
+    let mut has_battery = false;
+    for entry in entries.flatten() {
+        match read(entry.path().join("type")).trim() {
+            "Mains" if read(entry.path().join("online")).trim() == "1" => return false,
+            "Battery" => has_battery = true,
+            _ => {}
+        }
+    }
+
+    has_battery
+}
+
+/// What the daemon is up to, for `rbt daemon status`.
+#[cfg(unix)]
+#[derive(Debug)]
+struct Activity {
+    started: Instant,
+    state: Mutex<ActivityState>,
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+struct ActivityState {
+    answering: usize,
+    answered: u64,
+
+    /// When we last finished answering anyone (or started, if nobody's
+    /// asked for anything yet.)
+    idle_since: Instant,
+}
+
+#[cfg(unix)]
+impl Activity {
+    fn new() -> Self {
+        let now = Instant::now();
+        Activity {
+            started: now,
+            state: Mutex::new(ActivityState {
+                answering: 0,
+                answered: 0,
+                idle_since: now,
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ActivityState> {
+        // the counts are still right if an answer panicked, since `Busy`
+        // updates them when it's dropped.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn begin(self: &Arc<Self>) -> Busy {
+        self.state().answering += 1;
+        Busy(self.clone())
+    }
+
+    /// How long nobody's been asking for anything, or `None` if someone is
+    /// waiting for an answer right now.
+    fn idle_for(&self) -> Option<Duration> {
+        let state = self.state();
+        (state.answering == 0).then(|| state.idle_since.elapsed())
+    }
+}
+
+/// Marks the daemon busy for as long as it's held.
+#[cfg(unix)]
+#[derive(Debug)]
+struct Busy(Arc<Activity>);
+
+#[cfg(unix)]
+impl Drop for Busy {
+    fn drop(&mut self) {
+        let mut state = self.0.state();
+        state.answering -= 1;
+        state.answered += 1;
+        state.idle_since = Instant::now();
+    }
+}
+
+/// What `rbt daemon status` shows.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub pid: u32,
+    pub uptime: Duration,
+
+    /// Requests we're working on, including the one asking for this.
+    pub answering: usize,
+    pub answered: u64,
+    pub idle_timeout: IdleTimeout,
+    pub on_battery: bool,
+
+    /// CPU time the daemon has used since it started, across all threads.
+    pub cpu_time: Duration,
+
+    /// The most memory the daemon has had resident at once, in bytes.
+    pub peak_memory: u64,
+}
+
+/// Listens for requests on a Unix socket, one JSON line each way per
+/// connection. Each connection gets answered on its own thread (see
+/// `Connection`), so builds run side by side. They share `in_flight`, so a
+/// job one of them is running doesn't run again for another.
+#[cfg(unix)]
+pub struct Daemon {
+    listener: UnixListener,
+    path: PathBuf,
+    idle_timeout: IdleTimeout,
+    activity: Arc<Activity>,
+    in_flight: InFlight,
+}
+
+#[cfg(unix)]
+impl Daemon {
+    pub fn bind(root_dir: &Path, idle_timeout: IdleTimeout) -> Result<Self> {
+        let path = root_dir.join(SOCKET);
+
+        // a daemon that's still around would answer, and one that crashed
+        // leaves the socket file behind.
+        if UnixStream::connect(&path).is_ok() {
+            anyhow::bail!("there's already a daemon listening on `{}`", path.display())
+        }
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("could not remove stale `{}`", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("could not listen on `{}`", path.display()))?;
+
+        Ok(Daemon {
+            listener,
+            path,
+            idle_timeout,
+            activity: Arc::new(Activity::new()),
+            in_flight: InFlight::default(),
+        })
+    }
+
+    /// The jobs the builds we're answering are running, for every build's
+    /// coordinator to check before starting one.
+    pub fn in_flight(&self) -> &InFlight {
+        &self.in_flight
+    }
+
+    /// Wait for the next client that asks for something, or `None` once
+    /// nobody has for the idle timeout. We never time out while answering
+    /// someone, however long their build takes.
+    pub fn accept(&self) -> Result<Option<Connection>> {
+        loop {
+            let (timeout, check_again) = self.idle_timeout.current();
+            let wait = match (timeout, self.activity.idle_for()) {
+                (Some(timeout), Some(idle)) if idle >= timeout => return Ok(None),
+                (Some(timeout), Some(idle)) => Some(timeout - idle),
+                // when the current answer finishes, we start counting again
+                (Some(timeout), None) => Some(timeout),
+                (None, _) => None,
+            };
+            let wait = match (wait, check_again) {
+                (Some(wait), Some(check)) => Some(wait.min(check)),
+                (wait, check) => wait.or(check),
+            };
+
+            if !self.wait_for_client(wait)? {
+                continue;
+            }
+
+            let (stream, _) = self
+                .listener
+                .accept()
+                .context("could not accept a connection")?;
+
+            let mut line = String::new();
+            if let Err(problem) = BufReader::new(&stream).read_line(&mut line) {
+                tracing::warn!("could not read a request: {}", problem);
+                continue;
+            }
+
+            // `bind` checking for a running daemon connects without asking
+            // for anything.
+            if line.is_empty() {
+                continue;
+            }
+
+            return Ok(Some(Connection {
+                stream,
+                line,
+                _busy: self.activity.begin(),
+            }));
+        }
+    }
+
+    /// Block until a client connects (returning `true`) or `wait` passes.
+    /// This sleeps in `poll`, so a daemon that nobody's using doesn't wake
+    /// up until it's time to check the timeout.
+    fn wait_for_client(&self, wait: Option<Duration>) -> Result<bool> {
+        use std::os::unix::io::AsRawFd;
+
+        let Some(wait) = wait else {
+            return Ok(true);
+        };
+
+        let mut fd = libc::pollfd {
+            fd: self.listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // rounding up, so we don't spin for the last fraction of a
+        // millisecond.
+        let millis = wait.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+
+        // SAFETY: `fd` is one valid pollfd, and we say so.
+        match unsafe { libc::poll(&mut fd, 1, millis) } {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(err).context("could not wait for a connection")
+                }
+            }
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        let (answering, answered) = {
+            let state = self.activity.state();
+            (state.answering, state.answered)
+        };
+
+        // SAFETY: getrusage only writes to the struct we give it.
+        let usage = unsafe {
+            let mut usage: libc::rusage = std::mem::zeroed();
+            libc::getrusage(libc::RUSAGE_SELF, &mut usage);
+            usage
+        };
+        let time = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+        };
+
+        // Linux counts in kilobytes, macOS in bytes
+        let max_rss = usage.ru_maxrss as u64;
+        let peak_memory = if cfg!(target_os = "macos") {
+            max_rss
+        } else {
+            max_rss * 1024
+        };
+
+        Status {
+            pid: std::process::id(),
+            uptime: self.activity.started.elapsed(),
+            answering,
+            answered,
+            idle_timeout: self.idle_timeout,
+            on_battery: on_battery_in(Path::new(POWER_SUPPLIES)),
+            cpu_time: time(usage.ru_utime) + time(usage.ru_stime),
+            peak_memory,
+        }
+    }
+}
+
+/// A client waiting for an answer. Answering can take as long as a whole
+/// build, so the daemon does it on another thread and goes back to
+/// accepting connections, which is what lets queries like `rbt --daemon
+/// stats` get answered while a build is running.
+#[cfg(unix)]
+pub struct Connection {
+    stream: UnixStream,
+    line: String,
+
+    // we're busy until the connection is answered and dropped
+    _busy: Busy,
+}
+
+#[cfg(unix)]
+impl Connection {
+    /// Answer the request with whatever `handle` prints (or the error it
+    /// returns.) Problems talking to the client only end this request, so
+    /// they're logged instead of returned.
+    pub fn answer(self, handle: impl FnOnce(Request) -> Result<String>) {
+        if let Err(problem) = self.try_answer(handle) {
+            tracing::warn!("could not answer a request: {:?}", problem);
+        }
+    }
+
+    fn try_answer(self, handle: impl FnOnce(Request) -> Result<String>) -> Result<()> {
+        let request: Request =
+            serde_json::from_str(&self.line).context("could not parse request")?;
+
+        let response = match handle(request) {
+            Ok(output) => Response {
+                output,
+                error: None,
+            },
+            Err(err) => Response {
+                output: String::new(),
+                error: Some(format!("{:?}", err)),
+            },
+        };
+
+        let mut out = serde_json::to_vec(&response).context("could not serialize response")?;
+        out.push(b'\n');
+        (&self.stream)
+            .write_all(&out)
+            .context("could not send response")
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        // nothing to be done if it's already gone
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Ask the daemon in `root_dir` for `request`, waiting until it's done.
+/// A build's log stays with the daemon; we only get whether it worked, and
+/// what a query printed.
+#[cfg(unix)]
+pub fn send(root_dir: &Path, request: &Request) -> Result<String> {
+    let path = root_dir.join(SOCKET);
+    let stream = UnixStream::connect(&path).with_context(|| {
+        format!(
+            "could not connect to `{}`. Is `rbt daemon` running?",
+            path.display()
+        )
+    })?;
+
+    let mut out = serde_json::to_vec(request).context("could not serialize request")?;
+    out.push(b'\n');
+    (&stream)
+        .write_all(&out)
+        .context("could not send request")?;
+
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .context("could not read response")?;
+    let response: Response =
+        serde_json::from_str(&line).context("the daemon stopped before answering")?;
+
+    match response.error {
+        Some(error) => Err(anyhow::anyhow!(error)),
+        None => Ok(response.output),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send(_root_dir: &Path, _request: &Request) -> Result<String> {
+    anyhow::bail!("the daemon only runs on Unix-like systems so far")
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn answers_requests() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let daemon = Daemon::bind(temp.path(), IdleTimeout::default()).unwrap();
+        assert!(Daemon::bind(temp.path(), IdleTimeout::default()).is_err());
+
+        let root_dir = temp.path().to_path_buf();
+        let client = std::thread::spawn(move || {
+            let ok = send(
+                &root_dir,
+                &Request {
+                    args: vec!["build".to_string(), "app".to_string()],
+                    cwd: "/project".into(),
+                },
+            );
+            let failed = send(
+                &root_dir,
+                &Request {
+                    args: Vec::new(),
+                    cwd: "/project".into(),
+                },
+            );
+
+            (ok, failed)
+        });
+
+        daemon.accept().unwrap().unwrap().answer(|request| {
+            assert_eq!(vec!["build", "app"], request.args);
+            Ok("built\n".to_string())
+        });
+        daemon
+            .accept()
+            .unwrap()
+            .unwrap()
+            .answer(|_| Err(anyhow::anyhow!("no targets")));
+
+        let (ok, failed) = client.join().unwrap();
+        assert_eq!("built\n", ok.unwrap());
+        assert_eq!("no targets", failed.unwrap_err().to_string());
+
+        drop(daemon);
+        assert!(!temp.path().join(SOCKET).exists());
+    }
+
+    fn status_request() -> Request {
+        Request {
+            args: vec!["daemon".to_string(), "status".to_string()],
+            cwd: "/project".into(),
+        }
+    }
+
+    #[test]
+    fn stops_after_being_idle_but_not_while_answering() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let timeout = Duration::from_millis(100);
+        let daemon = Daemon::bind(
+            temp.path(),
+            IdleTimeout {
+                always: Some(timeout),
+                on_battery: None,
+            },
+        )
+        .unwrap();
+
+        let root_dir = temp.path().to_path_buf();
+        let client = std::thread::spawn(move || send(&root_dir, &status_request()));
+
+        // a slow build keeps us from timing out, however long it takes
+        let connection = daemon.accept().unwrap().unwrap();
+        let answering = std::thread::spawn(move || {
+            connection.answer(|_| {
+                std::thread::sleep(timeout * 3);
+                Ok(String::new())
+            })
+        });
+        let waiting = Instant::now();
+        assert!(daemon.accept().unwrap().is_none());
+        assert!(waiting.elapsed() >= timeout * 3);
+
+        answering.join().unwrap();
+        client.join().unwrap().unwrap();
+        assert_eq!(1, daemon.status().answered);
+    }
+
+    #[test]
+    fn waits_forever_without_a_timeout() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let daemon = Daemon::bind(temp.path(), IdleTimeout::default()).unwrap();
+
+        let root_dir = temp.path().to_path_buf();
+        let client = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            send(&root_dir, &status_request())
+        });
+
+        daemon
+            .accept()
+            .unwrap()
+            .expect("should not have timed out")
+            .answer(|_| Ok(String::new()));
+        client.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn reports_status() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let daemon = Arc::new(Daemon::bind(temp.path(), IdleTimeout::default()).unwrap());
+
+        let root_dir = temp.path().to_path_buf();
+        let client = std::thread::spawn(move || {
+            send(&root_dir, &status_request()).unwrap();
+            send(&root_dir, &status_request()).unwrap()
+        });
+
+        daemon
+            .accept()
+            .unwrap()
+            .unwrap()
+            .answer(|_| Ok(String::new()));
+        let status = daemon.clone();
+        daemon.accept().unwrap().unwrap().answer(move |_| {
+            let status = status.status();
+            Ok(format!("{} {}", status.answering, status.answered))
+        });
+
+        // the request asking counts as one we're answering
+        assert_eq!("1 1", client.join().unwrap());
+
+        let status = daemon.status();
+        assert_eq!(std::process::id(), status.pid);
+        assert_eq!(0, status.answering);
+        assert_eq!(2, status.answered);
+        assert!(status.peak_memory > 0);
+    }
+
+    #[test]
+    fn knows_when_on_battery() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let supply = |name: &str, kind: &str, online: &str| {
+            let dir = temp.path().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("type"), format!("{}\n", kind)).unwrap();
+            std::fs::write(dir.join("online"), format!("{}\n", online)).unwrap();
+        };
+
+        // a desktop, with no battery at all
+        supply("AC", "Mains", "0");
+        assert!(!on_battery_in(temp.path()));
+
+        supply("BAT0", "Battery", "1");
+        assert!(on_battery_in(temp.path()));
+
+        supply("AC", "Mains", "1");
+        assert!(!on_battery_in(temp.path()));
+
+        assert!(!on_battery_in(&temp.path().join("missing")));
+    }
+}
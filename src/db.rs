@@ -0,0 +1,658 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// rbt's database. Every subsystem that keeps state between builds gets its
+/// own sled tree, and every tree is listed in `Tree` below. Going through
+/// here instead of calling `open_tree` with a string means two subsystems
+/// can't end up sharing a tree by accident, and each tree's layout has a
+/// version we can check before trusting what's in it.
+#[derive(Debug)]
+pub struct Db {
+    db: sled::Db,
+
+    // whether we may take over trees that older versions of rbt can still
+    // use, leaving them unable to.
+    upgrade: bool,
+}
+
+/// The trees in the database, and what's in each. If you change what a
+/// tree's keys or values mean, bump its `version` in `layout`, think about
+/// which older layouts are still compatible, and add a step to `MIGRATIONS`
+/// if the old entries are worth converting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tree {
+    /// final job key (`Key::to_db_key`) -> store item hash, as hex
+    Store,
+
+    /// store item hash (32 bytes) -> day it was last used, as a
+    /// little-endian `u64` of days since the epoch
+    StoreAccess,
+
+    /// path metadata key (`PathMetaKey::to_db_key`, grouped by top-level
+    /// directory) -> blake3 hash of the file's contents (32 bytes)
+    FileHashes,
+
+    /// store item hash (32 bytes) -> how many days GC should keep the item
+    /// after it was last used, as a little-endian `u64` (`u64::MAX` means
+    /// forever.) Items without an entry get GC's default.
+    StoreRetention,
+
+    /// final job key (`Key::to_db_key`) -> store item hash, as hex, for
+    /// items we've started moving into the store but haven't recorded in
+    /// `Store` yet. See `Store::recover`.
+    StoreIntents,
+
+    /// store item hash (32 bytes) -> JSON object of each file's path in the
+    /// item to its `FileType`
+    StoreFileTypes,
+
+    /// base job key (`Key::to_db_key`) followed by when the check started,
+    /// as a big-endian `u64` of milliseconds since the epoch -> JSON
+    /// `FlakeCheck`
+    FlakeChecks,
+}
+
+impl Tree {
+    pub const ALL: [Tree; 7] = [
+        Tree::Store,
+        Tree::StoreAccess,
+        Tree::FileHashes,
+        Tree::StoreRetention,
+        Tree::StoreIntents,
+        Tree::StoreFileTypes,
+        Tree::FlakeChecks,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tree::Store => "store",
+            Tree::StoreAccess => "store_access",
+            Tree::FileHashes => "subtree_file_hashes",
+            Tree::StoreRetention => "store_retention",
+            Tree::StoreIntents => "store_intents",
+            Tree::StoreFileTypes => "store_file_types",
+            Tree::FlakeChecks => "flake_checks",
+        }
+    }
+
+    fn layout(self) -> Layout {
+        match self {
+            Tree::Store => Layout::new(1),
+            Tree::StoreAccess => Layout::new(1),
+            Tree::FileHashes => Layout::new(1),
+            Tree::StoreRetention => Layout::new(1),
+            Tree::StoreIntents => Layout::new(1),
+            Tree::StoreFileTypes => Layout::new(1),
+            Tree::FlakeChecks => Layout::new(1),
+        }
+    }
+}
+
+/// How many times, and how long apart, to try again when the database is
+/// locked. Together these cover sled's flusher winding down, but not
+/// another rbt holding the database for a whole build.
+const LOCK_RETRIES: usize = 20;
+const LOCK_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Trees we used to keep, and have replaced with a new tree instead of a new
+/// layout (usually because they're caches, and starting over is cheaper than
+/// converting.) Older versions of rbt may still use these, so we only remove
+/// them when asked to clean up.
+const RETIRED_TREES: &[&str] = &[
+    // keys weren't grouped by directory (now `Tree::FileHashes`)
+    "file_hashes",
+];
+
+/// Converts a tree's entries from one layout to the next, in place.
+type Migration = fn(&sled::Tree) -> Result<()>;
+
+/// How to get each tree from a layout we can't read any more to the next
+/// one: `(tree, from, step)` takes `tree` from layout `from` to `from + 1`.
+/// When we open a tree with an older layout, we run every step up to ours,
+/// as long as there's one for each version in between. We record the new
+/// version after each step, so a step has to cope with being run again on
+/// a tree it only got partway through.
+const MIGRATIONS: &[(Tree, u64, Migration)] = &[];
+
+/// Which layout of a tree this version of rbt uses, and how it gets along
+/// with other versions. Teams don't all upgrade rbt on the same day, so the
+/// same `.rbt` directory can see several versions over its life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    /// What we write.
+    version: u64,
+
+    /// The oldest layout we can read as if it were ours.
+    readable_since: u64,
+
+    /// The oldest layout whose readers can still use the tree after we've
+    /// written to it. We store this next to the version, so older versions
+    /// of rbt can tell whether to keep using the tree.
+    compatible_since: u64,
+}
+
+impl Layout {
+    /// A layout that only gets along with itself.
+    const fn new(version: u64) -> Self {
+        Layout {
+            version,
+            readable_since: version,
+            compatible_since: version,
+        }
+    }
+}
+
+/// How we can use a tree, given who wrote it last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadWrite,
+
+    /// We can read the tree, but writing to it would leave the older version
+    /// of rbt that wrote it unable to.
+    ReadOnly,
+}
+
+/// Decide how we can use a tree that was last written with `stored` (its
+/// version and compatible-since, if we know them) when we use `ours`.
+fn access(tree: Tree, stored: Option<(u64, u64)>, ours: Layout) -> Result<Access> {
+    let (version, compatible_since) = match stored {
+        Some(stored) => stored,
+        None => return Ok(Access::ReadWrite),
+    };
+
+    if version > ours.version {
+        // a newer rbt wrote this. It tells us whether we can still use it.
+        if compatible_since <= ours.version {
+            return Ok(Access::ReadWrite);
+        }
+
+        anyhow::bail!(
+            "the `{}` database was written by a newer version of rbt (layout {}, which needs at least layout {} to use), but I only understand layout {}. Please upgrade rbt.",
+            tree.name(),
+            version,
+            compatible_since,
+            ours.version,
+        )
+    }
+
+    if version < ours.readable_since {
+        anyhow::bail!(
+            "the `{}` database was written by an older version of rbt (layout {}), but I can only read layout {} or later. Please use the older version, or remove the database to start over.",
+            tree.name(),
+            version,
+            ours.readable_since,
+        )
+    }
+
+    if version < ours.compatible_since {
+        Ok(Access::ReadOnly)
+    } else {
+        Ok(Access::ReadWrite)
+    }
+}
+
+/// Keys in the default tree, which holds facts about the database itself.
+/// Everything that goes in there is listed here so keys can't collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Meta {
+    /// `job::KEY_FORMAT_VERSION` as of the last build. This predates the
+    /// registry, which is why it isn't namespaced like the others.
+    KeyFormatVersion,
+
+    /// The layout version of a tree, as of the last time we opened it.
+    TreeVersion(Tree),
+
+    /// The oldest layout that can still use a tree (see `Layout`.) Trees
+    /// from before we tracked this only get along with their own version.
+    TreeCompatibleSince(Tree),
+
+    /// When the last build started, in seconds since the Unix epoch. Used
+    /// to notice the clock jumping backwards.
+    LastBuildStarted,
+}
+
+impl Meta {
+    fn key(self) -> String {
+        match self {
+            Meta::KeyFormatVersion => "key_format_version".to_string(),
+            Meta::TreeVersion(tree) => format!("tree_version/{}", tree.name()),
+            Meta::TreeCompatibleSince(tree) => {
+                format!("tree_compatible_since/{}", tree.name())
+            }
+            Meta::LastBuildStarted => "last_build_started".to_string(),
+        }
+    }
+}
+
+impl Db {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut attempts = 0;
+
+        loop {
+            match sled::Config::default()
+                .path(path)
+                .mode(sled::Mode::HighThroughput)
+                .open()
+            {
+                Ok(db) => return Ok(Db { db, upgrade: false }),
+
+                // sled's flusher thread holds on to the lock for a moment
+                // after the last handle is dropped, so opening a database
+                // right after closing it in the same process (like
+                // compaction does) can find it still locked.
+                //
+                // sled only tells us it was the lock in the message.
+                Err(sled::Error::Io(err))
+                    if err.to_string().starts_with("could not acquire lock")
+                        && attempts < LOCK_RETRIES =>
+                {
+                    attempts += 1;
+                    std::thread::sleep(LOCK_RETRY_DELAY);
+                }
+
+                // only one process can have the database open, so there's
+                // probably a build going on.
+                Err(sled::Error::Io(err)) if err.to_string().starts_with("could not acquire lock") => {
+                    return Err(err).context("could not open sled database, because another rbt is using it. To ask about the database while building, run builds with `rbt daemon` and `--daemon`.")
+                }
+
+                Err(err) => return Err(err).context("could not open sled database"),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn temporary() -> Self {
+        Db {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+            upgrade: false,
+        }
+    }
+
+    /// Take over trees that an older version of rbt could otherwise keep
+    /// using. Only do this once nobody needs the older version any more!
+    pub fn allow_upgrades(&mut self) {
+        self.upgrade = true;
+    }
+
+    /// Open a tree we need to write to, making sure we get along with the
+    /// version of rbt that wrote it last.
+    pub fn tree(&self, tree: Tree) -> Result<sled::Tree> {
+        match self.tree_with_access(tree)? {
+            (opened, Access::ReadWrite) => Ok(opened),
+            (_, Access::ReadOnly) => anyhow::bail!(
+                "the `{}` database was written by an older version of rbt, which wouldn't be able to use it after I wrote to it. Once nobody needs the older version, run me with `--upgrade-db`.",
+                tree.name(),
+            ),
+        }
+    }
+
+    /// Open a tree, finding out whether we can write to it. Callers that can
+    /// get by without writing (like caches) should use this instead of
+    /// `tree`, so people can keep building while they upgrade. Trees we
+    /// haven't seen before (including ones from before we tracked versions)
+    /// are assumed to be current.
+    pub fn tree_with_access(&self, tree: Tree) -> Result<(sled::Tree, Access)> {
+        let ours = tree.layout();
+
+        let stored = match self.meta_u64(Meta::TreeVersion(tree))? {
+            Some(version) if version < ours.readable_since => {
+                Some(self.migrate(tree, version, ours, MIGRATIONS)?)
+            }
+            Some(version) => Some((
+                version,
+                self.meta_u64(Meta::TreeCompatibleSince(tree))?
+                    .unwrap_or(version),
+            )),
+            None => None,
+        };
+
+        let access = match access(tree, stored, ours)? {
+            Access::ReadOnly if self.upgrade => {
+                tracing::warn!(
+                    "upgrading the `{}` database, so older versions of rbt won't be able to use it",
+                    tree.name()
+                );
+                Access::ReadWrite
+            }
+            access => access,
+        };
+
+        // we only take over the stamps if we'd write the same or a newer
+        // layout. A newer rbt's stamps already say what it gets along with.
+        let newer = matches!(stored, Some((version, _)) if version > ours.version);
+        if access == Access::ReadWrite && !newer {
+            self.set_meta_u64(Meta::TreeVersion(tree), ours.version)?;
+            self.set_meta_u64(Meta::TreeCompatibleSince(tree), ours.compatible_since)?;
+        }
+
+        let opened = self
+            .db
+            .open_tree(tree.name())
+            .with_context(|| format!("could not open the `{}` database", tree.name()))?;
+
+        Ok((opened, access))
+    }
+
+    /// Bring `tree` from layout `from` up to `ours` with `migrations`,
+    /// returning the version and compatible-since it ends up with. If we
+    /// can't get all the way there, we leave it alone and `access` explains
+    /// what to do instead.
+    fn migrate(
+        &self,
+        tree: Tree,
+        from: u64,
+        ours: Layout,
+        migrations: &[(Tree, u64, Migration)],
+    ) -> Result<(u64, u64)> {
+        let steps: Option<Vec<Migration>> = (from..ours.version)
+            .map(|version| {
+                migrations
+                    .iter()
+                    .find(|(migrates, step_from, _)| *migrates == tree && *step_from == version)
+                    .map(|(_, _, step)| *step)
+            })
+            .collect();
+
+        let steps = match steps {
+            Some(steps) => steps,
+            None => return Ok((from, from)),
+        };
+
+        if !self.upgrade {
+            anyhow::bail!(
+                "the `{}` database was written by an older version of rbt (layout {}). I can upgrade it to layout {}, but then the older version won't be able to use it. Once nobody needs the older version, run me with `--upgrade-db`.",
+                tree.name(),
+                from,
+                ours.version,
+            )
+        }
+
+        let opened = self
+            .db
+            .open_tree(tree.name())
+            .with_context(|| format!("could not open the `{}` database", tree.name()))?;
+
+        for (version, step) in (from..).zip(steps) {
+            tracing::info!(
+                "migrating the `{}` database from layout {} to {}",
+                tree.name(),
+                version,
+                version + 1
+            );
+            step(&opened).with_context(|| {
+                format!(
+                    "could not migrate the `{}` database from layout {}",
+                    tree.name(),
+                    version
+                )
+            })?;
+
+            self.set_meta_u64(Meta::TreeVersion(tree), version + 1)?;
+            self.set_meta_u64(Meta::TreeCompatibleSince(tree), version + 1)?;
+        }
+
+        Ok((ours.version, ours.version))
+    }
+
+    /// Remove the trees in `RETIRED_TREES`, returning how many there were.
+    pub fn drop_retired_trees(&self) -> Result<usize> {
+        let mut dropped = 0;
+
+        for name in RETIRED_TREES {
+            if self
+                .db
+                .drop_tree(name)
+                .with_context(|| format!("could not remove the `{}` database", name))?
+            {
+                dropped += 1;
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    /// How big the database is, and how many entries each tree has. This
+    /// doesn't open trees the usual way, so it works no matter which
+    /// version of rbt wrote them and doesn't change who owns them.
+    pub fn stats(&self) -> Result<Stats> {
+        let mut entries = Vec::with_capacity(Tree::ALL.len());
+        for tree in Tree::ALL {
+            let opened = self
+                .db
+                .open_tree(tree.name())
+                .with_context(|| format!("could not open the `{}` database", tree.name()))?;
+
+            entries.push((tree, opened.len()));
+        }
+
+        Ok(Stats {
+            size_on_disk: self
+                .db
+                .size_on_disk()
+                .context("could not get database size")?,
+            entries,
+        })
+    }
+
+    /// Rewrite the database at `path` into a fresh one, leaving behind the
+    /// space sled keeps for old versions of values. Sled only reclaims that
+    /// space gradually, so long-lived databases can end up several times
+    /// bigger than what's in them. Nothing else can have the database open
+    /// while this runs (sled's lock makes sure of that.)
+    pub fn compact(path: &Path) -> Result<Compaction> {
+        let compacting = path.with_extension("compacting");
+        let old = path.with_extension("old");
+        for leftover in [&compacting, &old] {
+            if leftover.exists() {
+                std::fs::remove_dir_all(leftover).with_context(|| {
+                    format!(
+                        "could not remove `{}` from an earlier compaction",
+                        leftover.display()
+                    )
+                })?;
+            }
+        }
+
+        let before = Db::open(path)?;
+        let after = Db::open(&compacting)?;
+        after.db.import(before.db.export());
+        after
+            .db
+            .flush()
+            .context("could not write the compacted database")?;
+
+        let compaction = Compaction {
+            before: before
+                .db
+                .size_on_disk()
+                .context("could not get database size")?,
+            after: after
+                .db
+                .size_on_disk()
+                .context("could not get compacted database size")?,
+        };
+        drop((before, after));
+
+        // if we stop between these renames, the next build starts with an
+        // empty database and we have to hash files and find cache hits
+        // again, but nothing is lost for good: the old copy is still there.
+        std::fs::rename(path, &old)
+            .with_context(|| format!("could not move `{}` out of the way", path.display()))?;
+        std::fs::rename(&compacting, path).with_context(|| {
+            format!("could not move compacted database to `{}`", path.display())
+        })?;
+        std::fs::remove_dir_all(&old)
+            .with_context(|| format!("could not remove `{}`", old.display()))?;
+
+        Ok(compaction)
+    }
+
+    pub fn meta_u64(&self, meta: Meta) -> Result<Option<u64>> {
+        match self
+            .db
+            .get(meta.key())
+            .with_context(|| format!("could not read `{}`", meta.key()))?
+        {
+            Some(bytes) => Ok(Some(u64::from_le_bytes(
+                bytes
+                    .as_ref()
+                    .try_into()
+                    .with_context(|| format!("`{}` was not exactly 8 bytes", meta.key()))?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_meta_u64(&self, meta: Meta, value: u64) -> Result<()> {
+        self.db
+            .insert(meta.key(), &value.to_le_bytes())
+            .with_context(|| format!("could not write `{}`", meta.key()))?;
+
+        Ok(())
+    }
+}
+
+/// See `Db::stats`.
+#[derive(Debug)]
+pub struct Stats {
+    pub size_on_disk: u64,
+    pub entries: Vec<(Tree, usize)>,
+}
+
+/// Sizes on disk before and after `Db::compact`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compaction {
+    pub before: u64,
+    pub after: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn tree_names_are_unique() {
+        let names: HashSet<&str> = Tree::ALL.iter().map(|tree| tree.name()).collect();
+
+        assert_eq!(Tree::ALL.len(), names.len());
+    }
+
+    #[test]
+    fn rejects_trees_with_other_layouts() {
+        let db = Db::temporary();
+
+        assert!(db.tree(Tree::Store).is_ok());
+        assert_eq!(
+            Some(Tree::Store.layout().version),
+            db.meta_u64(Meta::TreeVersion(Tree::Store)).unwrap()
+        );
+
+        // a newer layout that doesn't get along with ours
+        for meta in [
+            Meta::TreeVersion(Tree::Store),
+            Meta::TreeCompatibleSince(Tree::Store),
+        ] {
+            db.set_meta_u64(meta, Tree::Store.layout().version + 1)
+                .unwrap();
+        }
+        assert!(db.tree(Tree::Store).is_err());
+    }
+
+    #[test]
+    fn migrates_old_layouts_when_allowed() {
+        let mut db = Db::temporary();
+        let ours = Layout::new(3);
+        let migrations: &[(Tree, u64, Migration)] = &[
+            (Tree::Store, 1, |tree| {
+                tree.insert("one", "")?;
+                Ok(())
+            }),
+            (Tree::Store, 2, |tree| {
+                tree.insert("two", "")?;
+                Ok(())
+            }),
+        ];
+
+        // nothing we can do without a step for every version
+        assert_eq!(
+            (0, 0),
+            db.migrate(Tree::Store, 0, ours, migrations).unwrap()
+        );
+
+        // and older versions of rbt couldn't use the tree after
+        assert!(db.migrate(Tree::Store, 1, ours, migrations).is_err());
+
+        db.allow_upgrades();
+        assert_eq!(
+            (3, 3),
+            db.migrate(Tree::Store, 1, ours, migrations).unwrap()
+        );
+
+        let store = db.db.open_tree(Tree::Store.name()).unwrap();
+        assert!(store.contains_key("one").unwrap());
+        assert!(store.contains_key("two").unwrap());
+        assert_eq!(
+            Some(3),
+            db.meta_u64(Meta::TreeVersion(Tree::Store)).unwrap()
+        );
+    }
+
+    #[test]
+    fn compacts_without_losing_entries() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("db");
+
+        {
+            let db = Db::open(&path).unwrap();
+            let store = db.tree(Tree::Store).unwrap();
+            // overwriting the same keys leaves old versions for sled to
+            // clean up later
+            for round in 0..10u64 {
+                for key in 0..100u64 {
+                    store
+                        .insert(key.to_be_bytes(), vec![round as u8; 1024])
+                        .unwrap();
+                }
+            }
+            db.db.flush().unwrap();
+        }
+
+        let compaction = Db::compact(&path).unwrap();
+        assert!(compaction.after <= compaction.before, "{:?}", compaction);
+        assert!(!path.with_extension("compacting").exists());
+        assert!(!path.with_extension("old").exists());
+
+        let db = Db::open(&path).unwrap();
+        let stats = db.stats().unwrap();
+        assert!(stats.entries.contains(&(Tree::Store, 100)));
+        assert_eq!(
+            Some(Tree::Store.layout().version),
+            db.meta_u64(Meta::TreeVersion(Tree::Store)).unwrap()
+        );
+    }
+
+    #[test]
+    fn gets_along_with_compatible_versions() {
+        let ours = Layout {
+            version: 3,
+            readable_since: 1,
+            compatible_since: 2,
+        };
+        let access = |version, compatible_since| {
+            access(Tree::Store, Some((version, compatible_since)), ours)
+        };
+
+        assert_eq!(Access::ReadWrite, access(3, 3).unwrap());
+
+        // newer versions say whether we can keep up
+        assert_eq!(Access::ReadWrite, access(4, 3).unwrap());
+        assert!(access(4, 4).is_err());
+
+        // older versions we can write for, read for, or neither
+        assert_eq!(Access::ReadWrite, access(2, 2).unwrap());
+        assert_eq!(Access::ReadOnly, access(1, 1).unwrap());
+        assert!(access(0, 0).is_err());
+    }
+}
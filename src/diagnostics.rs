@@ -0,0 +1,347 @@
+use crate::workspace::Workspace;
+use anyhow::{Context, Result};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long we'll wait for `tool --version` before giving up on it. Some
+/// tools don't understand the flag and wait for input instead.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Captures what a failed job's environment looked like, so that "it only
+/// fails on my machine" bugs can be compared across machines. Each failure
+/// gets a tarball containing:
+///
+/// - `command.txt`: the tool and arguments
+/// - `env.txt`: every environment variable the job saw
+/// - `tool.txt`: where the tool resolved to, and what `--version` said
+/// - `workspace.txt`: every file in the workspace, with sizes and link targets
+/// - `ulimits.txt`: the resource limits the job ran under
+#[derive(Debug, Clone)]
+pub struct Capture {
+    root: PathBuf,
+}
+
+impl Capture {
+    pub fn new(root: PathBuf) -> Self {
+        Capture { root }
+    }
+
+    /// Write a bundle for a failed run of `command` in `workspace`, and
+    /// return where we put it.
+    pub fn capture(&self, name: &str, command: &Command, workspace: &Workspace) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.root)
+            .with_context(|| format!("could not create `{}`", self.root.display()))?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context("system clock is before the epoch")?
+            .as_secs();
+        let path = self.root.join(format!("{}-{}.tar.gz", name, timestamp));
+
+        let file = File::create(&path)
+            .with_context(|| format!("could not create `{}`", path.display()))?;
+        let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        ));
+
+        let sections = [
+            ("command.txt", describe_command(command)),
+            ("env.txt", describe_env(command)),
+            ("tool.txt", describe_tool(command)),
+            ("workspace.txt", describe_workspace(workspace.root())),
+            ("ulimits.txt", describe_ulimits()),
+        ];
+
+        for (name, contents) in sections {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(timestamp);
+            header.set_cksum();
+
+            archive
+                .append_data(&mut header, name, contents.as_bytes())
+                .with_context(|| format!("could not add {} to diagnostics", name))?;
+        }
+
+        archive
+            .into_inner()
+            .context("could not finish diagnostics archive")?
+            .finish()
+            .context("could not finish compressing diagnostics")?;
+
+        Ok(path)
+    }
+
+    /// Capture a bundle for `problem` and point to it from the error. Not
+    /// being able to capture shouldn't hide the original failure, so we only
+    /// warn about that.
+    pub fn attach(
+        &self,
+        problem: anyhow::Error,
+        name: &str,
+        command: &Command,
+        workspace: &Workspace,
+    ) -> anyhow::Error {
+        match self.capture(name, command, workspace) {
+            Ok(bundle) => problem.context(format!(
+                "saved diagnostics for this failure to `{}`",
+                bundle.display()
+            )),
+            Err(err) => {
+                tracing::warn!("{:?}", err.context("could not capture diagnostics"));
+                problem
+            }
+        }
+    }
+}
+
+fn describe_command(command: &Command) -> String {
+    let mut out = format!("{}\n", command.get_program().to_string_lossy());
+    for arg in command.get_args() {
+        let _ = writeln!(out, "{}", arg.to_string_lossy());
+    }
+
+    out
+}
+
+fn describe_env(command: &Command) -> String {
+    // jobs run with a cleared environment, so everything they see was set
+    // explicitly and shows up here.
+    let mut vars: Vec<(String, String)> = command
+        .get_envs()
+        .filter_map(|(key, value)| {
+            value.map(|value| {
+                (
+                    key.to_string_lossy().to_string(),
+                    value.to_string_lossy().to_string(),
+                )
+            })
+        })
+        .collect();
+    vars.sort();
+
+    let mut out = String::new();
+    for (key, value) in vars {
+        let _ = writeln!(out, "{}={}", key, value);
+    }
+
+    out
+}
+
+fn describe_tool(command: &Command) -> String {
+    let program = command.get_program();
+
+    let resolved = match resolve_tool(program, command_path(command).as_deref()) {
+        Some(resolved) => resolved,
+        None => {
+            return format!(
+                "could not find `{}` on the PATH\n",
+                program.to_string_lossy()
+            )
+        }
+    };
+
+    format!(
+        "path: {}\n\n$ {} --version\n{}",
+        resolved.display(),
+        resolved.display(),
+        tool_version(&resolved, command)
+    )
+}
+
+/// The PATH the job would have used to find its tool: its own if it set one,
+/// ours otherwise.
+fn command_path(command: &Command) -> Option<std::ffi::OsString> {
+    command
+        .get_envs()
+        .find(|(key, _)| *key == "PATH")
+        .and_then(|(_, value)| value.map(|value| value.to_os_string()))
+        .or_else(|| std::env::var_os("PATH"))
+}
+
+fn resolve_tool(program: &OsStr, path: Option<&OsStr>) -> Option<PathBuf> {
+    let program = Path::new(program);
+    if program.components().count() > 1 {
+        return Some(program.to_path_buf());
+    }
+
+    std::env::split_paths(path?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+fn tool_version(tool: &Path, command: &Command) -> String {
+    let mut version = Command::new(tool);
+    version
+        .arg("--version")
+        .env_clear()
+        .envs(
+            command
+                .get_envs()
+                .filter_map(|(key, value)| value.map(|value| (key, value))),
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = match version.spawn() {
+        Ok(child) => child,
+        Err(err) => return format!("could not run: {}\n", err),
+    };
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() < VERSION_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(50))
+            }
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return format!("gave up after {:?}\n", VERSION_TIMEOUT);
+            }
+            Err(err) => return format!("could not wait for it: {}\n", err),
+        }
+    }
+
+    match child.wait_with_output() {
+        Ok(output) => format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(err) => format!("could not read output: {}\n", err),
+    }
+}
+
+fn describe_workspace(root: &Path) -> String {
+    let mut out = String::new();
+
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let _ = writeln!(out, "error: {}", err);
+                continue;
+            }
+        };
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+
+        if entry.path_is_symlink() {
+            let target = std::fs::read_link(entry.path())
+                .map(|target| target.display().to_string())
+                .unwrap_or_else(|err| format!("<{}>", err));
+            let _ = writeln!(out, "{} -> {}", relative.display(), target);
+        } else if entry.file_type().is_dir() {
+            let _ = writeln!(out, "{}/", relative.display());
+        } else {
+            let len = entry.metadata().map(|meta| meta.len()).unwrap_or_default();
+            let _ = writeln!(out, "{} ({} bytes)", relative.display(), len);
+        }
+    }
+
+    out
+}
+
+#[cfg(target_family = "unix")]
+fn describe_ulimits() -> String {
+    // children inherit our limits, so ours are theirs.
+    let limits = [
+        ("core", libc::RLIMIT_CORE),
+        ("cpu", libc::RLIMIT_CPU),
+        ("data", libc::RLIMIT_DATA),
+        ("fsize", libc::RLIMIT_FSIZE),
+        ("nofile", libc::RLIMIT_NOFILE),
+        ("nproc", libc::RLIMIT_NPROC),
+        ("stack", libc::RLIMIT_STACK),
+        ("as", libc::RLIMIT_AS),
+    ];
+
+    let show = |value: libc::rlim_t| {
+        if value == libc::RLIM_INFINITY {
+            "unlimited".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+
+    let mut out = String::new();
+    for (name, resource) in limits {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+
+        if unsafe { libc::getrlimit(resource, &mut limit) } == 0 {
+            let _ = writeln!(
+                out,
+                "{}: soft {}, hard {}",
+                name,
+                show(limit.rlim_cur),
+                show(limit.rlim_max)
+            );
+        } else {
+            let _ = writeln!(out, "{}: unknown", name);
+        }
+    }
+
+    out
+}
+
+#[cfg(not(target_family = "unix"))]
+fn describe_ulimits() -> String {
+    "resource limits aren't available on this platform\n".to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::job;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn bundles_everything() {
+        let temp = TempDir::new().unwrap();
+        let workspace = Workspace::create(&temp.path().join("workspaces"), &job::Key::default())
+            .await
+            .unwrap();
+        std::fs::write(workspace.join_build("input"), "hi").unwrap();
+
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("exit 1")
+            .env_clear()
+            .env("FOO", "bar");
+
+        let bundle = Capture::new(temp.path().join("diagnostics"))
+            .capture("job", &command, &workspace)
+            .unwrap();
+
+        let mut contents = HashMap::new();
+        let mut archive =
+            tar::Archive::new(flate2::read::GzDecoder::new(File::open(bundle).unwrap()));
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut text = String::new();
+            entry.read_to_string(&mut text).unwrap();
+            contents.insert(entry.path().unwrap().display().to_string(), text);
+        }
+
+        assert_eq!(contents["command.txt"], "sh\n-c\nexit 1\n");
+        assert_eq!(contents["env.txt"], "FOO=bar\n");
+        assert!(contents["tool.txt"].starts_with("path: "));
+        assert!(contents["workspace.txt"].contains("build/input (2 bytes)"));
+        assert!(contents.contains_key("ulimits.txt"));
+    }
+}
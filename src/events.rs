@@ -0,0 +1,190 @@
+use crate::file_type::FileTypes;
+use crate::job::Job;
+use crate::progress::Progress;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Things that happen during a build. The coordinator publishes these on a
+/// `Bus` instead of calling reports, progress displays, and so on directly, so
+/// adding one of those doesn't mean touching scheduling code. Events can be
+/// serialized, so a recorded stream can be replayed into a sink in tests.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Event {
+    /// Always the first event in a build: every job it's going to run (or
+    /// find in the cache.)
+    BuildStarted {
+        jobs: Vec<JobInfo>,
+    },
+
+    /// We already had the job's output, so it didn't need to run.
+    JobCached {
+        job: JobInfo,
+        #[serde(rename = "storePath")]
+        store_path: PathBuf,
+        #[serde(rename = "outputTypes", default)]
+        output_types: FileTypes,
+    },
+
+    JobStarted {
+        job: JobInfo,
+    },
+
+    /// A running job said how far along it is (see `RBT_PROGRESS`.)
+    JobProgress {
+        job: JobInfo,
+        progress: Progress,
+    },
+
+    JobSucceeded {
+        job: JobInfo,
+        duration: Duration,
+        #[serde(rename = "storePath")]
+        store_path: PathBuf,
+        #[serde(rename = "outputTypes", default)]
+        output_types: FileTypes,
+
+        /// Inputs the job never read, with `--warn-unused-inputs`.
+        #[serde(rename = "unusedInputs", default)]
+        unused_inputs: Vec<PathBuf>,
+    },
+
+    JobFailed {
+        job: JobInfo,
+        duration: Duration,
+        error: String,
+    },
+
+    /// The job never ran (or was stopped partway through) because another
+    /// job failed, or the build ran out of time.
+    JobSkipped {
+        job: JobInfo,
+    },
+
+    /// The job was running when the build ran out of time, so we stopped
+    /// it partway through.
+    JobCutShort {
+        job: JobInfo,
+        duration: Duration,
+    },
+
+    /// How many jobs are in each part of the queue, after the coordinator
+    /// scheduled what it could. `ready` jobs are waiting for a free slot,
+    /// `waiting` ones for resources, and `blocked` ones for dependencies.
+    QueueChanged {
+        ready: usize,
+        waiting: usize,
+        blocked: usize,
+        running: usize,
+    },
+
+    /// Always the last event in a build, whether or not it worked.
+    BuildFinished {
+        succeeded: bool,
+    },
+}
+
+/// Enough about a job to show it to people, without holding on to the job.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JobInfo {
+    /// What people see in logs, like `cc-3fa2c81e`. Only unique within one
+    /// build.
+    pub id: String,
+    pub key: String,
+    pub command: String,
+}
+
+impl From<&Job> for JobInfo {
+    fn from(job: &Job) -> Self {
+        JobInfo {
+            id: job.id.to_string(),
+            key: job.base_key.to_string(),
+            command: job.command.to_string(),
+        }
+    }
+}
+
+/// How many events a slow subscriber can fall behind before it starts
+/// missing them.
+const CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct Bus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+
+        Bus { sender }
+    }
+
+    /// Only events published after this call are received, so subscribe
+    /// before starting the build.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, event: Event) {
+        // sending only fails when nobody is subscribed, which is fine.
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus::new()
+    }
+}
+
+/// Call `handle` with every event until the build finishes (or the bus goes
+/// away.) This is the usual body of a sink's task.
+pub async fn each(mut events: broadcast::Receiver<Event>, mut handle: impl FnMut(&Event)) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                handle(&event);
+
+                if matches!(event, Event::BuildFinished { .. }) {
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!(
+                    "an event subscriber fell behind and missed {} events",
+                    missed
+                )
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn subscribers_see_events_until_the_build_finishes() {
+        let bus = Bus::new();
+        let events = bus.subscribe();
+
+        bus.publish(Event::JobStarted {
+            job: JobInfo {
+                id: "true-abc".to_string(),
+                key: "abc".to_string(),
+                command: "true".to_string(),
+            },
+        });
+        bus.publish(Event::BuildFinished { succeeded: true });
+        bus.publish(Event::BuildFinished { succeeded: false });
+
+        let mut seen = Vec::new();
+        each(events, |event| seen.push(event.clone())).await;
+
+        assert_eq!(2, seen.len());
+        assert_eq!(Event::BuildFinished { succeeded: true }, seen[1]);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::job::{self, Job};
+use crate::network::Network;
+use std::fmt::{self, Display};
+
+/// What went into a job's keys, for `rbt explain`. Each part is on a line
+/// of its own, in a stable order, so that when a job rebuilds when it
+/// shouldn't have, diffing this from before and after shows what changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    job: String,
+    base_key: String,
+
+    /// What went into the base key: everything about the job we know
+    /// without reading any files.
+    base: Vec<(&'static str, String)>,
+
+    /// `None` when something that goes into it isn't known yet, usually
+    /// because a dependency hasn't been built.
+    pub final_key: Option<String>,
+
+    /// What went into the final key on top of the base key.
+    finals: Vec<(&'static str, String)>,
+}
+
+impl Explanation {
+    /// Explain the base key of `job`. `name_of` gives the IDs of the jobs it
+    /// takes inputs from.
+    pub fn new(job: &Job, name_of: impl Fn(&job::Key<job::Base>) -> String) -> Self {
+        let mut base = Vec::new();
+
+        base.push(("tool", job.command.tool().to_string()));
+        if let Some(tool_job) = job.command.tool_job() {
+            base.push(("tool from", name_of(&tool_job)));
+        }
+        if let Some(sha256) = job.command.pinned_sha256() {
+            base.push(("tool sha256", sha256.to_string()));
+        }
+        for arg in job.command.args() {
+            base.push(("arg", arg.clone()));
+        }
+        for (name, value) in job.command.env() {
+            base.push(("env", format!("{}={}", name, value)));
+        }
+        for command in &job.then_run {
+            base.push(("then run", command.to_string()));
+        }
+
+        for file in &job.input_files {
+            base.push(("input", mapping(file)));
+        }
+        for (dep, files) in &job.input_jobs {
+            for file in files {
+                base.push(("input", format!("{} from {}", mapping(file), name_of(dep))));
+            }
+        }
+        for (spec, files) in &job.input_resolvers {
+            for file in files {
+                base.push(("input", format!("{} from {}", mapping(file), spec)));
+            }
+        }
+
+        for output in &job.outputs {
+            base.push(("output", output.display().to_string()));
+        }
+        if let Some(output) = &job.output_from_stdout {
+            base.push(("stdout to", output.display().to_string()));
+        }
+        for (output, filter) in &job.output_filters {
+            base.push(("filter", format!("{} {:?}", output.display(), filter)));
+        }
+
+        for name in &job.passthrough_env {
+            base.push(("passthrough", name.clone()));
+        }
+        if job.expect_failure {
+            base.push(("expect failure", "yes".to_string()));
+        }
+        if job.network == Network::Forbidden {
+            base.push(("network", "forbidden".to_string()));
+        }
+        for (name, address) in &job.names.hosts {
+            base.push(("host", format!("{} {}", name, address)));
+        }
+        for server in &job.names.dns_servers {
+            base.push(("dns server", server.to_string()));
+        }
+        base.push(("input strategy", format!("{:?}", job.input_strategy)));
+        if job.stamp {
+            base.push(("stamp", "yes".to_string()));
+        }
+        for dir in &job.incremental_state {
+            base.push(("incremental", dir.display().to_string()));
+        }
+        if let Some(dir) = &job.checkpoint_dir {
+            base.push(("checkpoints", dir.display().to_string()));
+        }
+        if let Some(file) = &job.response_file {
+            base.push(("response file", file.display().to_string()));
+        }
+        for dest in &job.writable_inputs {
+            base.push(("writable", dest.display().to_string()));
+        }
+        for (limit, value) in &job.limits {
+            base.push(("limit", format!("{} {}", limit.identity(), value)));
+        }
+        for (name, value) in &job.variant {
+            base.push(("matrix", format!("{}={}", name, value)));
+        }
+        if let Some(shard) = job.shard {
+            base.push(("shard", format!("{} of {}", shard.index + 1, shard.total)));
+        }
+
+        Self {
+            job: job.to_string(),
+            base_key: job.base_key.to_string(),
+            base,
+            final_key: None,
+            finals: Vec::new(),
+        }
+    }
+
+    /// Say that `value` went into the final key as `what`.
+    pub fn add_final(&mut self, what: &'static str, value: String) {
+        self.finals.push((what, value));
+    }
+}
+
+fn mapping(file: &job::FileMapping) -> String {
+    if file.source == file.dest {
+        file.source.display().to_string()
+    } else {
+        format!("{} -> {}", file.source.display(), file.dest.display())
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .base
+            .iter()
+            .chain(&self.finals)
+            .map(|(what, _)| what.len())
+            .max()
+            .unwrap_or(0);
+
+        writeln!(f, "{}", self.job)?;
+        writeln!(f, "base key {}", self.base_key)?;
+        for (what, value) in &self.base {
+            writeln!(f, "    {:<width$} {}", what, value)?;
+        }
+
+        match &self.final_key {
+            Some(final_key) => write!(f, "final key {}", final_key)?,
+            None => write!(
+                f,
+                "final key not known yet (build what the job depends on first)"
+            )?,
+        }
+        for (what, value) in &self.finals {
+            write!(f, "\n    {:<width$} {}", what, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::glue;
+    use roc_std::RocStr;
+    use std::collections::HashMap;
+
+    fn job() -> Job {
+        let glue_job = glue::Job::Job(glue::R1 {
+            outputs: [RocStr::from("main.o")].into_iter().collect(),
+            network: glue::Network::Forbidden,
+            ..glue::R1::for_test(job::system_command("cc", &["-c", "main.c"]))
+        });
+        Job::from_glue(&glue_job, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn lists_what_went_into_each_key() {
+        let job = job();
+        let mut explanation = Explanation::new(&job, |key| key.to_string());
+        explanation.final_key = Some("abc123".to_string());
+        explanation.add_final("salt", "nightly".to_string());
+
+        assert_eq!(
+            format!(
+                "{}\n\
+                 base key {}\n    \
+                 tool           cc\n    \
+                 arg            -c\n    \
+                 arg            main.c\n    \
+                 output         main.o\n    \
+                 network        forbidden\n    \
+                 input strategy Symlink\n\
+                 final key abc123\n    \
+                 salt           nightly",
+                job, job.base_key
+            ),
+            explanation.to_string()
+        );
+    }
+}
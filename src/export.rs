@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes root outputs to a single archive, for handing them to something
+/// that doesn't know about rbt's store (a release page, another machine.)
+/// The format comes from the file name:
+///
+/// - `.tar`: no compression
+/// - `.tar.gz` or `.tgz`: gzip, levels 0 to 9
+/// - `.tar.zst` or `.tzst`: zstd, levels 1 to 22
+///
+/// Each output goes in a directory named after its job's key. We stream
+/// straight from the store into the compressor and out to disk, so exporting
+/// a multi-gigabyte output doesn't need that much memory or temp space.
+/// Timestamps and owners are normalized, so exporting the same outputs
+/// twice gives the same archive.
+#[derive(Debug)]
+pub struct Export {
+    path: PathBuf,
+    format: Format,
+    level: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("the export path needs a file name")?;
+
+        if name.ends_with(".tar") {
+            Ok(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(Format::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Ok(Format::TarZst)
+        } else {
+            anyhow::bail!(
+                "I don't know what format to use for `{}`. I can write `.tar`, `.tar.gz`, and `.tar.zst` files.",
+                name
+            )
+        }
+    }
+}
+
+impl Export {
+    /// Check the path and compression level up front, so a typo doesn't
+    /// cost a whole build.
+    pub fn new(path: PathBuf, level: Option<i32>) -> Result<Self> {
+        let format = Format::from_path(&path)?;
+
+        let levels = match format {
+            Format::Tar => None,
+            Format::TarGz => Some(0..=9),
+            Format::TarZst => Some(zstd::compression_level_range()),
+        };
+
+        match (level, levels) {
+            (Some(_), None) => {
+                anyhow::bail!("`.tar` files aren't compressed, so they don't have levels")
+            }
+            (Some(level), Some(levels)) if !levels.contains(&level) => anyhow::bail!(
+                "{} isn't a valid compression level for `{}`. Try something from {} to {}.",
+                level,
+                path.display(),
+                levels.start(),
+                levels.end()
+            ),
+            _ => (),
+        }
+
+        Ok(Export {
+            path,
+            format,
+            level,
+        })
+    }
+
+    /// Write each `(name, store path)` pair into the archive.
+    pub fn write(&self, outputs: &[(String, PathBuf)]) -> Result<()> {
+        let file = BufWriter::new(
+            File::create(&self.path)
+                .with_context(|| format!("could not create `{}`", self.path.display()))?,
+        );
+
+        let file = match self.format {
+            Format::Tar => write_tar(file, outputs)?,
+            Format::TarGz => write_tar(
+                flate2::write::GzEncoder::new(
+                    file,
+                    self.level
+                        .map(|level| flate2::Compression::new(level as u32))
+                        .unwrap_or_default(),
+                ),
+                outputs,
+            )?
+            .finish()
+            .context("could not finish compressing")?,
+            Format::TarZst => write_tar(
+                zstd::Encoder::new(file, self.level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL))
+                    .context("could not start compressing")?,
+                outputs,
+            )?
+            .finish()
+            .context("could not finish compressing")?,
+        };
+
+        file.into_inner()
+            .map_err(|err| err.into_error())
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("could not write `{}`", self.path.display()))?;
+
+        tracing::info!("exported root outputs to `{}`", self.path.display());
+        Ok(())
+    }
+}
+
+/// Write a tarball with each `(name, path)` pair at `name`, returning the
+/// writer so the caller can finish whatever encoder it is.
+pub fn write_tar<W: Write>(writer: W, outputs: &[(String, PathBuf)]) -> Result<W> {
+    let mut archive = tar::Builder::new(writer);
+    archive.mode(tar::HeaderMode::Deterministic);
+
+    for (name, path) in outputs {
+        archive
+            .append_dir_all(name, path)
+            .with_context(|| format!("could not add `{}` to the archive", path.display()))?;
+    }
+
+    archive
+        .into_inner()
+        .context("could not finish writing the archive")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    fn exported(name: &str, level: Option<i32>) -> Vec<(PathBuf, String)> {
+        let temp = tempfile::TempDir::new().unwrap();
+        let item = temp.path().join("item");
+        std::fs::create_dir_all(item.join("nested")).unwrap();
+        std::fs::write(item.join("nested/out"), "hello").unwrap();
+
+        let path = temp.path().join(name);
+        Export::new(path.clone(), level)
+            .unwrap()
+            .write(&[("abc".to_string(), item)])
+            .unwrap();
+
+        let file = File::open(&path).unwrap();
+        let reader: Box<dyn Read> = match Format::from_path(&path).unwrap() {
+            Format::Tar => Box::new(file),
+            Format::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+            Format::TarZst => Box::new(zstd::Decoder::new(file).unwrap()),
+        };
+
+        let mut archive = tar::Archive::new(reader);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+
+                (entry.path().unwrap().into_owned(), contents)
+            })
+            .filter(|(_, contents)| !contents.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn exports_every_format() {
+        for (name, level) in [
+            ("out.tar", None),
+            ("out.tar.gz", Some(9)),
+            ("out.tar.zst", Some(19)),
+        ] {
+            assert_eq!(
+                vec![(PathBuf::from("abc/nested/out"), "hello".to_string())],
+                exported(name, level),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_formats_and_levels() {
+        assert!(Export::new(PathBuf::from("out.zip"), None).is_err());
+        assert!(Export::new(PathBuf::from("out.tar"), Some(1)).is_err());
+        assert!(Export::new(PathBuf::from("out.tar.gz"), Some(10)).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::{Context, Result};
+use path_absolutize::Absolutize;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// What we run jobs under to see which files they look at. This is a first
+/// pass: `strace` is on most Linux machines and CI images already, and
+/// doing our own `ptrace` would mean taking over reaping the job's process
+/// from tokio.
+pub const TRACER: &str = "strace";
+
+/// Syscalls that read a file or check whether one is there. Compilers
+/// looking for headers mostly `openat` or `stat` their way down the include
+/// path, so failed attempts are as interesting as successful ones.
+const READS: &[&str] = &[
+    "open",
+    "openat",
+    "openat2",
+    "creat",
+    "stat",
+    "lstat",
+    "newfstatat",
+    "fstatat64",
+    "statx",
+    "access",
+    "faccessat",
+    "faccessat2",
+    "readlink",
+    "readlinkat",
+    "execve",
+];
+
+/// Records which files a job's command (and everything it starts) touched,
+/// so we can point out the ones that should have been inputs. The trace
+/// lives in a temporary directory that goes away with this.
+#[derive(Debug)]
+pub struct Trace {
+    dir: tempfile::TempDir,
+
+    // the project's files (where undeclared inputs come from) and rbt's
+    // state directory inside it (which isn't input, so we skip it.)
+    project_root: PathBuf,
+    state_root: PathBuf,
+}
+
+impl Trace {
+    pub fn new(state_root: &Path) -> Result<Self> {
+        if !cfg!(target_os = "linux") {
+            anyhow::bail!("checking inputs is only supported on Linux so far")
+        }
+
+        Ok(Trace {
+            dir: tempfile::Builder::new()
+                .prefix("rbt-file-trace-")
+                .tempdir()
+                .context("could not create a directory for the file trace")?,
+            project_root: std::env::current_dir().context("could not get the current directory")?,
+            state_root: state_root
+                .absolutize()
+                .context("could not get absolute path to rbt's state")?
+                .into_owned(),
+        })
+    }
+
+    /// Arguments for `TRACER` to run `tool`, which gets its own arguments
+    /// after these. `-ff` writes a file per process, so lines from
+    /// different processes never get split up and interleaved.
+    pub fn args(&self, tool: &str) -> Vec<OsString> {
+        vec![
+            "-ff".into(),
+            "-qq".into(),
+            "-e".into(),
+            "trace=%file".into(),
+            "-o".into(),
+            self.dir.path().join("trace").into(),
+            "--".into(),
+            tool.into(),
+        ]
+    }
+
+    /// Everything the traced processes looked for, in the order each one
+    /// did. Processes are in no particular order.
+    fn accesses(&self) -> Result<Vec<Access>> {
+        let mut accesses = Vec::new();
+
+        for entry in std::fs::read_dir(self.dir.path()).context("could not read file trace")? {
+            let path = entry.context("could not read file trace entry")?.path();
+            let trace = std::fs::read_to_string(&path)
+                .with_context(|| format!("could not read `{}`", path.display()))?;
+
+            accesses.extend(trace.lines().filter_map(Access::parse));
+        }
+
+        Ok(accesses)
+    }
+
+    /// Problems with the job's declared inputs, as a sentence each. The
+    /// command ran in `build_root`, and `expected` is everything that's
+    /// supposed to be there (see `Job::expected_paths`.)
+    ///
+    /// We find two kinds: files the command looked for in the workspace
+    /// that exist in the project but weren't inputs (like a header nobody
+    /// listed), and project files the command read from outside the
+    /// workspace entirely (like `../../src/config.h` or an absolute path.)
+    /// Relative paths are taken as relative to the workspace, so commands
+    /// that change directories may get some of these wrong.
+    pub fn undeclared_reads(
+        &self,
+        build_root: &Path,
+        expected: &BTreeSet<PathBuf>,
+    ) -> Result<Vec<String>> {
+        let build_root = build_root
+            .absolutize()
+            .context("could not get absolute path to workspace")?;
+
+        let mut problems = BTreeSet::new();
+        for access in self.accesses()? {
+            let path = access
+                .path
+                .absolutize_from(&build_root)
+                .context("could not get absolute path to traced file")?;
+
+            if let Ok(relative) = path.strip_prefix(&build_root) {
+                if !access.found
+                    && !relative.as_os_str().is_empty()
+                    && !expected.contains(relative)
+                    && self.project_root.join(relative).is_file()
+                {
+                    problems.insert(format!(
+                        "looked for `{}`, which is in the project but isn't an input",
+                        relative.display()
+                    ));
+                }
+            } else if let Ok(relative) = path.strip_prefix(&self.project_root) {
+                if access.found && !path.starts_with(&self.state_root) && path.is_file() {
+                    problems.insert(format!(
+                        "read `{}` from the project instead of the workspace",
+                        relative.display()
+                    ));
+                }
+            }
+        }
+
+        Ok(problems.into_iter().collect())
+    }
+
+    /// Which of `inputs` (where they are in the workspace, which is at
+    /// `build_root`) the command never looked at. Looking at anything
+    /// inside a directory input counts as using it, and so does looking
+    /// for a file that turned out not to be there, since the answer could
+    /// change if the input did. A command that reads inputs by some path
+    /// other than their workspace one (after resolving links into the
+    /// store, say) makes them look unused, so this is a hint to check
+    /// rather than proof.
+    pub fn unread_inputs(
+        &self,
+        build_root: &Path,
+        inputs: &BTreeSet<PathBuf>,
+    ) -> Result<Vec<PathBuf>> {
+        let build_root = build_root
+            .absolutize()
+            .context("could not get absolute path to workspace")?;
+
+        let mut looked_at = BTreeSet::new();
+        for access in self.accesses()? {
+            let path = access
+                .path
+                .absolutize_from(&build_root)
+                .context("could not get absolute path to traced file")?;
+
+            if let Ok(relative) = path.strip_prefix(&build_root) {
+                looked_at.insert(relative.to_path_buf());
+            }
+        }
+
+        // paths compare by component, so whatever is inside an input
+        // comes right after it.
+        Ok(inputs
+            .iter()
+            .filter(|input| {
+                !looked_at
+                    .range::<PathBuf, _>(*input..)
+                    .next()
+                    .is_some_and(|path| path.starts_with(input))
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+/// One file a traced process looked for, and whether it was there.
+#[derive(Debug, PartialEq, Eq)]
+struct Access {
+    path: PathBuf,
+    found: bool,
+}
+
+impl Access {
+    /// Read a line of `strace` output, like
+    /// `openat(AT_FDCWD, "foo.h", O_RDONLY) = -1 ENOENT (No such file or directory)`.
+    /// Writes and anything that isn't a read are skipped.
+    fn parse(line: &str) -> Option<Access> {
+        let (syscall, rest) = line.split_once('(')?;
+        if !READS.contains(&syscall) {
+            return None;
+        }
+
+        let (path, rest) = unquote(rest.split_once('"')?.1)?;
+
+        let (args, result) = rest.rsplit_once(" = ")?;
+        if args.contains("O_WRONLY") || args.contains("O_CREAT") || syscall == "creat" {
+            return None;
+        }
+
+        Some(Access {
+            path: PathBuf::from(path),
+            found: !result.trim_start().starts_with('-'),
+        })
+    }
+}
+
+/// Undo strace's C-style escaping of a string, up to its closing quote,
+/// returning the string and everything after the quote.
+fn unquote(quoted: &str) -> Option<(String, &str)> {
+    let mut bytes = Vec::new();
+    let mut chars = quoted.char_indices();
+
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '"' => {
+                let unquoted = String::from_utf8_lossy(&bytes).into_owned();
+                return Some((unquoted, &quoted[index + 1..]));
+            }
+            '\\' => match chars.next()?.1 {
+                'n' => bytes.push(b'\n'),
+                't' => bytes.push(b'\t'),
+                'r' => bytes.push(b'\r'),
+                digit @ '0'..='7' => {
+                    let mut value = digit.to_digit(8)?;
+                    for _ in 0..2 {
+                        let (_, next) = chars.clone().next()?;
+                        match next.to_digit(8) {
+                            Some(next) => {
+                                value = value * 8 + next;
+                                chars.next();
+                            }
+                            None => break,
+                        }
+                    }
+                    bytes.push(u8::try_from(value).ok()?);
+                }
+                other => {
+                    let mut buf = [0; 4];
+                    bytes.extend(other.encode_utf8(&mut buf).as_bytes());
+                }
+            },
+            other => {
+                let mut buf = [0; 4];
+                bytes.extend(other.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_strace_lines() {
+        assert_eq!(
+            Some(Access {
+                path: "include/foo.h".into(),
+                found: false
+            }),
+            Access::parse(
+                r#"openat(AT_FDCWD, "include/foo.h", O_RDONLY|O_NOCTTY) = -1 ENOENT (No such file or directory)"#
+            )
+        );
+        assert_eq!(
+            Some(Access {
+                path: "/src/a \"b\"\n.c".into(),
+                found: true
+            }),
+            Access::parse(
+                r#"newfstatat(AT_FDCWD, "/src/a \"b\"\012.c", {st_mode=S_IFREG|0644, st_size=3, ...}, 0) = 0"#
+            )
+        );
+
+        // writes and syscalls that don't read aren't accesses
+        assert_eq!(
+            None,
+            Access::parse(r#"openat(AT_FDCWD, "out.o", O_WRONLY|O_CREAT|O_TRUNC, 0666) = 3"#)
+        );
+        assert_eq!(None, Access::parse(r#"mkdir("out", 0777) = 0"#));
+        assert_eq!(None, Access::parse("+++ exited with 0 +++"));
+    }
+
+    #[test]
+    fn finds_undeclared_reads() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let project = temp.path().join("project");
+        let build_root = project.join(".rbt/workspaces/abc/build");
+        std::fs::create_dir_all(&build_root).unwrap();
+        for file in ["main.c", "util.h", "config.h"] {
+            std::fs::write(project.join(file), "").unwrap();
+        }
+
+        let trace = Trace {
+            dir: tempfile::TempDir::new().unwrap(),
+            project_root: project.clone(),
+            state_root: project.join(".rbt"),
+        };
+        std::fs::write(
+            trace.dir.path().join("trace.123"),
+            format!(
+                concat!(
+                    "execve(\"/usr/bin/cc\", [\"cc\", \"main.c\"], 0x7ffd /* 3 vars */) = 0\n",
+                    "openat(AT_FDCWD, \"main.c\", O_RDONLY) = 3\n",
+                    "openat(AT_FDCWD, \"util.h\", O_RDONLY) = -1 ENOENT (No such file or directory)\n",
+                    "openat(AT_FDCWD, \"missing.h\", O_RDONLY) = -1 ENOENT (No such file or directory)\n",
+                    "openat(AT_FDCWD, \"{}\", O_RDONLY) = 3\n",
+                    "openat(AT_FDCWD, \"/usr/include/stdio.h\", O_RDONLY) = 3\n",
+                ),
+                project.join("config.h").display()
+            ),
+        )
+        .unwrap();
+
+        let expected = BTreeSet::from([PathBuf::from("main.c")]);
+        assert_eq!(
+            vec![
+                "looked for `util.h`, which is in the project but isn't an input".to_string(),
+                "read `config.h` from the project instead of the workspace".to_string(),
+            ],
+            trace.undeclared_reads(&build_root, &expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn finds_unread_inputs() {
+        let build_root = tempfile::TempDir::new().unwrap();
+        let trace = Trace {
+            dir: tempfile::TempDir::new().unwrap(),
+            project_root: build_root.path().to_path_buf(),
+            state_root: build_root.path().join(".rbt"),
+        };
+        std::fs::write(
+            trace.dir.path().join("trace.123"),
+            format!(
+                concat!(
+                    "openat(AT_FDCWD, \"main.c\", O_RDONLY) = 3\n",
+                    "openat(AT_FDCWD, \"include/util.h\", O_RDONLY) = 3\n",
+                    "openat(AT_FDCWD, \"{}\", O_RDONLY) = -1 ENOENT (No such file or directory)\n",
+                    "openat(AT_FDCWD, \"docs\", O_RDONLY|O_DIRECTORY) = 3\n",
+                ),
+                build_root.path().join("config.h").display()
+            ),
+        )
+        .unwrap();
+
+        let inputs = BTreeSet::from([
+            PathBuf::from("config.h"),
+            PathBuf::from("include"),
+            PathBuf::from("main.c"),
+            PathBuf::from("docs/README.md"),
+            PathBuf::from("unused.c"),
+        ]);
+        assert_eq!(
+            vec![PathBuf::from("docs/README.md"), PathBuf::from("unused.c")],
+            trace.unread_inputs(build_root.path(), &inputs).unwrap()
+        );
+    }
+}
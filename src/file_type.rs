@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// What kind of file an output is, going by its first few bytes. We sniff
+/// this while hashing outputs (since we're reading them then anyway) and
+/// keep it alongside the store item, so tooling downstream of a build can
+/// tell an executable from a tarball from text without reading the file
+/// again. It's only a guess from magic numbers: anything we don't recognize
+/// is `Text` if it looks like UTF-8, and `Binary` if not.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum FileType {
+    Elf,
+    MachO,
+    Wasm,
+    Tar,
+    Gzip,
+    Zstd,
+    Zip,
+    Text,
+    Binary,
+}
+
+/// The type of every file in a store item, by where it is in the item.
+pub type FileTypes = BTreeMap<PathBuf, FileType>;
+
+/// How much of the start of a file `sniff` wants to see. Tar's magic number
+/// is at the end of the first header block, 257 bytes in.
+pub const SNIFF_LEN: usize = 512;
+
+impl FileType {
+    /// Guess the type of a file from its first `SNIFF_LEN` bytes (or all of
+    /// it, if it's shorter than that.)
+    pub fn sniff(start: &[u8]) -> Self {
+        const MACH_O_MAGICS: [[u8; 4]; 4] = [
+            [0xfe, 0xed, 0xfa, 0xce],
+            [0xfe, 0xed, 0xfa, 0xcf],
+            [0xce, 0xfa, 0xed, 0xfe],
+            [0xcf, 0xfa, 0xed, 0xfe],
+        ];
+
+        if start.starts_with(b"\x7fELF") {
+            FileType::Elf
+        } else if MACH_O_MAGICS.iter().any(|magic| start.starts_with(magic)) {
+            FileType::MachO
+        } else if start.starts_with(b"\0asm") {
+            FileType::Wasm
+        } else if start.get(257..262) == Some(b"ustar") {
+            FileType::Tar
+        } else if start.starts_with(&[0x1f, 0x8b]) {
+            FileType::Gzip
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            FileType::Zstd
+        } else if start.starts_with(b"PK\x03\x04") || start.starts_with(b"PK\x05\x06") {
+            FileType::Zip
+        } else if Self::looks_like_text(start) {
+            FileType::Text
+        } else {
+            FileType::Binary
+        }
+    }
+
+    /// Valid UTF-8 without any NULs, except that we only see the start of
+    /// the file, so the last character might be cut off.
+    fn looks_like_text(start: &[u8]) -> bool {
+        if start.contains(&0) {
+            return false;
+        }
+
+        match std::str::from_utf8(start) {
+            Ok(_) => true,
+            Err(err) => err.error_len().is_none(),
+        }
+    }
+
+    /// The MIME type to serve or upload a file of this type with.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            FileType::Elf => "application/x-executable",
+            FileType::MachO => "application/x-mach-binary",
+            FileType::Wasm => "application/wasm",
+            FileType::Tar => "application/x-tar",
+            FileType::Gzip => "application/gzip",
+            FileType::Zstd => "application/zstd",
+            FileType::Zip => "application/zip",
+            FileType::Text => "text/plain; charset=utf-8",
+            FileType::Binary => "application/octet-stream",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sniffs_by_magic_number() {
+        let mut tar = vec![0; SNIFF_LEN];
+        tar[257..262].copy_from_slice(b"ustar");
+
+        assert_eq!(FileType::Elf, FileType::sniff(b"\x7fELF\x02\x01\x01\0"));
+        assert_eq!(FileType::Wasm, FileType::sniff(b"\0asm\x01\0\0\0"));
+        assert_eq!(FileType::Tar, FileType::sniff(&tar));
+        assert_eq!(FileType::Gzip, FileType::sniff(&[0x1f, 0x8b, 0x08, 0]));
+        assert_eq!(FileType::Text, FileType::sniff(b"fn main() {}\n"));
+        assert_eq!(FileType::Text, FileType::sniff(b""));
+        assert_eq!(FileType::Binary, FileType::sniff(&[0xff, 0xfe, 0, 1]));
+    }
+
+    #[test]
+    fn text_can_be_cut_off_mid_character() {
+        // the first two bytes of the three in "€"
+        assert_eq!(FileType::Text, FileType::sniff(&[b'a', 0xe2, 0x82]));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::job::{self, Job};
+use crate::ui;
+use anyhow::{Context, Result};
+use std::fmt::{self, Display};
+use std::time::{Duration, SystemTime};
+
+/// How a job did when `rbt flake-check` ran it over and over with the same
+/// inputs. Any difference between runs is the job's own doing, so a job
+/// that fails some runs and passes others is flaky.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlakeCheck {
+    /// The job's ID when we checked it.
+    pub job: String,
+
+    /// When the check started, in milliseconds since the epoch.
+    pub started_ms: u64,
+    pub runs: Vec<Run>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    /// The key this run's logs are under, for `rbt logs`.
+    pub log_key: String,
+    pub duration_ms: u64,
+
+    /// Why the run failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+}
+
+impl FlakeCheck {
+    pub fn new(job: &Job) -> Self {
+        Self {
+            job: job.id.to_string(),
+            started_ms: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        log_key: &job::Key<job::Final>,
+        duration: Duration,
+        failure: Option<&anyhow::Error>,
+    ) {
+        self.runs.push(Run {
+            log_key: log_key.to_string(),
+            duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+            failure: failure.map(|err| format!("{:#}", err)),
+        })
+    }
+
+    pub fn failures(&self) -> usize {
+        self.runs.iter().filter(|run| run.failure.is_some()).count()
+    }
+
+    /// The share of runs that failed, from 0 to 1.
+    pub fn flake_rate(&self) -> f64 {
+        if self.runs.is_empty() {
+            return 0.0;
+        }
+
+        self.failures() as f64 / self.runs.len() as f64
+    }
+}
+
+impl Display for FlakeCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed {} of {} runs ({:.0}%)",
+            self.job,
+            self.failures(),
+            self.runs.len(),
+            self.flake_rate() * 100.0
+        )?;
+
+        let mut durations: Vec<u64> = self.runs.iter().map(|run| run.duration_ms).collect();
+        durations.sort_unstable();
+        if let (Some(fastest), Some(slowest)) = (durations.first(), durations.last()) {
+            write!(
+                f,
+                "\nruns took {} to {} (median {})",
+                ui::elapsed(Duration::from_millis(*fastest)),
+                ui::elapsed(Duration::from_millis(*slowest)),
+                ui::elapsed(Duration::from_millis(durations[durations.len() / 2])),
+            )?;
+        }
+
+        for (index, run) in self.runs.iter().enumerate() {
+            if let Some(failure) = &run.failure {
+                write!(
+                    f,
+                    "\n    run {} ({}, logs: {}): {}",
+                    index + 1,
+                    ui::elapsed(Duration::from_millis(run.duration_ms)),
+                    run.log_key,
+                    failure
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Every flake check we've done, so we can tell whether a fix made a job
+/// any less flaky.
+#[derive(Debug)]
+pub struct History {
+    tree: sled::Tree,
+}
+
+impl History {
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    pub fn record(&self, base_key: &job::Key<job::Base>, check: &FlakeCheck) -> Result<()> {
+        let value = serde_json::to_vec(check).context("could not serialize flake check")?;
+        self.tree
+            .insert(Self::db_key(base_key, check.started_ms), value)
+            .context("could not record flake check")?;
+
+        Ok(())
+    }
+
+    /// The checks of the job with `base_key`, oldest first.
+    pub fn for_job(&self, base_key: &job::Key<job::Base>) -> Result<Vec<FlakeCheck>> {
+        self.tree
+            .scan_prefix(base_key.to_db_key())
+            .values()
+            .map(|value| {
+                let value = value.context("could not read flake check")?;
+                serde_json::from_slice(&value).context("could not deserialize flake check")
+            })
+            .collect()
+    }
+
+    /// Big-endian, so a job's checks sort by when they started.
+    fn db_key(base_key: &job::Key<job::Base>, started_ms: u64) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[..8].copy_from_slice(&base_key.to_db_key());
+        key[8..].copy_from_slice(&started_ms.to_be_bytes());
+        key
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{Db, Tree};
+
+    fn check(started_ms: u64, failures: &[bool]) -> FlakeCheck {
+        FlakeCheck {
+            job: "cc-3fa2".to_string(),
+            started_ms,
+            runs: failures
+                .iter()
+                .enumerate()
+                .map(|(index, failed)| Run {
+                    log_key: format!("{:x}", index),
+                    duration_ms: 1000 * (index as u64 + 1),
+                    failure: failed.then(|| "command failed with the exit code 1".to_string()),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn summarizes_failed_runs() {
+        assert_eq!(
+            "cc-3fa2 failed 1 of 4 runs (25%)\n\
+             runs took 1.0s to 4.0s (median 3.0s)\n    \
+             run 2 (2.0s, logs: 1): command failed with the exit code 1",
+            check(0, &[false, true, false, false]).to_string()
+        );
+    }
+
+    #[test]
+    fn keeps_every_check_in_order() {
+        let db = Db::temporary();
+        let history = History::new(db.tree(Tree::FlakeChecks).unwrap());
+        let key: job::Key<job::Base> = serde_json::from_str("1").unwrap();
+
+        let later = check(2000, &[false, false]);
+        let earlier = check(1000, &[true, false]);
+        history.record(&key, &later).unwrap();
+        history.record(&key, &earlier).unwrap();
+
+        assert_eq!(vec![earlier, later], history.for_job(&key).unwrap());
+    }
+}
@@ -13,11 +13,11 @@
 #![allow(clippy::unused_unit)]
 #![allow(clippy::missing_safety_doc)]
 #![allow(clippy::let_and_return)]
-#![allow(clippy::missing_safety_doc)]
-#![allow(clippy::redundant_static_lifetimes)]
 #![allow(clippy::needless_borrow)]
 #![allow(clippy::clone_on_copy)]
 #![allow(clippy::explicit_auto_deref)]
+#![allow(clippy::non_canonical_partial_ord_impl)]
+#![allow(clippy::enum_variant_names)]
 
 #[cfg(any(
     target_arch = "arm",
@@ -29,15 +29,19 @@
 #[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
 #[repr(u8)]
 pub enum discriminant_U1 {
-    FromJob = 0,
-    FromProjectSource = 1,
+    FromArchive = 0,
+    FromJob = 1,
+    FromProjectSource = 2,
+    FromResolver = 3,
 }
 
 impl core::fmt::Debug for discriminant_U1 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
+            Self::FromArchive => f.write_str("discriminant_U1::FromArchive"),
             Self::FromJob => f.write_str("discriminant_U1::FromJob"),
             Self::FromProjectSource => f.write_str("discriminant_U1::FromProjectSource"),
+            Self::FromResolver => f.write_str("discriminant_U1::FromResolver"),
         }
     }
 }
@@ -45,9 +49,11 @@ impl core::fmt::Debug for discriminant_U1 {
 #[cfg(any(target_arch = "arm", target_arch = "wasm32", target_arch = "x86"))]
 #[repr(C)]
 pub union U1 {
+    FromArchive: core::mem::ManuallyDrop<U1_FromArchive>,
     FromJob: core::mem::ManuallyDrop<U1_FromJob>,
     FromProjectSource: core::mem::ManuallyDrop<roc_std::RocList<FileMapping>>,
-    _sizer: [u8; 20],
+    FromResolver: core::mem::ManuallyDrop<U1_FromResolver>,
+    _sizer: [u8; 40],
 }
 
 #[cfg(any(
@@ -64,6 +70,43 @@ pub struct FileMapping {
     pub source: roc_std::RocStr,
 }
 
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct Define {
+    pub name: roc_std::RocStr,
+    pub value: roc_std::RocStr,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum Limit {
+    OpenFiles = 0,
+    StackSize = 1,
+}
+
+impl core::fmt::Debug for Limit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::OpenFiles => f.write_str("Limit::OpenFiles"),
+            Self::StackSize => f.write_str("Limit::StackSize"),
+        }
+    }
+}
+
 #[cfg(any(
     target_arch = "arm",
     target_arch = "aarch64",
@@ -72,9 +115,228 @@ pub struct FileMapping {
     target_arch = "x86_64"
 ))]
 #[derive(Clone, Debug, Eq, Ord, Hash, PartialEq, PartialOrd)]
-#[repr(transparent)]
+#[repr(C)]
+pub struct R3 {
+    pub value: u64,
+    pub limit: Limit,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum RetentionKind {
+    Days = 0,
+    Default = 1,
+    Ephemeral = 2,
+    Forever = 3,
+}
+
+impl core::fmt::Debug for RetentionKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Days => f.write_str("RetentionKind::Days"),
+            Self::Default => f.write_str("RetentionKind::Default"),
+            Self::Ephemeral => f.write_str("RetentionKind::Ephemeral"),
+            Self::Forever => f.write_str("RetentionKind::Forever"),
+        }
+    }
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct R4 {
+    pub days: u32,
+    pub kind: RetentionKind,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct R5 {
+    pub name: roc_std::RocStr,
+    pub values: roc_std::RocList<roc_std::RocStr>,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct R6 {
+    pub address: roc_std::RocStr,
+    pub name: roc_std::RocStr,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum InputStrategy {
+    Copy = 0,
+    Hardlink = 1,
+    Symlink = 2,
+}
+
+impl core::fmt::Debug for InputStrategy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Copy => f.write_str("InputStrategy::Copy"),
+            Self::Hardlink => f.write_str("InputStrategy::Hardlink"),
+            Self::Symlink => f.write_str("InputStrategy::Symlink"),
+        }
+    }
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum JobKind {
+    Build = 0,
+    Test = 1,
+}
+
+impl core::fmt::Debug for JobKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Build => f.write_str("JobKind::Build"),
+            Self::Test => f.write_str("JobKind::Test"),
+        }
+    }
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum Visibility {
+    Local = 0,
+    Public = 1,
+    Team = 2,
+}
+
+impl core::fmt::Debug for Visibility {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Local => f.write_str("Visibility::Local"),
+            Self::Public => f.write_str("Visibility::Public"),
+            Self::Team => f.write_str("Visibility::Team"),
+        }
+    }
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum Network {
+    Allowed = 0,
+    Forbidden = 1,
+}
+
+impl core::fmt::Debug for Network {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Allowed => f.write_str("Network::Allowed"),
+            Self::Forbidden => f.write_str("Network::Forbidden"),
+        }
+    }
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum OutputFilter {
+    NormalizeArchive = 0,
+    Strip = 1,
+    ZeroZipTimestamps = 2,
+}
+
+impl core::fmt::Debug for OutputFilter {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NormalizeArchive => f.write_str("OutputFilter::NormalizeArchive"),
+            Self::Strip => f.write_str("OutputFilter::Strip"),
+            Self::ZeroZipTimestamps => f.write_str("OutputFilter::ZeroZipTimestamps"),
+        }
+    }
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct R2 {
+    pub output: roc_std::RocStr,
+    pub filter: OutputFilter,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
 pub struct Rbt {
     pub default: Job,
+    pub targets: roc_std::RocDict<roc_std::RocStr, Job>,
 }
 
 #[cfg(any(
@@ -124,10 +386,50 @@ struct Job_Job {
 #[derive(Clone, Debug, Eq, Ord, Hash, PartialEq, PartialOrd)]
 #[repr(C)]
 pub struct R1 {
+    pub after: roc_std::RocList<Job>,
+    pub checkpointDir: roc_std::RocStr,
     pub command: Command,
+    pub dnsServers: roc_std::RocList<roc_std::RocStr>,
     pub env: roc_std::RocDict<roc_std::RocStr, roc_std::RocStr>,
+    pub hosts: roc_std::RocList<R6>,
+    pub incrementalState: roc_std::RocList<roc_std::RocStr>,
     pub inputs: roc_std::RocList<U1>,
+    pub limits: roc_std::RocList<R3>,
+    pub matrix: roc_std::RocList<R5>,
+    pub outputFilters: roc_std::RocList<R2>,
+    pub outputFromStdout: roc_std::RocStr,
     pub outputs: roc_std::RocList<roc_std::RocStr>,
+    pub passthroughEnv: roc_std::RocList<roc_std::RocStr>,
+    pub resources: roc_std::RocList<roc_std::RocStr>,
+    pub responseFile: roc_std::RocStr,
+    pub thenRun: roc_std::RocList<Command>,
+    pub writableOutputs: roc_std::RocList<roc_std::RocStr>,
+    pub retention: R4,
+    pub shards: u32,
+    pub allowHostPaths: bool,
+    pub expectFailure: bool,
+    pub inputStrategy: InputStrategy,
+    pub kind: JobKind,
+    pub network: Network,
+    pub persistentWorker: bool,
+    pub rerunOnMissingOutputs: bool,
+    pub stamp: bool,
+    pub visibility: Visibility,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+struct U1_FromArchive {
+    pub f0: roc_std::RocStr,
+    pub f1: roc_std::RocStr,
+    pub f2: roc_std::RocList<FileMapping>,
 }
 
 #[cfg(any(
@@ -144,6 +446,21 @@ struct U1_FromJob {
     pub f1: roc_std::RocList<FileMapping>,
 }
 
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+struct U1_FromResolver {
+    pub f0: roc_std::RocStr,
+    pub f1: roc_std::RocStr,
+    pub f2: roc_std::RocList<FileMapping>,
+}
+
 #[cfg(any(
     target_arch = "arm",
     target_arch = "aarch64",
@@ -165,10 +482,59 @@ pub struct Command {
     target_arch = "x86",
     target_arch = "x86_64"
 ))]
-#[repr(transparent)]
-#[derive(Clone, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
-pub struct Tool {
-    f0: SystemToolPayload,
+#[derive(Clone, Copy, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum discriminant_Tool {
+    FromJob = 0,
+    PinnedTool = 1,
+    SystemTool = 2,
+}
+
+impl core::fmt::Debug for discriminant_Tool {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::FromJob => f.write_str("discriminant_Tool::FromJob"),
+            Self::PinnedTool => f.write_str("discriminant_Tool::PinnedTool"),
+            Self::SystemTool => f.write_str("discriminant_Tool::SystemTool"),
+        }
+    }
+}
+
+#[cfg(any(target_arch = "arm", target_arch = "wasm32", target_arch = "x86"))]
+#[repr(C)]
+pub union Tool {
+    FromJob: core::mem::ManuallyDrop<FromJobPayload>,
+    PinnedTool: core::mem::ManuallyDrop<PinnedToolPayload>,
+    SystemTool: core::mem::ManuallyDrop<SystemToolPayload>,
+    _sizer: [u8; 28],
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct FromJobPayload {
+    pub job: Job,
+    pub name: roc_std::RocStr,
+}
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "wasm32",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Debug, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct PinnedToolPayload {
+    pub path: roc_std::RocStr,
+    pub sha256: roc_std::RocStr,
 }
 
 #[cfg(any(
@@ -186,10 +552,21 @@ pub struct SystemToolPayload {
 
 #[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
 #[repr(C)]
-pub union U1 {
-    FromJob: core::mem::ManuallyDrop<U1_FromJob>,
-    FromProjectSource: core::mem::ManuallyDrop<roc_std::RocList<FileMapping>>,
-    _sizer: [u8; 40],
+pub union Tool {
+    FromJob: core::mem::ManuallyDrop<FromJobPayload>,
+    PinnedTool: core::mem::ManuallyDrop<PinnedToolPayload>,
+    SystemTool: core::mem::ManuallyDrop<SystemToolPayload>,
+    _sizer: [u8; 56],
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+#[repr(C)]
+pub union U1 {
+    FromArchive: core::mem::ManuallyDrop<U1_FromArchive>,
+    FromJob: core::mem::ManuallyDrop<U1_FromJob>,
+    FromProjectSource: core::mem::ManuallyDrop<roc_std::RocList<FileMapping>>,
+    FromResolver: core::mem::ManuallyDrop<U1_FromResolver>,
+    _sizer: [u8; 80],
 }
 
 impl U1 {
@@ -199,7 +576,7 @@ impl U1 {
         unsafe {
             let bytes = core::mem::transmute::<&Self, &[u8; core::mem::size_of::<Self>()]>(self);
 
-            core::mem::transmute::<u8, discriminant_U1>(*bytes.as_ptr().add(16))
+            core::mem::transmute::<u8, discriminant_U1>(*bytes.as_ptr().add(36))
         }
     }
 
@@ -209,10 +586,94 @@ impl U1 {
         let discriminant_ptr: *mut discriminant_U1 = (self as *mut U1).cast();
 
         unsafe {
-            *(discriminant_ptr.add(16)) = discriminant;
+            *(discriminant_ptr.add(36)) = discriminant;
         }
     }
 
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Construct a tag named `FromArchive`, with the appropriate payload
+    pub fn FromArchive(
+        arg0: roc_std::RocStr,
+        arg1: roc_std::RocStr,
+        arg2: roc_std::RocList<FileMapping>,
+    ) -> Self {
+        let mut answer = Self {
+            FromArchive: core::mem::ManuallyDrop::new(U1_FromArchive {
+                f0: arg0,
+                f1: arg1,
+                f2: arg2,
+            }),
+        };
+
+        answer.set_discriminant(discriminant_U1::FromArchive);
+
+        answer
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `U1` has a `.discriminant()` of `FromArchive` and convert it to `FromArchive`'s payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `FromArchive`.
+    pub unsafe fn into_FromArchive(
+        mut self,
+    ) -> (
+        roc_std::RocStr,
+        roc_std::RocStr,
+        roc_std::RocList<FileMapping>,
+    ) {
+        debug_assert_eq!(self.discriminant(), discriminant_U1::FromArchive);
+        let payload = {
+            let mut uninitialized = core::mem::MaybeUninit::uninit();
+            let swapped = unsafe {
+                core::mem::replace(
+                    &mut self.FromArchive,
+                    core::mem::ManuallyDrop::new(uninitialized.assume_init()),
+                )
+            };
+
+            core::mem::forget(self);
+
+            core::mem::ManuallyDrop::into_inner(swapped)
+        };
+
+        (payload.f0, payload.f1, payload.f2)
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `U1` has a `.discriminant()` of `FromArchive` and return its payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `FromArchive`.
+    pub unsafe fn as_FromArchive(
+        &self,
+    ) -> (
+        &roc_std::RocStr,
+        &roc_std::RocStr,
+        &roc_std::RocList<FileMapping>,
+    ) {
+        debug_assert_eq!(self.discriminant(), discriminant_U1::FromArchive);
+        let payload = &self.FromArchive;
+
+        (&payload.f0, &payload.f1, &payload.f2)
+    }
+
     #[cfg(any(
         target_arch = "arm",
         target_arch = "aarch64",
@@ -341,13 +802,97 @@ impl U1 {
         &payload
     }
 
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Construct a tag named `FromResolver`, with the appropriate payload
+    pub fn FromResolver(
+        arg0: roc_std::RocStr,
+        arg1: roc_std::RocStr,
+        arg2: roc_std::RocList<FileMapping>,
+    ) -> Self {
+        let mut answer = Self {
+            FromResolver: core::mem::ManuallyDrop::new(U1_FromResolver {
+                f0: arg0,
+                f1: arg1,
+                f2: arg2,
+            }),
+        };
+
+        answer.set_discriminant(discriminant_U1::FromResolver);
+
+        answer
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `U1` has a `.discriminant()` of `FromResolver` and convert it to `FromResolver`'s payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `FromResolver`.
+    pub unsafe fn into_FromResolver(
+        mut self,
+    ) -> (
+        roc_std::RocStr,
+        roc_std::RocStr,
+        roc_std::RocList<FileMapping>,
+    ) {
+        debug_assert_eq!(self.discriminant(), discriminant_U1::FromResolver);
+        let payload = {
+            let mut uninitialized = core::mem::MaybeUninit::uninit();
+            let swapped = unsafe {
+                core::mem::replace(
+                    &mut self.FromResolver,
+                    core::mem::ManuallyDrop::new(uninitialized.assume_init()),
+                )
+            };
+
+            core::mem::forget(self);
+
+            core::mem::ManuallyDrop::into_inner(swapped)
+        };
+
+        (payload.f0, payload.f1, payload.f2)
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `U1` has a `.discriminant()` of `FromResolver` and return its payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `FromResolver`.
+    pub unsafe fn as_FromResolver(
+        &self,
+    ) -> (
+        &roc_std::RocStr,
+        &roc_std::RocStr,
+        &roc_std::RocList<FileMapping>,
+    ) {
+        debug_assert_eq!(self.discriminant(), discriminant_U1::FromResolver);
+        let payload = &self.FromResolver;
+
+        (&payload.f0, &payload.f1, &payload.f2)
+    }
+
     #[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
     /// Returns which variant this tag union holds. Note that this never includes a payload!
     pub fn discriminant(&self) -> discriminant_U1 {
         unsafe {
             let bytes = core::mem::transmute::<&Self, &[u8; core::mem::size_of::<Self>()]>(self);
 
-            core::mem::transmute::<u8, discriminant_U1>(*bytes.as_ptr().add(32))
+            core::mem::transmute::<u8, discriminant_U1>(*bytes.as_ptr().add(72))
         }
     }
 
@@ -357,7 +902,7 @@ impl U1 {
         let discriminant_ptr: *mut discriminant_U1 = (self as *mut U1).cast();
 
         unsafe {
-            *(discriminant_ptr.add(32)) = discriminant;
+            *(discriminant_ptr.add(72)) = discriminant;
         }
     }
 }
@@ -373,10 +918,16 @@ impl Drop for U1 {
     fn drop(&mut self) {
         // Drop the payloads
         match self.discriminant() {
+            discriminant_U1::FromArchive => unsafe {
+                core::mem::ManuallyDrop::drop(&mut self.FromArchive)
+            },
             discriminant_U1::FromJob => unsafe { core::mem::ManuallyDrop::drop(&mut self.FromJob) },
             discriminant_U1::FromProjectSource => unsafe {
                 core::mem::ManuallyDrop::drop(&mut self.FromProjectSource)
             },
+            discriminant_U1::FromResolver => unsafe {
+                core::mem::ManuallyDrop::drop(&mut self.FromResolver)
+            },
         }
     }
 }
@@ -398,10 +949,12 @@ impl PartialEq for U1 {
 
         unsafe {
             match self.discriminant() {
+                discriminant_U1::FromArchive => self.FromArchive == other.FromArchive,
                 discriminant_U1::FromJob => self.FromJob == other.FromJob,
                 discriminant_U1::FromProjectSource => {
                     self.FromProjectSource == other.FromProjectSource
                 }
+                discriminant_U1::FromResolver => self.FromResolver == other.FromResolver,
             }
         }
     }
@@ -423,10 +976,12 @@ impl PartialOrd for U1 {
 
         unsafe {
             match self.discriminant() {
+                discriminant_U1::FromArchive => self.FromArchive.partial_cmp(&other.FromArchive),
                 discriminant_U1::FromJob => self.FromJob.partial_cmp(&other.FromJob),
                 discriminant_U1::FromProjectSource => {
                     self.FromProjectSource.partial_cmp(&other.FromProjectSource)
                 }
+                discriminant_U1::FromResolver => self.FromResolver.partial_cmp(&other.FromResolver),
             }
         }
     }
@@ -448,10 +1003,12 @@ impl Ord for U1 {
 
         unsafe {
             match self.discriminant() {
+                discriminant_U1::FromArchive => self.FromArchive.cmp(&other.FromArchive),
                 discriminant_U1::FromJob => self.FromJob.cmp(&other.FromJob),
                 discriminant_U1::FromProjectSource => {
                     self.FromProjectSource.cmp(&other.FromProjectSource)
                 }
+                discriminant_U1::FromResolver => self.FromResolver.cmp(&other.FromResolver),
             }
         }
     }
@@ -468,12 +1025,18 @@ impl Clone for U1 {
     fn clone(&self) -> Self {
         let mut answer = unsafe {
             match self.discriminant() {
+                discriminant_U1::FromArchive => Self {
+                    FromArchive: self.FromArchive.clone(),
+                },
                 discriminant_U1::FromJob => Self {
                     FromJob: self.FromJob.clone(),
                 },
                 discriminant_U1::FromProjectSource => Self {
                     FromProjectSource: self.FromProjectSource.clone(),
                 },
+                discriminant_U1::FromResolver => Self {
+                    FromResolver: self.FromResolver.clone(),
+                },
             }
         };
 
@@ -493,6 +1056,10 @@ impl core::hash::Hash for U1 {
     ))]
     fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         match self.discriminant() {
+            discriminant_U1::FromArchive => unsafe {
+                discriminant_U1::FromArchive.hash(state);
+                self.FromArchive.hash(state);
+            },
             discriminant_U1::FromJob => unsafe {
                 discriminant_U1::FromJob.hash(state);
                 self.FromJob.hash(state);
@@ -501,6 +1068,10 @@ impl core::hash::Hash for U1 {
                 discriminant_U1::FromProjectSource.hash(state);
                 self.FromProjectSource.hash(state);
             },
+            discriminant_U1::FromResolver => unsafe {
+                discriminant_U1::FromResolver.hash(state);
+                self.FromResolver.hash(state);
+            },
         }
     }
 }
@@ -518,6 +1089,12 @@ impl core::fmt::Debug for U1 {
 
         unsafe {
             match self.discriminant() {
+                discriminant_U1::FromArchive => f
+                    .debug_tuple("FromArchive")
+                    .field(&(&*self.FromArchive).f0)
+                    .field(&(&*self.FromArchive).f1)
+                    .field(&(&*self.FromArchive).f2)
+                    .finish(),
                 discriminant_U1::FromJob => f
                     .debug_tuple("FromJob")
                     .field(&(&*self.FromJob).f0)
@@ -527,6 +1104,12 @@ impl core::fmt::Debug for U1 {
                     .debug_tuple("FromProjectSource")
                     .field(&*self.FromProjectSource)
                     .finish(),
+                discriminant_U1::FromResolver => f
+                    .debug_tuple("FromResolver")
+                    .field(&(&*self.FromResolver).f0)
+                    .field(&(&*self.FromResolver).f1)
+                    .field(&(&*self.FromResolver).f2)
+                    .finish(),
             }
         }
     }
@@ -787,6 +1370,73 @@ impl core::fmt::Debug for Job {
 }
 
 impl Tool {
+    #[cfg(any(target_arch = "arm", target_arch = "wasm32", target_arch = "x86"))]
+    /// Returns which variant this tag union holds. Note that this never includes a payload!
+    pub fn discriminant(&self) -> discriminant_Tool {
+        unsafe {
+            let bytes = core::mem::transmute::<&Self, &[u8; core::mem::size_of::<Self>()]>(self);
+
+            core::mem::transmute::<u8, discriminant_Tool>(*bytes.as_ptr().add(24))
+        }
+    }
+
+    #[cfg(any(target_arch = "arm", target_arch = "wasm32", target_arch = "x86"))]
+    /// Internal helper
+    fn set_discriminant(&mut self, discriminant: discriminant_Tool) {
+        let discriminant_ptr: *mut discriminant_Tool = (self as *mut Tool).cast();
+
+        unsafe {
+            *(discriminant_ptr.add(24)) = discriminant;
+        }
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Construct a tag named `FromJob`, with the appropriate payload
+    pub fn FromJob(arg: FromJobPayload) -> Self {
+        let mut answer = Self {
+            FromJob: core::mem::ManuallyDrop::new(arg),
+        };
+
+        answer.set_discriminant(discriminant_Tool::FromJob);
+
+        answer
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `Tool` has a `.discriminant()` of `FromJob` and convert it to `FromJob`'s payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `FromJob`.
+    pub unsafe fn into_FromJob(mut self) -> FromJobPayload {
+        debug_assert_eq!(self.discriminant(), discriminant_Tool::FromJob);
+        let payload = {
+            let mut uninitialized = core::mem::MaybeUninit::uninit();
+            let swapped = unsafe {
+                core::mem::replace(
+                    &mut self.FromJob,
+                    core::mem::ManuallyDrop::new(uninitialized.assume_init()),
+                )
+            };
+
+            core::mem::forget(self);
+
+            core::mem::ManuallyDrop::into_inner(swapped)
+        };
+
+        payload
+    }
+
     #[cfg(any(
         target_arch = "arm",
         target_arch = "aarch64",
@@ -794,9 +1444,14 @@ impl Tool {
         target_arch = "x86",
         target_arch = "x86_64"
     ))]
-    /// A tag named SystemTool, with the given payload.
-    pub fn SystemTool(f0: SystemToolPayload) -> Self {
-        Self { f0 }
+    /// Unsafely assume the given `Tool` has a `.discriminant()` of `FromJob` and return its payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `FromJob`.
+    pub unsafe fn as_FromJob(&self) -> &FromJobPayload {
+        debug_assert_eq!(self.discriminant(), discriminant_Tool::FromJob);
+        let payload = &self.FromJob;
+
+        &payload
     }
 
     #[cfg(any(
@@ -806,10 +1461,15 @@ impl Tool {
         target_arch = "x86",
         target_arch = "x86_64"
     ))]
-    /// Since `SystemTool` only has one tag (namely, `SystemTool`),
-    /// convert it to `SystemTool`'s payload.
-    pub fn into_SystemTool(self) -> SystemToolPayload {
-        self.f0
+    /// Construct a tag named `PinnedTool`, with the appropriate payload
+    pub fn PinnedTool(arg: PinnedToolPayload) -> Self {
+        let mut answer = Self {
+            PinnedTool: core::mem::ManuallyDrop::new(arg),
+        };
+
+        answer.set_discriminant(discriminant_Tool::PinnedTool);
+
+        answer
     }
 
     #[cfg(any(
@@ -819,10 +1479,279 @@ impl Tool {
         target_arch = "x86",
         target_arch = "x86_64"
     ))]
-    /// Since `SystemTool` only has one tag (namely, `SystemTool`),
-    /// convert it to `SystemTool`'s payload.
-    pub fn as_SystemTool(&self) -> &SystemToolPayload {
-        &self.f0
+    /// Unsafely assume the given `Tool` has a `.discriminant()` of `PinnedTool` and convert it to `PinnedTool`'s payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `PinnedTool`.
+    pub unsafe fn into_PinnedTool(mut self) -> PinnedToolPayload {
+        debug_assert_eq!(self.discriminant(), discriminant_Tool::PinnedTool);
+        let payload = {
+            let mut uninitialized = core::mem::MaybeUninit::uninit();
+            let swapped = unsafe {
+                core::mem::replace(
+                    &mut self.PinnedTool,
+                    core::mem::ManuallyDrop::new(uninitialized.assume_init()),
+                )
+            };
+
+            core::mem::forget(self);
+
+            core::mem::ManuallyDrop::into_inner(swapped)
+        };
+
+        payload
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `Tool` has a `.discriminant()` of `PinnedTool` and return its payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `PinnedTool`.
+    pub unsafe fn as_PinnedTool(&self) -> &PinnedToolPayload {
+        debug_assert_eq!(self.discriminant(), discriminant_Tool::PinnedTool);
+        let payload = &self.PinnedTool;
+
+        &payload
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Construct a tag named `SystemTool`, with the appropriate payload
+    pub fn SystemTool(arg: SystemToolPayload) -> Self {
+        let mut answer = Self {
+            SystemTool: core::mem::ManuallyDrop::new(arg),
+        };
+
+        answer.set_discriminant(discriminant_Tool::SystemTool);
+
+        answer
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `Tool` has a `.discriminant()` of `SystemTool` and convert it to `SystemTool`'s payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `SystemTool`.
+    pub unsafe fn into_SystemTool(mut self) -> SystemToolPayload {
+        debug_assert_eq!(self.discriminant(), discriminant_Tool::SystemTool);
+        let payload = {
+            let mut uninitialized = core::mem::MaybeUninit::uninit();
+            let swapped = unsafe {
+                core::mem::replace(
+                    &mut self.SystemTool,
+                    core::mem::ManuallyDrop::new(uninitialized.assume_init()),
+                )
+            };
+
+            core::mem::forget(self);
+
+            core::mem::ManuallyDrop::into_inner(swapped)
+        };
+
+        payload
+    }
+
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    /// Unsafely assume the given `Tool` has a `.discriminant()` of `SystemTool` and return its payload.
+    /// (Always examine `.discriminant()` first to make sure this is the correct variant!)
+    /// Panics in debug builds if the `.discriminant()` doesn't return `SystemTool`.
+    pub unsafe fn as_SystemTool(&self) -> &SystemToolPayload {
+        debug_assert_eq!(self.discriminant(), discriminant_Tool::SystemTool);
+        let payload = &self.SystemTool;
+
+        &payload
+    }
+
+    #[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+    /// Returns which variant this tag union holds. Note that this never includes a payload!
+    pub fn discriminant(&self) -> discriminant_Tool {
+        unsafe {
+            let bytes = core::mem::transmute::<&Self, &[u8; core::mem::size_of::<Self>()]>(self);
+
+            core::mem::transmute::<u8, discriminant_Tool>(*bytes.as_ptr().add(48))
+        }
+    }
+
+    #[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+    /// Internal helper
+    fn set_discriminant(&mut self, discriminant: discriminant_Tool) {
+        let discriminant_ptr: *mut discriminant_Tool = (self as *mut Tool).cast();
+
+        unsafe {
+            *(discriminant_ptr.add(48)) = discriminant;
+        }
+    }
+}
+
+impl Drop for Tool {
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    fn drop(&mut self) {
+        // Drop the payloads
+        match self.discriminant() {
+            discriminant_Tool::FromJob => unsafe {
+                core::mem::ManuallyDrop::drop(&mut self.FromJob)
+            },
+            discriminant_Tool::PinnedTool => unsafe {
+                core::mem::ManuallyDrop::drop(&mut self.PinnedTool)
+            },
+            discriminant_Tool::SystemTool => unsafe {
+                core::mem::ManuallyDrop::drop(&mut self.SystemTool)
+            },
+        }
+    }
+}
+
+impl Eq for Tool {}
+
+impl PartialEq for Tool {
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    fn eq(&self, other: &Self) -> bool {
+        if self.discriminant() != other.discriminant() {
+            return false;
+        }
+
+        unsafe {
+            match self.discriminant() {
+                discriminant_Tool::FromJob => self.FromJob == other.FromJob,
+                discriminant_Tool::PinnedTool => self.PinnedTool == other.PinnedTool,
+                discriminant_Tool::SystemTool => self.SystemTool == other.SystemTool,
+            }
+        }
+    }
+}
+
+impl PartialOrd for Tool {
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        match self.discriminant().partial_cmp(&other.discriminant()) {
+            Some(core::cmp::Ordering::Equal) => {}
+            not_eq => return not_eq,
+        }
+
+        unsafe {
+            match self.discriminant() {
+                discriminant_Tool::FromJob => self.FromJob.partial_cmp(&other.FromJob),
+                discriminant_Tool::PinnedTool => self.PinnedTool.partial_cmp(&other.PinnedTool),
+                discriminant_Tool::SystemTool => self.SystemTool.partial_cmp(&other.SystemTool),
+            }
+        }
+    }
+}
+
+impl Ord for Tool {
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        match self.discriminant().cmp(&other.discriminant()) {
+            core::cmp::Ordering::Equal => {}
+            not_eq => return not_eq,
+        }
+
+        unsafe {
+            match self.discriminant() {
+                discriminant_Tool::FromJob => self.FromJob.cmp(&other.FromJob),
+                discriminant_Tool::PinnedTool => self.PinnedTool.cmp(&other.PinnedTool),
+                discriminant_Tool::SystemTool => self.SystemTool.cmp(&other.SystemTool),
+            }
+        }
+    }
+}
+
+impl Clone for Tool {
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    fn clone(&self) -> Self {
+        let mut answer = unsafe {
+            match self.discriminant() {
+                discriminant_Tool::FromJob => Self {
+                    FromJob: self.FromJob.clone(),
+                },
+                discriminant_Tool::PinnedTool => Self {
+                    PinnedTool: self.PinnedTool.clone(),
+                },
+                discriminant_Tool::SystemTool => Self {
+                    SystemTool: self.SystemTool.clone(),
+                },
+            }
+        };
+
+        answer.set_discriminant(self.discriminant());
+
+        answer
+    }
+}
+
+impl core::hash::Hash for Tool {
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        match self.discriminant() {
+            discriminant_Tool::FromJob => unsafe {
+                discriminant_Tool::FromJob.hash(state);
+                self.FromJob.hash(state);
+            },
+            discriminant_Tool::PinnedTool => unsafe {
+                discriminant_Tool::PinnedTool.hash(state);
+                self.PinnedTool.hash(state);
+            },
+            discriminant_Tool::SystemTool => unsafe {
+                discriminant_Tool::SystemTool.hash(state);
+                self.SystemTool.hash(state);
+            },
+        }
     }
 }
 
@@ -835,6 +1764,22 @@ impl core::fmt::Debug for Tool {
         target_arch = "x86_64"
     ))]
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_tuple("Tool::SystemTool").field(&self.f0).finish()
+        f.write_str("Tool::")?;
+
+        unsafe {
+            match self.discriminant() {
+                discriminant_Tool::FromJob => {
+                    f.debug_tuple("FromJob").field(&*self.FromJob).finish()
+                }
+                discriminant_Tool::PinnedTool => f
+                    .debug_tuple("PinnedTool")
+                    .field(&*self.PinnedTool)
+                    .finish(),
+                discriminant_Tool::SystemTool => f
+                    .debug_tuple("SystemTool")
+                    .field(&*self.SystemTool)
+                    .finish(),
+            }
+        }
     }
 }
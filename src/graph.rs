@@ -0,0 +1,412 @@
+use crate::job::{self, Job};
+use crate::report::{Outcome, Report};
+use crate::ui;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The jobs in a build and how they depend on each other, for drawing big
+/// builds with Graphviz or feeding them to other tools.
+#[derive(Debug, serde::Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Node {
+    pub key: String,
+
+    /// The same thing we'd show for the job in logs.
+    pub label: String,
+
+    /// Was this job asked for directly, as opposed to being a dependency?
+    pub root: bool,
+
+    /// What happened to the job in the last build, if it was in it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<Outcome>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+
+    /// Is this job on the longest chain of dependencies in the last build,
+    /// by how long the jobs took? Speeding up anything else won't make the
+    /// build finish sooner.
+    pub critical: bool,
+}
+
+/// `from` has to finish before `to` can start.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+
+    /// Is this edge between two jobs on the critical path?
+    pub critical: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EdgeKind {
+    /// `to` uses files from `from` (`fromJob`.)
+    Input,
+
+    /// `to` only has to run after `from` (`runAfter`.)
+    After,
+}
+
+impl Graph {
+    pub fn new<'a>(jobs: impl IntoIterator<Item = &'a Job>, roots: &[job::Key<job::Base>]) -> Self {
+        let roots: HashSet<&job::Key<job::Base>> = roots.iter().collect();
+
+        let mut jobs: Vec<&Job> = jobs.into_iter().collect();
+        jobs.sort_by_key(|job| job.base_key);
+
+        let mut nodes = Vec::with_capacity(jobs.len());
+        let mut edges = Vec::new();
+
+        for job in jobs {
+            nodes.push(Node {
+                key: job.base_key.to_string(),
+                label: job.to_string(),
+                root: roots.contains(&job.base_key),
+                state: None,
+                duration_ms: None,
+                critical: false,
+            });
+
+            for (deps, kind) in [
+                (job.input_jobs.keys().collect::<Vec<_>>(), EdgeKind::Input),
+                (job.after.iter().collect(), EdgeKind::After),
+            ] {
+                for dep in deps {
+                    edges.push(Edge {
+                        from: dep.to_string(),
+                        to: job.base_key.to_string(),
+                        kind,
+                        critical: false,
+                    });
+                }
+            }
+        }
+
+        Graph { nodes, edges }
+    }
+
+    /// Fill in what happened to each job in `report` (usually the last
+    /// build's), and find the critical path through the jobs it has
+    /// durations for. Jobs whose keys changed since then aren't in it, so
+    /// they stay blank.
+    pub fn annotate(&mut self, report: &Report) {
+        let by_key: HashMap<&str, _> = report
+            .jobs
+            .iter()
+            .map(|job| (job.key.as_str(), job))
+            .collect();
+
+        for node in &mut self.nodes {
+            if let Some(job) = by_key.get(node.key.as_str()) {
+                node.state = Some(job.outcome);
+                node.duration_ms = Some(job.duration_ms);
+            }
+        }
+
+        self.mark_critical_path();
+    }
+
+    /// The critical path ends at the job that finished last if everything
+    /// had started as soon as its dependencies were done. We walk back from
+    /// there through whichever dependency finished last each time.
+    fn mark_critical_path(&mut self) {
+        let index: HashMap<&str, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.key.as_str(), i))
+            .collect();
+
+        let mut deps: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for edge in &self.edges {
+            if let (Some(&from), Some(&to)) =
+                (index.get(edge.from.as_str()), index.get(edge.to.as_str()))
+            {
+                deps[to].push(from);
+            }
+        }
+
+        let mut memo = vec![None; self.nodes.len()];
+        let finishes: Vec<u64> = (0..self.nodes.len())
+            .map(|node| finish(node, &self.nodes, &deps, &mut memo))
+            .collect();
+
+        let mut current = match (0..self.nodes.len())
+            .filter(|&node| finishes[node] > 0)
+            .max_by_key(|&node| finishes[node])
+        {
+            Some(node) => node,
+            None => return,
+        };
+
+        let mut path = vec![current];
+        let mut links = HashSet::new();
+        while let Some(dep) = deps[current]
+            .iter()
+            .copied()
+            .filter(|&dep| finishes[dep] > 0)
+            .max_by_key(|&dep| finishes[dep])
+        {
+            links.insert((dep, current));
+            path.push(dep);
+            current = dep;
+        }
+
+        for edge in &mut self.edges {
+            if let (Some(&from), Some(&to)) =
+                (index.get(edge.from.as_str()), index.get(edge.to.as_str()))
+            {
+                edge.critical = links.contains(&(from, to));
+            }
+        }
+
+        for node in path {
+            self.nodes[node].critical = true;
+        }
+    }
+
+    /// Render the graph in Graphviz's DOT language, for `dot -Tsvg`. Jobs
+    /// are filled in by how the last build went, if we know, and the
+    /// critical path is red.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph rbt {\n    node [shape=box];\n");
+
+        for node in &self.nodes {
+            let mut label = escape(&node.label);
+            let mut attributes = String::new();
+
+            if let Some(state) = node.state {
+                // `fmt::Write` for `String` never fails
+                let _ = write!(attributes, ", style=filled, fillcolor={}", color(state));
+
+                match (state, node.duration_ms) {
+                    (Outcome::Ran | Outcome::Failed, Some(millis)) => {
+                        let _ = write!(label, "\\n{}", ui::elapsed(Duration::from_millis(millis)));
+                    }
+                    _ => {
+                        let _ = write!(label, "\\n{}", state.name());
+                    }
+                }
+            }
+            if node.root {
+                attributes.push_str(", penwidth=2");
+            }
+            if node.critical {
+                attributes.push_str(", color=red");
+            }
+
+            let _ = writeln!(
+                out,
+                "    \"{}\" [label=\"{}\"{}];",
+                node.key, label, attributes,
+            );
+        }
+
+        for edge in &self.edges {
+            let mut attributes = Vec::new();
+            if edge.kind == EdgeKind::After {
+                attributes.push("style=dashed");
+            }
+            if edge.critical {
+                attributes.push("color=red, penwidth=2");
+            }
+
+            let _ = write!(out, "    \"{}\" -> \"{}\"", edge.from, edge.to);
+            if !attributes.is_empty() {
+                let _ = write!(out, " [{}]", attributes.join(", "));
+            }
+            out.push_str(";\n");
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// How many milliseconds into the build `node` would finish if every job
+/// started as soon as its dependencies were done. Jobs we don't have a
+/// duration for count as instant. The graph can't have cycles (the
+/// coordinator would have refused it), so this always bottoms out.
+fn finish(node: usize, nodes: &[Node], deps: &[Vec<usize>], memo: &mut [Option<u64>]) -> u64 {
+    if let Some(done) = memo[node] {
+        return done;
+    }
+
+    let mut start = 0;
+    for &dep in &deps[node] {
+        start = start.max(finish(dep, nodes, deps, memo));
+    }
+
+    let done = start + nodes[node].duration_ms.unwrap_or(0);
+    memo[node] = Some(done);
+    done
+}
+
+/// Graphviz color names, picked to be told apart at a glance.
+fn color(state: Outcome) -> &'static str {
+    match state {
+        Outcome::Cached => "lightblue",
+        Outcome::Ran => "palegreen",
+        Outcome::Failed => "salmon",
+        Outcome::Skipped => "lightgrey",
+        Outcome::CutShort => "orange",
+    }
+}
+
+/// Labels come from job commands, which can have anything in them.
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::JobReport;
+
+    #[test]
+    fn renders_dot() {
+        let graph = Graph {
+            nodes: vec![
+                Node {
+                    key: "a".to_string(),
+                    label: "a (bash -c \"echo hi\")".to_string(),
+                    root: false,
+                    state: None,
+                    duration_ms: None,
+                    critical: false,
+                },
+                Node {
+                    key: "b".to_string(),
+                    label: "b (cat)".to_string(),
+                    root: true,
+                    state: None,
+                    duration_ms: None,
+                    critical: false,
+                },
+            ],
+            edges: vec![
+                Edge {
+                    from: "a".to_string(),
+                    to: "b".to_string(),
+                    kind: EdgeKind::Input,
+                    critical: false,
+                },
+                Edge {
+                    from: "a".to_string(),
+                    to: "b".to_string(),
+                    kind: EdgeKind::After,
+                    critical: false,
+                },
+            ],
+        };
+
+        assert_eq!(
+            "digraph rbt {
+    node [shape=box];
+    \"a\" [label=\"a (bash -c \\\"echo hi\\\")\"];
+    \"b\" [label=\"b (cat)\", penwidth=2];
+    \"a\" -> \"b\";
+    \"a\" -> \"b\" [style=dashed];
+}
+",
+            graph.to_dot()
+        );
+    }
+
+    #[test]
+    fn highlights_the_critical_path() {
+        let node = |key: &str| Node {
+            key: key.to_string(),
+            label: key.to_string(),
+            root: key == "link",
+            state: None,
+            duration_ms: None,
+            critical: false,
+        };
+        let edge = |from: &str, to: &str| Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+            kind: EdgeKind::Input,
+            critical: false,
+        };
+        let job = |key: &str, outcome, duration_ms| JobReport {
+            id: key.to_string(),
+            key: key.to_string(),
+            command: key.to_string(),
+            outcome,
+            duration_ms,
+            store_path: None,
+            output_types: Default::default(),
+            unused_inputs: Vec::new(),
+            error: None,
+        };
+
+        // the slow compile holds up linking, so speeding up the fast one
+        // wouldn't help.
+        let mut graph = Graph {
+            nodes: vec![node("fast"), node("slow"), node("lib"), node("link")],
+            edges: vec![
+                edge("fast", "link"),
+                edge("slow", "link"),
+                edge("lib", "link"),
+            ],
+        };
+        graph.annotate(&Report {
+            profile: None,
+            jobs: vec![
+                job("fast", Outcome::Ran, 100),
+                job("slow", Outcome::Ran, 2500),
+                job("lib", Outcome::Cached, 0),
+                job("link", Outcome::Ran, 300),
+            ],
+        });
+
+        assert_eq!(
+            vec![false, true, false, true],
+            graph
+                .nodes
+                .iter()
+                .map(|node| node.critical)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![false, true, false],
+            graph
+                .edges
+                .iter()
+                .map(|edge| edge.critical)
+                .collect::<Vec<_>>()
+        );
+
+        let dot = graph.to_dot();
+        assert!(
+            dot.contains(
+                "\"slow\" [label=\"slow\\n2.5s\", style=filled, fillcolor=palegreen, color=red];"
+            ),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains("\"lib\" [label=\"lib\\ncached\", style=filled, fillcolor=lightblue];"),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains("\"slow\" -> \"link\" [color=red, penwidth=2];"),
+            "{}",
+            dot
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::job;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// The jobs some build is running right now, by final key. Builds that
+/// share one (like the ones the daemon runs side by side) check here before
+/// starting a job. If another build is already running it, they wait for
+/// that to finish and take the output from the store instead of running
+/// the job again in a workspace of their own.
+///
+/// Every build has its own async runtime, so we only use channels that work
+/// across runtimes.
+#[derive(Debug, Clone, Default)]
+pub struct InFlight {
+    running: Arc<Mutex<HashMap<job::Key<job::Final>, watch::Receiver<()>>>>,
+}
+
+#[derive(Debug)]
+pub enum Claim {
+    /// Nobody else is running the job, so it's ours to run.
+    Run(Running),
+
+    /// Another build is running the job, so we should wait for it.
+    Wait(Waiting),
+}
+
+impl InFlight {
+    /// Say we're about to run the job with `key`, unless someone else
+    /// already is.
+    pub fn claim(&self, key: job::Key<job::Final>) -> Claim {
+        let mut running = self.lock();
+        if let Some(done) = running.get(&key) {
+            return Claim::Wait(Waiting { done: done.clone() });
+        }
+
+        let (done, waiting) = watch::channel(());
+        running.insert(key, waiting);
+
+        Claim::Run(Running {
+            key,
+            in_flight: self.clone(),
+            _done: done,
+        })
+    }
+
+    fn lock(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<job::Key<job::Final>, watch::Receiver<()>>> {
+        self.running
+            .lock()
+            .expect("in-flight jobs lock was poisoned")
+    }
+}
+
+/// Our claim on running a job. Hold on to it until the job's output is in
+/// the store (or the job failed), since dropping it wakes everyone waiting.
+#[derive(Debug)]
+pub struct Running {
+    key: job::Key<job::Final>,
+    in_flight: InFlight,
+
+    // we never send anything; waiters find out we're done when this closes
+    _done: watch::Sender<()>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        // this happens before `_done` closes, so anyone who wakes up and
+        // claims the job again won't find us still running it.
+        self.in_flight.lock().remove(&self.key);
+    }
+}
+
+/// Another build's run of a job, which we're waiting on.
+#[derive(Debug)]
+pub struct Waiting {
+    done: watch::Receiver<()>,
+}
+
+impl Waiting {
+    /// Wait for the other build to be done with the job. That doesn't mean
+    /// it worked, so check the store afterwards.
+    pub async fn finished(mut self) {
+        // nothing ever gets sent, so this only returns once the sender is
+        // gone (including if it already was.)
+        while self.done.changed().await.is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(n: u64) -> job::Key<job::Final> {
+        serde_json::from_str(&n.to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn second_claim_waits_for_the_first() {
+        let in_flight = InFlight::default();
+
+        let running = match in_flight.claim(key(1)) {
+            Claim::Run(running) => running,
+            Claim::Wait(_) => panic!("nobody was running the job yet"),
+        };
+        let waiting = match in_flight.claim(key(1)) {
+            Claim::Wait(waiting) => waiting,
+            Claim::Run(_) => panic!("the job was already running"),
+        };
+        assert!(
+            matches!(in_flight.claim(key(2)), Claim::Run(_)),
+            "a different job should be free to run"
+        );
+
+        let waiter = tokio::spawn(waiting.finished());
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        drop(running);
+        waiter.await.unwrap();
+        assert!(
+            matches!(in_flight.claim(key(1)), Claim::Run(_)),
+            "the job should be free to run again once the first run is done"
+        );
+    }
+}
@@ -1,12 +1,18 @@
-use crate::{glue, store};
+use crate::limits::{Limit, Limits};
+use crate::network::{self, Network};
+use crate::output_filter::Filter;
+use crate::store::{Retention, Visibility};
+use crate::workspace::InputStrategy;
+use crate::{file_trace, glue, resolver, store};
 use anyhow::{Context, Result};
 use itertools::Itertools;
+use path_absolutize::Absolutize;
 use roc_std::RocStr;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{self, Display};
-use std::hash::{BuildHasher, Hash, Hasher};
+use std::hash::BuildHasher;
 use std::marker::PhantomData;
-use std::path::{Component, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use xxhash_rust::xxh3::Xxh3;
 
 /// See docs on `Key`
@@ -37,12 +43,152 @@ impl<Finality> Key<Finality> {
     }
 }
 
+impl Key<Base> {
+    /// A key for one run of this job in a flake check that started at
+    /// `started_ms`. Nothing from these runs goes in the store, so this
+    /// isn't a cache key, but logs and checkpoints are kept by final key
+    /// and every run needs its own: the logs so each can be looked at
+    /// afterwards, and the checkpoints so no run resumes from another.
+    pub fn flake_run(&self, started_ms: u64, run: usize) -> Key<Final> {
+        let mut hasher = KeyHasher::new();
+        hasher.u64(self.key);
+        hasher.tag("flakeRun");
+        hasher.u64(started_ms);
+        hasher.len(run);
+
+        Key {
+            key: hasher.finish(),
+            phantom: PhantomData,
+        }
+    }
+}
+
 impl<Finality> Display for Key<Finality> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:x}", self.key)
     }
 }
 
+/// A name for a job that people can read and type: the name of its tool and
+/// the start of its base key, like `cc-3fa2c81e`. We use as much of the key
+/// as it takes to tell every job in the build apart (see `disambiguate`), so
+/// an ID is only stable within a build. Anything that has to outlive the
+/// build should use the whole key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Id {
+    name: String,
+    key: Key<Base>,
+    len: usize,
+}
+
+/// How many hex digits of the key an ID starts with. Collisions at this
+/// length are rare enough that builds almost never need more.
+const MIN_ID_KEY_LEN: usize = 8;
+
+impl Id {
+    fn new(tool: &str, key: Key<Base>) -> Self {
+        let name = Path::new(tool)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| tool.to_string());
+
+        Id {
+            name,
+            key,
+            len: MIN_ID_KEY_LEN,
+        }
+    }
+
+    /// The key in hex, padded so that prefixes line up.
+    fn hex(&self) -> String {
+        format!("{:016x}", self.key.key)
+    }
+
+    /// Does `query` refer to this job? It can be the ID, the whole key, or
+    /// (like abbreviated git commits) any start of the key, with or without
+    /// the name in front.
+    fn matches(&self, query: &str) -> bool {
+        let hex = self.hex();
+        let prefix = match query.strip_prefix(&format!("{}-", self.name)) {
+            Some(prefix) => prefix,
+            None => query,
+        };
+
+        query == self.key.to_string()
+            || (prefix.len() >= MIN_RESOLVE_LEN && hex.starts_with(prefix))
+    }
+}
+
+impl Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.name, &self.hex()[..self.len])
+    }
+}
+
+/// Give every job an ID that no other job in `jobs` has, using the same key
+/// length for all of them so they line up in logs.
+pub fn disambiguate<'a>(jobs: impl IntoIterator<Item = &'a mut Job>) {
+    let mut jobs: Vec<&mut Job> = jobs.into_iter().collect();
+
+    let mut len = MIN_ID_KEY_LEN;
+    while len < 16 {
+        let mut seen = HashSet::with_capacity(jobs.len());
+        if jobs
+            .iter()
+            .all(|job| seen.insert((&job.id.name, job.id.hex()[..len].to_string())))
+        {
+            break;
+        }
+
+        len += 1;
+    }
+
+    for job in jobs.iter_mut() {
+        job.id.len = len;
+    }
+}
+
+/// Don't look for jobs by very short key prefixes, since they'd match
+/// something in most builds whether or not that's what someone meant.
+const MIN_RESOLVE_LEN: usize = 4;
+
+/// Could `query` be an ID or key (as opposed to, say, a misspelled target
+/// name)? Doesn't check whether any job actually has it.
+pub fn looks_like_id(query: &str) -> bool {
+    let hex = match query.rsplit_once('-') {
+        Some((_, hex)) => hex,
+        None => query,
+    };
+
+    hex.len() >= MIN_RESOLVE_LEN && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Find the job that `query` (an ID, a key, or the start of a key) refers
+/// to.
+pub fn resolve<'a>(jobs: impl IntoIterator<Item = &'a Job>, query: &str) -> Result<&'a Job> {
+    let mut matches: Vec<&Job> = jobs
+        .into_iter()
+        .filter(|job| job.id.matches(query))
+        .collect();
+
+    match matches.len() {
+        0 => anyhow::bail!("there's no job matching `{}` in this build", query),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            matches.sort_by_key(|job| job.base_key);
+            anyhow::bail!(
+                "`{}` could be any of these jobs, so please use more of the key: {}",
+                query,
+                matches
+                    .iter()
+                    .map(|job| job.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        }
+    }
+}
+
 #[cfg(test)]
 impl Default for Key<Final> {
     fn default() -> Self {
@@ -53,40 +199,399 @@ impl Default for Key<Final> {
     }
 }
 
-#[derive(Debug)]
+/// Everything here that contributes to a key lives in an ordered collection,
+/// so iterating over it always gives the same order. Please keep it that way:
+/// iterating over a `HashSet` or `HashMap` while hashing would make keys
+/// change from run to run, and remembering to call `sorted()` at every site
+/// is exactly the kind of thing that slips in review.
+#[derive(Debug, Clone)]
 pub struct Job {
     pub base_key: Key<Base>,
+    pub id: Id,
     pub command: Command,
-    pub input_files: HashSet<FileMapping>,
-    pub input_jobs: HashMap<Key<Base>, HashSet<FileMapping>>,
-    pub outputs: HashSet<PathBuf>,
+
+    /// More commands to run after `command`, in order, stopping at the
+    /// first one that fails (see `thenRun`.) They share its environment.
+    pub then_run: Vec<Command>,
+    pub input_files: BTreeSet<FileMapping>,
+    pub input_jobs: BTreeMap<Key<Base>, BTreeSet<FileMapping>>,
+    pub input_resolvers: BTreeMap<resolver::Spec, BTreeSet<FileMapping>>,
+    pub outputs: BTreeSet<PathBuf>,
+    pub output_filters: Vec<(PathBuf, Filter)>,
+    pub resources: Vec<String>,
+
+    /// Variables to copy from our own environment (see
+    /// `withPassthroughEnv`.) Their names are part of the key, but their
+    /// values only make it into the final key with `--hash-passthrough-env`.
+    pub passthrough_env: BTreeSet<String>,
+    pub expect_failure: bool,
+    pub network: Network,
+
+    /// Hosts and DNS servers the job sees instead of the host's (see
+    /// `withHost` and `withDnsServer`.)
+    pub names: network::Names,
+    pub input_strategy: InputStrategy,
+    pub incremental_state: BTreeSet<PathBuf>,
+
+    /// Where the job keeps checkpoints (see `withCheckpointDir`.) Like
+    /// incremental state, it's linked in from outside the workspace, but
+    /// only runs with the same final key see it.
+    pub checkpoint_dir: Option<PathBuf>,
+    pub response_file: Option<PathBuf>,
+
+    /// Where to write the command's stdout. This is also in `outputs`.
+    pub output_from_stdout: Option<PathBuf>,
+
+    /// Inputs from other jobs that have to be writable copies instead of
+    /// links into the store (see `withWritableOutput`), by destination.
+    pub writable_inputs: BTreeSet<PathBuf>,
+    pub limits: Limits,
+    pub retention: Option<Retention>,
+
+    /// Where the job's items can go besides this machine (see
+    /// `withVisibility`.) The coordinator narrows this to the least visible
+    /// job we take files from, since our outputs could contain theirs.
+    pub visibility: Visibility,
+    pub shard: Option<Shard>,
+
+    /// The value of each parameter this copy of a matrix job was made for
+    /// (see `withMatrix`), which it gets as environment variables. Empty
+    /// for jobs without a matrix.
+    pub variant: BTreeMap<String, String>,
+
+    /// For shards and matrix copies, the key the job would have had as one
+    /// job, so we can report on all of them together.
+    pub group: Option<Key<Base>>,
+    pub persistent_worker: bool,
+
+    /// Skip `lint::HostPaths` for this job. It doesn't change how the job
+    /// runs, so it's not part of the key either.
+    pub allow_host_paths: bool,
+
+    /// Run the job again if files go missing from its store item, instead
+    /// of failing whatever uses them (see `rerunOnMissingOutputs`.) Like
+    /// `allow_host_paths`, it doesn't change what the job produces, so it's
+    /// not part of the key.
+    pub rerun_on_missing_outputs: bool,
+
+    /// Whether the job gets the build status files, with the stable part
+    /// going into its final key. See `status::Status`.
+    pub stamp: bool,
+
+    /// Whether `rbt test` runs this job (see `withKind`.) Like
+    /// `allow_host_paths`, this isn't part of the key: a test's result
+    /// doesn't depend on us calling it one.
+    pub is_test: bool,
+
+    /// Jobs that have to finish before this one starts, even though we
+    /// don't take any files from them. These aren't part of the key.
+    pub after: BTreeSet<Key<Base>>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+/// Which slice of a sharded job this is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub total: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct FileMapping {
     pub source: PathBuf,
     pub dest: PathBuf,
 }
 
+/// A glue job, compared and hashed by where it lives instead of what's in
+/// it. Glue jobs' own `Hash` and `Eq` walk the whole job, dependencies and
+/// all, which gets quadratic (or worse) on big graphs. Every use of a job
+/// value in Roc shares one allocation, so the address is a cheap stand-in.
+/// Equal jobs built separately get different addresses, but they convert
+/// to the same key anyway.
+#[derive(Debug, Clone, Copy)]
+pub struct GlueRef<'job>(pub &'job glue::Job);
+
+impl GlueRef<'_> {
+    fn address(&self) -> *const glue::R1 {
+        self.0.as_Job()
+    }
+}
+
+impl PartialEq for GlueRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.address(), other.address())
+    }
+}
+
+impl Eq for GlueRef<'_> {}
+
+impl std::hash::Hash for GlueRef<'_> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.address().hash(state)
+    }
+}
+
+/// `glue.rs` is generated, so this lives here instead.
+#[cfg(test)]
+impl glue::R1 {
+    /// A job that runs `command` with everything else left at what `Rbt.roc`
+    /// would give it by default. Tests set what they care about on top with
+    /// struct update syntax, so adding a field to the glue means adding it
+    /// here and nowhere else.
+    pub fn for_test(command: glue::Command) -> Self {
+        use roc_std::{RocDict, RocList};
+
+        glue::R1 {
+            after: RocList::empty(),
+            checkpointDir: RocStr::empty(),
+            command,
+            dnsServers: RocList::empty(),
+            env: RocDict::with_capacity(0),
+            hosts: RocList::empty(),
+            incrementalState: RocList::empty(),
+            inputs: RocList::empty(),
+            limits: RocList::empty(),
+            matrix: RocList::empty(),
+            outputFilters: RocList::empty(),
+            outputFromStdout: RocStr::empty(),
+            outputs: RocList::empty(),
+            passthroughEnv: RocList::empty(),
+            resources: RocList::empty(),
+            responseFile: RocStr::empty(),
+            thenRun: RocList::empty(),
+            writableOutputs: RocList::empty(),
+            retention: glue::R4 {
+                days: 0,
+                kind: glue::RetentionKind::Default,
+            },
+            shards: 1,
+            allowHostPaths: false,
+            expectFailure: false,
+            inputStrategy: glue::InputStrategy::Symlink,
+            kind: glue::JobKind::Build,
+            network: glue::Network::Allowed,
+            persistentWorker: false,
+            rerunOnMissingOutputs: false,
+            stamp: false,
+            visibility: glue::Visibility::Public,
+        }
+    }
+}
+
+/// A `glue::Command` that runs `tool` from the system with `args`.
+#[cfg(test)]
+pub fn system_command(tool: &str, args: &[&str]) -> glue::Command {
+    glue::Command {
+        tool: glue::Tool::SystemTool(glue::SystemToolPayload {
+            name: RocStr::from(tool),
+        }),
+        args: args.iter().map(|arg| RocStr::from(*arg)).collect(),
+    }
+}
+
+/// How big a graph from the build definition we're willing to take on. A
+/// buggy definition (like a recursive function that doesn't bottom out when
+/// it should) can build a graph that takes the whole machine down with it
+/// once we start converting, so we stop at these and say why instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GraphLimits {
+    /// How many distinct jobs the whole graph can have.
+    pub jobs: usize,
+
+    /// How long the longest chain of jobs depending on each other can be.
+    pub depth: usize,
+
+    /// How many files a single job can take from its inputs.
+    pub inputs_per_job: usize,
+}
+
+impl Default for GraphLimits {
+    /// Far bigger than any real build we know of, but small enough to stop
+    /// before running out of memory on a shared build machine.
+    fn default() -> Self {
+        GraphLimits {
+            jobs: 1_000_000,
+            depth: 10_000,
+            inputs_per_job: 250_000,
+        }
+    }
+}
+
+/// Every job `roots` need, each after all the jobs it depends on (the order
+/// `Job::from_glue` needs them in.) We get that from a depth-first search,
+/// writing each job down once we've finished with its dependencies. A job
+/// can be a dependency of many others (and roots often share most of their
+/// graph), so we keep track of the jobs we've seen to visit each one once.
+/// Graphs bigger than `limits` are an error.
+pub fn dependency_order<'job>(
+    roots: impl IntoIterator<Item = &'job glue::Job>,
+    limits: &GraphLimits,
+) -> Result<Vec<&'job glue::Job>> {
+    let mut order = Vec::new();
+    let mut seen: HashSet<GlueRef> = HashSet::new();
+
+    // the longest chain of dependencies ending at each job we've finished
+    let mut depths: HashMap<GlueRef, usize> = HashMap::new();
+
+    // the `bool` is whether we've pushed the job's dependencies yet. If we
+    // have, they're done by the time we see the job again.
+    let mut stack: Vec<(&glue::Job, bool)> = roots.into_iter().map(|job| (job, false)).collect();
+
+    while let Some((glue_job, descended)) = stack.pop() {
+        if descended {
+            // Roc values can't refer to themselves, so every dependency is
+            // finished by now.
+            let depth = 1 + glue_dependencies(glue_job)
+                .filter_map(|dep| depths.get(&GlueRef(dep)))
+                .max()
+                .unwrap_or(&0);
+            if depth > limits.depth {
+                anyhow::bail!(
+                    "`{}` is at the end of a chain of more than {} jobs that each depend on the next, which is more than `--max-graph-depth` allows. Does the build definition have a recursive function that doesn't stop?",
+                    Command::new(glue_job.as_Job()),
+                    limits.depth,
+                );
+            }
+
+            depths.insert(GlueRef(glue_job), depth);
+            order.push(glue_job);
+            continue;
+        }
+
+        if !seen.insert(GlueRef(glue_job)) {
+            continue;
+        }
+        if seen.len() > limits.jobs {
+            anyhow::bail!(
+                "the build has more than {} jobs, which is more than `--max-jobs` allows. Does the build definition have a recursive function that doesn't stop?",
+                limits.jobs,
+            );
+        }
+
+        let inputs = input_count(glue_job.as_Job());
+        if inputs > limits.inputs_per_job {
+            anyhow::bail!(
+                "`{}` takes {} input files, which is more than `--max-inputs-per-job` allows ({})",
+                Command::new(glue_job.as_Job()),
+                inputs,
+                limits.inputs_per_job,
+            );
+        }
+
+        stack.push((glue_job, true));
+        for dep in glue_dependencies(glue_job) {
+            if !seen.contains(&GlueRef(dep)) {
+                stack.push((dep, false));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// The jobs `glue_job` depends on. Jobs we only have to run after (or only
+/// take a tool from) need converting just like jobs we take files from.
+fn glue_dependencies(glue_job: &glue::Job) -> impl Iterator<Item = &glue::Job> {
+    let unwrapped = glue_job.as_Job();
+
+    unwrapped
+        .inputs
+        .iter()
+        .filter(|item| item.discriminant() == glue::discriminant_U1::FromJob)
+        .map(|item| unsafe { item.as_FromJob() }.0)
+        .chain(unwrapped.after.iter())
+        .chain(
+            std::iter::once(&unwrapped.command)
+                .chain(unwrapped.thenRun.iter())
+                .filter_map(glue_tool_job)
+                .map(|tool| &tool.job),
+        )
+}
+
+/// Which job builds `command`'s tool (and where it is in that job's
+/// outputs), if it isn't a system tool.
+fn glue_tool_job(command: &glue::Command) -> Option<&glue::FromJobPayload> {
+    match command.tool.discriminant() {
+        glue::discriminant_Tool::FromJob => Some(unsafe { command.tool.as_FromJob() }),
+        glue::discriminant_Tool::PinnedTool | glue::discriminant_Tool::SystemTool => None,
+    }
+}
+
+/// How many files a job takes from all its inputs put together.
+fn input_count(unwrapped: &glue::R1) -> usize {
+    unwrapped
+        .inputs
+        .iter()
+        .map(|input| match input.discriminant() {
+            glue::discriminant_U1::FromJob => unsafe { input.as_FromJob() }.1.len(),
+            glue::discriminant_U1::FromProjectSource => {
+                unsafe { input.as_FromProjectSource() }.len()
+            }
+            glue::discriminant_U1::FromResolver => unsafe { input.as_FromResolver() }.2.len(),
+            glue::discriminant_U1::FromArchive => unsafe { input.as_FromArchive() }.2.len(),
+        })
+        .sum()
+}
+
 impl Job {
     pub fn from_glue<S>(
         job: &glue::Job,
-        glue_job_to_key: &HashMap<&glue::Job, Key<Base>, S>,
+        glue_job_to_key: &HashMap<GlueRef, Key<Base>, S>,
     ) -> Result<Self>
     where
         S: BuildHasher,
     {
         let unwrapped = job.as_Job();
 
-        let mut hasher = Xxh3::new();
+        let mut hasher = KeyHasher::new();
 
-        // TODO: when we can get commands from other jobs, we need to hash the
-        // other tool and job instead of relying on the derived `Hash` trait
-        // for this.
-        unwrapped.command.hash(&mut hasher);
+        let mut command = Command::new(unwrapped);
+        let mut then_run: Vec<Command> = unwrapped
+            .thenRun
+            .iter()
+            .map(|next| Command::with_env(next, command.env.clone()))
+            .collect();
+
+        let mut input_files: BTreeSet<FileMapping> = BTreeSet::new();
+        let mut input_jobs: BTreeMap<Key<Base>, BTreeSet<FileMapping>> = BTreeMap::new();
+
+        // A tool from another job makes that job a dependency, just without
+        // any files in the workspace: the tool runs from the store. Its
+        // content hash gets into the final key like any other dependency's.
+        for (glue_command, command) in std::iter::once(&unwrapped.command)
+            .chain(unwrapped.thenRun.iter())
+            .zip(std::iter::once(&mut command).chain(then_run.iter_mut()))
+        {
+            if let Some(tool) = glue_tool_job(glue_command) {
+                sanitize_file_path(&tool.name)
+                    .context("got an unacceptable path for a tool from another job")?;
+
+                let key = glue_job_to_key.get(&GlueRef(&tool.job)).context("could not get job key to determine build order. This indicates an internal bug in the coordinator module and should be reported.")?;
+                input_jobs.entry(*key).or_default();
+                command.tool_job = Some(*key);
+            }
+
+            if let Some(sha256) = &command.pinned_sha256 {
+                if !Path::new(&command.tool).is_absolute() {
+                    anyhow::bail!(
+                        "`{}` needs to be an absolute path to pin it to a hash",
+                        command.tool
+                    )
+                }
+
+                if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                    anyhow::bail!(
+                        "`{}` is not a SHA-256 hash (it should be 64 hex digits) for `{}`",
+                        sha256,
+                        command.tool
+                    )
+                }
+            }
+        }
 
-        let mut input_files: HashSet<FileMapping> = HashSet::new();
-        let mut input_jobs: HashMap<Key<Base>, HashSet<FileMapping>> = HashMap::new();
+        hasher.tag("command");
+        command.hash_into(&mut hasher);
+        let mut input_resolvers: BTreeMap<resolver::Spec, BTreeSet<FileMapping>> = BTreeMap::new();
+        let mut writable_inputs: BTreeSet<PathBuf> = BTreeSet::new();
 
         for input in unwrapped.inputs.iter().sorted() {
             match input.discriminant() {
@@ -99,19 +604,35 @@ impl Job {
                     // would cause a rebuild on any source change in the
                     // dependent job, even (for example) a comment moving
                     // around.
-                    let key = glue_job_to_key.get(glue_job).context("could not get job key to determine build order. This indicates an internal bug in the coordinator module and should be reported.")?;
-                    let mut job_files = HashSet::new();
+                    let key = glue_job_to_key.get(&GlueRef(glue_job)).context("could not get job key to determine build order. This indicates an internal bug in the coordinator module and should be reported.")?;
+                    let job_files = input_jobs.entry(*key).or_default();
+
+                    let writable_outputs = glue_job
+                        .as_Job()
+                        .writableOutputs
+                        .iter()
+                        .map(sanitize_file_path)
+                        .collect::<Result<Vec<PathBuf>>>()
+                        .context("got an unacceptable writable output path")?;
+
+                    hasher.tag("fromJob");
+                    hasher.len(files.len());
 
-                    for glue::FileMapping { source, dest } in files {
+                    for glue::FileMapping { source, dest } in files.iter().sorted() {
                         let source_path = sanitize_file_path(source)
                             .context("got an unacceptable source file path")?;
 
                         let dest_path = sanitize_file_path(dest)
                             .context("got an unacceptable destination file path")?;
 
-                        source_path.hash(&mut hasher);
-                        if source_path != dest_path {
-                            dest_path.hash(&mut hasher);
+                        hasher.str(source);
+                        hasher.str(dest);
+
+                        if writable_outputs
+                            .iter()
+                            .any(|output| source_path.starts_with(output))
+                        {
+                            writable_inputs.insert(dest_path.clone());
                         }
 
                         job_files.insert(FileMapping {
@@ -119,23 +640,22 @@ impl Job {
                             dest: dest_path,
                         });
                     }
-
-                    input_jobs.insert(*key, job_files);
                 }
                 glue::discriminant_U1::FromProjectSource => {
-                    for glue::FileMapping { source, dest } in
-                        unsafe { input.as_FromProjectSource() }.iter().sorted()
-                    {
+                    let files = unsafe { input.as_FromProjectSource() };
+
+                    hasher.tag("fromProjectSource");
+                    hasher.len(files.len());
+
+                    for glue::FileMapping { source, dest } in files.iter().sorted() {
                         let source_path = sanitize_file_path(source)
                             .context("got an unacceptable input file path")?;
 
                         let dest_path = sanitize_file_path(dest)
                             .context("got an unacceptable destination file path")?;
 
-                        source_path.hash(&mut hasher);
-                        if source_path != dest_path {
-                            dest_path.hash(&mut hasher);
-                        }
+                        hasher.str(source);
+                        hasher.str(dest);
 
                         input_files.insert(FileMapping {
                             source: source_path,
@@ -143,174 +663,1013 @@ impl Job {
                         });
                     }
                 }
+                glue::discriminant_U1::FromResolver | glue::discriminant_U1::FromArchive => {
+                    // archives are resolver inputs too, we just build their
+                    // spec ourselves so `fromArchive` doesn't have to write
+                    // JSON in Roc.
+                    let (spec, files) =
+                        if input.discriminant() == glue::discriminant_U1::FromArchive {
+                            let (url, sha256, files) = unsafe { input.as_FromArchive() };
+                            (resolver::Spec::archive(url, sha256), files)
+                        } else {
+                            let (resolver, spec, files) = unsafe { input.as_FromResolver() };
+                            (
+                                resolver::Spec {
+                                    resolver: resolver.to_string(),
+                                    spec: spec.to_string(),
+                                },
+                                files,
+                            )
+                        };
+
+                    // unlike job dependencies, we hash the spec itself: it's
+                    // all we know about the input until the resolver runs.
+                    hasher.tag("fromResolver");
+                    hasher.str(&spec.resolver);
+                    hasher.str(&spec.spec);
+                    hasher.len(files.len());
+
+                    let resolver_files = input_resolvers.entry(spec).or_default();
+
+                    for glue::FileMapping { source, dest } in files.iter().sorted() {
+                        let source_path = sanitize_file_path(source)
+                            .context("got an unacceptable source file path")?;
+
+                        let dest_path = sanitize_file_path(dest)
+                            .context("got an unacceptable destination file path")?;
+
+                        hasher.str(source);
+                        hasher.str(dest);
+
+                        resolver_files.insert(FileMapping {
+                            source: source_path,
+                            dest: dest_path,
+                        });
+                    }
+                }
             }
         }
 
-        let mut outputs = HashSet::new();
+        let mut outputs = BTreeSet::new();
+        hasher.tag("outputs");
         for output_str in unwrapped.outputs.iter().sorted() {
             let output =
                 sanitize_file_path(output_str).context("got an unacceptable output file path")?;
 
             if outputs.contains(&output) {
-                log::warn!(
+                tracing::warn!(
                     "`{}` appears twice in the list of outputs",
                     output.display()
                 );
                 continue;
             }
 
-            output.hash(&mut hasher);
+            hasher.str(output_str);
             outputs.insert(output);
         }
 
-        for (key, value) in unwrapped.env.iter().sorted() {
-            key.hash(&mut hasher);
-            value.hash(&mut hasher);
+        // this is an output like any other once the runner has written it,
+        // but we hash it on its own so that keys for jobs without it stay
+        // the same.
+        let output_from_stdout = if unwrapped.outputFromStdout.is_empty() {
+            None
+        } else {
+            let path = sanitize_file_path(&unwrapped.outputFromStdout)
+                .context("got an unacceptable path for stdout")?;
+
+            hasher.tag("outputFromStdout");
+            hasher.str(&unwrapped.outputFromStdout);
+            outputs.insert(path.clone());
+
+            Some(path)
+        };
+
+        // inputs are linked into the workspace, so a job writing to an
+        // output in the same place as an input would write through the link
+        // and change the original (a source file, or another job's output.)
+        let inputs = input_files
+            .iter()
+            .map(|file| (file, "a project file".to_string()))
+            .chain(
+                input_jobs
+                    .values()
+                    .flatten()
+                    .map(|file| (file, "another job's output".to_string())),
+            )
+            .chain(input_resolvers.iter().flat_map(|(spec, files)| {
+                files.iter().map(move |file| {
+                    (
+                        file,
+                        format!("a file from the `{}` resolver", spec.resolver),
+                    )
+                })
+            }));
+
+        for (input, source) in inputs {
+            if let Some(output) = outputs
+                .iter()
+                .find(|output| output.starts_with(&input.dest) || input.dest.starts_with(output))
+            {
+                anyhow::bail!(
+                    "`{}` is an output, but `{}` is an input ({} from `{}`.) Outputs can't be in the same place as inputs, since writing the output would change the input's original.",
+                    output.display(),
+                    input.dest.display(),
+                    source,
+                    input.source.display(),
+                )
+            }
         }
 
-        let command = Command::new(unwrapped);
-        command.hash(&mut hasher);
+        let response_file = if unwrapped.responseFile.is_empty() {
+            None
+        } else {
+            let path = sanitize_file_path(&unwrapped.responseFile)
+                .context("got an unacceptable response file path")?;
 
-        Ok(Job {
-            base_key: Key {
-                key: hasher.finish(),
-                phantom: PhantomData,
-            },
-            command,
-            input_files,
-            input_jobs,
-            outputs,
-        })
-    }
+            if outputs.contains(&path)
+                || input_dests(&input_files, &input_jobs, &input_resolvers)
+                    .any(|dest| dest == &path)
+            {
+                anyhow::bail!(
+                    "the response file `{}` is in the same place as one of the job's inputs or outputs",
+                    path.display()
+                )
+            }
 
-    pub fn final_key(
-        &self,
-        path_to_hash: &HashMap<PathBuf, blake3::Hash>,
-        job_to_content_hash: &HashMap<Key<Base>, store::Item>,
-    ) -> Result<Key<Final>> {
-        let mut hasher = Xxh3::new();
+            Some(path)
+        };
 
-        self.base_key.hash(&mut hasher);
+        // filters on the same output run in order, so unlike most things in
+        // the key we don't sort these.
+        let mut output_filters = Vec::with_capacity(unwrapped.outputFilters.len());
+        for glue::R2 { output, filter } in unwrapped.outputFilters.iter() {
+            let path =
+                sanitize_file_path(output).context("got an unacceptable output file path")?;
 
-        for path in &self.input_files {
-            match path_to_hash.get(&path.source) {
-                Some(hash) => {
-                    // we don't need to hash the path, as we already have it in the base key
-                    hash.hash(&mut hasher);
-                },
-                None => anyhow::bail!("`{}` was specified as a file dependency, but I didn't have a hash for it! This is a bug in rbt's coordinator, please file it!", path.source.display()),
+            if !outputs.contains(&path) {
+                anyhow::bail!(
+                    "there's an output filter for `{}`, but it isn't one of the job's outputs",
+                    path.display()
+                )
             }
-        }
 
-        for key in self.input_jobs.keys().sorted() {
-            let dep = job_to_content_hash.get(key).context("could not look up output hash for dependency. This is a bug in rbt's coordinator. Please file it!")?.hash();
-            dep.hash(&mut hasher);
+            output_filters.push((path, Filter::from_glue(*filter)));
         }
 
-        Ok(Key {
-            key: hasher.finish(),
-            phantom: PhantomData,
-        })
-    }
-}
+        let mut writable_outputs = BTreeSet::new();
+        for output in unwrapped.writableOutputs.iter() {
+            let path =
+                sanitize_file_path(output).context("got an unacceptable writable output path")?;
 
-#[derive(Debug)]
-pub struct Command {
-    tool: String,
-    args: Vec<String>,
-    env: HashMap<String, String>,
-}
+            if !outputs.contains(&path) {
+                anyhow::bail!(
+                    "`{}` is supposed to be a writable output, but it isn't one of the job's outputs",
+                    path.display()
+                )
+            }
 
-impl Command {
-    fn new(glue_job: &glue::R1) -> Self {
-        let mut env = HashMap::with_capacity(glue_job.env.len());
-        for (k, v) in &glue_job.env {
-            env.insert(k.as_str().into(), v.as_str().into());
+            writable_outputs.insert(path);
         }
 
-        Command {
-            tool: glue_job.command.tool.as_SystemTool().name.to_string(),
-            args: glue_job
-                .command
-                .args
-                .iter()
-                .map(|arg| arg.as_str().into())
-                .collect(),
-            env,
-        }
-    }
-}
+        let mut limits = Limits::new();
+        for glue::R3 { limit, value } in unwrapped.limits.iter() {
+            let limit = Limit::from_glue(*limit);
 
-impl Display for Command {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // intention: make a best-effort version of part of how the command
-        // would look if it were invoked from a shell. It's OK for right now
-        // if it wouldn't work (due to unescaped quotes or whatever.) Point is
-        // for us to have some human-readable output in addition to the ID.
-        let mut chars = 0;
+            if limits.insert(limit, *value).is_some() {
+                anyhow::bail!(
+                    "the job sets its `{}` limit more than once",
+                    limit.identity()
+                )
+            }
+        }
 
-        chars += self.tool.len();
+        // Resources only affect when a job runs, not what it produces, so we
+        // leave them out of the key on purpose. Jobs should produce the same
+        // outputs no matter which port (for example) they were handed.
+        let resources = unwrapped
+            .resources
+            .iter()
+            .map(|resource| resource.to_string())
+            .sorted()
+            .collect();
 
-        write!(f, "{}", self.tool)?;
+        // Fields below here are optional in the Roc API. Only hashing them
+        // when they're set keeps keys stable for all the jobs that don't use
+        // them.
+        if unwrapped.expectFailure {
+            hasher.tag("expectFailure");
+        }
 
-        for arg in &self.args {
-            if chars >= 20 {
-                continue;
+        if !then_run.is_empty() {
+            // each of these would need to know which command it's about
+            for (set, what) in [
+                (unwrapped.persistentWorker, "use a persistent worker"),
+                (unwrapped.expectFailure, "be expected to fail"),
+                (
+                    !unwrapped.outputFromStdout.is_empty(),
+                    "take an output from stdout",
+                ),
+            ] {
+                if set {
+                    anyhow::bail!("jobs with more than one command can't {} yet", what)
+                }
             }
 
-            if arg.contains(' ') {
-                write!(f, " \"{}\"", arg)?;
-                chars += arg.len() + 3;
-            } else {
-                write!(f, " {}", arg)?;
-                chars += arg.len() + 1;
+            hasher.tag("thenRun");
+            hasher.len(then_run.len());
+            for next in &then_run {
+                next.hash_into(&mut hasher);
             }
         }
 
-        Ok(())
-    }
-}
-
-impl Hash for Command {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.tool.hash(state);
-        self.args.hash(state);
+        // A job that can't reach the network might fail where one that can
+        // would succeed (that's the point) so the two can't share outputs.
+        let network = Network::from_glue(unwrapped.network);
+        if network == Network::Forbidden {
+            hasher.tag("networkForbidden");
+        }
 
-        for (key, value) in self.env.iter().sorted() {
-            key.hash(state);
-            value.hash(state);
+        // the same goes for names that resolve somewhere else. The order of
+        // DNS servers matters (resolvers try them in turn) but hosts are
+        // sorted by name already.
+        let names = network::Names::from_glue(&unwrapped.hosts, &unwrapped.dnsServers)
+            .context("got an unacceptable host or DNS server")?;
+        if !names.hosts.is_empty() {
+            hasher.tag("hosts");
+            hasher.len(names.hosts.len());
+            for (name, address) in &names.hosts {
+                hasher.str(name);
+                hasher.str(&address.to_string());
+            }
+        }
+        if !names.dns_servers.is_empty() {
+            hasher.tag("dnsServers");
+            hasher.len(names.dns_servers.len());
+            for server in &names.dns_servers {
+                hasher.str(&server.to_string());
+            }
         }
-    }
-}
 
-impl From<&Command> for tokio::process::Command {
-    fn from(job_command: &Command) -> Self {
-        let mut command = tokio::process::Command::new(job_command.tool.as_str());
+        let mut passthrough_env = BTreeSet::new();
+        for name in unwrapped.passthroughEnv.iter() {
+            if name.is_empty() || name.contains('=') || name.contains('\0') {
+                anyhow::bail!("`{}` isn't a valid environment variable name", name);
+            }
+            if command.env().contains_key(name.as_str()) {
+                anyhow::bail!(
+                    "`{}` is in both the job's env and its passthrough env, so I don't know which value to use",
+                    name
+                );
+            }
 
-        for arg in &job_command.args {
-            command.arg(arg.as_str());
+            passthrough_env.insert(name.to_string());
+        }
+        if !passthrough_env.is_empty() {
+            hasher.tag("passthroughEnv");
+            hasher.len(passthrough_env.len());
+            for name in &passthrough_env {
+                hasher.str(name);
+            }
         }
 
-        command.env_clear();
+        // tools that trip over symlinks may well do something different
+        // with a copy, so a job's output can depend on how it got inputs.
+        let input_strategy = InputStrategy::from_glue(unwrapped.inputStrategy);
+        if input_strategy != InputStrategy::Symlink {
+            hasher.tag("inputStrategy");
+            hasher.tag(input_strategy.name());
+        }
 
-        for (key, value) in &job_command.env {
-            command.env(key, value);
+        // stamped jobs see status files that other jobs don't
+        if unwrapped.stamp {
+            hasher.tag("stamp");
         }
 
-        command
-    }
-}
+        // What's *in* these directories is deliberately left out of the key:
+        // they're how we let tools like `tsc --incremental` see their
+        // previous state. We only hash where they go.
+        let mut incremental_state = BTreeSet::new();
+        for dir_str in unwrapped.incrementalState.iter().sorted() {
+            let dir = sanitize_file_path(dir_str)
+                .context("got an unacceptable incremental state path")?;
 
-impl Display for Job {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} ({})", self.base_key, self.command)
-    }
-}
+            if let Some(output) = outputs.iter().find(|output| output.starts_with(&dir)) {
+                anyhow::bail!(
+                    "`{}` is an output, but it's inside the incremental state directory `{}`. Outputs have to live outside incremental state so they can be cached.",
+                    output.display(),
+                    dir.display(),
+                )
+            }
 
-pub fn sanitize_file_path(roc_str: &RocStr) -> Result<PathBuf> {
-    let sanitized: PathBuf = roc_str.as_str().into();
+            incremental_state.insert(dir);
+        }
 
-    // verify that the specified path is safe. We can't allow accessing any
+        // what's in here is left out of the key too, but it only lasts
+        // until the job succeeds.
+        let checkpoint_dir = if unwrapped.checkpointDir.is_empty() {
+            None
+        } else {
+            let dir = sanitize_file_path(&unwrapped.checkpointDir)
+                .context("got an unacceptable checkpoint directory path")?;
+
+            if let Some(output) = outputs.iter().find(|output| output.starts_with(&dir)) {
+                anyhow::bail!(
+                    "`{}` is an output, but it's inside the checkpoint directory `{}`. Outputs have to live outside it so they can be cached.",
+                    output.display(),
+                    dir.display(),
+                )
+            }
+
+            Some(dir)
+        };
+
+        if !output_filters.is_empty() {
+            hasher.tag("outputFilters");
+            hasher.len(output_filters.len());
+            for (path, filter) in &output_filters {
+                hasher.str(&path.to_string_lossy());
+                hasher.str(filter.identity());
+            }
+        }
+
+        // a tool can behave differently when it runs out of file handles or
+        // stack, so limits are part of what the job produces.
+        if !limits.is_empty() {
+            hasher.tag("limits");
+            hasher.len(limits.len());
+            for (limit, value) in &limits {
+                hasher.str(limit.identity());
+                hasher.u64(*value);
+            }
+        }
+
+        // the output is the same either way, but consumers getting a copy
+        // they can change (or not) can change what *they* produce.
+        if !writable_outputs.is_empty() {
+            hasher.tag("writableOutputs");
+            hasher.len(writable_outputs.len());
+            for output in &writable_outputs {
+                hasher.str(&output.to_string_lossy());
+            }
+        }
+
+        // and we don't hash the keys of the jobs we get inputs from, so we
+        // have to say which of those inputs are copies ourselves.
+        if !writable_inputs.is_empty() {
+            hasher.tag("writableInputs");
+            hasher.len(writable_inputs.len());
+            for dest in &writable_inputs {
+                hasher.str(&dest.to_string_lossy());
+            }
+        }
+
+        // the list is made from the inputs, which are already in the key,
+        // but hashing exactly what the tool will see means the key changes
+        // if we ever change the file's format.
+        if let Some(path) = &response_file {
+            hasher.tag("responseFile");
+            hasher.str(&path.to_string_lossy());
+            hasher.str(&response_file_contents(
+                &input_files,
+                &input_jobs,
+                &input_resolvers,
+            ));
+        }
+
+        if !incremental_state.is_empty() {
+            hasher.tag("incrementalState");
+            hasher.len(incremental_state.len());
+            for dir in &incremental_state {
+                // sanitize_file_path only accepts paths that came from a
+                // RocStr, so this is always valid unicode.
+                hasher.str(&dir.to_string_lossy());
+            }
+        }
+
+        if let Some(dir) = &checkpoint_dir {
+            hasher.tag("checkpointDir");
+            hasher.str(&dir.to_string_lossy());
+        }
+
+        // Order-only dependencies are left out of the key on purpose: the
+        // whole point is that changing the job we wait for shouldn't make
+        // this one run again.
+        let mut after = BTreeSet::new();
+        for glue_job in unwrapped.after.iter() {
+            after.insert(*glue_job_to_key.get(&GlueRef(glue_job)).context("could not get job key to determine build order. This indicates an internal bug in the coordinator module and should be reported.")?);
+        }
+
+        let base_key = Key {
+            key: hasher.finish(),
+            phantom: PhantomData,
+        };
+
+        Ok(Job {
+            base_key,
+            id: Id::new(command.tool(), base_key),
+            command,
+            then_run,
+            input_files,
+            input_jobs,
+            input_resolvers,
+            outputs,
+            output_filters,
+            resources,
+            passthrough_env,
+            expect_failure: unwrapped.expectFailure,
+            network,
+            names,
+            input_strategy,
+            incremental_state,
+            checkpoint_dir,
+            response_file,
+            output_from_stdout,
+            writable_inputs,
+            limits,
+            shard: None,
+            variant: BTreeMap::new(),
+            group: None,
+            after,
+
+            // how long to keep the output is up to whoever runs GC, not part
+            // of what the job produces.
+            retention: Retention::from_glue(&unwrapped.retention),
+            visibility: Visibility::from_glue(unwrapped.visibility),
+
+            // like resources, this is about how the job runs rather than
+            // what it produces, so it's not part of the key.
+            persistent_worker: unwrapped.persistentWorker,
+            allow_host_paths: unwrapped.allowHostPaths,
+            rerun_on_missing_outputs: unwrapped.rerunOnMissingOutputs,
+            stamp: unwrapped.stamp,
+            is_test: unwrapped.kind == glue::JobKind::Test,
+        })
+    }
+
+    /// The job's command, then whatever it runs after that.
+    pub fn commands(&self) -> impl Iterator<Item = &Command> {
+        std::iter::once(&self.command).chain(&self.then_run)
+    }
+
+    /// Does `path` come out of this job? It does if it's one of the
+    /// outputs, or inside one (outputs can be directories, and dependents
+    /// can take single files from them.)
+    pub fn produces(&self, path: &Path) -> bool {
+        self.outputs.iter().any(|output| path.starts_with(output))
+    }
+
+    /// Every path that's supposed to be in the job's workspace after it runs:
+    /// its inputs and outputs, and what rbt puts there for it. Anything else
+    /// the command left behind is an undeclared output.
+    pub fn expected_paths(&self) -> BTreeSet<PathBuf> {
+        let mut expected: BTreeSet<PathBuf> = self
+            .input_files
+            .iter()
+            .chain(self.input_jobs.values().flatten())
+            .chain(self.input_resolvers.values().flatten())
+            .map(|mapping| mapping.dest.clone())
+            .collect();
+
+        expected.extend(self.outputs.iter().cloned());
+        expected.extend(self.incremental_state.iter().cloned());
+        expected.extend(self.checkpoint_dir.iter().cloned());
+        expected.extend(self.response_file.iter().cloned());
+        if self.expect_failure {
+            expected.extend(["stdout".into(), "stderr".into()]);
+        }
+
+        expected
+    }
+
+    /// Where every input ends up in the workspace.
+    pub fn input_dests(&self) -> BTreeSet<PathBuf> {
+        input_dests(&self.input_files, &self.input_jobs, &self.input_resolvers)
+            .cloned()
+            .collect()
+    }
+
+    /// What goes in the job's response file: the workspace path of every
+    /// input, one per line, quoted the way GCC-style tools expect.
+    pub fn response_file_contents(&self) -> String {
+        response_file_contents(&self.input_files, &self.input_jobs, &self.input_resolvers)
+    }
+
+    /// Split this job into `total` jobs that each run one shard of it. Each
+    /// shard gets its own key (so shards are cached independently) and is
+    /// told which slice it is through `RBT_SHARD_INDEX` and `RBT_SHARD_TOTAL`.
+    pub fn into_shards(self, total: u32) -> Vec<Job> {
+        (0..total)
+            .map(|index| {
+                let mut hasher = KeyHasher::new();
+                hasher.u64(self.base_key.key);
+                hasher.tag("shard");
+                hasher.u64(index.into());
+                hasher.u64(total.into());
+
+                let base_key = Key {
+                    key: hasher.finish(),
+                    phantom: PhantomData,
+                };
+
+                Job {
+                    base_key,
+                    id: Id::new(self.command.tool(), base_key),
+                    shard: Some(Shard { index, total }),
+                    group: self.group.or(Some(self.base_key)),
+                    ..self.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// Make a copy of this job for every combination of values in
+    /// `matrix` (see `withMatrix`.) Each copy gets its own key, made from
+    /// only its own values, so adding a value doesn't change the keys of
+    /// the copies that were already there. Without a matrix, this is just
+    /// the job.
+    pub fn into_variants(self, matrix: &[glue::R5]) -> Result<Vec<Job>> {
+        if matrix.is_empty() {
+            return Ok(vec![self]);
+        }
+
+        let mut variants = vec![BTreeMap::new()];
+        for axis in matrix {
+            // parameters are mostly for scripts to read, so they have to be
+            // names a shell can use
+            let name = axis.name.as_str();
+            let mut chars = name.chars();
+            let valid = chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                anyhow::bail!(
+                    "`{}` isn't a valid matrix parameter name. Use letters, digits, and underscores, and don't start with a digit.",
+                    name
+                );
+            }
+
+            // the runner sets these itself, and would quietly win
+            if ["HOME", "TMPDIR", "PWD", "PATH"].contains(&name) || name.starts_with("RBT_") {
+                anyhow::bail!(
+                    "{} has a matrix parameter named `{}`, but rbt sets that variable itself",
+                    self,
+                    name
+                )
+            }
+            if self.command.env().contains_key(name) || self.passthrough_env.contains(name) {
+                anyhow::bail!(
+                    "`{}` is both a matrix parameter and in the job's env, so I don't know which value to use",
+                    name
+                )
+            }
+            if variants[0].contains_key(name) {
+                anyhow::bail!(
+                    "{} has more than one matrix parameter named `{}`",
+                    self,
+                    name
+                )
+            }
+            if axis.values.is_empty() {
+                anyhow::bail!("{} has no values for its matrix parameter `{}`", self, name)
+            }
+
+            let values: BTreeSet<&str> = axis.values.iter().map(|value| value.as_str()).collect();
+            variants = variants
+                .into_iter()
+                .flat_map(|variant| {
+                    values.iter().map(move |value| {
+                        let mut variant = variant.clone();
+                        variant.insert(name.to_string(), value.to_string());
+                        variant
+                    })
+                })
+                .collect();
+        }
+
+        Ok(variants
+            .into_iter()
+            .map(|variant| {
+                let mut hasher = KeyHasher::new();
+                hasher.u64(self.base_key.key);
+                hasher.tag("matrix");
+                hasher.len(variant.len());
+                for (name, value) in &variant {
+                    hasher.str(name);
+                    hasher.str(value);
+                }
+
+                let base_key = Key {
+                    key: hasher.finish(),
+                    phantom: PhantomData,
+                };
+
+                Job {
+                    base_key,
+                    id: Id::new(self.command.tool(), base_key),
+                    variant,
+                    group: Some(self.base_key),
+                    ..self.clone()
+                }
+            })
+            .collect())
+    }
+
+    pub fn final_key(
+        &self,
+        path_to_hash: &HashMap<PathBuf, blake3::Hash>,
+        job_to_content_hash: &HashMap<Key<Base>, store::Item>,
+        spec_to_resolved: &HashMap<resolver::Spec, resolver::Resolved>,
+        salt: Option<&str>,
+        stable_status: Option<&str>,
+        host_env: Option<&HashMap<String, String>>,
+    ) -> Result<Key<Final>> {
+        let mut hasher = KeyHasher::new();
+
+        hasher.u64(self.base_key.key);
+
+        // only hashed when it's set, so unsalted keys stay the same as they
+        // were before salts existed.
+        if let Some(salt) = salt {
+            hasher.tag("salt");
+            hasher.str(salt);
+        }
+
+        for path in &self.input_files {
+            match path_to_hash.get(&path.source) {
+                Some(hash) => {
+                    // we don't need to hash the path, as we already have it in the base key
+                    hasher.bytes(hash.as_bytes());
+                },
+                None => anyhow::bail!("`{}` was specified as a file dependency, but I didn't have a hash for it! This is a bug in rbt's coordinator, please file it!", path.source.display()),
+            }
+        }
+
+        for key in self.input_jobs.keys() {
+            let dep = job_to_content_hash.get(key).context("could not look up output hash for dependency. This is a bug in rbt's coordinator. Please file it!")?.hash();
+            hasher.bytes(dep.as_bytes());
+        }
+
+        for spec in self.input_resolvers.keys() {
+            let resolved = spec_to_resolved.get(spec).with_context(|| format!("could not look up resolved files for {}. This is a bug in rbt's coordinator. Please file it!", spec))?;
+            hasher.str(resolved.hash());
+        }
+
+        // volatile status is left out on purpose: that's what keeps stamping
+        // the build time from re-running everything on every build.
+        if self.stamp {
+            let stable_status = stable_status.context("did not have build status for a stamped job. This is a bug in rbt's coordinator. Please file it!")?;
+            hasher.tag("stableStatus");
+            hasher.str(stable_status);
+        }
+
+        // with `--hash-passthrough-env`, only jobs that have seen different
+        // values have to run again.
+        if let Some(host_env) = host_env.filter(|_| !self.passthrough_env.is_empty()) {
+            hasher.tag("passthroughEnvValues");
+            for name in &self.passthrough_env {
+                match host_env.get(name) {
+                    Some(value) => hasher.str(value),
+                    None => hasher.tag("unset"),
+                }
+            }
+        }
+
+        Ok(Key {
+            key: hasher.finish(),
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// Bump this only when keys for existing jobs change (see
+/// docs/internals/changing-job-keys.md).
+pub const KEY_FORMAT_VERSION: u64 = 1;
+
+/// Feeds values into a cache key using an explicit encoding instead of
+/// `std::hash::Hash`. The standard library doesn't promise that `Hash` output
+/// stays the same across Rust versions or platforms (lengths are hashed as
+/// `usize`, for example) but keys have to stay put when someone upgrades their
+/// compiler, or everyone's cache silently goes stale.
+///
+/// Strings are length-prefixed so that `["ab", "c"]` and `["a", "bc"]` can't
+/// produce the same key, and sections are tagged so that (for example) an
+/// output can't be confused for an input.
+struct KeyHasher(Xxh3);
+
+impl KeyHasher {
+    fn new() -> Self {
+        let mut hasher = KeyHasher(Xxh3::new());
+        hasher.u64(KEY_FORMAT_VERSION);
+
+        hasher
+    }
+
+    fn tag(&mut self, tag: &str) {
+        self.str(tag)
+    }
+
+    fn str(&mut self, str: &str) {
+        self.len(str.len());
+        self.0.update(str.as_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u64(len as u64)
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.0.update(&n.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0.digest()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Command {
+    /// A name to look up on the `PATH`, or, for a tool from another job,
+    /// where it is in that job's outputs.
+    tool: String,
+    tool_job: Option<Key<Base>>,
+
+    /// The SHA-256 hash (in lowercase hex) a pinned tool has to have.
+    pinned_sha256: Option<String>,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl Command {
+    fn new(glue_job: &glue::R1) -> Self {
+        let mut env = BTreeMap::new();
+        for (k, v) in &glue_job.env {
+            env.insert(k.as_str().into(), v.as_str().into());
+        }
+
+        Command::with_env(&glue_job.command, env)
+    }
+
+    fn with_env(command: &glue::Command, env: BTreeMap<String, String>) -> Self {
+        let (tool, pinned_sha256) = match command.tool.discriminant() {
+            glue::discriminant_Tool::FromJob => {
+                (unsafe { command.tool.as_FromJob() }.name.as_str(), None)
+            }
+            glue::discriminant_Tool::PinnedTool => {
+                let pinned = unsafe { command.tool.as_PinnedTool() };
+                (
+                    pinned.path.as_str(),
+                    Some(pinned.sha256.as_str().to_ascii_lowercase()),
+                )
+            }
+            glue::discriminant_Tool::SystemTool => {
+                (unsafe { command.tool.as_SystemTool() }.name.as_str(), None)
+            }
+        };
+
+        // `from_glue` fills in `tool_job`, since it knows the other jobs'
+        // keys.
+        Command {
+            tool: tool.to_string(),
+            tool_job: None,
+            pinned_sha256,
+            args: command.args.iter().map(|arg| arg.as_str().into()).collect(),
+            env,
+        }
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // intention: make a best-effort version of part of how the command
+        // would look if it were invoked from a shell. It's OK for right now
+        // if it wouldn't work (due to unescaped quotes or whatever.) Point is
+        // for us to have some human-readable output in addition to the ID.
+        let mut chars = 0;
+
+        chars += self.tool.len();
+
+        write!(f, "{}", self.tool)?;
+
+        for arg in &self.args {
+            if chars >= 20 {
+                continue;
+            }
+
+            if arg.contains(' ') {
+                write!(f, " \"{}\"", arg)?;
+                chars += arg.len() + 3;
+            } else {
+                write!(f, " {}", arg)?;
+                chars += arg.len() + 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Command {
+    pub fn tool(&self) -> &str {
+        &self.tool
+    }
+
+    /// The job that builds this command's tool, if it isn't a system tool.
+    pub fn tool_job(&self) -> Option<Key<Base>> {
+        self.tool_job
+    }
+
+    /// The hash this command's tool has to have, if it's pinned.
+    pub fn pinned_sha256(&self) -> Option<&str> {
+        self.pinned_sha256.as_deref()
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    fn hash_into(&self, hasher: &mut KeyHasher) {
+        hasher.str(&self.tool);
+
+        hasher.len(self.args.len());
+        for arg in &self.args {
+            hasher.str(arg);
+        }
+
+        hasher.len(self.env.len());
+        for (key, value) in &self.env {
+            hasher.str(key);
+            hasher.str(value);
+        }
+
+        // the job itself is in the final key along with the other jobs we
+        // depend on, but `tool` means something else when it's from one.
+        if self.tool_job.is_some() {
+            hasher.tag("toolFromJob");
+        }
+
+        // the binary at the path can change without the path changing, so
+        // what we promise about it goes in instead.
+        if let Some(sha256) = &self.pinned_sha256 {
+            hasher.tag("pinnedTool");
+            hasher.str(sha256);
+        }
+    }
+
+    /// This command with a tool from another job replaced by where that
+    /// tool is in the store, ready to run from any workspace. Commands with
+    /// system tools come back the way they are.
+    pub fn in_store(
+        &self,
+        job_to_content_hash: &HashMap<Key<Base>, store::Item>,
+    ) -> Result<Command> {
+        let mut command = self.clone();
+
+        if let Some(key) = self.tool_job {
+            let item = job_to_content_hash
+                .get(&key)
+                .with_context(|| format!("could not find a store path for job {}", key))?;
+
+            command.tool = item
+                .join(&self.tool)
+                .absolutize()
+                .context("could not get absolute path to tool")?
+                .display()
+                .to_string();
+        }
+
+        Ok(command)
+    }
+
+    /// `path` (a `PATH`-style list of directories) with the directory a
+    /// tool from another job is in at the front, so the tool can find
+    /// anything else its job built by name. `None` for system tools.
+    pub fn search_path(&self, path: Option<&str>) -> Result<Option<String>> {
+        if self.tool_job.is_none() {
+            return Ok(None);
+        }
+
+        let dir = Path::new(&self.tool)
+            .parent()
+            .context("tool from another job has no directory")?;
+        let dirs = std::iter::once(dir.to_path_buf()).chain(
+            path.filter(|path| !path.is_empty())
+                .into_iter()
+                .flat_map(std::env::split_paths),
+        );
+
+        let joined = std::env::join_paths(dirs)
+            .with_context(|| format!("could not add `{}` to the PATH", dir.display()))?;
+
+        Ok(Some(joined.to_string_lossy().into_owned()))
+    }
+}
+
+impl Command {
+    /// A process for this command, kept off the network if `network` says so,
+    /// seeing `names` in place of the host's, and run under
+    /// `file_trace::TRACER` if there's a `trace`.
+    pub fn to_process(
+        &self,
+        network: Network,
+        names: &network::NameFiles,
+        trace: Option<&file_trace::Trace>,
+    ) -> tokio::process::Command {
+        let mut command = match trace {
+            Some(trace) => {
+                let mut command = network::command(file_trace::TRACER, network, names);
+                command.args(trace.args(&self.tool));
+                command
+            }
+            None => network::command(&self.tool, network, names),
+        };
+
+        for arg in &self.args {
+            command.arg(arg.as_str());
+        }
+
+        command.env_clear();
+
+        for (key, value) in &self.env {
+            command.env(key, value);
+        }
+
+        command
+    }
+}
+
+impl Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.id, self.command)?;
+
+        if !self.variant.is_empty() {
+            let values: Vec<String> = self
+                .variant
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            write!(f, " [{}]", values.join(" "))?;
+        }
+
+        if let Some(shard) = self.shard {
+            write!(f, " [shard {} of {}]", shard.index + 1, shard.total)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where every input ends up in the workspace, whatever kind of input it is.
+fn input_dests<'a>(
+    input_files: &'a BTreeSet<FileMapping>,
+    input_jobs: &'a BTreeMap<Key<Base>, BTreeSet<FileMapping>>,
+    input_resolvers: &'a BTreeMap<resolver::Spec, BTreeSet<FileMapping>>,
+) -> impl Iterator<Item = &'a PathBuf> {
+    input_files
+        .iter()
+        .chain(input_jobs.values().flatten())
+        .chain(input_resolvers.values().flatten())
+        .map(|file| &file.dest)
+}
+
+fn response_file_contents(
+    input_files: &BTreeSet<FileMapping>,
+    input_jobs: &BTreeMap<Key<Base>, BTreeSet<FileMapping>>,
+    input_resolvers: &BTreeMap<resolver::Spec, BTreeSet<FileMapping>>,
+) -> String {
+    let mut contents = String::new();
+
+    for dest in input_dests(input_files, input_jobs, input_resolvers).sorted() {
+        // sanitize_file_path only accepts paths that came from a RocStr, so
+        // this is always valid unicode.
+        let dest = dest.to_string_lossy();
+
+        if dest.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+            contents.push('"');
+            contents.push_str(&dest.replace('\\', "\\\\").replace('"', "\\\""));
+            contents.push('"');
+        } else {
+            contents.push_str(&dest);
+        }
+
+        contents.push('\n');
+    }
+
+    contents
+}
+
+pub fn sanitize_file_path(roc_str: &RocStr) -> Result<PathBuf> {
+    let sanitized: PathBuf = roc_str.as_str().into();
+
+    // verify that the specified path is safe. We can't allow accessing any
     // path outside the workspace. To get this, we don't allow any parent path
     // segments (`..`) This restriction also enforces unambiguous paths in the
     // Roc API (e.g. you wouldn't want to add "foo/../bar" as an output path!)
@@ -321,56 +1680,1370 @@ pub fn sanitize_file_path(roc_str: &RocStr) -> Result<PathBuf> {
                 sanitized.display(),
             ),
 
-            Component::ParentDir => anyhow::bail!(
-                "Relative paths containing `..` (like `{}`) are not allowed. Remove the `..` to fix this!",
-                sanitized.display(),
-            ),
+            Component::ParentDir => anyhow::bail!(
+                "Relative paths containing `..` (like `{}`) are not allowed. Remove the `..` to fix this!",
+                sanitized.display(),
+            ),
+
+            Component::CurDir | Component::Normal(_) => (),
+        };
+    }
+
+    Ok(sanitized)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use roc_std::RocList;
+
+    #[test]
+    fn job_hash_stability() {
+        // It's important that job IDs don't change accidentally over time. For
+        // example, if we update a dependency and the hash here suddenly changes,
+        // we should look into it and consider a smooth migration path for
+        // callers. Similarly, it might be inappropriate new optional fields in the
+        // Roc API to contribute to the ID, since doing so would mean completely
+        // re-running all build steps.
+        //
+        // `golden_keys` below covers more job shapes. If you need to change
+        // either, follow docs/internals/changing-job-keys.md.
+        let glue_job = glue::Job::Job(glue::R1 {
+            inputs: RocList::from_slice(&[glue::U1::FromProjectSource(RocList::from([
+                glue::FileMapping {
+                    source: "input_file".into(),
+                    dest: "input_file".into(),
+                },
+            ]))]),
+            outputs: RocList::from_slice(&["output_file".into()]),
+            ..glue::R1::for_test(system_command("bash", &["-c", "Hello, World"]))
+        });
+
+        let job = Job::from_glue(&glue_job, &HashMap::new()).unwrap();
+
+        assert_eq!(
+            Key {
+                key: 5039616039242196913,
+                phantom: PhantomData
+            },
+            job.base_key
+        );
+    }
+
+    /// A compact description of a glue job, so we can write down lots of
+    /// job shapes for the golden key tests.
+    #[derive(Clone)]
+    struct Fixture {
+        tool: &'static str,
+        tool_job: Option<glue::Job>,
+        args: Vec<&'static str>,
+        inputs: Vec<glue::U1>,
+        after: Vec<glue::Job>,
+        outputs: Vec<&'static str>,
+        output_filters: Vec<glue::R2>,
+        limits: Vec<glue::R3>,
+        resources: Vec<&'static str>,
+        passthrough_env: Vec<&'static str>,
+        then_run: Vec<(&'static str, Vec<&'static str>)>,
+        expect_failure: bool,
+        incremental_state: Vec<&'static str>,
+        checkpoint_dir: &'static str,
+        response_file: &'static str,
+        output_from_stdout: &'static str,
+        writable_outputs: Vec<&'static str>,
+        retention: glue::R4,
+        network: glue::Network,
+        hosts: Vec<(&'static str, &'static str)>,
+        dns_servers: Vec<&'static str>,
+        input_strategy: glue::InputStrategy,
+        kind: glue::JobKind,
+        stamp: bool,
+        rerun_on_missing_outputs: bool,
+        visibility: glue::Visibility,
+    }
+
+    impl Fixture {
+        fn new(tool: &'static str, args: &[&'static str]) -> Self {
+            Fixture {
+                tool,
+                tool_job: None,
+                args: args.to_vec(),
+                inputs: Vec::new(),
+                after: Vec::new(),
+                outputs: Vec::new(),
+                output_filters: Vec::new(),
+                limits: Vec::new(),
+                resources: Vec::new(),
+                passthrough_env: Vec::new(),
+                then_run: Vec::new(),
+                expect_failure: false,
+                incremental_state: Vec::new(),
+                checkpoint_dir: "",
+                response_file: "",
+                output_from_stdout: "",
+                writable_outputs: Vec::new(),
+                retention: glue::R4 {
+                    days: 0,
+                    kind: glue::RetentionKind::Default,
+                },
+                network: glue::Network::Allowed,
+                hosts: Vec::new(),
+                dns_servers: Vec::new(),
+                input_strategy: glue::InputStrategy::Symlink,
+                kind: glue::JobKind::Build,
+                stamp: false,
+                rerun_on_missing_outputs: false,
+                visibility: glue::Visibility::Public,
+            }
+        }
+
+        fn project_files(mut self, files: &[(&str, &str)]) -> Self {
+            self.inputs
+                .push(glue::U1::FromProjectSource(file_mappings(files)));
+            self
+        }
+
+        fn job_files(mut self, job: &glue::Job, files: &[(&str, &str)]) -> Self {
+            self.inputs
+                .push(glue::U1::FromJob(job.clone(), file_mappings(files)));
+            self
+        }
+
+        fn archive_files(mut self, url: &str, sha256: &str, files: &[(&str, &str)]) -> Self {
+            self.inputs.push(glue::U1::FromArchive(
+                url.into(),
+                sha256.into(),
+                file_mappings(files),
+            ));
+            self
+        }
+
+        fn resolver_files(mut self, resolver: &str, spec: &str, files: &[(&str, &str)]) -> Self {
+            self.inputs.push(glue::U1::FromResolver(
+                resolver.into(),
+                spec.into(),
+                file_mappings(files),
+            ));
+            self
+        }
+
+        /// Run `tool` from `job`'s outputs instead of from the system.
+        fn tool_from(mut self, job: &glue::Job) -> Self {
+            self.tool_job = Some(job.clone());
+            self
+        }
+
+        fn after(mut self, job: &glue::Job) -> Self {
+            self.after.push(job.clone());
+            self
+        }
+
+        fn outputs(mut self, outputs: &[&'static str]) -> Self {
+            self.outputs.extend_from_slice(outputs);
+            self
+        }
+
+        fn output_filter(mut self, output: &str, filter: glue::OutputFilter) -> Self {
+            self.output_filters.push(glue::R2 {
+                output: output.into(),
+                filter,
+            });
+            self
+        }
+
+        fn limit(mut self, limit: glue::Limit, value: u64) -> Self {
+            self.limits.push(glue::R3 { limit, value });
+            self
+        }
+
+        fn resources(mut self, resources: &[&'static str]) -> Self {
+            self.resources.extend_from_slice(resources);
+            self
+        }
+
+        fn incremental_state(mut self, dirs: &[&'static str]) -> Self {
+            self.incremental_state.extend_from_slice(dirs);
+            self
+        }
+
+        fn checkpoint_dir(mut self, dir: &'static str) -> Self {
+            self.checkpoint_dir = dir;
+            self
+        }
+
+        fn response_file(mut self, name: &'static str) -> Self {
+            self.response_file = name;
+            self
+        }
+
+        fn output_from_stdout(mut self, output: &'static str) -> Self {
+            self.output_from_stdout = output;
+            self
+        }
+
+        fn writable_outputs(mut self, outputs: &[&'static str]) -> Self {
+            self.writable_outputs.extend_from_slice(outputs);
+            self
+        }
+
+        fn retention(mut self, kind: glue::RetentionKind, days: u32) -> Self {
+            self.retention = glue::R4 { days, kind };
+            self
+        }
+
+        fn visibility(mut self, visibility: glue::Visibility) -> Self {
+            self.visibility = visibility;
+            self
+        }
+
+        fn expect_failure(mut self) -> Self {
+            self.expect_failure = true;
+            self
+        }
+
+        fn test(mut self) -> Self {
+            self.kind = glue::JobKind::Test;
+            self
+        }
+
+        fn forbid_network(mut self) -> Self {
+            self.network = glue::Network::Forbidden;
+            self
+        }
+
+        fn host(mut self, name: &'static str, address: &'static str) -> Self {
+            self.hosts.push((name, address));
+            self
+        }
+
+        fn dns_server(mut self, server: &'static str) -> Self {
+            self.dns_servers.push(server);
+            self
+        }
+
+        fn then_run(mut self, tool: &'static str, args: &[&'static str]) -> Self {
+            self.then_run.push((tool, args.to_vec()));
+            self
+        }
+
+        fn passthrough_env(mut self, names: &[&'static str]) -> Self {
+            self.passthrough_env.extend_from_slice(names);
+            self
+        }
+
+        fn input_strategy(mut self, input_strategy: glue::InputStrategy) -> Self {
+            self.input_strategy = input_strategy;
+            self
+        }
+
+        fn stamp(mut self) -> Self {
+            self.stamp = true;
+            self
+        }
+
+        fn rerun_on_missing_outputs(mut self) -> Self {
+            self.rerun_on_missing_outputs = true;
+            self
+        }
+
+        fn to_glue(&self) -> glue::Job {
+            let command = glue::Command {
+                tool: match &self.tool_job {
+                    Some(job) => glue::Tool::FromJob(glue::FromJobPayload {
+                        job: job.clone(),
+                        name: RocStr::from(self.tool),
+                    }),
+                    None => glue::Tool::SystemTool(glue::SystemToolPayload {
+                        name: RocStr::from(self.tool),
+                    }),
+                },
+                args: self.args.iter().map(|arg| RocStr::from(*arg)).collect(),
+            };
+
+            // roc_std can't build a `RocDict` with items in it yet, so env is
+            // left empty here and tested separately in `golden_command_env_keys`.
+            glue::Job::Job(glue::R1 {
+                after: RocList::from_slice(&self.after),
+                checkpointDir: RocStr::from(self.checkpoint_dir),
+                incrementalState: self
+                    .incremental_state
+                    .iter()
+                    .map(|dir| RocStr::from(*dir))
+                    .collect(),
+                inputs: RocList::from_slice(&self.inputs),
+                limits: RocList::from_slice(&self.limits),
+                outputFilters: RocList::from_slice(&self.output_filters),
+                outputFromStdout: RocStr::from(self.output_from_stdout),
+                outputs: self.outputs.iter().map(|out| RocStr::from(*out)).collect(),
+                passthroughEnv: self
+                    .passthrough_env
+                    .iter()
+                    .map(|name| RocStr::from(*name))
+                    .collect(),
+                resources: self
+                    .resources
+                    .iter()
+                    .map(|res| RocStr::from(*res))
+                    .collect(),
+                responseFile: RocStr::from(self.response_file),
+                thenRun: self
+                    .then_run
+                    .iter()
+                    .map(|(tool, args)| system_command(tool, args))
+                    .collect(),
+                writableOutputs: self
+                    .writable_outputs
+                    .iter()
+                    .map(|out| RocStr::from(*out))
+                    .collect(),
+                retention: self.retention.clone(),
+                expectFailure: self.expect_failure,
+                inputStrategy: self.input_strategy,
+                kind: self.kind,
+                network: self.network,
+                hosts: self
+                    .hosts
+                    .iter()
+                    .map(|(name, address)| glue::R6 {
+                        address: RocStr::from(*address),
+                        name: RocStr::from(*name),
+                    })
+                    .collect(),
+                dnsServers: self
+                    .dns_servers
+                    .iter()
+                    .map(|server| RocStr::from(*server))
+                    .collect(),
+                rerunOnMissingOutputs: self.rerun_on_missing_outputs,
+                stamp: self.stamp,
+                visibility: self.visibility,
+                ..glue::R1::for_test(command)
+            })
+        }
+
+        fn key(&self, deps: &[&glue::Job]) -> Key<Base> {
+            let glue_job_to_key: HashMap<GlueRef, Key<Base>> = deps
+                .iter()
+                .map(|dep| {
+                    (
+                        GlueRef(dep),
+                        Job::from_glue(dep, &HashMap::new()).unwrap().base_key,
+                    )
+                })
+                .collect();
+
+            Job::from_glue(&self.to_glue(), &glue_job_to_key)
+                .unwrap()
+                .base_key
+        }
+    }
+
+    fn file_mappings(files: &[(&str, &str)]) -> RocList<glue::FileMapping> {
+        files
+            .iter()
+            .map(|(source, dest)| glue::FileMapping {
+                source: (*source).into(),
+                dest: (*dest).into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn golden_keys() {
+        // Every shape of job we support should have an entry here, with the
+        // exact key we expect. When you add a field to the Roc API, add a
+        // fixture that sets it! If any of these change, something about key
+        // derivation changed, and everyone's cache is about to be invalidated.
+        // See docs/internals/changing-job-keys.md before updating them.
+        let dep = Fixture::new("bash", &["-c", "printf Hello > greeting"])
+            .outputs(&["greeting"])
+            .to_glue();
+        let writable_dep = Fixture::new("sqlite3", &["fixture.db", ".read schema.sql"])
+            .outputs(&["fixture.db"])
+            .writable_outputs(&["fixture.db"])
+            .to_glue();
+        let compiler = Fixture::new("make", &["bin/cc"])
+            .outputs(&["bin"])
+            .to_glue();
+
+        let fixtures = vec![
+            (
+                "bare command",
+                Fixture::new("bash", &[]),
+                5630392698560435607,
+            ),
+            (
+                "command with args",
+                Fixture::new("bash", &["-c", "echo hi"]),
+                9913902487590605026,
+            ),
+            (
+                "project source files",
+                Fixture::new("cat", &["a", "b"]).project_files(&[("a", "a"), ("b", "b")]),
+                13167629517690513862,
+            ),
+            (
+                "renamed project source file",
+                Fixture::new("cat", &["b"]).project_files(&[("a", "b")]),
+                13058497737112156482,
+            ),
+            (
+                "nested project source file",
+                Fixture::new("cat", &["src/a"]).project_files(&[("src/a", "src/a")]),
+                378042564259732683,
+            ),
+            (
+                "files from another job",
+                Fixture::new("cat", &["greeting"]).job_files(&dep, &[("greeting", "greeting")]),
+                1601289265486559871,
+            ),
+            (
+                "renamed file from another job",
+                Fixture::new("cat", &["hello"]).job_files(&dep, &[("greeting", "hello")]),
+                1673104756656925433,
+            ),
+            (
+                "files from a resolver",
+                Fixture::new("cat", &["lib.a"]).resolver_files(
+                    "blobs",
+                    "{\"name\": \"lib\", \"version\": 2}",
+                    &[("lib.a", "lib.a")],
+                ),
+                13443070340255341050,
+            ),
+            (
+                "outputs",
+                Fixture::new("touch", &["a", "b"]).outputs(&["a", "b"]),
+                9607473790126099251,
+            ),
+            (
+                "expect failure",
+                Fixture::new("false", &[])
+                    .outputs(&["stderr"])
+                    .expect_failure(),
+                5014602639158111397,
+            ),
+            (
+                "incremental state",
+                Fixture::new("tsc", &["--incremental"])
+                    .outputs(&["out.js"])
+                    .incremental_state(&[".tsbuildinfo"]),
+                4507121146672834891,
+            ),
+            (
+                "checkpoint dir",
+                Fixture::new("train", &["--resume-from", "checkpoints"])
+                    .outputs(&["model.bin"])
+                    .checkpoint_dir("checkpoints"),
+                14092956718790187602,
+            ),
+            (
+                "output filters",
+                Fixture::new("ar", &["rcs", "lib.a", "a.o"])
+                    .outputs(&["lib.a"])
+                    .output_filter("lib.a", glue::OutputFilter::Strip)
+                    .output_filter("lib.a", glue::OutputFilter::NormalizeArchive),
+                3576275160293829351,
+            ),
+            (
+                "response file",
+                Fixture::new("ld", &["-o", "app"])
+                    .project_files(&[("a.o", "a.o"), ("b.o", "b.o")])
+                    .outputs(&["app"])
+                    .response_file("objects.rsp"),
+                1257485577414190230,
+            ),
+            (
+                "limits",
+                Fixture::new("javac", &["Main.java"])
+                    .outputs(&["Main.class"])
+                    .limit(glue::Limit::OpenFiles, 4096)
+                    .limit(glue::Limit::StackSize, 16 * 1024 * 1024),
+                12961176187504435655,
+            ),
+            (
+                "writable outputs",
+                Fixture::new("sqlite3", &["fixture.db", ".read schema.sql"])
+                    .outputs(&["fixture.db"])
+                    .writable_outputs(&["fixture.db"]),
+                11285044374745801154,
+            ),
+            (
+                "writable inputs",
+                Fixture::new("pytest", &[]).job_files(&writable_dep, &[("fixture.db", "test.db")]),
+                7389341598565465717,
+            ),
+            (
+                "hosts",
+                Fixture::new("npm", &["test"]).host("db.test", "10.0.0.2"),
+                15933963686879187010,
+            ),
+            (
+                "dns servers",
+                Fixture::new("npm", &["test"])
+                    .dns_server("10.0.0.53")
+                    .dns_server("10.0.0.54"),
+                9755509670842239802,
+            ),
+            (
+                "network forbidden",
+                Fixture::new("cargo", &["build", "--offline"]).forbid_network(),
+                4419503552499020814,
+            ),
+            (
+                "output from stdout",
+                Fixture::new("sort", &["words.txt"]).output_from_stdout("sorted.txt"),
+                11505675597920739306,
+            ),
+            (
+                "inputs copied",
+                Fixture::new("tsc", &[])
+                    .project_files(&[("index.ts", "index.ts")])
+                    .input_strategy(glue::InputStrategy::Copy),
+                2121854941430754439,
+            ),
+            (
+                "stamped",
+                Fixture::new("ld", &["-o", "app", "main.o"]).stamp(),
+                4027119333326316615,
+            ),
+            (
+                "more than one command",
+                Fixture::new("cc", &["-c", "main.c"])
+                    .then_run("ld", &["-o", "app", "main.o"])
+                    .outputs(&["app"]),
+                9338085172250631391,
+            ),
+            (
+                "passthrough env",
+                Fixture::new("curl", &["-O", "https://example.com/a.tar.gz"])
+                    .passthrough_env(&["SSL_CERT_FILE", "LANG"]),
+                4287537511622387386,
+            ),
+            (
+                "tool from another job",
+                Fixture::new("bin/cc", &["-c", "main.c"]).tool_from(&compiler),
+                14532856701599273031,
+            ),
+        ];
+
+        let mismatches: Vec<String> = fixtures
+            .iter()
+            .filter_map(|(name, fixture, expected)| {
+                let actual = fixture.key(&[&dep, &writable_dep, &compiler]).key;
+
+                if actual == *expected {
+                    None
+                } else {
+                    Some(format!("{}: expected {}, got {}", name, expected, actual))
+                }
+            })
+            .collect();
+
+        assert!(
+            mismatches.is_empty(),
+            "some keys changed:\n\n{}\n\nsee docs/internals/changing-job-keys.md",
+            mismatches.join("\n")
+        );
+    }
+
+    #[test]
+    fn key_ignores_input_order() {
+        let forwards = Fixture::new("cat", &[])
+            .project_files(&[("a", "a"), ("b", "b")])
+            .project_files(&[("c", "c")]);
+        let backwards = Fixture::new("cat", &[])
+            .project_files(&[("c", "c")])
+            .project_files(&[("b", "b"), ("a", "a")]);
+
+        assert_eq!(forwards.key(&[]), backwards.key(&[]));
+    }
+
+    #[test]
+    fn golden_command_env_keys() {
+        let key = |env: &[(&str, &str)]| {
+            let command = Command {
+                tool: "env".into(),
+                tool_job: None,
+                pinned_sha256: None,
+                args: Vec::new(),
+                env: env
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            };
+
+            let mut hasher = KeyHasher::new();
+            command.hash_into(&mut hasher);
+            hasher.finish()
+        };
+
+        assert_eq!(
+            key(&[("HELLO", "Hello"), ("WORLD", "World")]),
+            key(&[("WORLD", "World"), ("HELLO", "Hello")]),
+        );
+        assert_eq!(
+            12175956033345533830,
+            key(&[("HELLO", "Hello"), ("WORLD", "World")])
+        );
+    }
+
+    #[test]
+    fn key_ignores_output_order_and_duplicates() {
+        let forwards = Fixture::new("touch", &[]).outputs(&["a", "b"]);
+        let backwards = Fixture::new("touch", &[]).outputs(&["b", "a", "b"]);
 
-            Component::CurDir | Component::Normal(_) => (),
-        };
+        assert_eq!(forwards.key(&[]), backwards.key(&[]));
     }
 
-    Ok(sanitized)
-}
+    #[test]
+    fn key_distinguishes_string_boundaries() {
+        let one = Fixture::new("echo", &["ab", "c"]);
+        let other = Fixture::new("echo", &["a", "bc"]);
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use roc_std::{RocDict, RocList};
+        assert_ne!(one.key(&[]), other.key(&[]));
+    }
 
     #[test]
-    fn job_hash_stability() {
-        // It's important that job IDs don't change accidentally over time. For
-        // example, if we update a dependency and the hash here suddenly changes,
-        // we should look into it and consider a smooth migration path for
-        // callers. Similarly, it might be inappropriate new optional fields in the
-        // Roc API to contribute to the ID, since doing so would mean completely
-        // re-running all build steps.
-        let glue_job = glue::Job::Job(glue::R1 {
-            command: glue::Command {
-                tool: glue::Tool::SystemTool(glue::SystemToolPayload {
-                    name: RocStr::from("bash"),
-                }),
-                args: RocList::from_slice(&["-c".into(), "Hello, World".into()]),
-            },
-            env: RocDict::with_capacity(0),
-            inputs: RocList::from_slice(&[glue::U1::FromProjectSource(RocList::from([
-                glue::FileMapping {
-                    source: "input_file".into(),
-                    dest: "input_file".into(),
-                },
-            ]))]),
-            outputs: RocList::from_slice(&["output_file".into()]),
+    fn key_distinguishes_renames() {
+        let plain = Fixture::new("cat", &[]).project_files(&[("a", "a")]);
+        let renamed = Fixture::new("cat", &[]).project_files(&[("a", "b")]);
+
+        assert_ne!(plain.key(&[]), renamed.key(&[]));
+    }
+
+    #[test]
+    fn produces_files_inside_outputs() {
+        let job = Job::from_glue(
+            &Fixture::new("esbuild", &["index.ts"])
+                .outputs(&["dist", "stats.json"])
+                .to_glue(),
+            &HashMap::new(),
+        )
+        .unwrap();
+
+        assert!(job.produces(Path::new("stats.json")));
+        assert!(job.produces(Path::new("dist")));
+        assert!(job.produces(Path::new("dist/bundle.js")));
+        assert!(!job.produces(Path::new("distfiles/bundle.js")));
+        assert!(!job.produces(Path::new("index.ts")));
+    }
+
+    #[test]
+    fn expect_failure_changes_key() {
+        let expecting_success = Fixture::new("rustc", &["bad.rs"]).outputs(&["stderr"]);
+        let expecting_failure = expecting_success.clone().expect_failure();
+
+        let job = Job::from_glue(&expecting_failure.to_glue(), &HashMap::new()).unwrap();
+
+        assert!(job.expect_failure);
+        assert_ne!(expecting_success.key(&[]), expecting_failure.key(&[]));
+    }
+
+    #[test]
+    fn output_from_stdout_is_an_output() {
+        let job = Job::from_glue(
+            &Fixture::new("sort", &["words.txt"])
+                .output_from_stdout("out/sorted.txt")
+                .to_glue(),
+            &HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(Path::new("out/sorted.txt")),
+            job.output_from_stdout.as_deref()
+        );
+        assert!(job.outputs.contains(Path::new("out/sorted.txt")));
+    }
+
+    #[test]
+    fn resources_do_not_change_key() {
+        let without = Fixture::new("psql", &[]);
+        let with = without.clone().resources(&["port:5432", "kvm"]);
+
+        let job = Job::from_glue(&with.to_glue(), &HashMap::new()).unwrap();
+
+        assert_eq!(job.resources, vec!["kvm", "port:5432"]);
+        assert_eq!(without.key(&[]), with.key(&[]));
+    }
+
+    #[test]
+    fn glue_refs_compare_by_address() {
+        let job = Fixture::new("cc", &["main.c"]).to_glue();
+        let shared = job.clone();
+        let separate = Fixture::new("cc", &["main.c"]).to_glue();
+
+        assert_eq!(GlueRef(&job), GlueRef(&shared));
+        assert_ne!(GlueRef(&job), GlueRef(&separate));
+        assert_eq!(
+            Job::from_glue(&job, &HashMap::new()).unwrap().base_key,
+            Job::from_glue(&separate, &HashMap::new()).unwrap().base_key
+        );
+    }
+
+    #[test]
+    fn dependency_order_puts_dependencies_first() {
+        let lib = Fixture::new("cc", &["lib.c"]).outputs(&["lib.o"]).to_glue();
+        let main = Fixture::new("cc", &["main.c"])
+            .outputs(&["main.o"])
+            .to_glue();
+        let link = Fixture::new("ld", &[])
+            .job_files(&lib, &[("lib.o", "lib.o")])
+            .job_files(&main, &[("main.o", "main.o")])
+            .to_glue();
+        let test = Fixture::new("test", &[]).after(&link).to_glue();
+
+        let order = dependency_order([&test, &link], &GraphLimits::default()).unwrap();
+
+        let position = |job: &glue::Job| {
+            order
+                .iter()
+                .position(|other| GlueRef(other) == GlueRef(job))
+                .unwrap()
+        };
+        assert_eq!(4, order.len());
+        assert!(position(&lib) < position(&link));
+        assert!(position(&main) < position(&link));
+        assert!(position(&link) < position(&test));
+    }
+
+    #[test]
+    fn dependency_order_stops_at_limits() {
+        let chain = (0..5).fold(Fixture::new("seed", &[]).to_glue(), |prev, _| {
+            Fixture::new("step", &[]).after(&prev).to_glue()
         });
+        let wide = Fixture::new("cat", &[])
+            .project_files(&[("a", "a"), ("b", "b"), ("c", "c")])
+            .to_glue();
+        let limits = GraphLimits {
+            jobs: 10,
+            depth: 10,
+            inputs_per_job: 10,
+        };
 
-        let job = Job::from_glue(&glue_job, &HashMap::new()).unwrap();
+        assert!(dependency_order([&chain], &limits).is_ok());
+        assert!(dependency_order([&chain], &GraphLimits { jobs: 5, ..limits }).is_err());
+        assert!(dependency_order([&chain], &GraphLimits { depth: 5, ..limits }).is_err());
+        assert!(dependency_order(
+            [&wide],
+            &GraphLimits {
+                inputs_per_job: 2,
+                ..limits
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn order_only_dependencies_do_not_change_key() {
+        let migrate = Fixture::new("migrate", &[]).to_glue();
+
+        let without = Fixture::new("test", &[]);
+        let with = without.clone().after(&migrate);
+
+        let migrate_key = Job::from_glue(&migrate, &HashMap::new()).unwrap().base_key;
+        let job = Job::from_glue(
+            &with.to_glue(),
+            &HashMap::from([(GlueRef(&migrate), migrate_key)]),
+        )
+        .unwrap();
+
+        assert_eq!(BTreeSet::from([migrate_key]), job.after);
+        assert!(job.input_jobs.is_empty());
+        assert_eq!(without.key(&[]), with.key(&[&migrate]));
+    }
+
+    #[test]
+    fn retention_does_not_change_key() {
+        let without = Fixture::new("cargo", &["build", "--release"]);
+        let with = without.clone().retention(glue::RetentionKind::Days, 90);
+
+        let job = Job::from_glue(&with.to_glue(), &HashMap::new()).unwrap();
+
+        assert_eq!(Some(Retention::Days(90)), job.retention);
+        assert_eq!(without.key(&[]), with.key(&[]));
+    }
+
+    #[test]
+    fn being_a_test_does_not_change_key() {
+        let build = Fixture::new("cargo", &["test"]);
+        let test = build.clone().test();
+
+        assert!(
+            !Job::from_glue(&build.to_glue(), &HashMap::new())
+                .unwrap()
+                .is_test
+        );
+        assert!(
+            Job::from_glue(&test.to_glue(), &HashMap::new())
+                .unwrap()
+                .is_test
+        );
+        assert_eq!(build.key(&[]), test.key(&[]));
+    }
+
+    #[test]
+    fn rerunning_on_missing_outputs_does_not_change_key() {
+        let without = Fixture::new("cc", &["-o", "main.o", "main.c"]);
+        let with = without.clone().rerun_on_missing_outputs();
+
+        let job = Job::from_glue(&with.to_glue(), &HashMap::new()).unwrap();
+
+        assert!(job.rerun_on_missing_outputs);
+        assert_eq!(without.key(&[]), with.key(&[]));
+    }
 
+    #[test]
+    fn archive_specs_are_escaped() {
+        let url = "https://example.com/a\"b\\c.tar.gz";
+        let fixture =
+            Fixture::new("cat", &["lib.a"]).archive_files(url, "b3a2", &[("lib.a", "lib.a")]);
+
+        let job = Job::from_glue(&fixture.to_glue(), &HashMap::new()).unwrap();
+        let spec = job.input_resolvers.keys().next().unwrap();
+
+        assert_eq!(resolver::ARCHIVE, spec.resolver);
+        let parsed: serde_json::Value = serde_json::from_str(&spec.spec).unwrap();
+        assert_eq!(url, parsed["url"]);
+        assert_eq!("b3a2", parsed["sha256"]);
+
+        // it's the same input as asking the archive resolver by hand
+        let by_hand = Fixture::new("cat", &["lib.a"]).resolver_files(
+            resolver::ARCHIVE,
+            &spec.spec,
+            &[("lib.a", "lib.a")],
+        );
+        assert_eq!(fixture.key(&[]), by_hand.key(&[]));
+    }
+
+    #[test]
+    fn rejects_hosts_and_dns_servers_that_are_not_addresses() {
+        for fixture in [
+            Fixture::new("npm", &["test"]).host("db.test", "db.internal"),
+            Fixture::new("npm", &["test"]).host("db test", "10.0.0.2"),
+            Fixture::new("npm", &["test"])
+                .host("db.test", "10.0.0.2")
+                .host("db.test", "10.0.0.3"),
+            Fixture::new("npm", &["test"]).dns_server("ns.example.com"),
+        ] {
+            assert!(Job::from_glue(&fixture.to_glue(), &HashMap::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn dns_server_order_changes_key() {
+        let first = Fixture::new("npm", &["test"])
+            .dns_server("10.0.0.53")
+            .dns_server("10.0.0.54");
+        let second = Fixture::new("npm", &["test"])
+            .dns_server("10.0.0.54")
+            .dns_server("10.0.0.53");
+
+        assert_ne!(first.key(&[]), second.key(&[]));
+    }
+
+    #[test]
+    fn visibility_does_not_change_key() {
+        let public = Fixture::new("cc", &["-o", "licensed", "licensed.c"]);
+        let local = public.clone().visibility(glue::Visibility::Local);
+
+        let job = Job::from_glue(&local.to_glue(), &HashMap::new()).unwrap();
+
+        assert_eq!(Visibility::Local, job.visibility);
+        assert_eq!(public.key(&[]), local.key(&[]));
+    }
+
+    #[test]
+    fn shards_are_keyed_separately() {
+        let job =
+            Job::from_glue(&Fixture::new("cargo", &["test"]).to_glue(), &HashMap::new()).unwrap();
+        let group = job.base_key;
+
+        let keys: BTreeSet<Key<Base>> = job
+            .clone()
+            .into_shards(3)
+            .iter()
+            .map(|shard| shard.base_key)
+            .collect();
+
+        assert_eq!(3, keys.len());
+        assert!(!keys.contains(&group));
         assert_eq!(
-            Key {
-                key: 4258861697155208119,
-                phantom: PhantomData
-            },
-            job.base_key
+            keys,
+            job.into_shards(3)
+                .iter()
+                .map(|shard| shard.base_key)
+                .collect()
+        );
+    }
+
+    #[test]
+    fn matrix_copies_are_keyed_by_their_own_values() {
+        let job = Job::from_glue(
+            &Fixture::new("cargo", &["build"]).to_glue(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let axis = |name: &str, values: &[&str]| glue::R5 {
+            name: RocStr::from(name),
+            values: values.iter().map(|value| RocStr::from(*value)).collect(),
+        };
+
+        let variants = job
+            .clone()
+            .into_variants(&[
+                axis("ARCH", &["x86_64", "aarch64"]),
+                axis("OS", &["linux", "macos"]),
+            ])
+            .unwrap();
+        assert_eq!(4, variants.len());
+        assert!(variants
+            .iter()
+            .all(|variant| variant.group == Some(job.base_key)));
+
+        // adding a value leaves the copies that were already there alone
+        let more = job
+            .clone()
+            .into_variants(&[
+                axis("ARCH", &["x86_64", "aarch64", "riscv64"]),
+                axis("OS", &["linux", "macos"]),
+            ])
+            .unwrap();
+        let keys: BTreeSet<Key<Base>> = more.iter().map(|variant| variant.base_key).collect();
+        assert_eq!(6, keys.len());
+        assert!(variants
+            .iter()
+            .all(|variant| keys.contains(&variant.base_key)));
+
+        assert!(job.clone().into_variants(&[axis("HOME", &["/"])]).is_err());
+        for bad in ["", "1ARCH", "TARGET-OS", "OS NAME"] {
+            assert!(job.clone().into_variants(&[axis(bad, &["x"])]).is_err());
+        }
+        assert!(job.into_variants(&[axis("_target_os2", &["x"])]).is_ok());
+    }
+
+    #[test]
+    fn key_contributing_fields_iterate_in_order() {
+        let job = Job::from_glue(
+            &Fixture::new("touch", &[])
+                .project_files(&[("z", "z"), ("y", "y")])
+                .project_files(&[("x", "x")])
+                .outputs(&["c", "a", "b"])
+                .to_glue(),
+            &HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            job.input_files
+                .iter()
+                .map(|file| file.source.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["x", "y", "z"],
+        );
+        assert_eq!(
+            job.outputs
+                .iter()
+                .map(|output| output.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "c"],
+        );
+    }
+
+    #[test]
+    fn final_key_ignores_input_order() {
+        let path_to_hash = HashMap::from([
+            (PathBuf::from("a"), blake3::hash(b"a")),
+            (PathBuf::from("b"), blake3::hash(b"b")),
+        ]);
+
+        let final_key = |fixture: Fixture| {
+            Job::from_glue(&fixture.to_glue(), &HashMap::new())
+                .unwrap()
+                .final_key(
+                    &path_to_hash,
+                    &HashMap::new(),
+                    &HashMap::new(),
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap()
+        };
+
+        assert_eq!(
+            final_key(Fixture::new("cat", &[]).project_files(&[("a", "a"), ("b", "b")])),
+            final_key(Fixture::new("cat", &[]).project_files(&[("b", "b"), ("a", "a")])),
+        );
+    }
+
+    #[test]
+    fn files_from_the_same_job_are_merged() {
+        let dep = Fixture::new("bash", &["-c", "touch a b"])
+            .outputs(&["a", "b"])
+            .to_glue();
+        let dep_key = Job::from_glue(&dep, &HashMap::new()).unwrap().base_key;
+
+        let job = Job::from_glue(
+            &Fixture::new("cat", &["a", "b"])
+                .job_files(&dep, &[("a", "a")])
+                .job_files(&dep, &[("b", "b")])
+                .to_glue(),
+            &HashMap::from([(GlueRef(&dep), dep_key)]),
+        )
+        .unwrap();
+
+        assert_eq!(job.input_jobs.get(&dep_key).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn ids_tell_jobs_apart() {
+        let job = |key: u64| {
+            let mut job =
+                Job::from_glue(&Fixture::new("/usr/bin/cc", &[]).to_glue(), &HashMap::new())
+                    .unwrap();
+            job.base_key.key = key;
+            job.id.key = job.base_key;
+            job
+        };
+
+        let mut jobs = vec![job(0x3fa2_c81e_0000_0001), job(0x3fa2_c81e_0000_0002)];
+        assert_eq!("cc-3fa2c81e", jobs[0].id.to_string());
+
+        disambiguate(jobs.iter_mut());
+        assert_eq!("cc-3fa2c81e00000001", jobs[0].id.to_string());
+
+        for query in ["cc-3fa2c81e00000002", "3fa2c81e00000002"] {
+            assert_eq!(jobs[1].base_key, resolve(&jobs, query).unwrap().base_key);
+        }
+        assert!(resolve(&jobs, "3fa2c81e").is_err());
+        assert!(resolve(&jobs, "ld-3fa2c81e00000002").is_err());
+
+        assert!(looks_like_id("cc-3fa2c81e"));
+        assert!(looks_like_id("3fa2"));
+        assert!(!looks_like_id("frontend"));
+    }
+
+    #[test]
+    fn writable_outputs_become_writable_inputs() {
+        let dep = Fixture::new("bash", &["-c", "mkdir -p db && touch db/a log"])
+            .outputs(&["db", "log"])
+            .writable_outputs(&["db"])
+            .to_glue();
+        let dep_key = Job::from_glue(&dep, &HashMap::new()).unwrap().base_key;
+
+        let job = Job::from_glue(
+            &Fixture::new("cat", &[])
+                .job_files(&dep, &[("db/a", "a"), ("log", "log")])
+                .to_glue(),
+            &HashMap::from([(GlueRef(&dep), dep_key)]),
+        )
+        .unwrap();
+
+        assert_eq!(BTreeSet::from([PathBuf::from("a")]), job.writable_inputs);
+
+        let not_an_output = Fixture::new("touch", &["a"])
+            .outputs(&["a"])
+            .writable_outputs(&["b"]);
+        assert!(Job::from_glue(&not_an_output.to_glue(), &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn outputs_cannot_live_in_incremental_state() {
+        let fixture = Fixture::new("cargo", &["build"])
+            .outputs(&["target/debug/app"])
+            .incremental_state(&["target"]);
+
+        assert!(Job::from_glue(&fixture.to_glue(), &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn outputs_cannot_live_in_checkpoints() {
+        let fixture = Fixture::new("train", &[])
+            .outputs(&["checkpoints/model.bin"])
+            .checkpoint_dir("checkpoints");
+
+        assert!(Job::from_glue(&fixture.to_glue(), &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn outputs_cannot_overlap_inputs() {
+        let dep = Fixture::new("touch", &["lib"]).outputs(&["lib"]).to_glue();
+        let overlaps = |fixture: Fixture| {
+            let glue_job_to_key = HashMap::from([(
+                GlueRef(&dep),
+                Job::from_glue(&dep, &HashMap::new()).unwrap().base_key,
+            )]);
+
+            Job::from_glue(&fixture.to_glue(), &glue_job_to_key).is_err()
+        };
+
+        assert!(overlaps(
+            Fixture::new("sed", &["-i", "s/a/b/", "a"])
+                .project_files(&[("a", "a")])
+                .outputs(&["a"])
+        ));
+        assert!(overlaps(
+            Fixture::new("cp", &["lib", "lib/copy"])
+                .job_files(&dep, &[("lib", "lib")])
+                .outputs(&["lib/copy"])
+        ));
+        assert!(!overlaps(
+            Fixture::new("cp", &["a", "b"])
+                .project_files(&[("a", "a")])
+                .outputs(&["b"])
+        ));
+    }
+
+    #[test]
+    fn response_files_list_every_input() {
+        let dep = Fixture::new("touch", &["lib.a"])
+            .outputs(&["lib.a"])
+            .to_glue();
+        let glue_job_to_key = HashMap::from([(
+            GlueRef(&dep),
+            Job::from_glue(&dep, &HashMap::new()).unwrap().base_key,
+        )]);
+
+        let job = Job::from_glue(
+            &Fixture::new("ld", &[])
+                .project_files(&[("b.o", "b.o"), ("my file.o", "my file.o")])
+                .job_files(&dep, &[("lib.a", "lib/dep.a")])
+                .response_file("objects.rsp")
+                .to_glue(),
+            &glue_job_to_key,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "b.o\nlib/dep.a\n\"my file.o\"\n",
+            job.response_file_contents()
+        );
+    }
+
+    #[test]
+    fn golden_final_key() {
+        let job = Job::from_glue(
+            &Fixture::new("cat", &["a", "b"])
+                .project_files(&[("a", "a"), ("b", "b")])
+                .to_glue(),
+            &HashMap::new(),
+        )
+        .unwrap();
+
+        let path_to_hash = HashMap::from([
+            (PathBuf::from("a"), blake3::hash(b"a")),
+            (PathBuf::from("b"), blake3::hash(b"b")),
+        ]);
+
+        assert_eq!(
+            8491958363260322456,
+            job.final_key(
+                &path_to_hash,
+                &HashMap::new(),
+                &HashMap::new(),
+                None,
+                None,
+                None
+            )
+            .unwrap()
+            .key
+        );
+    }
+
+    #[test]
+    fn salt_changes_final_key() {
+        let job = Job::from_glue(&Fixture::new("cat", &[]).to_glue(), &HashMap::new()).unwrap();
+        let final_key = |salt| {
+            job.final_key(
+                &HashMap::new(),
+                &HashMap::new(),
+                &HashMap::new(),
+                salt,
+                None,
+                None,
+            )
+            .unwrap()
+        };
+
+        assert_ne!(final_key(None), final_key(Some("a")));
+        assert_ne!(final_key(Some("a")), final_key(Some("b")));
+        assert_eq!(final_key(Some("a")), final_key(Some("a")));
+    }
+
+    #[test]
+    fn passthrough_values_only_change_final_key_when_asked() {
+        let final_key = |fixture: Fixture, host_env: Option<&HashMap<String, String>>| {
+            Job::from_glue(&fixture.to_glue(), &HashMap::new())
+                .unwrap()
+                .final_key(
+                    &HashMap::new(),
+                    &HashMap::new(),
+                    &HashMap::new(),
+                    None,
+                    None,
+                    host_env,
+                )
+                .unwrap()
+        };
+        let curl = || Fixture::new("curl", &[]).passthrough_env(&["SSL_CERT_FILE"]);
+        let one = HashMap::from([("SSL_CERT_FILE".to_string(), "/etc/a.pem".to_string())]);
+        let other = HashMap::from([("SSL_CERT_FILE".to_string(), "/etc/b.pem".to_string())]);
+        let unset = HashMap::new();
+
+        assert_eq!(final_key(curl(), None), final_key(curl(), None));
+        assert_ne!(
+            final_key(curl(), Some(&one)),
+            final_key(curl(), Some(&other))
+        );
+        assert_ne!(
+            final_key(curl(), Some(&one)),
+            final_key(curl(), Some(&unset))
+        );
+        assert_eq!(
+            final_key(Fixture::new("curl", &[]), None),
+            final_key(Fixture::new("curl", &[]), Some(&one))
+        );
+    }
+
+    #[test]
+    fn then_run_commands_are_in_order() {
+        let job = Job::from_glue(
+            &Fixture::new("cc", &["-c", "main.c"])
+                .then_run("ld", &["-o", "app", "main.o"])
+                .then_run("strip", &["app"])
+                .to_glue(),
+            &HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec!["ld", "strip"],
+            job.then_run
+                .iter()
+                .map(|command| command.tool())
+                .collect::<Vec<_>>()
+        );
+
+        let swapped = Fixture::new("cc", &["-c", "main.c"])
+            .then_run("strip", &["app"])
+            .then_run("ld", &["-o", "app", "main.o"]);
+        assert_ne!(
+            job.base_key,
+            Job::from_glue(&swapped.to_glue(), &HashMap::new())
+                .unwrap()
+                .base_key
+        );
+    }
+
+    #[test]
+    fn then_run_needs_to_know_which_command() {
+        let chained = || Fixture::new("cc", &[]).then_run("ld", &[]);
+
+        assert!(Job::from_glue(&chained().expect_failure().to_glue(), &HashMap::new()).is_err());
+        assert!(Job::from_glue(
+            &chained().output_from_stdout("out").to_glue(),
+            &HashMap::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn tools_from_other_jobs_are_dependencies() {
+        let compiler = Fixture::new("make", &["bin/cc"])
+            .outputs(&["bin"])
+            .to_glue();
+        let compiler_key = Job::from_glue(&compiler, &HashMap::new()).unwrap().base_key;
+        let compile = Fixture::new("bin/cc", &["main.c"])
+            .tool_from(&compiler)
+            .to_glue();
+
+        let order = dependency_order([&compile], &GraphLimits::default()).unwrap();
+        assert_eq!(2, order.len());
+        assert_eq!(GlueRef(&compiler), GlueRef(order[0]));
+
+        let job = Job::from_glue(
+            &compile,
+            &HashMap::from([(GlueRef(&compiler), compiler_key)]),
+        )
+        .unwrap();
+        assert_eq!(Some(compiler_key), job.command.tool_job());
+        assert_eq!(
+            Some(&BTreeSet::new()),
+            job.input_jobs.get(&compiler_key),
+            "the tool runs from the store, so there's nothing to put in the workspace"
+        );
+        assert_eq!(
+            Some("bin:/usr/bin".to_string()),
+            job.command.search_path(Some("/usr/bin")).unwrap()
+        );
+        assert_eq!(
+            Some("bin".to_string()),
+            job.command.search_path(None).unwrap()
+        );
+
+        let system = Job::from_glue(&Fixture::new("cc", &[]).to_glue(), &HashMap::new()).unwrap();
+        assert_eq!(None, system.command.search_path(Some("/usr/bin")).unwrap());
+    }
+
+    #[test]
+    fn tools_from_other_jobs_stay_inside_them() {
+        let compiler = Fixture::new("make", &[]).outputs(&["bin"]).to_glue();
+        let glue_job_to_key = HashMap::from([(
+            GlueRef(&compiler),
+            Job::from_glue(&compiler, &HashMap::new()).unwrap().base_key,
+        )]);
+
+        for name in ["../cc", "/usr/bin/cc"] {
+            let glue_job = Fixture::new(name, &[]).tool_from(&compiler).to_glue();
+
+            assert!(
+                Job::from_glue(&glue_job, &glue_job_to_key).is_err(),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn passthrough_env_needs_valid_names() {
+        for name in ["", "LANG=C"] {
+            let glue_job = Fixture::new("curl", &[]).passthrough_env(&[name]).to_glue();
+
+            assert!(
+                Job::from_glue(&glue_job, &HashMap::new()).is_err(),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn only_stamped_jobs_depend_on_stable_status() {
+        let final_key = |fixture: Fixture, status| {
+            Job::from_glue(&fixture.to_glue(), &HashMap::new())
+                .unwrap()
+                .final_key(
+                    &HashMap::new(),
+                    &HashMap::new(),
+                    &HashMap::new(),
+                    None,
+                    status,
+                    None,
+                )
+                .unwrap()
+        };
+        let stamped = || Fixture::new("ld", &[]).stamp();
+        let unstamped = || Fixture::new("ld", &[]);
+
+        assert_ne!(
+            final_key(stamped(), Some("main")),
+            final_key(stamped(), Some("release"))
+        );
+        assert_eq!(
+            final_key(unstamped(), Some("main")),
+            final_key(unstamped(), Some("release"))
         );
     }
 
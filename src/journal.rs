@@ -0,0 +1,176 @@
+use crate::events::{self, Event};
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+
+/// Where in the root dir we keep the journal.
+pub const FILE: &str = "journal";
+
+/// The `version` of every entry we write. Adding fields or events doesn't
+/// change it; changing or removing them does, so readers should skip
+/// entries with a version they don't know.
+pub const VERSION: u32 = 1;
+
+/// If the journal is bigger than this when a build starts, we move it to
+/// `journal.1` (replacing the one that was there) and start a new one, so it
+/// doesn't grow forever.
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// One line of the journal: an event from the build, plus enough to tell
+/// which build it came from and when.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub version: u32,
+
+    /// Random, and the same for every entry from one build.
+    pub build: String,
+
+    /// When we wrote the entry, in milliseconds since the epoch.
+    pub at_ms: u64,
+
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Append every event in a build to the journal at `path` as it happens,
+/// one JSON entry per line, returning once the build is done. Progress
+/// updates are left out, since they're only interesting while they're new.
+pub async fn record(path: PathBuf, events: broadcast::Receiver<Event>) -> Result<()> {
+    let mut journal = Journal::open(&path)?;
+    let mut failed = None;
+
+    events::each(events, |event| {
+        if failed.is_some() || matches!(event, Event::JobProgress { .. }) {
+            return;
+        }
+
+        if let Err(err) = journal.append(event) {
+            failed = Some(err);
+        }
+    })
+    .await;
+
+    match failed {
+        Some(err) => Err(err).with_context(|| format!("could not write to `{}`", path.display())),
+        None => Ok(()),
+    }
+}
+
+struct Journal {
+    file: File,
+    build: String,
+}
+
+impl Journal {
+    fn open(path: &Path) -> Result<Self> {
+        if std::fs::metadata(path).is_ok_and(|meta| meta.len() > MAX_SIZE) {
+            let mut old = OsString::from(path.as_os_str());
+            old.push(".1");
+            std::fs::rename(path, &old)
+                .with_context(|| format!("could not move `{}` out of the way", path.display()))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("could not open `{}`", path.display()))?;
+
+        Ok(Self {
+            file,
+            build: format!("{:016x}", rand::random::<u64>()),
+        })
+    }
+
+    /// Each line goes out in a single write to a file opened for appending,
+    /// so tools tailing the journal never see half an entry, and two builds
+    /// writing at once (say, one in the daemon) can't mix up their lines.
+    fn append(&mut self, event: &Event) -> Result<()> {
+        let entry = Entry {
+            version: VERSION,
+            build: self.build.clone(),
+            at_ms: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+            event: event.clone(),
+        };
+
+        let mut line = serde_json::to_vec(&entry).context("could not serialize journal entry")?;
+        line.push(b'\n');
+        self.file.write_all(&line).context("could not append entry")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{Bus, JobInfo};
+    use crate::progress::Progress;
+
+    fn job() -> JobInfo {
+        JobInfo {
+            id: "cc-abc".to_string(),
+            key: "abc".to_string(),
+            command: "cc -c main.c".to_string(),
+        }
+    }
+
+    async fn build(path: &Path, succeeded: bool) {
+        let bus = Bus::new();
+        let recording = tokio::spawn(record(path.to_path_buf(), bus.subscribe()));
+
+        bus.publish(Event::BuildStarted { jobs: vec![job()] });
+        bus.publish(Event::JobStarted { job: job() });
+        bus.publish(Event::JobProgress {
+            job: job(),
+            progress: Progress {
+                percent: Some(50),
+                stage: None,
+            },
+        });
+        bus.publish(Event::BuildFinished { succeeded });
+
+        recording.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn appends_one_line_per_event() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join(FILE);
+
+        build(&path, false).await;
+        build(&path, true).await;
+
+        let entries: Vec<Entry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(
+            vec![
+                Event::BuildStarted { jobs: vec![job()] },
+                Event::JobStarted { job: job() },
+                Event::BuildFinished { succeeded: false },
+                Event::BuildStarted { jobs: vec![job()] },
+                Event::JobStarted { job: job() },
+                Event::BuildFinished { succeeded: true },
+            ],
+            entries
+                .iter()
+                .map(|entry| entry.event.clone())
+                .collect::<Vec<_>>()
+        );
+        assert!(entries.iter().all(|entry| entry.version == VERSION));
+        assert_eq!(entries[0].build, entries[2].build);
+        assert_ne!(entries[0].build, entries[3].build);
+    }
+}
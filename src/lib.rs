@@ -1,13 +1,48 @@
 #![allow(non_snake_case)]
 #![allow(clippy::missing_safety_doc)]
 
+mod build_stats;
 mod cli;
 mod coordinator;
+mod daemon;
+mod db;
+mod diagnostics;
+mod events;
+mod explain;
+mod export;
+mod file_trace;
+mod file_type;
+mod flake_check;
 mod glue;
+mod graph;
+mod in_flight;
 mod job;
+mod journal;
+mod limits;
+mod lint;
+mod log_sink;
+mod logs;
+mod network;
+mod ninja;
+mod out_link;
+mod output_filter;
 mod path_meta_key;
+mod pause;
+mod profile;
+mod progress;
+mod prune;
+mod remote_cache;
+mod report;
+mod resolver;
+mod resources;
 mod runner;
+mod snapshot;
+mod status;
 mod store;
+mod tools;
+mod trace;
+mod ui;
+mod worker;
 mod workspace;
 
 use clap::Parser;
@@ -40,7 +75,7 @@ pub(crate) unsafe extern "C" fn roc_panic(c_ptr: *mut c_void, tag_id: u32) {
         0 => {
             let slice = CStr::from_ptr(c_ptr as *const c_char);
             let string = slice.to_str().unwrap();
-            log::error!("Roc hit a panic: {}", string);
+            tracing::error!("Roc hit a panic: {}", string);
             std::process::exit(1);
         }
         _ => todo!(),
@@ -92,15 +127,26 @@ pub unsafe extern "C" fn roc_getppid() -> i32 {
 pub fn rust_main() -> isize {
     let cli = cli::Cli::parse();
 
-    simple_logger::SimpleLogger::new()
-        .with_module_level("sled", log::LevelFilter::Info)
-        .with_level(cli.log_level)
-        .init()
-        .expect("failed to initialize logger");
+    let trace = cli.init_logging().expect("failed to initialize logger");
 
-    if let Err(problem) = cli.run() {
+    let mut result = cli.run();
+
+    // failed builds are worth profiling too, so we write the trace either way
+    if let Some(trace) = trace {
+        if let Err(problem) = trace.finish() {
+            result = result.and(Err(problem));
+        }
+    }
+
+    if let Err(problem) = result {
         eprintln!("{:?}", problem);
-        1
+
+        // the same as `timeout(1)`, which CI systems already know about
+        if problem.chain().any(|err| err.is::<coordinator::TimedOut>()) {
+            124
+        } else {
+            1
+        }
     } else {
         0
     }
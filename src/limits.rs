@@ -0,0 +1,169 @@
+use crate::glue;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use tokio::process::Command;
+
+/// Resource limits (as in `ulimit`) a job asks for. We set these on the job's
+/// process ourselves instead of trusting whatever the user's shell happened
+/// to have, since a build that only works after `ulimit -n 4096` in someone's
+/// `.bashrc` isn't reproducible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Limit {
+    /// How many files the job can have open at once (`ulimit -n`.)
+    OpenFiles,
+
+    /// The size of the main thread's stack, in bytes.
+    StackSize,
+}
+
+pub type Limits = BTreeMap<Limit, u64>;
+
+impl Limit {
+    pub fn from_glue(limit: glue::Limit) -> Self {
+        match limit {
+            glue::Limit::OpenFiles => Limit::OpenFiles,
+            glue::Limit::StackSize => Limit::StackSize,
+        }
+    }
+
+    /// What goes in the job key for this limit.
+    pub fn identity(self) -> &'static str {
+        match self {
+            Limit::OpenFiles => "openFiles",
+            Limit::StackSize => "stackSize",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Limit::OpenFiles => "open files",
+            Limit::StackSize => "stack size",
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+mod unix {
+    use super::{Limit, Limits};
+    use anyhow::Result;
+    use tokio::process::Command;
+
+    fn get(limit: Limit) -> std::io::Result<libc::rlimit> {
+        let resource = match limit {
+            Limit::OpenFiles => libc::RLIMIT_NOFILE,
+            Limit::StackSize => libc::RLIMIT_STACK,
+        };
+
+        let mut current = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(resource, &mut current) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(current)
+    }
+
+    /// Set the soft limit to `value`, raising the hard limit too if it's
+    /// lower (which only works if we're privileged.) This runs between fork
+    /// and exec, so it can't allocate.
+    fn set(limit: Limit, value: u64) -> std::io::Result<()> {
+        let resource = match limit {
+            Limit::OpenFiles => libc::RLIMIT_NOFILE,
+            Limit::StackSize => libc::RLIMIT_STACK,
+        };
+
+        let current = get(limit)?;
+        let value = value as libc::rlim_t;
+        let new = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: current.rlim_max.max(value),
+        };
+
+        if unsafe { libc::setrlimit(resource, &new) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn check(limits: &Limits) -> Result<()> {
+        let privileged = unsafe { libc::geteuid() } == 0;
+
+        for (limit, value) in limits {
+            let current = get(*limit)?;
+
+            if !privileged && *value as libc::rlim_t > current.rlim_max {
+                anyhow::bail!(
+                    "the job needs a {} limit of {}, but this machine only allows up to {} (the hard limit.) An administrator can raise it, or you can ask for less.",
+                    limit.description(),
+                    value,
+                    current.rlim_max,
+                )
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(command: &mut Command, limits: &Limits) {
+        if limits.is_empty() {
+            return;
+        }
+
+        let limits: Vec<(Limit, u64)> = limits.iter().map(|(k, v)| (*k, *v)).collect();
+        unsafe {
+            command.pre_exec(move || {
+                for (limit, value) in &limits {
+                    set(*limit, *value)?;
+                }
+
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Make sure the host can give a job the limits it asks for, so we can say
+/// which one is the problem instead of failing to start the process.
+pub fn check(limits: &Limits) -> Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        unix::check(limits)
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        if !limits.is_empty() {
+            anyhow::bail!("resource limits are only supported on Unix so far")
+        }
+
+        Ok(())
+    }
+}
+
+/// Set up `command` so its process starts with `limits`.
+pub fn apply(command: &mut Command, limits: &Limits) {
+    #[cfg(target_family = "unix")]
+    unix::apply(command, limits);
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn applies_limits_to_the_process() {
+        let mut limits = Limits::new();
+        limits.insert(Limit::OpenFiles, 64);
+        check(&limits).unwrap();
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "ulimit -n"]);
+        apply(&mut command, &limits);
+
+        let output = command.output().await.unwrap();
+        assert_eq!("64\n", String::from_utf8(output.stdout).unwrap());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::job::Job;
+use std::path::{Path, PathBuf};
+
+/// Where paths that only make sense on one machine usually start. Jobs
+/// that mention them get different keys (or different outputs) on every
+/// machine, so they never share cache hits.
+const HOST_PREFIXES: &[&str] = &["/home/", "/Users/", "/var/folders/", "C:\\Users\\"];
+
+/// Looks for host-specific paths in jobs' commands. We check the project
+/// directory and `HOME` we're running with too, since those are the ones
+/// that usually leak in (through a `Path.cwd`-style helper or an env var
+/// copied from the shell.)
+#[derive(Debug)]
+pub struct HostPaths {
+    prefixes: Vec<String>,
+}
+
+impl HostPaths {
+    pub fn new(project_root: Option<&Path>, home: Option<&Path>) -> Self {
+        let mut prefixes: Vec<String> = HOST_PREFIXES.iter().map(|p| p.to_string()).collect();
+
+        for dir in [project_root, home].into_iter().flatten() {
+            let dir = dir.display().to_string();
+
+            // `/` would match every absolute path, system tools included
+            if dir.len() > 1 && !prefixes.iter().any(|prefix| dir.starts_with(prefix)) {
+                prefixes.push(dir);
+            }
+        }
+
+        HostPaths { prefixes }
+    }
+
+    /// Use the directory we're running in and our `HOME`.
+    pub fn from_env() -> Self {
+        HostPaths::new(
+            std::env::current_dir().ok().as_deref(),
+            std::env::var_os("HOME").map(PathBuf::from).as_deref(),
+        )
+    }
+
+    /// What's wrong with `job`, as a sentence each. Jobs that really do need
+    /// host paths can say so with `allowHostPaths`.
+    pub fn check(&self, job: &Job) -> Vec<String> {
+        if job.allow_host_paths {
+            return Vec::new();
+        }
+
+        let mut problems = Vec::new();
+
+        for (name, value) in job.command.env() {
+            if let Some(path) = self.find(value) {
+                problems.push(format!(
+                    "the `{}` environment variable has a path on this machine (`{}`)",
+                    name, path
+                ));
+            }
+        }
+
+        for arg in job.commands().flat_map(|command| command.args()) {
+            if let Some(path) = self.find(arg) {
+                problems.push(format!(
+                    "the argument `{}` has a path on this machine (`{}`)",
+                    arg, path
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// The first host path in `value`, up to the next separator. A prefix
+    /// only counts at the start of a path: at the start of the value, after
+    /// something that isn't part of a path (like `:` in `PATH` or `=` in
+    /// `--flag=...`), or after a short flag like `-I`.
+    fn find<'value>(&self, value: &'value str) -> Option<&'value str> {
+        for prefix in &self.prefixes {
+            for (index, _) in value.match_indices(prefix.as_str()) {
+                let before = &value[..index];
+                let starts_path = match before.chars().last() {
+                    None => true,
+                    Some(char) if !char.is_alphanumeric() && !"._-/\\".contains(char) => true,
+                    Some(_) => is_short_flag(before),
+                };
+
+                if starts_path {
+                    // `C:\Users\` has a separator in it, so start looking
+                    // for the end after the prefix.
+                    let rest = &value[index..];
+                    let end = rest[prefix.len()..]
+                        .find(|char: char| char == ':' || char == ';' || char.is_whitespace())
+                        .map_or(rest.len(), |end| prefix.len() + end);
+
+                    return Some(&rest[..end]);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Does `before` end with something like `-I` or ` -L`?
+fn is_short_flag(before: &str) -> bool {
+    let word = before.rsplit([' ', '=']).next().unwrap_or(before);
+    let mut chars = word.chars();
+
+    chars.next() == Some('-')
+        && chars.next().is_some_and(|char| char.is_ascii_alphabetic())
+        && chars.next().is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lint() -> HostPaths {
+        HostPaths::new(Some(Path::new("/src/project")), Some(Path::new("/")))
+    }
+
+    #[test]
+    fn finds_host_paths() {
+        assert_eq!(
+            Some("/home/alice/.cargo/bin"),
+            lint().find("/usr/bin:/home/alice/.cargo/bin:/bin")
+        );
+        assert_eq!(
+            Some("/Users/alice/include"),
+            lint().find("-I/Users/alice/include")
+        );
+        assert_eq!(
+            Some("/src/project/vendor"),
+            lint().find("--sysroot=/src/project/vendor")
+        );
+        assert_eq!(
+            Some("C:\\Users\\alice\\sdk"),
+            lint().find("C:\\Users\\alice\\sdk")
+        );
+    }
+
+    #[test]
+    fn ignores_paths_that_travel() {
+        assert_eq!(None, lint().find("/usr/bin:/bin"));
+        assert_eq!(None, lint().find("src/home/page.html"));
+        assert_eq!(None, lint().find("--output=build/Users/list"));
+
+        // HOME being `/` shouldn't flag every absolute path
+        assert_eq!(None, lint().find("/opt/tool"));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::logs::Stream;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+/// Somewhere else to send job output, on top of the logs directory, for CI
+/// systems that collect logs in their own place. Given with `--log-sink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Spec {
+    /// `file:PATH`: append to a file.
+    File(PathBuf),
+
+    /// `stdout`: our own stdout, even when job output isn't shown.
+    Stdout,
+
+    /// `syslog`: the local syslog daemon, through `/dev/log`.
+    Syslog,
+
+    /// `command:COMMAND`: the stdin of a shell command (like `vector` or a
+    /// cloud logging agent) that we start once per build.
+    Command(String),
+}
+
+impl FromStr for Spec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("file", path)) if !path.is_empty() => Ok(Spec::File(path.into())),
+            Some(("command", command)) if !command.is_empty() => {
+                Ok(Spec::Command(command.to_string()))
+            }
+            None if s == "stdout" => Ok(Spec::Stdout),
+            None if s == "syslog" => Ok(Spec::Syslog),
+            _ => anyhow::bail!(
+                "log sinks should look like `file:PATH`, `stdout`, `syslog`, or `command:COMMAND`"
+            ),
+        }
+    }
+}
+
+/// One line of job output, the way sinks get it (except syslog, which has
+/// its own format.) The line doesn't include the newline.
+#[derive(Debug, serde::Serialize)]
+struct Line<'a> {
+    key: &'a str,
+    stream: &'a str,
+    line: &'a str,
+}
+
+enum Sink {
+    File(std::fs::File),
+    Stdout,
+    #[cfg(unix)]
+    Syslog(UnixDatagram),
+    Command(Child),
+}
+
+/// The sinks for a build. Lines from jobs running at the same time go out
+/// whole, one after another, and each sink sees them in the same order.
+#[derive(Clone, Default)]
+pub struct Sinks(Arc<Mutex<Vec<Sink>>>);
+
+impl std::fmt::Debug for Sinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Sinks")
+    }
+}
+
+impl Sinks {
+    pub fn open(specs: &[Spec]) -> Result<Self> {
+        let mut sinks = Vec::with_capacity(specs.len());
+
+        for spec in specs {
+            sinks.push(match spec {
+                Spec::File(path) => Sink::File(
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .with_context(|| format!("could not open `{}`", path.display()))?,
+                ),
+                Spec::Stdout => Sink::Stdout,
+                #[cfg(unix)]
+                Spec::Syslog => {
+                    let socket = UnixDatagram::unbound().context("could not create socket")?;
+                    socket
+                        .connect("/dev/log")
+                        .context("could not connect to syslog at `/dev/log`")?;
+                    Sink::Syslog(socket)
+                }
+                #[cfg(not(unix))]
+                Spec::Syslog => anyhow::bail!("syslog only works on Unix-like systems"),
+                Spec::Command(command) => Sink::Command(
+                    std::process::Command::new("sh")
+                        .arg("-c")
+                        .arg(command)
+                        .stdin(Stdio::piped())
+                        .spawn()
+                        .with_context(|| format!("could not start `{}`", command))?,
+                ),
+            });
+        }
+
+        Ok(Sinks(Arc::new(Mutex::new(sinks))))
+    }
+
+    /// Send a line of output from the job with this base key (the prefix
+    /// we show its lines with.) A sink that stops working (like a log
+    /// shipper that crashed) only gets a warning, since the job's output is
+    /// still in the logs directory.
+    pub fn send(&self, key: &str, stream: Stream, line: &[u8]) {
+        let text = String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line));
+        let stream_name = match stream {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        };
+
+        let mut json = match serde_json::to_vec(&Line {
+            key,
+            stream: stream_name,
+            line: &text,
+        }) {
+            Ok(json) => json,
+            Err(err) => {
+                tracing::warn!("could not serialize job output: {}", err);
+                return;
+            }
+        };
+        json.push(b'\n');
+
+        let mut sinks = self.0.lock().expect("log sinks lock was poisoned");
+        for sink in sinks.iter_mut() {
+            let result = match sink {
+                Sink::File(file) => file.write_all(&json),
+                Sink::Stdout => std::io::stdout().lock().write_all(&json),
+                #[cfg(unix)]
+                Sink::Syslog(socket) => socket
+                    .send(syslog_message(key, stream, &text).as_bytes())
+                    .map(|_| ()),
+                Sink::Command(child) => match child.stdin.as_mut() {
+                    Some(stdin) => stdin.write_all(&json),
+                    None => Ok(()),
+                },
+            };
+
+            if let Err(err) = result {
+                tracing::warn!("could not send job output to a log sink: {}", err);
+            }
+        }
+    }
+
+    /// Flush everything and wait for sink commands to finish with what
+    /// they've got, so nothing is lost when we exit.
+    pub fn finish(&self) -> Result<()> {
+        let mut sinks = self.0.lock().expect("log sinks lock was poisoned");
+
+        for sink in sinks.iter_mut() {
+            match sink {
+                Sink::File(file) => file.flush().context("could not flush log sink")?,
+                Sink::Stdout => std::io::stdout()
+                    .flush()
+                    .context("could not flush log sink")?,
+                #[cfg(unix)]
+                Sink::Syslog(_) => (),
+                Sink::Command(child) => {
+                    // closing stdin tells the command we're done
+                    drop(child.stdin.take());
+
+                    let status = child.wait().context("could not wait for log sink")?;
+                    if !status.success() {
+                        tracing::warn!("log sink command exited with {}", status);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An RFC 3164 message with the user facility: stdout is informational
+/// and stderr is a warning.
+#[cfg(unix)]
+fn syslog_message(key: &str, stream: Stream, line: &str) -> String {
+    let priority = match stream {
+        Stream::Stdout => 8 + 6,
+        Stream::Stderr => 8 + 4,
+    };
+
+    format!(
+        "<{}>rbt[{}]: [{}] {}",
+        priority,
+        std::process::id(),
+        key,
+        line
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_specs() {
+        assert_eq!(
+            Spec::File("ci/jobs.log".into()),
+            "file:ci/jobs.log".parse().unwrap()
+        );
+        assert_eq!(Spec::Stdout, "stdout".parse().unwrap());
+        assert_eq!(Spec::Syslog, "syslog".parse().unwrap());
+        assert_eq!(
+            Spec::Command("vector --config ci.toml".to_string()),
+            "command:vector --config ci.toml".parse().unwrap()
+        );
+        assert!("file:".parse::<Spec>().is_err());
+        assert!("journald".parse::<Spec>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sends_lines_to_files_and_commands() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join("jobs.log");
+        let piped = temp.path().join("piped.log");
+
+        let sinks = Sinks::open(&[
+            Spec::File(file.clone()),
+            Spec::Command(format!("cat > '{}'", piped.display())),
+        ])
+        .unwrap();
+        sinks.send("abc", Stream::Stdout, b"compiling\n");
+        sinks.send("abc", Stream::Stderr, b"warning: unused \"x\"");
+        sinks.finish().unwrap();
+
+        let expected = concat!(
+            r#"{"key":"abc","stream":"stdout","line":"compiling"}"#,
+            "\n",
+            r#"{"key":"abc","stream":"stderr","line":"warning: unused \"x\""}"#,
+            "\n",
+        );
+        assert_eq!(expected, std::fs::read_to_string(file).unwrap());
+        assert_eq!(expected, std::fs::read_to_string(piped).unwrap());
+    }
+}
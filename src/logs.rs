@@ -0,0 +1,647 @@
+use crate::job;
+use crate::log_sink::Sinks;
+use anyhow::{Context, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::task::JoinHandle;
+
+/// Where job output goes. Each line is shown on our own stdout or stderr as
+/// soon as the job writes it (prefixed with the job's key, since several jobs
+/// run at once) and saved under `logs/<final key>` in the root dir. Since the
+/// logs are keyed the same way as the store, a job that's a cache hit next
+/// time still has the output from the run that produced it. See
+/// docs/adrs/010-logs.md.
+///
+/// Each log has an index next to it, with a record for every line: where
+/// the line ends in the log and when we got it (in milliseconds since the
+/// Unix epoch), as little-endian u64s. That's what lets `rbt logs` find
+/// the last few lines, or the lines from a stretch of time, without reading
+/// the whole log. A record that ends where the one before it did (which a
+/// line never does) marks the end of the stream.
+#[derive(Debug, Clone)]
+pub struct Logs {
+    root: PathBuf,
+
+    /// whether to echo lines to our stdout and stderr, or only save them
+    show: bool,
+
+    /// `--log-sink`s, if we got any
+    sinks: Sinks,
+}
+
+impl Logs {
+    pub fn new(root: PathBuf, show: bool) -> Self {
+        Logs {
+            root,
+            show,
+            sinks: Sinks::default(),
+        }
+    }
+
+    /// Send every line to `sinks` as well.
+    pub fn send_to(&mut self, sinks: Sinks) {
+        self.sinks = sinks;
+    }
+
+    /// Get ready to log a run of the job with this key, replacing the logs
+    /// from any earlier run. Its output is prefixed with its base key.
+    pub async fn for_job(&self, key: &job::Key<job::Final>, base_key: String) -> Result<JobLog> {
+        let dir = self.root.join(key.to_string());
+
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("could not create log directory `{}`", dir.display()))?;
+
+        // so `rbt logs` can find these by the job's ID too
+        let base_key_file = dir.join(BASE_KEY_FILE);
+        tokio::fs::write(&base_key_file, &base_key)
+            .await
+            .with_context(|| format!("could not write `{}`", base_key_file.display()))?;
+
+        Ok(JobLog {
+            dir,
+            prefix: base_key,
+            show: self.show,
+            sinks: self.sinks.clone(),
+        })
+    }
+}
+
+/// Holds the whole base key of the job whose logs are in the same
+/// directory.
+const BASE_KEY_FILE: &str = "base-key";
+
+/// How long each record in a log's index is. See `Logs`.
+const INDEX_RECORD_LEN: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    const ALL: [Stream; 2] = [Stream::Stdout, Stream::Stderr];
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout.log",
+            Stream::Stderr => "stderr.log",
+        }
+    }
+
+    fn index_file_name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout.index",
+            Stream::Stderr => "stderr.index",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobLog {
+    dir: PathBuf,
+    prefix: String,
+    show: bool,
+    sinks: Sinks,
+}
+
+impl JobLog {
+    /// Copy everything from `reader` into the log a line at a time, showing
+    /// each line as it arrives. If `also` is set, it gets an exact copy of
+    /// the output too (this is how jobs that expect to fail get `stdout` and
+    /// `stderr` files in their workspace.)
+    pub fn stream<R>(
+        &self,
+        stream: Stream,
+        reader: R,
+        also: Option<PathBuf>,
+    ) -> JoinHandle<Result<()>>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let path = self.dir.join(stream.file_name());
+        let index_path = self.dir.join(stream.index_file_name());
+        let prefix = format!("[{}] ", self.prefix);
+        let show_lines = self.show;
+        let name = self.prefix.clone();
+        let sinks = self.sinks.clone();
+
+        tokio::spawn(async move {
+            let mut log = File::create(&path)
+                .await
+                .with_context(|| format!("could not create `{}`", path.display()))?;
+
+            let mut index = File::create(&index_path)
+                .await
+                .with_context(|| format!("could not create `{}`", index_path.display()))?;
+
+            let mut also = match also {
+                Some(also) => Some(
+                    File::create(&also)
+                        .await
+                        .with_context(|| format!("could not create `{}`", also.display()))?,
+                ),
+                None => None,
+            };
+
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            let mut offset = 0;
+            loop {
+                line.clear();
+                if reader
+                    .read_until(b'\n', &mut line)
+                    .await
+                    .context("could not read job output")?
+                    == 0
+                {
+                    break;
+                }
+
+                log.write_all(&line)
+                    .await
+                    .context("could not write to log")?;
+
+                offset += line.len() as u64;
+                index
+                    .write_all(&index_record(offset, SystemTime::now()))
+                    .await
+                    .context("could not write to log index")?;
+
+                if let Some(also) = &mut also {
+                    also.write_all(&line)
+                        .await
+                        .context("could not write captured output")?;
+                }
+
+                if show_lines {
+                    show(stream, &prefix, &line);
+                }
+
+                sinks.send(&name, stream, &line);
+            }
+
+            log.flush().await.context("could not write to log")?;
+
+            // only mark the end once everything before it is in the log, so
+            // anyone following along doesn't stop early.
+            index
+                .write_all(&index_record(offset, SystemTime::now()))
+                .await
+                .context("could not write to log index")?;
+            index
+                .flush()
+                .await
+                .context("could not write to log index")?;
+
+            if let Some(also) = &mut also {
+                also.flush()
+                    .await
+                    .context("could not write captured output")?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+fn index_record(end: u64, at: SystemTime) -> [u8; INDEX_RECORD_LEN as usize] {
+    let millis = at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let mut record = [0; INDEX_RECORD_LEN as usize];
+    record[..8].copy_from_slice(&end.to_le_bytes());
+    record[8..].copy_from_slice(&millis.to_le_bytes());
+    record
+}
+
+/// A job's saved output, read back for `rbt logs`. Each read picks up where
+/// the last one left off, so following a job that's still running is a
+/// matter of reading again until it's `finished`.
+#[derive(Debug)]
+pub struct SavedLog {
+    dir: PathBuf,
+    streams: Vec<SavedStream>,
+}
+
+/// Which lines `SavedLog::read` returns.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Lines {
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+
+    /// Only the last this many of the lines between `since` and `until`
+    pub last: Option<usize>,
+}
+
+impl Lines {
+    fn include(&self, at: SystemTime) -> bool {
+        self.since.is_none_or(|since| at >= since) && self.until.is_none_or(|until| at <= until)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedLine {
+    pub stream: Stream,
+    pub at: SystemTime,
+    pub text: Vec<u8>,
+}
+
+impl SavedLog {
+    /// Find the logs for `query`: a job's final key, its base key (what
+    /// its output is prefixed with during builds), its ID (like
+    /// `cc-3fa2c81e`), or enough of the start of a key to tell it apart.
+    /// A job whose inputs changed has logs under more than one final key;
+    /// we use the ones from its latest run.
+    pub fn find(root: &Path, query: &str) -> Result<Self> {
+        let entries = match std::fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                anyhow::bail!("no jobs have run here yet, so there are no logs")
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not read `{}`", root.display()))
+            }
+        };
+
+        // IDs are the tool's name, a dash, and the start of the base key
+        let base_query = query.rsplit_once('-').map_or(query, |(_, key)| key);
+
+        let mut by_final_key = Vec::new();
+        let mut by_base_key: Vec<(String, SystemTime, PathBuf)> = Vec::new();
+        for entry in entries {
+            let dir = entry
+                .with_context(|| format!("could not read `{}`", root.display()))?
+                .path();
+
+            let final_key = dir.file_name().and_then(|name| name.to_str());
+            if final_key.is_some_and(|final_key| final_key.starts_with(query)) {
+                by_final_key.push(dir);
+                continue;
+            }
+
+            // logs from before we kept track of base keys can still be
+            // found by final key.
+            let base_key_file = dir.join(BASE_KEY_FILE);
+            let base_key = match std::fs::read_to_string(&base_key_file) {
+                Ok(base_key) => base_key,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("could not read `{}`", base_key_file.display()))
+                }
+            };
+
+            if base_key.starts_with(base_query) {
+                // we write the base key each time the job runs
+                let ran = std::fs::metadata(&base_key_file)
+                    .and_then(|meta| meta.modified())
+                    .with_context(|| {
+                        format!("could not get metadata for `{}`", base_key_file.display())
+                    })?;
+                by_base_key.push((base_key, ran, dir));
+            }
+        }
+
+        let dir = match by_final_key.len() {
+            1 => by_final_key.pop().unwrap(),
+            0 => {
+                let jobs: std::collections::BTreeSet<&str> =
+                    by_base_key.iter().map(|(key, _, _)| key.as_str()).collect();
+                if jobs.len() > 1 {
+                    anyhow::bail!(
+                        "`{}` could be any of {} jobs. Give more of the key to pick one.",
+                        query,
+                        jobs.len()
+                    )
+                }
+
+                by_base_key
+                    .into_iter()
+                    .max_by_key(|(_, ran, _)| *ran)
+                    .map(|(_, _, dir)| dir)
+                    .with_context(|| format!("could not find logs for `{}`", query))?
+            }
+            matches => anyhow::bail!(
+                "`{}` could be any of {} final keys. Give more of the key to pick one.",
+                query,
+                matches
+            ),
+        };
+
+        Ok(SavedLog {
+            dir,
+            streams: Stream::ALL.into_iter().map(SavedStream::new).collect(),
+        })
+    }
+
+    /// Where the log files are.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the job has finished writing to both streams.
+    pub fn finished(&self) -> bool {
+        self.streams.iter().all(|stream| stream.finished)
+    }
+
+    /// Read the lines written since the last read (or all of them, the
+    /// first time) that `which` picks, in the order the job wrote them (as
+    /// well as we can tell across streams.)
+    pub fn read(&mut self, which: Lines) -> Result<Vec<SavedLine>> {
+        let mut picked = Vec::new();
+        for (which_stream, stream) in self.streams.iter_mut().enumerate() {
+            for record in stream.new_records(&self.dir)? {
+                if which.include(record.at) {
+                    picked.push((which_stream, record));
+                }
+            }
+        }
+
+        // a stable sort, so lines from the same stream stay in order
+        picked.sort_by_key(|(_, record)| record.at);
+        if let Some(last) = which.last {
+            picked.drain(..picked.len().saturating_sub(last));
+        }
+
+        // whatever's left of each stream is one stretch of its log, so we
+        // can read each stretch in one go.
+        let mut texts = Vec::new();
+        for (which_stream, stream) in self.streams.iter().enumerate() {
+            let (start, end) = picked
+                .iter()
+                .filter(|(picked_stream, _)| *picked_stream == which_stream)
+                .fold(None, |span, (_, record)| match span {
+                    None => Some((record.start, record.end)),
+                    Some((start, end)) => Some((start.min(record.start), end.max(record.end))),
+                })
+                .unwrap_or((0, 0));
+
+            texts.push((start, stream.read_text(&self.dir, start, end)?));
+        }
+
+        Ok(picked
+            .into_iter()
+            .map(|(which_stream, record)| {
+                let (start, text) = &texts[which_stream];
+                SavedLine {
+                    stream: self.streams[which_stream].stream,
+                    at: record.at,
+                    text: text[(record.start - start) as usize..(record.end - start) as usize]
+                        .to_vec(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug)]
+struct SavedStream {
+    stream: Stream,
+
+    /// how much of the index we've read
+    index_read: u64,
+
+    /// where the next line starts in the log
+    offset: u64,
+
+    finished: bool,
+}
+
+/// Where a line is in a log, and when it was written.
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    start: u64,
+    end: u64,
+    at: SystemTime,
+}
+
+impl SavedStream {
+    fn new(stream: Stream) -> Self {
+        SavedStream {
+            stream,
+            index_read: 0,
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Read the index records for lines written since we last looked. We
+    /// leave out lines whose text isn't in the log yet, since the index
+    /// and log are written separately.
+    fn new_records(&mut self, dir: &Path) -> Result<Vec<Record>> {
+        if self.finished {
+            return Ok(Vec::new());
+        }
+
+        let index_path = dir.join(self.stream.index_file_name());
+        let log_path = dir.join(self.stream.file_name());
+        let log_len = match std::fs::metadata(&log_path) {
+            Ok(meta) => meta.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("could not get metadata for `{}`", log_path.display())
+                })
+            }
+        };
+
+        let mut index = match std::fs::File::open(&index_path) {
+            Ok(index) => index,
+            // the job hasn't started writing this stream yet
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && log_len == 0 => {
+                return Ok(Vec::new())
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => anyhow::bail!(
+                "`{}` was written by an older version of rbt, without an index. Look at it directly instead.",
+                log_path.display()
+            ),
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not open `{}`", index_path.display()))
+            }
+        };
+
+        let index_len = index
+            .metadata()
+            .with_context(|| format!("could not get metadata for `{}`", index_path.display()))?
+            .len();
+        if index_len < self.index_read {
+            anyhow::bail!("the job started running again, replacing these logs")
+        }
+
+        let mut bytes = Vec::new();
+        index
+            .seek(SeekFrom::Start(self.index_read))
+            .and_then(|_| index.read_to_end(&mut bytes))
+            .with_context(|| format!("could not read `{}`", index_path.display()))?;
+
+        let mut records = Vec::new();
+        for record in bytes.chunks_exact(INDEX_RECORD_LEN as usize) {
+            let end = u64::from_le_bytes(record[..8].try_into().unwrap());
+            let millis = u64::from_le_bytes(record[8..].try_into().unwrap());
+
+            if end == self.offset {
+                self.index_read += INDEX_RECORD_LEN;
+                self.finished = true;
+                break;
+            }
+
+            if end < self.offset {
+                anyhow::bail!("`{}` is corrupt", index_path.display())
+            }
+
+            if end > log_len {
+                break;
+            }
+
+            records.push(Record {
+                start: self.offset,
+                end,
+                at: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+            });
+            self.index_read += INDEX_RECORD_LEN;
+            self.offset = end;
+        }
+
+        Ok(records)
+    }
+
+    fn read_text(&self, dir: &Path, start: u64, end: u64) -> Result<Vec<u8>> {
+        let log_path = dir.join(self.stream.file_name());
+        let mut text = vec![0; (end - start) as usize];
+        if text.is_empty() {
+            return Ok(text);
+        }
+
+        std::fs::File::open(&log_path)
+            .and_then(|mut log| {
+                log.seek(SeekFrom::Start(start))?;
+                log.read_exact(&mut text)
+            })
+            .with_context(|| format!("could not read `{}`", log_path.display()))?;
+
+        Ok(text)
+    }
+}
+
+/// Write a saved line back out to the stream the job originally wrote it
+/// to.
+pub fn print(line: &SavedLine) -> std::io::Result<()> {
+    match line.stream {
+        Stream::Stdout => write_line(&mut std::io::stdout().lock(), "", &line.text),
+        Stream::Stderr => write_line(&mut std::io::stderr().lock(), "", &line.text),
+    }
+}
+
+/// Show one line of a job's output. We write the whole line while holding
+/// the lock so lines from different jobs don't get mixed together.
+fn show(stream: Stream, prefix: &str, line: &[u8]) {
+    let result = match stream {
+        Stream::Stdout => write_line(&mut std::io::stdout().lock(), prefix, line),
+        Stream::Stderr => write_line(&mut std::io::stderr().lock(), prefix, line),
+    };
+
+    if let Err(err) = result {
+        tracing::warn!("could not show job output: {}", err)
+    }
+}
+
+fn write_line(out: &mut impl Write, prefix: &str, line: &[u8]) -> std::io::Result<()> {
+    out.write_all(prefix.as_bytes())?;
+    out.write_all(line)?;
+
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_output_under_final_key() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let logs = Logs::new(temp.path().join("logs"), true);
+        let key = job::Key::default();
+
+        let log = logs.for_job(&key, "job".to_string()).await.unwrap();
+        let also = temp.path().join("stdout");
+        log.stream(
+            Stream::Stdout,
+            std::io::Cursor::new(b"one\ntwo".to_vec()),
+            Some(also.clone()),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            "one\ntwo",
+            std::fs::read_to_string(
+                temp.path()
+                    .join("logs")
+                    .join(key.to_string())
+                    .join("stdout.log")
+            )
+            .unwrap()
+        );
+        assert_eq!("one\ntwo", std::fs::read_to_string(also).unwrap());
+    }
+
+    #[tokio::test]
+    async fn reads_saved_lines_back() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let logs = Logs::new(temp.path().to_path_buf(), false);
+        let log = logs
+            .for_job(&job::Key::default(), "3fa2c81e0b".to_string())
+            .await
+            .unwrap();
+        log.stream(
+            Stream::Stdout,
+            std::io::Cursor::new(b"one\ntwo\nthree".to_vec()),
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        let mut saved = SavedLog::find(temp.path(), "cc-3fa2c81e").unwrap();
+        let lines = saved
+            .read(Lines {
+                last: Some(2),
+                ..Lines::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            vec![b"two\n".to_vec(), b"three".to_vec()],
+            lines.into_iter().map(|line| line.text).collect::<Vec<_>>()
+        );
+
+        // stderr never got started, so the job could still write to it
+        assert!(!saved.finished());
+        log.stream(Stream::Stderr, std::io::Cursor::new(b"oops".to_vec()), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let lines = saved.read(Lines::default()).unwrap();
+        assert_eq!(
+            vec![(Stream::Stderr, b"oops".to_vec())],
+            lines
+                .into_iter()
+                .map(|line| (line.stream, line.text))
+                .collect::<Vec<_>>()
+        );
+        assert!(saved.finished());
+    }
+}
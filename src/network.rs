@@ -0,0 +1,327 @@
+use crate::glue;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Whether a job's process can reach the network. Jobs are supposed to get
+/// everything they need from their inputs, but nothing stops a compiler or
+/// test suite from quietly downloading something, which makes the build
+/// depend on whatever the server said that day. Forbidding the network turns
+/// that into a failure you can see.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Network {
+    #[default]
+    Allowed,
+    Forbidden,
+}
+
+impl Network {
+    pub fn from_glue(network: glue::Network) -> Self {
+        match network {
+            glue::Network::Allowed => Network::Allowed,
+            glue::Network::Forbidden => Network::Forbidden,
+        }
+    }
+}
+
+/// Names a job looks up its own way instead of asking the host: extra
+/// `/etc/hosts` entries, and the DNS servers for `/etc/resolv.conf`. Test
+/// jobs use these to reach local services by stable names, whatever the
+/// machine running them has configured.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Names {
+    pub hosts: BTreeMap<String, IpAddr>,
+    pub dns_servers: Vec<IpAddr>,
+}
+
+impl Names {
+    pub fn from_glue(
+        hosts: &roc_std::RocList<glue::R6>,
+        dns_servers: &roc_std::RocList<roc_std::RocStr>,
+    ) -> Result<Self> {
+        let mut names = Names::default();
+
+        for glue::R6 { address, name } in hosts {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '#') {
+                anyhow::bail!("`{}` isn't a host name we can put in /etc/hosts", name);
+            }
+
+            let address: IpAddr = address
+                .parse()
+                .with_context(|| format!("`{}` (for `{}`) isn't an IP address", address, name))?;
+
+            if let Some(previous) = names.hosts.insert(name.to_string(), address) {
+                if previous != address {
+                    anyhow::bail!(
+                        "`{}` points to both {} and {}, so I don't know which one to use",
+                        name,
+                        previous,
+                        address
+                    );
+                }
+            }
+        }
+
+        for server in dns_servers {
+            let server: IpAddr = server
+                .parse()
+                .with_context(|| format!("DNS server `{}` isn't an IP address", server))?;
+
+            names.dns_servers.push(server);
+        }
+
+        Ok(names)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.dns_servers.is_empty()
+    }
+
+    /// Write the files the job should see in place of the host's into
+    /// `dir`. We only replace the ones the job changes: a job with hosts
+    /// but no DNS servers still uses the host's `resolv.conf`.
+    pub async fn write(&self, dir: &Path) -> Result<NameFiles> {
+        let mut files = NameFiles::default();
+
+        if !self.hosts.is_empty() {
+            // tools expect to find localhost even when nothing else is there
+            let mut contents = String::from("127.0.0.1 localhost\n::1 localhost\n");
+            for (name, address) in &self.hosts {
+                contents.push_str(&format!("{} {}\n", address, name));
+            }
+
+            let path = dir.join("hosts");
+            tokio::fs::write(&path, contents)
+                .await
+                .with_context(|| format!("could not write `{}`", path.display()))?;
+            files.hosts = Some(path);
+        }
+
+        if !self.dns_servers.is_empty() {
+            let contents: String = self
+                .dns_servers
+                .iter()
+                .map(|server| format!("nameserver {}\n", server))
+                .collect();
+
+            let path = dir.join("resolv.conf");
+            tokio::fs::write(&path, contents)
+                .await
+                .with_context(|| format!("could not write `{}`", path.display()))?;
+            files.resolv_conf = Some(path);
+        }
+
+        Ok(files)
+    }
+}
+
+/// Files from `Names::write` for a job's process to see at `/etc/hosts` and
+/// `/etc/resolv.conf`.
+#[derive(Debug, Default)]
+pub struct NameFiles {
+    hosts: Option<PathBuf>,
+    resolv_conf: Option<PathBuf>,
+}
+
+impl NameFiles {
+    fn is_empty(&self) -> bool {
+        self.hosts.is_none() && self.resolv_conf.is_none()
+    }
+}
+
+/// On macOS we can't change how a process starts from the inside, so
+/// `sandbox-exec` runs the tool for us with this profile.
+#[cfg(target_os = "macos")]
+const SANDBOX_PROFILE: &str = "(version 1) (allow default) (deny network*)";
+
+/// Make sure we know how to keep a job off the network on this platform, so
+/// we can say so instead of running it with the network anyway.
+pub fn check(network: Network, names: &Names) -> Result<()> {
+    if network == Network::Forbidden && !cfg!(any(target_os = "linux", target_os = "macos")) {
+        anyhow::bail!("forbidding the network is only supported on Linux and macOS so far")
+    }
+
+    if !names.is_empty() && !cfg!(target_os = "linux") {
+        anyhow::bail!("custom hosts and DNS servers are only supported on Linux so far")
+    }
+
+    Ok(())
+}
+
+/// A process that runs `tool`, without network access if `network` says so
+/// and seeing `names` in place of the host's. Callers add arguments and
+/// environment as usual.
+pub fn command(tool: &str, network: Network, names: &NameFiles) -> Command {
+    if network == Network::Allowed && names.is_empty() {
+        return Command::new(tool);
+    }
+
+    isolated(tool, network, names)
+}
+
+/// Start the process in a new network namespace if it's kept off the
+/// network, which only has a loopback interface (and that one's down.) For
+/// custom names, it also gets a mount namespace of its own, where we
+/// bind-mount the job's files over `/etc/hosts` and `/etc/resolv.conf`.
+/// Unless we're root, we need a new user namespace to be allowed to do
+/// either; the process keeps our permissions on the workspace either way.
+#[cfg(target_os = "linux")]
+fn isolated(tool: &str, network: Network, names: &NameFiles) -> Command {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut command = Command::new(tool);
+
+    // we can't allocate between fork and exec, so the paths have to be
+    // ready beforehand. Ours come from the workspace, which never has NULs
+    // in its path.
+    let mounts: Vec<(CString, &'static CStr)> = [
+        (&names.hosts, c"/etc/hosts"),
+        (&names.resolv_conf, c"/etc/resolv.conf"),
+    ]
+    .into_iter()
+    .filter_map(|(source, target)| {
+        let source = CString::new(source.as_ref()?.as_os_str().as_bytes())
+            .expect("workspace paths don't contain NUL bytes");
+        Some((source, target))
+    })
+    .collect();
+
+    unsafe {
+        command.pre_exec(move || {
+            let mut flags = 0;
+            if network == Network::Forbidden {
+                flags |= libc::CLONE_NEWNET;
+            }
+            if !mounts.is_empty() {
+                flags |= libc::CLONE_NEWNS;
+            }
+            if libc::geteuid() != 0 {
+                flags |= libc::CLONE_NEWUSER;
+            }
+
+            if libc::unshare(flags) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            if mounts.is_empty() {
+                return Ok(());
+            }
+
+            // the new namespace starts out sharing mount events with the
+            // host's, and our bind mounts must not leak back out.
+            if libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            for (source, target) in &mounts {
+                if libc::mount(
+                    source.as_ptr(),
+                    target.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_BIND,
+                    std::ptr::null(),
+                ) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    command
+}
+
+// `check` refuses custom names outside Linux, so we only have to handle the
+// network here.
+#[cfg(target_os = "macos")]
+fn isolated(tool: &str, _network: Network, _names: &NameFiles) -> Command {
+    let mut command = Command::new("sandbox-exec");
+    command.args(["-p", SANDBOX_PROFILE, tool]);
+    command
+}
+
+// `check` refuses to run these jobs, so we never get here.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn isolated(tool: &str, _network: Network, _names: &NameFiles) -> Command {
+    Command::new(tool)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn forbidden_jobs_only_see_loopback() {
+        let mut command = command("cat", Network::Forbidden, &NameFiles::default());
+        command.arg("/proc/self/net/dev");
+
+        let output = command.output().await.unwrap();
+        assert!(output.status.success(), "{:?}", output);
+
+        let interfaces: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, _)| name.trim().to_string())
+            .collect();
+        assert_eq!(vec!["lo".to_string()], interfaces);
+    }
+
+    #[tokio::test]
+    async fn jobs_see_their_own_hosts_and_dns_servers() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let names = Names {
+            hosts: BTreeMap::from([("db.test".to_string(), "10.0.0.2".parse().unwrap())]),
+            dns_servers: vec!["10.0.0.53".parse().unwrap()],
+        };
+        let files = names.write(temp.path()).await.unwrap();
+
+        // with and without the network, since they're separate namespaces
+        for network in [Network::Allowed, Network::Forbidden] {
+            let mut command = command("cat", network, &files);
+            command.args(["/etc/hosts", "/etc/resolv.conf"]);
+
+            let output = command.output().await.unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            assert_eq!(
+                "127.0.0.1 localhost\n::1 localhost\n10.0.0.2 db.test\nnameserver 10.0.0.53\n",
+                String::from_utf8(output.stdout).unwrap(),
+            );
+        }
+
+        // and nothing changed for everyone else
+        assert!(!std::fs::read_to_string("/etc/hosts")
+            .unwrap()
+            .contains("db.test"));
+    }
+
+    #[tokio::test]
+    async fn only_replaces_the_files_a_job_changes() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let names = Names {
+            hosts: BTreeMap::from([("db.test".to_string(), "10.0.0.2".parse().unwrap())]),
+            dns_servers: Vec::new(),
+        };
+        let files = names.write(temp.path()).await.unwrap();
+        assert!(!temp.path().join("resolv.conf").exists());
+
+        let mut command = command("cat", Network::Allowed, &files);
+        command.arg("/etc/resolv.conf");
+
+        let output = command.output().await.unwrap();
+        let expected = std::fs::read("/etc/resolv.conf").unwrap_or_default();
+        assert_eq!(expected, output.stdout);
+    }
+}
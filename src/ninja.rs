@@ -0,0 +1,790 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+/// A build definition converted from a Ninja file, so projects with an
+/// existing Ninja build can try rbt's caching and scheduling before they
+/// rewrite anything by hand.
+///
+/// Each build edge becomes a job running its command with `sh -c`, taking
+/// inputs from the jobs that produce them (or from the project, if nothing
+/// does.) Every explicit output becomes a target, so `rbt build foo.o` works
+/// like `ninja foo.o` does. Anything we can't carry over (like depfiles, which
+/// would have rbt discover inputs after the fact) goes in `problems` and in a
+/// comment at the top of the definition.
+#[derive(Debug)]
+pub struct Import {
+    pub definition: String,
+    pub problems: Vec<String>,
+}
+
+pub fn import(path: &Path) -> Result<Import> {
+    let mut manifest = Manifest::default();
+    manifest.load(path)?;
+
+    Ok(manifest.convert())
+}
+
+#[derive(Debug, Default)]
+struct Manifest {
+    vars: HashMap<String, String>,
+
+    /// rule name to unevaluated bindings
+    rules: HashMap<String, HashMap<String, String>>,
+    edges: Vec<Edge>,
+    defaults: Vec<String>,
+    problems: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Edge {
+    rule: String,
+    outputs: Vec<String>,
+    implicit_outputs: Vec<String>,
+    inputs: Vec<String>,
+    implicit_inputs: Vec<String>,
+    order_only: Vec<String>,
+    command: String,
+}
+
+/// Rule variables that change how Ninja runs an edge in ways rbt doesn't
+/// (or doesn't need to) copy, and why.
+const UNSUPPORTED_RULE_VARIABLES: &[(&str, &str)] = &[
+    ("depfile", "rbt needs every input declared up front, so inputs listed only in the depfile won't be in the workspace"),
+    ("deps", "rbt needs every input declared up front, so inputs the compiler reports won't be in the workspace"),
+    ("dyndep", "rbt doesn't load dependencies while building"),
+    ("generator", "rbt doesn't regenerate its build definition"),
+    ("restat", "rbt always compares outputs by content, so this isn't needed"),
+    ("rspfile", "the response file won't be written; consider `withResponseFile`"),
+];
+
+impl Manifest {
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read `{}`", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let lines = logical_lines(&text);
+        let mut index = 0;
+        while index < lines.len() {
+            let (indented, line) = &lines[index];
+            index += 1;
+
+            if *indented {
+                anyhow::bail!("`{}` is indented, but isn't part of a rule or build", line);
+            }
+
+            // everything indented after a declaration belongs to it
+            let mut bindings = Vec::new();
+            while let Some((true, binding)) = lines.get(index) {
+                bindings.push(split_binding(binding)?);
+                index += 1;
+            }
+
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "rule" => {
+                    self.rules
+                        .insert(rest.trim().to_string(), bindings.into_iter().collect());
+                }
+                "build" => self.add_edge(rest, bindings)?,
+                "default" => {
+                    let vars = &self.vars;
+                    for target in split_paths(rest) {
+                        self.defaults
+                            .push(eval(&target, &|name| vars.get(name).cloned()));
+                    }
+                }
+                "pool" => (),
+                "include" | "subninja" => {
+                    if keyword == "subninja" {
+                        self.problems.push(format!(
+                            "`subninja {}` was read like an `include`, so its variables aren't scoped to it",
+                            rest
+                        ));
+                    }
+
+                    let vars = &self.vars;
+                    let included = eval(rest.trim(), &|name| vars.get(name).cloned());
+                    self.load(&dir.join(included))?;
+                }
+                _ => {
+                    let (name, value) = split_binding(line)?;
+                    let vars = &self.vars;
+                    let value = eval(&value, &|name| vars.get(name).cloned());
+                    self.vars.insert(name, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_edge(&mut self, line: &str, bindings: Vec<(String, String)>) -> Result<()> {
+        // edge bindings are evaluated right away, in the file's scope
+        let vars = &self.vars;
+        let bindings: HashMap<String, String> = bindings
+            .into_iter()
+            .map(|(name, value)| {
+                let value = eval(&value, &|name| vars.get(name).cloned());
+                (name, value)
+            })
+            .collect();
+        let lookup = |name: &str| bindings.get(name).or_else(|| vars.get(name)).cloned();
+
+        let mut edge = Edge::default();
+        let mut section = Section::Outputs;
+        for token in build_tokens(line) {
+            let token = match token {
+                Token::Op(op) => {
+                    section = match (section, op) {
+                        (Section::Outputs, "|") => Section::ImplicitOutputs,
+                        (Section::Outputs | Section::ImplicitOutputs, ":") => Section::Rule,
+                        (Section::Inputs, "|") => Section::ImplicitInputs,
+                        (Section::Inputs | Section::ImplicitInputs, "||") => Section::OrderOnly,
+                        (_, "|@") => Section::Validations,
+                        _ => anyhow::bail!("could not understand `build {}`", line),
+                    };
+                    continue;
+                }
+                Token::Path(path) => eval(&path, &lookup),
+            };
+
+            match section {
+                Section::Outputs => edge.outputs.push(token),
+                Section::ImplicitOutputs => edge.implicit_outputs.push(token),
+                Section::Rule => {
+                    edge.rule = token;
+                    section = Section::Inputs;
+                }
+                Section::Inputs => edge.inputs.push(token),
+                Section::ImplicitInputs => edge.implicit_inputs.push(token),
+                Section::OrderOnly => edge.order_only.push(token),
+                Section::Validations => self.problems.push(format!(
+                    "`{}` validates `{}`, but rbt doesn't have validations; it won't be built",
+                    edge.outputs.join(" "),
+                    token
+                )),
+            }
+        }
+
+        if edge.rule == "phony" {
+            self.edges.push(edge);
+            return Ok(());
+        }
+
+        let rule = self
+            .rules
+            .get(&edge.rule)
+            .with_context(|| format!("`build {}` uses an unknown rule `{}`", line, edge.rule))?;
+
+        let description = edge.outputs.join(" ");
+        for (variable, why) in UNSUPPORTED_RULE_VARIABLES {
+            if bindings.contains_key(*variable) || rule.contains_key(*variable) {
+                self.problems
+                    .push(format!("`{}` uses `{}`: {}", description, variable, why));
+            }
+        }
+
+        let mut pool = bindings.get("pool").cloned();
+        if let Some(raw) = rule.get("pool") {
+            pool = pool.or_else(|| Some(eval(raw, &lookup)));
+        }
+        match pool.as_deref() {
+            None | Some("") => (),
+            Some("console") => self.problems.push(format!(
+                "`{}` uses the console pool, but rbt jobs don't get a terminal",
+                description
+            )),
+            Some(pool) => self.problems.push(format!(
+                "`{}` is in the `{}` pool; consider `withResource` and `--resource`",
+                description, pool
+            )),
+        }
+
+        // rule variables are evaluated in the edge's scope, which also has
+        // the paths and the rule's other variables.
+        let inputs = shell_join(&edge.inputs);
+        let outputs = shell_join(&edge.outputs);
+        let in_newline = edge.inputs.join("\n");
+        let command = rule.get("command").cloned().unwrap_or_default();
+
+        fn edge_lookup(
+            name: &str,
+            depth: usize,
+            special: &[(&str, &str)],
+            rule: &HashMap<String, String>,
+            lookup: &dyn Fn(&str) -> Option<String>,
+        ) -> Option<String> {
+            if let Some((_, value)) = special.iter().find(|(special, _)| *special == name) {
+                return Some(value.to_string());
+            }
+
+            if let Some(value) = lookup(name) {
+                return Some(value);
+            }
+
+            // rule variables can refer to each other, but not forever
+            let raw = rule.get(name)?;
+            if depth > 16 {
+                return None;
+            }
+
+            Some(eval(raw, &|inner| {
+                edge_lookup(inner, depth + 1, special, rule, lookup)
+            }))
+        }
+
+        let special = [
+            ("in", inputs.as_str()),
+            ("out", outputs.as_str()),
+            ("in_newline", in_newline.as_str()),
+        ];
+        edge.command = eval(&command, &|name| {
+            edge_lookup(name, 0, &special, rule, &lookup)
+        });
+
+        self.edges.push(edge);
+        Ok(())
+    }
+
+    fn convert(mut self) -> Import {
+        // which edge makes each path, so we can wire up `fromJob`
+        let mut producers: HashMap<&str, usize> = HashMap::new();
+        for (index, edge) in self.edges.iter().enumerate() {
+            for output in edge.outputs.iter().chain(&edge.implicit_outputs) {
+                producers.insert(output, index);
+            }
+        }
+
+        let mut defs = String::new();
+        let mut names: Vec<Option<String>> = vec![None; self.edges.len()];
+        let mut consumed: BTreeSet<usize> = BTreeSet::new();
+        let mut problems = std::mem::take(&mut self.problems);
+
+        let mut job_number = 0;
+        for (index, edge) in self.edges.iter().enumerate() {
+            if edge.rule == "phony" {
+                continue;
+            }
+            job_number += 1;
+            names[index] = Some(format!("job{}", job_number));
+        }
+
+        let mut phony_number = 0;
+        for (index, edge) in self.edges.iter().enumerate() {
+            if edge.rule != "phony" {
+                continue;
+            }
+
+            let jobs: BTreeSet<usize> = self
+                .expand(edge.inputs.iter().chain(&edge.implicit_inputs), &producers)
+                .iter()
+                .filter_map(|path| producers.get(path.as_str()).copied())
+                .filter(|producer| self.edges[*producer].rule != "phony")
+                .collect();
+            if jobs.is_empty() {
+                continue;
+            }
+
+            phony_number += 1;
+            let name = format!("phony{}", phony_number);
+            let _ = writeln!(defs, "\n# {}", edge.outputs.join(" "));
+            write_aggregate(
+                &mut defs,
+                &name,
+                jobs.iter().map(|job| names[*job].as_deref()),
+            );
+            consumed.extend(&jobs);
+            names[index] = Some(name);
+        }
+
+        for (index, edge) in self.edges.iter().enumerate() {
+            let name = match (&names[index], edge.rule.as_str()) {
+                (Some(name), rule) if rule != "phony" => name,
+                _ => continue,
+            };
+
+            let mut from_project: BTreeSet<String> = BTreeSet::new();
+            let mut from_jobs: BTreeMap<usize, BTreeSet<String>> = BTreeMap::new();
+            for path in self.expand(edge.inputs.iter().chain(&edge.implicit_inputs), &producers) {
+                if let Some(problem) = unacceptable(&path) {
+                    problems.push(format!(
+                        "`{}` reads `{}`, which {}; it was left out",
+                        edge.outputs.join(" "),
+                        path,
+                        problem
+                    ));
+                    continue;
+                }
+
+                match producers.get(path.as_str()) {
+                    Some(producer) => {
+                        consumed.insert(*producer);
+                        from_jobs.entry(*producer).or_default().insert(path);
+                    }
+                    None => {
+                        from_project.insert(path);
+                    }
+                }
+            }
+
+            let after: BTreeSet<usize> = self
+                .expand(edge.order_only.iter(), &producers)
+                .iter()
+                .filter_map(|path| producers.get(path.as_str()).copied())
+                .filter(|producer| !from_jobs.contains_key(producer))
+                .collect();
+            consumed.extend(&after);
+
+            let mut outputs = Vec::new();
+            for output in edge.outputs.iter().chain(&edge.implicit_outputs) {
+                match unacceptable(output) {
+                    Some(problem) => problems.push(format!(
+                        "`{}` is an output, but it {}; it was left out",
+                        output, problem
+                    )),
+                    None => outputs.push(roc_str(output)),
+                }
+            }
+
+            let _ = writeln!(defs, "\n# {}", edge.outputs.join(" "));
+            let _ = writeln!(defs, "{} : Job", name);
+            let _ = writeln!(defs, "{} =", name);
+            let _ = writeln!(defs, "    job {{");
+            let _ = writeln!(
+                defs,
+                "        command: exec (systemTool \"sh\") [\"-c\", {}],",
+                roc_str(&edge.command)
+            );
+            let _ = writeln!(defs, "        inputs: [");
+            if !from_project.is_empty() {
+                let _ = writeln!(
+                    defs,
+                    "            projectFiles [{}],",
+                    source_files(&from_project)
+                );
+            }
+            for (producer, files) in &from_jobs {
+                if let Some(producer) = &names[*producer] {
+                    let _ = writeln!(
+                        defs,
+                        "            fromJob {} [{}],",
+                        producer,
+                        source_files(files)
+                    );
+                }
+            }
+            let _ = writeln!(defs, "        ],");
+            let _ = writeln!(defs, "        outputs: [{}],", outputs.join(", "));
+            let _ = writeln!(defs, "        env: Dict.empty,");
+            let _ = writeln!(defs, "    }}");
+            for producer in after {
+                if let Some(producer) = &names[producer] {
+                    let _ = writeln!(defs, "    |> runAfter {}", producer);
+                }
+            }
+        }
+
+        // Ninja builds the `default` targets, or everything nothing else
+        // depends on if there aren't any.
+        let defaults: Vec<Option<&str>> = if self.defaults.is_empty() {
+            (0..self.edges.len())
+                .filter(|index| !consumed.contains(index))
+                .map(|index| names[index].as_deref())
+                .collect()
+        } else {
+            let mut defaults = Vec::new();
+            for target in &self.defaults {
+                match producers.get(target.as_str()) {
+                    Some(producer) => defaults.push(names[*producer].as_deref()),
+                    None => problems.push(format!(
+                        "`{}` is a default target, but nothing builds it",
+                        target
+                    )),
+                }
+            }
+            defaults
+        };
+
+        let defaults: Vec<&str> = defaults.into_iter().flatten().collect();
+        let default = match defaults.as_slice() {
+            [only] => only.to_string(),
+            _ => {
+                let _ = writeln!(defs, "\n# everything Ninja would build by default");
+                write_aggregate(&mut defs, "all", defaults.iter().map(|name| Some(*name)));
+                "all".to_string()
+            }
+        };
+
+        let mut definition = String::new();
+        let _ = writeln!(
+            definition,
+            "# Converted from a Ninja file by `rbt import-ninja`. Point `pf` at rbt's"
+        );
+        let _ = writeln!(definition, "# Package-Config.roc before building.");
+        if !problems.is_empty() {
+            let _ = writeln!(definition, "#\n# Some things couldn't be converted:\n#");
+            for problem in &problems {
+                let _ = writeln!(definition, "# - {}", problem);
+            }
+        }
+        let _ = writeln!(definition, "app \"build\"");
+        let _ = writeln!(definition, "    packages {{ pf: \"Package-Config.roc\" }}");
+        let _ = writeln!(
+            definition,
+            "    imports [pf.Rbt.{{ Rbt, Config, Job, job, exec, systemTool, projectFiles, fromJob, sourceFile, runAfter }}]"
+        );
+        let _ = writeln!(definition, "    provides [init] to pf");
+        let _ = writeln!(definition, "\ninit : Config -> Rbt");
+        let _ = writeln!(definition, "init = \\_ ->");
+        let _ = writeln!(definition, "    Rbt.init {{");
+        let _ = writeln!(definition, "        default: {},", default);
+        let _ = writeln!(definition, "        targets: Dict.empty");
+        for edge in &self.edges {
+            for output in &edge.outputs {
+                let name = producers
+                    .get(output.as_str())
+                    .and_then(|producer| names[*producer].as_ref());
+                if let Some(name) = name {
+                    let _ = writeln!(
+                        definition,
+                        "        |> Dict.insert {} {}",
+                        roc_str(output),
+                        name
+                    );
+                }
+            }
+        }
+        let _ = writeln!(definition, "        ,");
+        let _ = writeln!(definition, "    }}");
+        definition.push_str(&defs);
+
+        Import {
+            definition,
+            problems,
+        }
+    }
+
+    /// Replace the outputs of phony edges with the paths they stand for.
+    fn expand<'a>(
+        &self,
+        paths: impl Iterator<Item = &'a String>,
+        producers: &HashMap<&str, usize>,
+    ) -> BTreeSet<String> {
+        let mut expanded = BTreeSet::new();
+        let mut to_visit: Vec<&String> = paths.collect();
+        let mut seen = BTreeSet::new();
+
+        while let Some(path) = to_visit.pop() {
+            if !seen.insert(path) {
+                continue;
+            }
+
+            match producers
+                .get(path.as_str())
+                .map(|index| &self.edges[*index])
+            {
+                Some(edge) if edge.rule == "phony" => {
+                    to_visit.extend(edge.inputs.iter().chain(&edge.implicit_inputs))
+                }
+                _ => {
+                    expanded.insert(path.clone());
+                }
+            }
+        }
+
+        expanded
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Outputs,
+    ImplicitOutputs,
+    Rule,
+    Inputs,
+    ImplicitInputs,
+    OrderOnly,
+    Validations,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Path(String),
+    Op(&'static str),
+}
+
+/// Join continued lines (ending in `$`) and drop comments and blank lines.
+/// Each line comes back unevaluated, with whether it was indented.
+fn logical_lines(text: &str) -> Vec<(bool, String)> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut chars = text.chars().peekable();
+
+    let mut finish = |line: &mut String| {
+        let indented = line.starts_with(' ');
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            lines.push((indented, trimmed.to_string()));
+        }
+        line.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => match chars.next() {
+                Some('\n') => while chars.next_if_eq(&' ').is_some() {},
+                Some('\r') if chars.next_if_eq(&'\n').is_some() => {
+                    while chars.next_if_eq(&' ').is_some() {}
+                }
+                Some(next) => {
+                    line.push('$');
+                    line.push(next);
+                }
+                None => line.push('$'),
+            },
+            '\n' => finish(&mut line),
+            '\r' => (),
+            _ => line.push(c),
+        }
+    }
+    finish(&mut line);
+
+    lines
+}
+
+fn split_binding(line: &str) -> Result<(String, String)> {
+    let (name, value) = line
+        .split_once('=')
+        .with_context(|| format!("expected `name = value`, but got `{}`", line))?;
+
+    Ok((name.trim().to_string(), value.trim_start().to_string()))
+}
+
+/// Split the part of a build line after `build` into paths and operators.
+/// Paths stay unevaluated.
+fn build_tokens(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+
+        match chars.peek() {
+            None => break,
+            Some(':') => {
+                chars.next();
+                tokens.push(Token::Op(":"));
+            }
+            Some('|') => {
+                chars.next();
+                if chars.next_if_eq(&'|').is_some() {
+                    tokens.push(Token::Op("||"));
+                } else if chars.next_if_eq(&'@').is_some() {
+                    tokens.push(Token::Op("|@"));
+                } else {
+                    tokens.push(Token::Op("|"));
+                }
+            }
+            Some(_) => {
+                let mut path = String::new();
+                while let Some(c) = chars.next_if(|c| !matches!(c, ' ' | ':' | '|')) {
+                    path.push(c);
+                    if c == '$' {
+                        if let Some(escaped) = chars.next() {
+                            path.push(escaped);
+                        }
+                    }
+                }
+                tokens.push(Token::Path(path));
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Split a `default` line into (unevaluated) paths.
+fn split_paths(line: &str) -> Vec<String> {
+    build_tokens(line)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Path(path) => Some(path),
+            Token::Op(_) => None,
+        })
+        .collect()
+}
+
+fn is_var_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Expand variables and escapes in a Ninja string. Unknown variables are
+/// empty, like in Ninja.
+fn eval(raw: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(escaped @ ('$' | ' ' | ':')) => out.push(escaped),
+            Some('{') => {
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                out.push_str(&lookup(&name).unwrap_or_default());
+            }
+            Some(first) if is_var_char(first) => {
+                let mut name = first.to_string();
+                while let Some(c) = chars.next_if(|c| is_var_char(*c)) {
+                    name.push(c);
+                }
+                out.push_str(&lookup(&name).unwrap_or_default());
+            }
+            Some(other) => {
+                out.push('$');
+                out.push(other);
+            }
+            None => out.push('$'),
+        }
+    }
+
+    out
+}
+
+/// Join words (like the paths for `$in` and `$out`) into a command line,
+/// quoting the ones the shell would split.
+pub fn shell_join(paths: &[String]) -> String {
+    paths
+        .iter()
+        .map(|path| {
+            if path
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_+-./=,@%".contains(c))
+            {
+                path.clone()
+            } else {
+                format!("'{}'", path.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// rbt only lets jobs read and write inside the project, so say why a path
+/// won't work, if it won't.
+fn unacceptable(path: &str) -> Option<&'static str> {
+    let path = Path::new(path);
+
+    if path.is_absolute() {
+        Some("is outside the project (rbt only reads files in the project)")
+    } else if path
+        .components()
+        .any(|component| component == std::path::Component::ParentDir)
+    {
+        Some("goes up out of the project with `..`")
+    } else {
+        None
+    }
+}
+
+fn roc_str(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn source_files(paths: &BTreeSet<String>) -> String {
+    paths
+        .iter()
+        .map(|path| format!("sourceFile {}", roc_str(path)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// A job that does nothing itself, but makes `rbt` build all of `jobs`.
+fn write_aggregate<'a>(defs: &mut String, name: &str, jobs: impl Iterator<Item = Option<&'a str>>) {
+    let _ = writeln!(defs, "{} : Job", name);
+    let _ = writeln!(defs, "{} =", name);
+    let _ = writeln!(
+        defs,
+        "    job {{ command: exec (systemTool \"true\") [], inputs: [], outputs: [], env: Dict.empty }}"
+    );
+    for job in jobs.flatten() {
+        let _ = writeln!(defs, "    |> runAfter {}", job);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evaluates_variables_and_escapes() {
+        let vars = HashMap::from([("cc".to_string(), "clang".to_string())]);
+        let lookup = |name: &str| vars.get(name).cloned();
+
+        assert_eq!("clang -c a$ b:c", eval("$cc -c a$$ b$:c", &lookup));
+        assert_eq!("clang-x", eval("${cc}-x", &lookup));
+        assert_eq!("", eval("$missing", &lookup));
+    }
+
+    #[test]
+    fn converts_edges_into_jobs() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("build.ninja");
+        std::fs::write(
+            &path,
+            "# a comment
+cflags = -O2
+rule cc
+  command = cc $cflags -c $in -o $out
+  depfile = $out.d
+rule link
+  command = cc $in -o $out $
+      -lm
+build gen.h: cc gen.c
+build main.o: cc main.c | gen.h
+  cflags = -O0
+build app: link main.o
+build everything: phony app
+default everything
+",
+        )
+        .unwrap();
+
+        let import = import(&path).unwrap();
+
+        assert!(import.definition.contains(
+            "command: exec (systemTool \"sh\") [\"-c\", \"cc -O0 -c main.c -o main.o\"],"
+        ));
+        assert!(import
+            .definition
+            .contains("projectFiles [sourceFile \"main.c\"],"));
+        assert!(import
+            .definition
+            .contains("fromJob job1 [sourceFile \"gen.h\"],"));
+        assert!(import.definition.contains("\"cc main.o -o app -lm\""));
+        assert!(import.definition.contains("default: phony1,"));
+        assert!(import.definition.contains("|> Dict.insert \"app\" job3"));
+
+        // both `cc` edges have depfiles
+        assert_eq!(2, import.problems.len());
+        assert!(import.problems[0].contains("depfile"));
+    }
+}
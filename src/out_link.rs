@@ -0,0 +1,235 @@
+use crate::job::Job;
+use crate::store;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Links the outputs of root jobs somewhere outside the store, named by a
+/// template like `app-{version}-{target}`. Placeholders are filled in from
+/// the job's environment variables, plus a few things rbt knows about every
+/// job:
+///
+/// - `{key}`: the job's key
+/// - `{hash}`: the hash of the job's store item
+/// - `{shard}`: which shard this is (starting at 0), or empty if the job
+///   isn't sharded
+///
+/// Use `{{` and `}}` for literal braces. This only decides what the link is
+/// called; it doesn't change anything about the store.
+#[derive(Debug)]
+pub struct OutLink {
+    template: String,
+}
+
+impl OutLink {
+    pub fn new(template: String) -> Self {
+        OutLink { template }
+    }
+
+    /// Link each root's store item to wherever the template says it goes.
+    /// Names have to be distinct, so a template for a sharded job should
+    /// include `{shard}`.
+    pub fn link_all<'a>(
+        &self,
+        roots: impl IntoIterator<Item = (&'a Job, &'a store::Item)>,
+    ) -> Result<Vec<PathBuf>> {
+        let mut linked = Vec::new();
+        let mut seen = HashSet::new();
+
+        for (job, item) in roots {
+            let name = render(&self.template, &variables(job, item))
+                .with_context(|| format!("could not name the output link for {}", job))?;
+            let link = PathBuf::from(name);
+
+            if !seen.insert(link.clone()) {
+                anyhow::bail!(
+                    "more than one job would be linked to `{}`. Add a placeholder like `{{shard}}` to the template to tell them apart.",
+                    link.display()
+                )
+            }
+
+            replace_link(&link, item.path())
+                .with_context(|| format!("could not link `{}`", link.display()))?;
+
+            tracing::info!("linked {} to `{}`", job, link.display());
+            linked.push(link);
+        }
+
+        Ok(linked)
+    }
+}
+
+fn variables(job: &Job, item: &store::Item) -> BTreeMap<String, String> {
+    let mut vars = job.command.env().clone();
+
+    vars.insert("key".to_string(), job.base_key.to_string());
+    vars.insert("hash".to_string(), item.hash().to_string());
+    vars.insert(
+        "shard".to_string(),
+        job.shard
+            .map(|shard| shard.index.to_string())
+            .unwrap_or_default(),
+    );
+
+    vars
+}
+
+/// Fill in `{name}` placeholders in `template` from `vars`.
+fn render(template: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => anyhow::bail!("`{}` has a `{{` without a matching `}}`", template),
+                    }
+                }
+
+                let value = vars.get(&name).with_context(|| {
+                    format!(
+                        "I don't know what to put in for `{{{}}}`. I know about: {}",
+                        name,
+                        vars.keys()
+                            .map(|key| format!("`{{{}}}`", key))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )
+                })?;
+
+                if value.contains(std::path::is_separator) {
+                    anyhow::bail!(
+                        "the value for `{{{}}}` (`{}`) contains a path separator",
+                        name,
+                        value
+                    )
+                }
+
+                out.push_str(value);
+            }
+            '}' => anyhow::bail!("`{}` has a `}}` without a matching `{{`", template),
+            c => out.push(c),
+        }
+    }
+
+    if out.is_empty() {
+        anyhow::bail!("`{}` rendered to an empty name", template)
+    }
+
+    Ok(out)
+}
+
+/// Point `link` at `target`, replacing a link from a previous build. We
+/// won't replace anything that isn't a symlink, since that's probably
+/// someone's real file.
+/// Point `link` at `target`, replacing whatever link is there already. We
+/// make the new link next to the old one and rename it into place, so
+/// anything following the link (like a script running the last build's
+/// output) sees either the old target or the new one, and never nothing.
+fn replace_link(link: &Path, target: &Path) -> Result<()> {
+    if let Ok(meta) = std::fs::symlink_metadata(link) {
+        if !meta.file_type().is_symlink() {
+            anyhow::bail!("it already exists and isn't a symlink, so I'm leaving it alone")
+        }
+    }
+
+    let name = link
+        .file_name()
+        .context("the link needs a file name")?
+        .to_string_lossy();
+    let temp = link.with_file_name(format!(".{}.rbt-{}", name, rand::random::<u32>()));
+
+    if let Some(parent) = link.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent).context("could not create parent directory")?;
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    std::os::unix::fs::symlink(target, &temp).context("could not create symlink")?;
+
+    // Windows can't rename over a directory link, so there's a moment
+    // without one there.
+    #[cfg(target_family = "windows")]
+    {
+        std::os::windows::fs::symlink_dir(target, &temp).context("could not create symlink")?;
+        if std::fs::symlink_metadata(link).is_ok() {
+            std::fs::remove_dir(link).context("could not remove the old link")?;
+        }
+    }
+
+    if let Err(err) = std::fs::rename(&temp, link) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(err).context("could not move the new link into place");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            "app-1.2.0-x86_64.tar.gz",
+            render(
+                "app-{version}-{target}.tar.gz",
+                &vars(&[("version", "1.2.0"), ("target", "x86_64")])
+            )
+            .unwrap()
+        );
+        assert_eq!("{literal}", render("{{literal}}", &vars(&[])).unwrap());
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn replaces_links_in_place() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (old, new) = (temp.path().join("old"), temp.path().join("new"));
+        let link = temp.path().join("out").join("result");
+
+        replace_link(&link, &old).unwrap();
+        replace_link(&link, &new).unwrap();
+
+        assert_eq!(new, std::fs::read_link(&link).unwrap());
+        assert_eq!(
+            vec![std::ffi::OsString::from("result")],
+            std::fs::read_dir(temp.path().join("out"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>()
+        );
+
+        // but it won't replace something that isn't a link
+        assert!(replace_link(&old.with_file_name("out"), &new).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(render("app-{version}", &vars(&[])).is_err());
+        assert!(render("app-{version", &vars(&[("version", "1")])).is_err());
+        assert!(render("app-{target}", &vars(&[("target", "../../etc")])).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::glue;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Clean-ups rbt can run on an output after a job succeeds and before it
+/// goes in the store, so that what we store doesn't depend on when or where
+/// the job ran. Filters on the same output run in the order they were
+/// declared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    /// Zero the timestamps, owners, and modes in an `ar` archive (like
+    /// `ar D` does), since static libraries otherwise differ every build.
+    NormalizeArchive,
+
+    /// Remove debug info with the system's `strip -S`.
+    Strip,
+
+    /// Set every timestamp in a zip file (including jars) to the earliest
+    /// date zip can represent.
+    ZeroZipTimestamps,
+}
+
+/// The earliest date zip files can hold (1980-01-01) in MS-DOS format.
+const ZIP_EPOCH_DATE: u16 = (1 << 5) | 1;
+
+/// Extra field holding Unix timestamps, which many zip tools add.
+const ZIP_EXTENDED_TIMESTAMP: u16 = 0x5455;
+
+impl Filter {
+    pub fn from_glue(filter: glue::OutputFilter) -> Self {
+        match filter {
+            glue::OutputFilter::NormalizeArchive => Filter::NormalizeArchive,
+            glue::OutputFilter::Strip => Filter::Strip,
+            glue::OutputFilter::ZeroZipTimestamps => Filter::ZeroZipTimestamps,
+        }
+    }
+
+    /// What goes in the job key for this filter. If you change what a
+    /// filter does to its output, change its identity too, so jobs using it
+    /// re-run instead of reusing output filtered the old way.
+    pub fn identity(self) -> &'static str {
+        match self {
+            Filter::NormalizeArchive => "normalizeArchive/1",
+            Filter::Strip => "strip/1",
+            Filter::ZeroZipTimestamps => "zeroZipTimestamps/1",
+        }
+    }
+
+    pub fn apply(self, path: &Path) -> Result<()> {
+        // the job's outputs should be files it wrote, but if one is a link to
+        // an input we'd be rewriting a source file or a store item.
+        let meta = std::fs::symlink_metadata(path)
+            .with_context(|| format!("`{}` does not exist", path.display()))?;
+        if !meta.is_file() {
+            anyhow::bail!(
+                "`{}` isn't a regular file (it might be a link to an input), so I won't change it",
+                path.display()
+            )
+        }
+
+        match self {
+            Filter::NormalizeArchive => rewrite(path, normalize_archive),
+            Filter::ZeroZipTimestamps => rewrite(path, zero_zip_timestamps),
+            Filter::Strip => {
+                let status = std::process::Command::new("strip")
+                    .arg("-S")
+                    .arg(path)
+                    .status()
+                    .context("could not run `strip`")?;
+
+                if !status.success() {
+                    anyhow::bail!("`strip` failed with {}", status)
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+fn rewrite(path: &Path, filter: fn(&mut [u8]) -> Result<()>) -> Result<()> {
+    let mut bytes =
+        std::fs::read(path).with_context(|| format!("could not read `{}`", path.display()))?;
+
+    filter(&mut bytes).with_context(|| format!("could not filter `{}`", path.display()))?;
+
+    std::fs::write(path, bytes).with_context(|| format!("could not write `{}`", path.display()))
+}
+
+/// Rewrite every member header in an `ar` archive the way `ar D` would.
+/// Headers are fixed-width text, so nothing moves and the symbol table's
+/// offsets stay correct.
+fn normalize_archive(bytes: &mut [u8]) -> Result<()> {
+    const MAGIC: &[u8] = b"!<arch>\n";
+    const HEADER_LEN: usize = 60;
+
+    if !bytes.starts_with(MAGIC) {
+        anyhow::bail!("this isn't an `ar` archive")
+    }
+
+    let mut offset = MAGIC.len();
+    while offset < bytes.len() {
+        let header = bytes
+            .get_mut(offset..offset + HEADER_LEN)
+            .context("archive ended in the middle of a member header")?;
+
+        if &header[58..60] != b"`\n" {
+            anyhow::bail!("member header at byte {} is malformed", offset)
+        }
+
+        let size: usize = std::str::from_utf8(&header[48..58])
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .with_context(|| format!("member header at byte {} has a bad size", offset))?;
+
+        fill_field(&mut header[16..28], "0"); // modification time
+        fill_field(&mut header[28..34], "0"); // owner
+        fill_field(&mut header[34..40], "0"); // group
+        fill_field(&mut header[40..48], "644"); // mode
+
+        // members are padded to an even length
+        offset += HEADER_LEN + size + size % 2;
+    }
+
+    Ok(())
+}
+
+fn fill_field(field: &mut [u8], value: &str) {
+    field.fill(b' ');
+    field[..value.len()].copy_from_slice(value.as_bytes());
+}
+
+/// Set the modification time of every entry in a zip file to the zip epoch,
+/// in both the central directory and the local headers, and zero any Unix
+/// timestamps in extra fields.
+fn zero_zip_timestamps(bytes: &mut [u8]) -> Result<()> {
+    const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+    const CENTRAL_HEADER: u32 = 0x0201_4b50;
+    const LOCAL_HEADER: u32 = 0x0403_4b50;
+
+    // the end of central directory record is at least 22 bytes and can be
+    // followed by a comment of up to 65535 bytes, so we look backwards.
+    let end = (0..=bytes.len().saturating_sub(22))
+        .rev()
+        .take(22 + u16::MAX as usize)
+        .find(|offset| read_u32(bytes, *offset) == Some(END_OF_CENTRAL_DIRECTORY))
+        .context("this isn't a zip file (no end of central directory record)")?;
+
+    let entries = read_u16(bytes, end + 10).context("truncated end of central directory")?;
+    let directory = read_u32(bytes, end + 16).context("truncated end of central directory")?;
+    if entries == u16::MAX || directory == u32::MAX {
+        anyhow::bail!("zip64 files aren't supported yet")
+    }
+
+    let mut offset = directory as usize;
+    for _ in 0..entries {
+        if read_u32(bytes, offset) != Some(CENTRAL_HEADER) {
+            anyhow::bail!("expected a central directory entry at byte {}", offset)
+        }
+
+        write_u16(bytes, offset + 12, 0)?;
+        write_u16(bytes, offset + 14, ZIP_EPOCH_DATE)?;
+
+        let name_len = read_u16(bytes, offset + 28).context("truncated entry")? as usize;
+        let extra_len = read_u16(bytes, offset + 30).context("truncated entry")? as usize;
+        let comment_len = read_u16(bytes, offset + 32).context("truncated entry")? as usize;
+        let local = read_u32(bytes, offset + 42).context("truncated entry")? as usize;
+
+        let extra = offset + 46 + name_len;
+        zero_extra_timestamps(bytes, extra, extra_len)?;
+
+        if read_u32(bytes, local) != Some(LOCAL_HEADER) {
+            anyhow::bail!("expected a local file header at byte {}", local)
+        }
+
+        write_u16(bytes, local + 10, 0)?;
+        write_u16(bytes, local + 12, ZIP_EPOCH_DATE)?;
+
+        let local_name_len = read_u16(bytes, local + 26).context("truncated entry")? as usize;
+        let local_extra_len = read_u16(bytes, local + 28).context("truncated entry")? as usize;
+        zero_extra_timestamps(bytes, local + 30 + local_name_len, local_extra_len)?;
+
+        offset = extra + extra_len + comment_len;
+    }
+
+    Ok(())
+}
+
+fn zero_extra_timestamps(bytes: &mut [u8], start: usize, len: usize) -> Result<()> {
+    let mut offset = start;
+    while offset + 4 <= start + len {
+        let id = read_u16(bytes, offset).context("truncated extra field")?;
+        let size = read_u16(bytes, offset + 2).context("truncated extra field")? as usize;
+
+        // the first byte says which timestamps follow; the rest are times.
+        if id == ZIP_EXTENDED_TIMESTAMP && size > 1 {
+            bytes
+                .get_mut(offset + 5..offset + 4 + size)
+                .context("truncated extended timestamp")?
+                .fill(0);
+        }
+
+        offset += 4 + size;
+    }
+
+    Ok(())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) -> Result<()> {
+    bytes
+        .get_mut(offset..offset + 2)
+        .context("zip file ended early")?
+        .copy_from_slice(&value.to_le_bytes());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ar_member(name: &str, mtime: &str, content: &[u8]) -> Vec<u8> {
+        let mut member = format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            name,
+            mtime,
+            "1000",
+            "1000",
+            "100755",
+            content.len()
+        )
+        .into_bytes();
+        member.extend_from_slice(content);
+        if content.len() % 2 == 1 {
+            member.push(b'\n');
+        }
+
+        member
+    }
+
+    #[test]
+    fn normalizes_archives() {
+        let archive = |mtime| {
+            let mut bytes = b"!<arch>\n".to_vec();
+            bytes.extend(ar_member("a.o/", mtime, b"odd"));
+            bytes.extend(ar_member("b.o/", mtime, b"even"));
+            bytes
+        };
+
+        let mut first = archive("1666000000");
+        let mut second = archive("1777000000");
+        normalize_archive(&mut first).unwrap();
+        normalize_archive(&mut second).unwrap();
+
+        assert_eq!(first, second);
+        assert!(normalize_archive(&mut b"not an archive".to_vec()).is_err());
+    }
+
+    /// A zip with one empty, stored entry named `a`.
+    fn zip(time: u16, date: u16) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        // local file header
+        bytes.extend(0x0403_4b50u32.to_le_bytes());
+        bytes.extend([20, 0, 0, 0, 0, 0]); // version, flags, method
+        bytes.extend(time.to_le_bytes());
+        bytes.extend(date.to_le_bytes());
+        bytes.extend([0; 12]); // crc, sizes
+        bytes.extend(1u16.to_le_bytes()); // name length
+        bytes.extend(0u16.to_le_bytes()); // extra length
+        bytes.push(b'a');
+
+        let directory = bytes.len() as u32;
+        bytes.extend(0x0201_4b50u32.to_le_bytes());
+        bytes.extend([20, 0, 20, 0, 0, 0, 0, 0]); // versions, flags, method
+        bytes.extend(time.to_le_bytes());
+        bytes.extend(date.to_le_bytes());
+        bytes.extend([0; 12]); // crc, sizes
+        bytes.extend(1u16.to_le_bytes()); // name length
+        bytes.extend([0; 12]); // extra, comment, disk, attributes
+        bytes.extend(0u32.to_le_bytes()); // local header offset
+        bytes.push(b'a');
+        let directory_len = bytes.len() as u32 - directory;
+
+        bytes.extend(0x0605_4b50u32.to_le_bytes());
+        bytes.extend([0; 4]); // disks
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(directory_len.to_le_bytes());
+        bytes.extend(directory.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes()); // comment length
+
+        bytes
+    }
+
+    #[test]
+    fn zeroes_zip_timestamps() {
+        let mut zipped = zip(0x6000, 0x5555);
+        zero_zip_timestamps(&mut zipped).unwrap();
+
+        assert_eq!(zip(0, ZIP_EPOCH_DATE), zipped);
+    }
+}
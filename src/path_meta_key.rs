@@ -1,68 +1,447 @@
 use anyhow::{Context, Result};
-use std::convert::TryFrom;
+use std::collections::HashMap;
 use std::fs::Metadata;
 use std::hash::{Hash, Hasher};
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use xxhash_rust::xxh3::Xxh3;
 
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::MetadataExt;
 
 #[derive(Debug, Hash)]
-pub struct PathMetaKey {
-    // common
-    modified: SystemTime,
-    len: u64,
+pub enum PathMetaKey {
+    /// Everything we know about the file. This is what we use on normal
+    /// local filesystems.
+    Full {
+        // common
+        modified: SystemTime,
+        len: u64,
 
-    // Unix-only
-    #[cfg(target_family = "unix")]
-    inode: u64,
-    #[cfg(target_family = "unix")]
-    mode: u32,
-    #[cfg(target_family = "unix")]
-    uid: u32,
-    #[cfg(target_family = "unix")]
-    gid: u32,
-    // TODO: extra info for Windows
+        // Unix-only
+        #[cfg(target_family = "unix")]
+        inode: u64,
+        #[cfg(target_family = "unix")]
+        mode: u32,
+        #[cfg(target_family = "unix")]
+        uid: u32,
+        #[cfg(target_family = "unix")]
+        gid: u32,
+        // TODO: extra info for Windows
+    },
+
+    /// Just modification time and size, for filesystems where the rest isn't
+    /// trustworthy. Since we can't rely on the inode to tell files apart any
+    /// more, the path goes in too.
+    MtimeAndSize {
+        path: PathBuf,
+        modified: SystemTime,
+        len: u64,
+    },
 }
 
 impl PathMetaKey {
-    pub fn to_db_key(&self) -> [u8; 8] {
+    /// Get a key for `path`, or `None` if the strategy says we can't trust
+    /// metadata at all (in which case the caller should hash the contents
+    /// every time.)
+    pub fn new(path: &Path, meta: &Metadata, strategy: Strategy) -> Result<Option<Self>> {
+        let modified = || {
+            meta.modified()
+                .context("mtime is not supported on this system")
+        };
+
+        match strategy {
+            Strategy::Full => Ok(Some(PathMetaKey::Full {
+                modified: modified()?,
+                len: meta.len(),
+                #[cfg(target_family = "unix")]
+                inode: meta.ino(),
+                #[cfg(target_family = "unix")]
+                mode: meta.mode(),
+                #[cfg(target_family = "unix")]
+                uid: meta.uid(),
+                #[cfg(target_family = "unix")]
+                gid: meta.gid(),
+            })),
+            Strategy::MtimeAndSize => Ok(Some(PathMetaKey::MtimeAndSize {
+                path: path.to_path_buf(),
+                modified: modified()?,
+                len: meta.len(),
+            })),
+            Strategy::ContentOnly => Ok(None),
+        }
+    }
+
+    /// The key for this file in the file hashes tree: the top-level
+    /// directory `path` is in, a NUL, then a hash of the metadata. Grouping
+    /// keys by directory keeps one part of a big monorepo together on disk,
+    /// so a build that only touches that part reads fewer pages, and lets
+    /// `prune_missing_subtrees` drop a whole directory without a full scan.
+    pub fn to_db_key(&self, path: &Path) -> Vec<u8> {
         let mut hasher = Xxh3::new();
         self.hash(&mut hasher);
 
-        hasher.finish().to_le_bytes()
+        let mut key = subtree(path);
+        key.push(0);
+        key.extend_from_slice(&hasher.finish().to_le_bytes());
+        key
     }
 }
 
-#[cfg(target_family = "unix")]
-impl TryFrom<Metadata> for PathMetaKey {
-    type Error = anyhow::Error;
-
-    fn try_from(meta: Metadata) -> Result<PathMetaKey> {
-        Ok(PathMetaKey {
-            modified: meta
-                .modified()
-                .context("mtime is not supported on this system")?,
-            len: meta.len(),
-            inode: meta.ino(),
-            mode: meta.mode(),
-            uid: meta.uid(),
-            gid: meta.gid(),
-        })
+/// Which top-level directory `path` is in, as bytes. Files right at the top
+/// of the project share the empty subtree.
+fn subtree(path: &Path) -> Vec<u8> {
+    let mut components = path.components();
+
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first.as_os_str().as_encoded_bytes().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// Every subtree with entries in the file hashes tree. Instead of reading
+/// every key, we jump from the first key in each subtree to the first key
+/// after it, so this only touches one entry per subtree.
+fn subtrees(tree: &sled::Tree) -> Result<Vec<Vec<u8>>> {
+    let mut subtrees = Vec::new();
+    let mut next = tree.first().context("could not read file hashes")?;
+
+    while let Some((key, _)) = next {
+        let end = key
+            .iter()
+            .position(|byte| *byte == 0)
+            .context("found a file hash key without a subtree")?;
+        let subtree = key[..end].to_vec();
+
+        // keys in this subtree all start with `subtree\0`, so the first key
+        // after them is at or past `subtree\1`.
+        let mut after = subtree.clone();
+        after.push(1);
+        next = tree
+            .range(after..)
+            .next()
+            .transpose()
+            .context("could not read file hashes")?;
+
+        subtrees.push(subtree);
+    }
+
+    Ok(subtrees)
+}
+
+/// Remove the hashes for every top-level directory that `exists` says is
+/// gone, returning how many entries that was. Hashes for files that are
+/// still around but changed stay until their directory goes away; they're
+/// small, and telling them apart would mean statting every file again.
+pub fn prune_missing_subtrees(tree: &sled::Tree, exists: impl Fn(&Path) -> bool) -> Result<usize> {
+    let mut removed = 0;
+
+    for subtree in subtrees(tree)? {
+        // the empty subtree is the project itself. Names that aren't
+        // unicode get mangled here, which at worst means re-hashing them.
+        if subtree.is_empty() || exists(Path::new(&*String::from_utf8_lossy(&subtree))) {
+            continue;
+        }
+
+        let mut prefix = subtree;
+        prefix.push(0);
+
+        let mut batch = sled::Batch::default();
+        for entry in tree.scan_prefix(&prefix) {
+            let (key, _) = entry.context("could not read file hashes")?;
+            batch.remove(key);
+            removed += 1;
+        }
+        tree.apply_batch(batch)
+            .context("could not remove file hashes")?;
+    }
+
+    Ok(removed)
+}
+
+/// How much of a file's metadata we trust to tell us whether it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Full,
+    MtimeAndSize,
+    ContentOnly,
+}
+
+/// Decides which `Strategy` to use for each filesystem we see files on. Some
+/// filesystems (FUSE and network mounts, mostly) report inodes or owners that
+/// change from one mount to the next or are always zero. Trusting those would
+/// mean either spurious rebuilds or, worse, false cache hits, so we fall back
+/// to less information and warn about it once per filesystem.
+///
+/// We also watch for clock skew (common on VMs and dual-boot machines.) If
+/// the clock jumped back since the last build, a file edited now could end
+/// up with the same mtime it had back then, and we'd use the old hash. So
+/// for that build, we hash everything. Files modified in the future get
+/// hashed every time until the clock catches up with them.
+#[derive(Debug)]
+pub struct Strategies {
+    #[cfg(target_family = "unix")]
+    by_device: HashMap<u64, Strategy>,
+    warned_about_mtimes: bool,
+    now: SystemTime,
+    clock_went_back: bool,
+    warned_about_future: bool,
+}
+
+/// How far clocks (ours between builds, or ours and a file server's) can
+/// disagree before we stop trusting mtimes. Network filesystems are often a
+/// few seconds off, which is harmless.
+const CLOCK_SKEW_TOLERANCE: Duration = Duration::from_secs(60);
+
+impl Default for Strategies {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Strategies {
+    /// `last_build_started` is when the previous build in this root dir
+    /// started, according to the clock back then.
+    pub fn new(last_build_started: Option<SystemTime>) -> Self {
+        let now = SystemTime::now();
+
+        let clock_went_back = match last_build_started
+            .and_then(|last| last.duration_since(now + CLOCK_SKEW_TOLERANCE).ok())
+        {
+            Some(jump) => {
+                tracing::warn!(
+                    "the clock says it's {} seconds earlier than when the last build started, so it probably jumped backwards. Modification times can't be trusted to tell if files changed, so I'll re-hash every input file this build.",
+                    jump.as_secs() + CLOCK_SKEW_TOLERANCE.as_secs(),
+                );
+                true
+            }
+            None => false,
+        };
+
+        Strategies {
+            #[cfg(target_family = "unix")]
+            by_device: HashMap::new(),
+            warned_about_mtimes: false,
+            now,
+            clock_went_back,
+            warned_about_future: false,
+        }
+    }
+
+    pub fn for_path(&mut self, path: &Path, meta: &Metadata) -> Strategy {
+        if self.clock_went_back {
+            return Strategy::ContentOnly;
+        }
+
+        if let Some(ahead) = meta.modified().ok().and_then(|modified| {
+            modified
+                .duration_since(self.now + CLOCK_SKEW_TOLERANCE)
+                .ok()
+        }) {
+            if !self.warned_about_future {
+                tracing::warn!(
+                    "`{}` was modified {} seconds in the future, so this machine's clock (or the one on the machine that wrote it) is probably off. I'll re-hash it (and any other files like it) on every build until the clock catches up.",
+                    path.display(),
+                    ahead.as_secs() + CLOCK_SKEW_TOLERANCE.as_secs(),
+                );
+                self.warned_about_future = true;
+            }
+
+            return Strategy::ContentOnly;
+        }
+
+        // some filesystems (and some archive tools) leave every file with the
+        // same mtime, which makes it useless for noticing changes. This is a
+        // property of the file rather than the filesystem, so we check it
+        // every time.
+        if !matches!(meta.modified(), Ok(modified) if modified != SystemTime::UNIX_EPOCH) {
+            if !self.warned_about_mtimes {
+                tracing::warn!(
+                    "`{}` doesn't have a modification time, so I'll re-hash it (and any other files like it) on every build. This is correct, but slow!",
+                    path.display(),
+                );
+                self.warned_about_mtimes = true;
+            }
+
+            return Strategy::ContentOnly;
+        }
+
+        #[cfg(target_family = "unix")]
+        {
+            *self
+                .by_device
+                .entry(meta.dev())
+                .or_insert_with(|| Self::detect(path, meta))
+        }
+
+        #[cfg(not(target_family = "unix"))]
+        {
+            Strategy::Full
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    fn detect(path: &Path, meta: &Metadata) -> Strategy {
+        let reason = match exotic_filesystem(path) {
+            Some(name) => format!("a {} filesystem", name),
+            None if meta.ino() == 0 => "a filesystem that reports every inode as zero".to_string(),
+            None => return Strategy::Full,
+        };
+
+        tracing::warn!(
+            "`{}` is on {}, where inodes and owners aren't reliable. I'll only use modification time and size to tell if files there changed, so an edit that keeps both the same won't trigger a rebuild.",
+            path.display(),
+            reason,
+        );
+
+        Strategy::MtimeAndSize
+    }
+}
+
+/// If `path` is on a filesystem known to report unstable inodes or owners,
+/// get a human-readable name for it.
+#[cfg(target_os = "linux")]
+fn exotic_filesystem(path: &Path) -> Option<&'static str> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    // magic numbers from linux/magic.h and statfs(2). They all fit in 32
+    // bits, but `f_type`'s size varies by platform.
+    match stat.f_type as u32 {
+        0x6573_5546 => Some("FUSE"),
+        0x6969 => Some("NFS"),
+        0x517b => Some("SMB"),
+        0xff53_4d42 => Some("CIFS"),
+        0xfe53_4d42 => Some("SMB2"),
+        0x0102_1997 => Some("9p"),
+        0x00c3_6400 => Some("Ceph"),
+        0x5346_414f => Some("AFS"),
+        0x7375_7245 => Some("Coda"),
+        0x786f_4256 => Some("VirtualBox shared folder"),
+        _ => None,
     }
 }
 
-#[cfg(not(target_family = "unix"))]
-impl TryFrom<Metadata> for PathMetaKey {
-    type Error = anyhow::Error;
-
-    fn try_from(meta: Metadata) -> Result<PathMetaKey> {
-        Ok(PathMetaKey {
-            modified: meta
-                .modified()
-                .context("mtime is not supported on this system")?,
-            len: meta.len(),
-        })
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+fn exotic_filesystem(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn mtime_and_size_keys_tell_paths_apart() {
+        let temp = TempDir::new().unwrap();
+        let a = temp.path().join("a");
+        let b = temp.path().join("b");
+        std::fs::write(&a, "same").unwrap();
+        std::fs::write(&b, "same").unwrap();
+
+        let key = |path: &Path| {
+            PathMetaKey::new(path, &path.metadata().unwrap(), Strategy::MtimeAndSize)
+                .unwrap()
+                .unwrap()
+                .to_db_key(path)
+        };
+
+        assert_ne!(key(&a), key(&b));
+    }
+
+    #[test]
+    fn content_only_has_no_key() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("a");
+        std::fs::write(&path, "hi").unwrap();
+
+        assert!(
+            PathMetaKey::new(&path, &path.metadata().unwrap(), Strategy::ContentOnly)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn missing_mtimes_mean_content_only() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("a");
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+
+        assert_eq!(
+            Strategy::ContentOnly,
+            Strategies::default().for_path(&path, &path.metadata().unwrap())
+        );
+    }
+
+    #[test]
+    fn clock_skew_means_content_only() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("a");
+        let file = std::fs::File::create(&path).unwrap();
+        let meta = path.metadata().unwrap();
+
+        // a file with a normal mtime, after the clock jumped back a day
+        let day = Duration::from_secs(60 * 60 * 24);
+        assert_eq!(
+            Strategy::ContentOnly,
+            Strategies::new(Some(SystemTime::now() + day)).for_path(&path, &meta)
+        );
+        assert_ne!(
+            Strategy::ContentOnly,
+            Strategies::new(Some(SystemTime::now() - day)).for_path(&path, &meta)
+        );
+
+        // a file from the future
+        file.set_modified(SystemTime::now() + day).unwrap();
+        assert_eq!(
+            Strategy::ContentOnly,
+            Strategies::default().for_path(&path, &path.metadata().unwrap())
+        );
+    }
+
+    #[test]
+    fn keys_are_grouped_by_subtree_and_pruned_without_scanning() {
+        let tree = crate::db::Db::temporary()
+            .tree(crate::db::Tree::FileHashes)
+            .unwrap();
+        let temp = TempDir::new().unwrap();
+        let meta_path = temp.path().join("a");
+        std::fs::write(&meta_path, "hi").unwrap();
+        let key = PathMetaKey::new(&meta_path, &meta_path.metadata().unwrap(), Strategy::Full)
+            .unwrap()
+            .unwrap();
+
+        for path in [
+            "README.md",
+            "kept/a.rs",
+            "kept/b/c.rs",
+            "gone/a.rs",
+            "gone/b.rs",
+        ] {
+            tree.insert(key.to_db_key(Path::new(path)), &[0; 32])
+                .unwrap();
+        }
+        assert!(key.to_db_key(Path::new("kept/a.rs")).starts_with(b"kept\0"));
+
+        assert_eq!(
+            vec![b"".to_vec(), b"gone".to_vec(), b"kept".to_vec()],
+            subtrees(&tree).unwrap()
+        );
+
+        let removed = prune_missing_subtrees(&tree, |path| path != Path::new("gone")).unwrap();
+
+        // `Full` keys don't include the path, so the two files in each
+        // subtree share an entry here.
+        assert_eq!(1, removed);
+        assert_eq!(2, tree.len());
     }
 }
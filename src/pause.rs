@@ -0,0 +1,188 @@
+use crate::runner::Children;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Lets people pause a build (say, because it's eating their battery during
+/// a meeting) with SIGTSTP. That's Ctrl-Z in a terminal, or
+/// `kill -TSTP <pid>` from anywhere else. We stop scheduling new jobs, write
+/// down what was queued, and stop ourselves until someone sends SIGCONT
+/// (`fg`, or `kill -CONT <pid>`.)
+///
+/// Jobs run in our process group, so Ctrl-Z in a terminal stops them too:
+/// the terminal sends SIGTSTP to the whole foreground group, and `fg`
+/// continues all of it. `kill -TSTP <pid>` only reaches rbt, so running jobs
+/// keep going unless we're asked to stop them as well. We don't give jobs
+/// their own process groups, since then Ctrl-C would stop rbt and leave its
+/// jobs running.
+#[derive(Debug)]
+pub struct Pauser {
+    state_path: PathBuf,
+    stop_children: bool,
+
+    #[cfg(target_family = "unix")]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+/// What the coordinator was doing when we paused. We write this next to the
+/// database while we're paused, so that other tools can see that a build is
+/// paused, what it's waiting to run, and which process to send SIGCONT to.
+#[derive(Debug, serde::Serialize)]
+pub struct QueueState {
+    pub pid: u32,
+    pub ready: Vec<String>,
+    pub blocked: Vec<String>,
+    pub running: usize,
+}
+
+impl Pauser {
+    pub fn new(state_path: PathBuf, stop_children: bool) -> Self {
+        Pauser {
+            state_path,
+            stop_children,
+
+            #[cfg(target_family = "unix")]
+            signal: None,
+        }
+    }
+
+    /// Start listening for pause requests. This has to happen inside the
+    /// async runtime, so we can't do it in `new`.
+    pub fn listen(&mut self) -> Result<()> {
+        // only one build can use a root dir at a time, so if there's a state
+        // file here already, it's from a build that was killed while paused.
+        self.remove_state()?;
+
+        #[cfg(target_family = "unix")]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            self.signal = Some(
+                signal(SignalKind::from_raw(libc::SIGTSTP))
+                    .context("could not listen for SIGTSTP")?,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Wait until someone asks us to pause. If we can't listen for pause
+    /// requests, this never finishes.
+    pub async fn requested(&mut self) {
+        #[cfg(target_family = "unix")]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+
+        futures::future::pending().await
+    }
+
+    /// Pause until someone tells us to continue. We don't cancel anything:
+    /// jobs that were running either keep running (and we pick up their
+    /// results when we resume) or are stopped and continued along with us.
+    #[cfg_attr(not(target_family = "unix"), allow(unused_variables))]
+    pub fn pause(&self, state: &QueueState, children: &Children) -> Result<()> {
+        tracing::info!(
+            "pausing with {} jobs running and {} waiting. Send SIGCONT to {} to resume.",
+            state.running,
+            state.ready.len() + state.blocked.len(),
+            state.pid,
+        );
+
+        self.write_state(state)?;
+
+        #[cfg(target_family = "unix")]
+        {
+            if self.stop_children {
+                children.signal(libc::SIGSTOP);
+            }
+
+            // this blocks the whole process until we get SIGCONT, which is
+            // exactly what we want.
+            unsafe { libc::raise(libc::SIGSTOP) };
+
+            if self.stop_children {
+                children.signal(libc::SIGCONT);
+            }
+        }
+
+        tracing::info!("resuming");
+
+        self.remove_state()
+    }
+
+    /// Write `state` where other tools can find it. We write to a temporary
+    /// file first and move it into place, so nobody reading it ever sees
+    /// half of it.
+    fn write_state(&self, state: &QueueState) -> Result<()> {
+        let dir = self
+            .state_path
+            .parent()
+            .context("queue state path had no parent")?;
+
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .context("could not create a temporary file for the queue state")?;
+        serde_json::to_writer_pretty(&mut file, state).context("could not write queue state")?;
+        file.flush().context("could not write queue state")?;
+
+        file.persist(&self.state_path)
+            .with_context(|| format!("could not write `{}`", self.state_path.display()))?;
+
+        Ok(())
+    }
+
+    fn remove_state(&self) -> Result<()> {
+        match std::fs::remove_file(&self.state_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err)
+                .with_context(|| format!("could not remove `{}`", self.state_path.display())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state() -> QueueState {
+        QueueState {
+            pid: 1234,
+            ready: vec!["abc".to_string()],
+            blocked: vec!["def".to_string(), "ghi".to_string()],
+            running: 2,
+        }
+    }
+
+    #[test]
+    fn writes_queue_state_while_paused_and_removes_it_after() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("paused.json");
+        let pauser = Pauser::new(path.clone(), false);
+
+        pauser.write_state(&state()).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(1234, written["pid"]);
+        assert_eq!(2, written["blocked"].as_array().unwrap().len());
+
+        // the temporary file we wrote first got moved into place
+        assert_eq!(1, std::fs::read_dir(temp.path()).unwrap().count());
+
+        pauser.remove_state().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn removes_state_left_by_a_killed_build() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("paused.json");
+        std::fs::write(&path, "{}").unwrap();
+
+        let mut pauser = Pauser::new(path.clone(), false);
+        pauser.listen().unwrap();
+
+        assert!(!path.exists());
+    }
+}
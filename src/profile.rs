@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+/// The project config, read from `rbt.json` next to the build definition.
+/// So far it only holds profiles.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named bundle of build settings (like `dev`, `ci`, or `release`) so
+/// nobody has to remember which six flags CI passes. Flags given on the
+/// command line win over the profile's settings.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields, default)]
+pub struct Profile {
+    /// Same as `--remote-cache`.
+    pub remote_cache: Option<String>,
+
+    /// Same as `--remote-cache-upload`.
+    pub remote_cache_upload: bool,
+
+    /// Same as `--snapshot-inputs`.
+    pub snapshot_inputs: bool,
+
+    /// Same as `--capture-diagnostics`.
+    pub capture_diagnostics: bool,
+
+    /// Same as `--max-local-jobs`.
+    pub max_local_jobs: Option<NonZeroUsize>,
+
+    /// Same as `--define`, for each entry. Defines given on the command line
+    /// replace these.
+    pub defines: BTreeMap<String, String>,
+
+    /// Mixed into every job's key, so builds with this profile never share
+    /// cache entries with builds using a different salt (or none.) Change it
+    /// to throw away a cache you don't trust anymore.
+    pub salt: Option<String>,
+
+    /// Same as `--strict-outputs`.
+    pub strict_outputs: bool,
+
+    /// Same as `--stable-paths`.
+    pub stable_paths: bool,
+
+    /// Same as `--verify-store`.
+    pub verify_store: bool,
+
+    /// Same as `--status-command`.
+    pub status_command: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("could not read `{}`", path.display()))?;
+
+        serde_json::from_slice(&bytes)
+            .with_context(|| format!("could not parse `{}`", path.display()))
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).with_context(|| {
+            if self.profiles.is_empty() {
+                format!(
+                    "there's no profile named `{}`, and no profiles at all",
+                    name
+                )
+            } else {
+                format!(
+                    "there's no profile named `{}`. Try one of these: {}",
+                    name,
+                    self.profiles
+                        .keys()
+                        .map(|name| format!("`{}`", name))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_profiles_and_lists_them_on_typos() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "profiles": {
+                "ci": {
+                    "remoteCache": "https://cache.example.com",
+                    "remoteCacheUpload": true,
+                    "defines": {"mode": "release"},
+                    "salt": "2022-11"
+                },
+                "dev": {}
+            }
+        }))
+        .unwrap();
+
+        let ci = config.profile("ci").unwrap();
+        assert_eq!(
+            Some("https://cache.example.com"),
+            ci.remote_cache.as_deref()
+        );
+        assert!(ci.remote_cache_upload);
+        assert!(!ci.snapshot_inputs);
+        assert_eq!(Some("release"), ci.defines.get("mode").map(String::as_str));
+        assert_eq!(&Profile::default(), config.profile("dev").unwrap());
+
+        let err = config.profile("cl").unwrap_err().to_string();
+        assert!(err.contains("`ci`, `dev`"), "{}", err);
+    }
+}
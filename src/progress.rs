@@ -0,0 +1,176 @@
+use crate::events::{Bus, Event, JobInfo};
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Jobs find the file to write progress to in this environment variable.
+/// Writing to it is optional: most jobs are over too quickly to bother.
+pub const ENV_VAR: &str = "RBT_PROGRESS";
+
+/// What the file is called in the job's home directory.
+pub const FILE_NAME: &str = ".rbt-progress";
+
+/// How often we look for new progress lines. Jobs that report progress take
+/// minutes, so there's no need to watch the file more closely.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How far along a long-running job says it is. Jobs append lines like
+/// `42% linking`, `42%`, or `linking` to the file in `RBT_PROGRESS`, and
+/// the last complete line is the current progress.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Progress {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+}
+
+impl Progress {
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+
+        let (first, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let percent = first
+            .strip_suffix('%')
+            .and_then(|percent| percent.parse::<u8>().ok())
+            .filter(|percent| *percent <= 100);
+
+        let stage = match percent {
+            Some(_) => rest.trim(),
+            None => line,
+        };
+
+        Some(Progress {
+            percent,
+            stage: (!stage.is_empty()).then(|| stage.to_string()),
+        })
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.percent, &self.stage) {
+            (Some(percent), Some(stage)) => write!(f, "{}% {}", percent, stage),
+            (Some(percent), None) => write!(f, "{}%", percent),
+            (None, Some(stage)) => write!(f, "{}", stage),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Watch a job's progress file until the task is aborted (when the job
+/// finishes), logging new progress and publishing it for other sinks. A
+/// missing or unreadable file just means no progress yet.
+pub async fn watch(path: PathBuf, job: JobInfo, events: Bus) {
+    let mut offset = 0;
+    let mut partial = Vec::new();
+    let mut last = None;
+
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let mut new = Vec::new();
+        let read = async {
+            let mut file = tokio::fs::File::open(&path).await?;
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            file.read_to_end(&mut new).await
+        };
+        match read.await {
+            Ok(bytes) => offset += bytes as u64,
+            Err(_) => continue,
+        }
+
+        // a line the job is halfway through writing waits for the next look
+        partial.extend(new);
+        let complete = match partial.iter().rposition(|byte| *byte == b'\n') {
+            Some(end) => partial.drain(..=end).collect::<Vec<u8>>(),
+            None => continue,
+        };
+
+        let latest = String::from_utf8_lossy(&complete)
+            .lines()
+            .rev()
+            .find_map(Progress::parse);
+        if latest.is_none() || latest == last {
+            continue;
+        }
+        last = latest.clone();
+
+        if let Some(progress) = latest {
+            tracing::info!("{}: {}", job.id, progress);
+            events.publish(Event::JobProgress {
+                job: job.clone(),
+                progress,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_percent_and_stage() {
+        assert_eq!(
+            Some(Progress {
+                percent: Some(42),
+                stage: Some("linking app".to_string())
+            }),
+            Progress::parse("42% linking app\n")
+        );
+        assert_eq!(
+            Some(Progress {
+                percent: Some(100),
+                stage: None
+            }),
+            Progress::parse("100%")
+        );
+        assert_eq!(
+            Some(Progress {
+                percent: None,
+                stage: Some("150% done".to_string())
+            }),
+            Progress::parse("150% done")
+        );
+        assert_eq!(None, Progress::parse("  "));
+    }
+
+    #[tokio::test]
+    async fn publishes_the_latest_complete_line() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("progress");
+        std::fs::write(&path, "10% fetching\n20% compiling\n30% link").unwrap();
+
+        let bus = Bus::new();
+        let mut events = bus.subscribe();
+        let job = JobInfo {
+            id: "make-abc".to_string(),
+            key: "abc".to_string(),
+            command: "make".to_string(),
+        };
+        let watcher = tokio::spawn(watch(path, job.clone(), bus));
+
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        watcher.abort();
+
+        assert_eq!(
+            Event::JobProgress {
+                job,
+                progress: Progress {
+                    percent: Some(20),
+                    stage: Some("compiling".to_string())
+                }
+            },
+            event
+        );
+    }
+}
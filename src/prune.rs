@@ -0,0 +1,153 @@
+use crate::store::Store;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// What a long-running daemon has pruned so far, for `rbt --daemon stats`.
+#[derive(Debug, Default)]
+pub struct Pruned {
+    dirs: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Pruned {
+    pub fn add(&self, dirs: u64, bytes: u64) {
+        self.dirs.fetch_add(dirs, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// How many directories we've removed, and how many bytes were in them.
+    pub fn totals(&self) -> (u64, u64) {
+        (
+            self.dirs.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Remove the directories left behind in `root_dir` by builds that didn't
+/// clean up after themselves (because they were killed, or crashed) once
+/// they haven't changed in `max_age`: temporary directories in the store
+/// and the resolved-input cache, and workspaces. Only call this while nothing is building, since those
+/// are exactly the directories a running build is using. Returns how many
+/// directories we removed and how many bytes were in them.
+pub fn stale_dirs(root_dir: &Path, max_age: Duration) -> Result<(u64, u64)> {
+    let store = root_dir.join("store");
+    let (store_dirs, store_bytes) = prune_children(&store, max_age, |name| {
+        name.starts_with(crate::store::TEMP_PREFIX)
+    })?;
+
+    // remote cache downloads get a workspace of their own in the store
+    let (remote_dirs, remote_bytes) = prune_children(&store.join("remote"), max_age, |_| true)?;
+
+    let (resolved_dirs, resolved_bytes) =
+        prune_children(&root_dir.join("resolved"), max_age, |name| {
+            name.starts_with(crate::store::TEMP_PREFIX)
+        })?;
+
+    let (workspace_dirs, workspace_bytes) =
+        prune_children(&root_dir.join("workspaces"), max_age, |_| true)?;
+
+    Ok((
+        store_dirs + remote_dirs + resolved_dirs + workspace_dirs,
+        store_bytes + remote_bytes + resolved_bytes + workspace_bytes,
+    ))
+}
+
+/// Remove the directories in `parent` whose names `ours` accepts and that
+/// haven't been modified in `max_age`.
+fn prune_children(
+    parent: &Path,
+    max_age: Duration,
+    ours: impl Fn(&str) -> bool,
+) -> Result<(u64, u64)> {
+    let entries = match std::fs::read_dir(parent) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(err) => {
+            return Err(err).with_context(|| format!("could not read `{}`", parent.display()))
+        }
+    };
+
+    let now = SystemTime::now();
+    let (mut dirs, mut bytes) = (0, 0);
+    for entry in entries {
+        let entry = entry.with_context(|| format!("could not read `{}`", parent.display()))?;
+        let meta = entry
+            .metadata()
+            .with_context(|| format!("could not get metadata for `{}`", entry.path().display()))?;
+
+        let name = entry.file_name();
+        if !meta.is_dir() || !name.to_str().is_some_and(&ours) {
+            continue;
+        }
+
+        // a clock that went backwards makes everything look new, which
+        // only means we prune later than we could have.
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age < max_age {
+            continue;
+        }
+
+        let size = size_of(&entry.path());
+        Store::remove_item(&entry.path())?;
+        tracing::debug!(
+            "pruned `{}` ({} bytes, unchanged for {}s)",
+            entry.path().display(),
+            size,
+            age.as_secs()
+        );
+
+        dirs += 1;
+        bytes += size;
+    }
+
+    Ok((dirs, bytes))
+}
+
+/// How many bytes the files below `path` take up. Anything we can't read
+/// counts as empty, since this is only for reporting.
+fn size_of(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prunes_only_stale_leftovers() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let store = temp.path().join("store");
+        let leftover = store.join(format!("{}123", crate::store::TEMP_PREFIX));
+        let item = store.join("not-a-temp-dir");
+        let workspace = temp.path().join("workspaces").join("pool-0000");
+
+        for dir in [&leftover, &item, &workspace] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join("out"), "hello").unwrap();
+        }
+
+        // nothing's old enough yet
+        assert_eq!(
+            (0, 0),
+            stale_dirs(temp.path(), Duration::from_secs(3600)).unwrap()
+        );
+
+        assert_eq!((2, 10), stale_dirs(temp.path(), Duration::ZERO).unwrap());
+        assert!(!leftover.exists());
+        assert!(!workspace.exists());
+        assert!(item.exists());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::export;
+use crate::job;
+use anyhow::{Context, Result};
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+/// A cache of store items shared between machines over HTTP, so CI runners
+/// (and people) don't all have to build the same things. The protocol is
+/// as small as we could make it, so any server that can store files will do:
+///
+/// - `GET <url>/<final key>.tar.zst` gets the item a job produced, or 404s.
+/// - `PUT <url>/<final key>.tar.zst` uploads one.
+///
+/// Items are zstd-compressed tarballs of everything in the store item. We
+/// don't trust the server to tell us the item's hash: downloads are unpacked
+/// into a workspace and stored just like a job's outputs would be.
+#[derive(Debug, Clone)]
+pub struct RemoteCache {
+    url: String,
+    upload: bool,
+    agent: ureq::Agent,
+}
+
+/// Long enough for a slow network, short enough that an unreachable cache
+/// doesn't stall the build for ages. We fall back to running the job.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl RemoteCache {
+    pub fn new(url: &str, upload: bool) -> Self {
+        RemoteCache {
+            url: url.trim_end_matches('/').to_string(),
+            upload,
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(CONNECT_TIMEOUT)
+                .build(),
+        }
+    }
+
+    /// Should we upload items after building them locally?
+    pub fn uploads(&self) -> bool {
+        self.upload
+    }
+
+    fn url_for(&self, key: &job::Key<job::Final>) -> String {
+        format!("{}/{}.tar.zst", self.url, key)
+    }
+
+    /// Download the item for `key` and unpack it into `dest`. Returns `false`
+    /// if the cache doesn't have it.
+    pub fn download(&self, key: &job::Key<job::Final>, dest: &Path) -> Result<bool> {
+        let url = self.url_for(key);
+
+        let response = match self.agent.get(&url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(false),
+            Err(err) => return Err(err).with_context(|| format!("could not get `{}`", url)),
+        };
+
+        let decoder =
+            zstd::Decoder::new(response.into_reader()).context("could not start decompressing")?;
+
+        tar::Archive::new(decoder)
+            .unpack(dest)
+            .with_context(|| format!("could not unpack `{}`", url))?;
+
+        Ok(true)
+    }
+
+    /// Upload the item at `item` as the output for `key`.
+    pub fn upload(&self, key: &job::Key<job::Final>, item: &Path) -> Result<()> {
+        let url = self.url_for(key);
+
+        // we spool to a temporary file so we can send a length up front.
+        // Plenty of simple servers don't understand chunked uploads.
+        let file = tempfile::tempfile().context("could not create a file to upload from")?;
+        let encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)
+            .context("could not start compressing")?;
+        let mut file = export::write_tar(encoder, &[(".".to_string(), item.to_path_buf())])?
+            .finish()
+            .context("could not finish compressing")?;
+
+        let len = file
+            .seek(SeekFrom::End(0))
+            .context("could not get the size of the upload")?;
+        file.rewind().context("could not rewind the upload")?;
+
+        self.agent
+            .put(&url)
+            .set("Content-Type", "application/zstd")
+            .set("Content-Length", &len.to_string())
+            .send(file)
+            .with_context(|| format!("could not upload `{}`", url))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Just enough of an HTTP server to hold onto whatever gets PUT and hand
+    /// it back on GET.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/cache/", listener.local_addr().unwrap());
+        let files: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::default();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut parts = request.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let path = parts.next().unwrap().to_string();
+
+                let mut len = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            len = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+
+                let mut files = files.lock().unwrap();
+                let (status, body) = if method == "PUT" {
+                    let mut body = vec![0; len];
+                    reader.read_exact(&mut body).unwrap();
+                    files.insert(path, body);
+                    ("200 OK", Vec::new())
+                } else {
+                    match files.get(&path) {
+                        Some(body) => ("200 OK", body.clone()),
+                        None => ("404 Not Found", Vec::new()),
+                    }
+                };
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn round_trips_items() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let item = temp.path().join("item");
+        std::fs::create_dir_all(item.join("nested")).unwrap();
+        std::fs::write(item.join("nested/out"), "hello").unwrap();
+
+        let cache = RemoteCache::new(&serve(), true);
+        let key = job::Key::default();
+        let dest = temp.path().join("dest");
+        std::fs::create_dir(&dest).unwrap();
+
+        assert!(!cache.download(&key, &dest).unwrap());
+
+        cache.upload(&key, &item).unwrap();
+        assert!(cache.download(&key, &dest).unwrap());
+
+        assert_eq!(
+            "hello",
+            std::fs::read_to_string(dest.join("nested/out")).unwrap()
+        );
+    }
+}
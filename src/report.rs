@@ -0,0 +1,628 @@
+use crate::events::{self, Event};
+use crate::file_type::FileTypes;
+use crate::ui;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Where in the root dir we keep the last build's report, so `--emit-graph`
+/// can show how it went.
+pub const LAST_BUILD_FILE: &str = "last-build.json";
+
+/// What happened to every job in a build, in a shape that's easy to feed
+/// into CI dashboards. Jobs appear in the order they finished.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Report {
+    /// The `--profile` the build used, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    pub jobs: Vec<JobReport>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobReport {
+    /// Like `cc-3fa2c81e`, as seen in the logs.
+    pub id: String,
+    pub key: String,
+    pub command: String,
+    pub outcome: Outcome,
+
+    /// How long the job ran, or zero for cache hits.
+    pub duration_ms: u64,
+
+    /// Only set if the job succeeded (or was a cache hit.)
+    pub store_path: Option<PathBuf>,
+
+    /// The MIME type of each file in the job's output, by its path in the
+    /// store item, for publishing steps and the like. Left out for jobs
+    /// without outputs, and items stored before we sniffed types.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub output_types: BTreeMap<PathBuf, String>,
+
+    /// Inputs the job never read, so they could come out of its
+    /// definition. Only set for jobs that ran with `--warn-unused-inputs`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unused_inputs: Vec<PathBuf>,
+
+    /// Only set if the job failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Outcome {
+    Cached,
+    Ran,
+    Failed,
+    Skipped,
+    CutShort,
+}
+
+impl Outcome {
+    /// What we call this in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Cached => "cached",
+            Outcome::Ran => "ran",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+            Outcome::CutShort => "cut short",
+        }
+    }
+}
+
+impl Report {
+    /// Build a report from build events, returning once the build is done.
+    pub async fn collect(events: broadcast::Receiver<Event>) -> Report {
+        let mut report = Report::default();
+        events::each(events, |event| report.record(event)).await;
+
+        report
+    }
+
+    /// Read a report we wrote before, or `None` if there isn't one yet.
+    pub fn read(path: &Path) -> Result<Option<Report>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not read `{}`", path.display()))
+            }
+        };
+
+        serde_json::from_slice(&bytes)
+            .with_context(|| format!("could not parse `{}`", path.display()))
+            .map(Some)
+    }
+
+    fn record(&mut self, event: &Event) {
+        let report = match event {
+            Event::JobCached {
+                job,
+                store_path,
+                output_types,
+            } => JobReport {
+                id: job.id.clone(),
+                key: job.key.clone(),
+                command: job.command.clone(),
+                outcome: Outcome::Cached,
+                duration_ms: 0,
+                store_path: Some(store_path.clone()),
+                output_types: mime_types(output_types),
+                unused_inputs: Vec::new(),
+                error: None,
+            },
+            Event::JobSucceeded {
+                job,
+                duration,
+                store_path,
+                output_types,
+                unused_inputs,
+            } => JobReport {
+                id: job.id.clone(),
+                key: job.key.clone(),
+                command: job.command.clone(),
+                outcome: Outcome::Ran,
+                duration_ms: millis(*duration),
+                store_path: Some(store_path.clone()),
+                output_types: mime_types(output_types),
+                unused_inputs: unused_inputs.clone(),
+                error: None,
+            },
+            Event::JobFailed {
+                job,
+                duration,
+                error,
+            } => JobReport {
+                id: job.id.clone(),
+                key: job.key.clone(),
+                command: job.command.clone(),
+                outcome: Outcome::Failed,
+                duration_ms: millis(*duration),
+                store_path: None,
+                output_types: BTreeMap::new(),
+                unused_inputs: Vec::new(),
+                error: Some(error.clone()),
+            },
+            Event::JobSkipped { job } => JobReport {
+                id: job.id.clone(),
+                key: job.key.clone(),
+                command: job.command.clone(),
+                outcome: Outcome::Skipped,
+                duration_ms: 0,
+                store_path: None,
+                output_types: BTreeMap::new(),
+                unused_inputs: Vec::new(),
+                error: None,
+            },
+            Event::JobCutShort { job, duration } => JobReport {
+                id: job.id.clone(),
+                key: job.key.clone(),
+                command: job.command.clone(),
+                outcome: Outcome::CutShort,
+                duration_ms: millis(*duration),
+                store_path: None,
+                output_types: BTreeMap::new(),
+                unused_inputs: Vec::new(),
+                error: None,
+            },
+            Event::BuildStarted { .. }
+            | Event::JobStarted { .. }
+            | Event::JobProgress { .. }
+            | Event::QueueChanged { .. }
+            | Event::BuildFinished { .. } => return,
+        };
+
+        self.jobs.push(report)
+    }
+}
+
+/// How one build did compared to another, for `rbt compare-reports`. Jobs
+/// are matched up by key, so a job whose definition changed between the
+/// builds counts as a new job.
+#[derive(Debug)]
+pub struct Comparison<'a> {
+    /// Cache hits out of jobs that got that far, before and after.
+    pub hit_rates: (Option<f64>, Option<f64>),
+    pub job_counts: (usize, usize),
+
+    /// Jobs that failed in the second build but not the first.
+    pub new_failures: Vec<&'a JobReport>,
+
+    /// Jobs that ran in the second build but were cache hits in the first
+    /// (or weren't in it at all, which the `bool` says.)
+    pub rebuilt: Vec<(&'a JobReport, bool)>,
+
+    /// Jobs that ran in both builds and took at least the threshold longer
+    /// or shorter, biggest change first.
+    pub duration_changes: Vec<(&'a JobReport, &'a JobReport)>,
+}
+
+impl<'a> Comparison<'a> {
+    pub fn new(before: &'a Report, after: &'a Report, threshold: Duration) -> Self {
+        let before_by_key: HashMap<&str, &JobReport> = before
+            .jobs
+            .iter()
+            .map(|job| (job.key.as_str(), job))
+            .collect();
+
+        let mut new_failures = Vec::new();
+        let mut rebuilt = Vec::new();
+        let mut duration_changes = Vec::new();
+
+        for job in &after.jobs {
+            let previous = before_by_key.get(job.key.as_str()).copied();
+
+            match (job.outcome, previous.map(|previous| previous.outcome)) {
+                (Outcome::Failed, Some(Outcome::Failed)) => (),
+                (Outcome::Failed, _) => new_failures.push(job),
+                (Outcome::Ran, None) => rebuilt.push((job, true)),
+                (Outcome::Ran, Some(Outcome::Cached)) => rebuilt.push((job, false)),
+                _ => (),
+            }
+
+            if let Some(previous) = previous
+                .filter(|previous| previous.outcome == Outcome::Ran && job.outcome == Outcome::Ran)
+            {
+                if previous.duration_ms.abs_diff(job.duration_ms) >= millis(threshold) {
+                    duration_changes.push((previous, job));
+                }
+            }
+        }
+
+        new_failures.sort_by(|a, b| a.id.cmp(&b.id));
+        rebuilt.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+        duration_changes.sort_by_key(|(previous, job)| {
+            std::cmp::Reverse(previous.duration_ms.abs_diff(job.duration_ms))
+        });
+
+        Comparison {
+            hit_rates: (before.hit_rate(), after.hit_rate()),
+            job_counts: (before.jobs.len(), after.jobs.len()),
+            new_failures,
+            rebuilt,
+            duration_changes,
+        }
+    }
+
+    /// Markdown, for posting as a comment on a pull request.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::from("### Build comparison\n\n");
+
+        let rate = |rate: Option<f64>| match rate {
+            Some(rate) => format!("{:.1}%", rate * 100.0),
+            None => "n/a".to_string(),
+        };
+        let _ = write!(
+            out,
+            "- Cache hit rate: {} → {}",
+            rate(self.hit_rates.0),
+            rate(self.hit_rates.1)
+        );
+        if let (Some(before), Some(after)) = self.hit_rates {
+            let _ = write!(out, " ({:+.1} points)", (after - before) * 100.0);
+        }
+        let _ = writeln!(
+            out,
+            "\n- Jobs: {} → {}",
+            self.job_counts.0, self.job_counts.1
+        );
+
+        if !self.new_failures.is_empty() {
+            let _ = writeln!(out, "\n#### New failures ({})\n", self.new_failures.len());
+            for job in &self.new_failures {
+                let error = job
+                    .error
+                    .as_deref()
+                    .and_then(|error| error.lines().next())
+                    .unwrap_or("no error recorded");
+                let _ = writeln!(out, "- `{}` ({}): {}", job.id, job.command, error);
+            }
+        }
+
+        if !self.rebuilt.is_empty() {
+            let _ = writeln!(out, "\n#### Rebuilt ({})\n", self.rebuilt.len());
+            for (job, new) in &self.rebuilt {
+                let _ = writeln!(
+                    out,
+                    "- `{}` ({}) in {}{}",
+                    job.id,
+                    job.command,
+                    ui::elapsed(Duration::from_millis(job.duration_ms)),
+                    if *new { ", new or changed" } else { "" },
+                );
+            }
+        }
+
+        if !self.duration_changes.is_empty() {
+            let _ = writeln!(
+                out,
+                "\n#### Duration changes ({})\n\n| Job | Before | After | Change |\n| --- | --- | --- | --- |",
+                self.duration_changes.len()
+            );
+            for (previous, job) in &self.duration_changes {
+                let change = Duration::from_millis(previous.duration_ms.abs_diff(job.duration_ms));
+                let _ = writeln!(
+                    out,
+                    "| `{}` | {} | {} | {}{} |",
+                    job.id,
+                    ui::elapsed(Duration::from_millis(previous.duration_ms)),
+                    ui::elapsed(Duration::from_millis(job.duration_ms)),
+                    if job.duration_ms > previous.duration_ms {
+                        "+"
+                    } else {
+                        "-"
+                    },
+                    ui::elapsed(change),
+                );
+            }
+        }
+
+        if self.new_failures.is_empty()
+            && self.rebuilt.is_empty()
+            && self.duration_changes.is_empty()
+        {
+            out.push_str("\nNothing else changed.\n");
+        }
+
+        out
+    }
+}
+
+impl Report {
+    /// How each of `tests` went, for `rbt test`: one line per test, then
+    /// how many there were of each. Tests that didn't get to run (because
+    /// something they need failed) count as not run.
+    pub fn test_results(&self, tests: &[events::JobInfo]) -> String {
+        let by_key: HashMap<&str, &JobReport> = self
+            .jobs
+            .iter()
+            .map(|job| (job.key.as_str(), job))
+            .collect();
+
+        let mut out = String::new();
+        let (mut passed, mut cached, mut failed, mut not_run) = (0, 0, 0, 0);
+        for test in tests {
+            let _ = match by_key.get(test.key.as_str()).map(|job| (job.outcome, job)) {
+                Some((Outcome::Ran, job)) => {
+                    passed += 1;
+                    writeln!(
+                        out,
+                        "passed       {} ({}) in {}",
+                        test.id,
+                        test.command,
+                        ui::elapsed(Duration::from_millis(job.duration_ms))
+                    )
+                }
+                Some((Outcome::Cached, _)) => {
+                    cached += 1;
+                    writeln!(out, "cached pass  {} ({})", test.id, test.command)
+                }
+                Some((Outcome::Failed, job)) => {
+                    failed += 1;
+                    let error = job
+                        .error
+                        .as_deref()
+                        .and_then(|error| error.lines().next())
+                        .unwrap_or("no error recorded");
+                    writeln!(
+                        out,
+                        "failed       {} ({}): {}",
+                        test.id, test.command, error
+                    )
+                }
+                Some((Outcome::Skipped | Outcome::CutShort, _)) | None => {
+                    not_run += 1;
+                    writeln!(out, "not run      {} ({})", test.id, test.command)
+                }
+            };
+        }
+
+        let _ = write!(
+            out,
+            "\n{} passed ({} cached), {} failed, {} not run",
+            passed + cached,
+            cached,
+            failed,
+            not_run
+        );
+
+        out
+    }
+
+    /// What fraction of the jobs that finished were cache hits, or `None`
+    /// if nothing finished.
+    fn hit_rate(&self) -> Option<f64> {
+        let cached = self
+            .jobs
+            .iter()
+            .filter(|job| job.outcome == Outcome::Cached)
+            .count();
+        let finished = self
+            .jobs
+            .iter()
+            .filter(|job| matches!(job.outcome, Outcome::Cached | Outcome::Ran))
+            .count();
+
+        (finished > 0).then(|| cached as f64 / finished as f64)
+    }
+}
+
+// serde_json can't do u128, and nothing runs for 500 million years anyway.
+fn mime_types(file_types: &FileTypes) -> BTreeMap<PathBuf, String> {
+    file_types
+        .iter()
+        .map(|(path, file_type)| (path.clone(), file_type.mime_type().to_string()))
+        .collect()
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{Bus, JobInfo};
+
+    #[tokio::test]
+    async fn collects_finished_jobs_from_events() {
+        let job = JobInfo {
+            id: "cc-abc".to_string(),
+            key: "abc".to_string(),
+            command: "cc main.c".to_string(),
+        };
+
+        // a recorded build, as it would come off the bus
+        let recorded = serde_json::json!([
+            {"event": "jobStarted", "job": job},
+            {"event": "jobSucceeded", "job": job, "duration": {"secs": 1, "nanos": 500_000_000}, "storePath": "/store/abc"},
+            {"event": "buildFinished", "succeeded": true},
+        ]);
+
+        let bus = Bus::new();
+        let report = tokio::spawn(Report::collect(bus.subscribe()));
+        for event in serde_json::from_value::<Vec<Event>>(recorded).unwrap() {
+            bus.publish(event);
+        }
+
+        let report = report.await.unwrap();
+        assert_eq!(1, report.jobs.len());
+        assert_eq!(Outcome::Ran, report.jobs[0].outcome);
+        assert_eq!(1500, report.jobs[0].duration_ms);
+    }
+
+    #[tokio::test]
+    async fn tells_jobs_cut_short_from_skipped_ones() {
+        let job = |key: &str| JobInfo {
+            id: format!("cc-{}", key),
+            key: key.to_string(),
+            command: format!("cc {}.c", key),
+        };
+
+        let bus = Bus::new();
+        let report = tokio::spawn(Report::collect(bus.subscribe()));
+        bus.publish(Event::JobCutShort {
+            job: job("a"),
+            duration: Duration::from_secs(60),
+        });
+        bus.publish(Event::JobSkipped { job: job("b") });
+        bus.publish(Event::BuildFinished { succeeded: false });
+
+        let report = report.await.unwrap();
+        assert_eq!(
+            vec![(Outcome::CutShort, 60_000), (Outcome::Skipped, 0)],
+            report
+                .jobs
+                .iter()
+                .map(|job| (job.outcome, job.duration_ms))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    fn job(key: &str, outcome: Outcome, duration_ms: u64) -> JobReport {
+        JobReport {
+            id: format!("cc-{}", key),
+            key: key.to_string(),
+            command: format!("cc {}.c", key),
+            outcome,
+            duration_ms,
+            store_path: None,
+            output_types: BTreeMap::new(),
+            unused_inputs: Vec::new(),
+            error: (outcome == Outcome::Failed).then(|| "command failed\nmore".to_string()),
+        }
+    }
+
+    #[test]
+    fn reports_how_each_test_went() {
+        let report = Report {
+            profile: None,
+            jobs: vec![
+                job("a", Outcome::Cached, 0),
+                job("b", Outcome::Ran, 1500),
+                job("c", Outcome::Failed, 10),
+                job("lib", Outcome::Ran, 1000),
+            ],
+        };
+        let tests: Vec<JobInfo> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|key| JobInfo {
+                id: format!("cc-{}", key),
+                key: key.to_string(),
+                command: format!("cc {}.c", key),
+            })
+            .collect();
+
+        assert_eq!(
+            "cached pass  cc-a (cc a.c)\n\
+             passed       cc-b (cc b.c) in 1.5s\n\
+             failed       cc-c (cc c.c): command failed\n\
+             not run      cc-d (cc d.c)\n\
+             \n\
+             2 passed (1 cached), 1 failed, 1 not run",
+            report.test_results(&tests)
+        );
+    }
+
+    #[test]
+    fn compares_builds_by_key() {
+        let before = Report {
+            profile: None,
+            jobs: vec![
+                job("a", Outcome::Cached, 0),
+                job("b", Outcome::Ran, 1000),
+                job("c", Outcome::Ran, 1000),
+                job("d", Outcome::Failed, 10),
+            ],
+        };
+        let after = Report {
+            profile: None,
+            jobs: vec![
+                job("a", Outcome::Ran, 500),
+                job("b", Outcome::Ran, 3000),
+                job("c", Outcome::Ran, 1100),
+                job("d", Outcome::Failed, 10),
+                job("e", Outcome::Failed, 10),
+                job("f", Outcome::Ran, 200),
+            ],
+        };
+
+        let comparison = Comparison::new(&before, &after, Duration::from_millis(500));
+
+        let ids = |jobs: Vec<&JobReport>| jobs.iter().map(|job| job.id.clone()).collect::<Vec<_>>();
+        assert_eq!(vec!["cc-e"], ids(comparison.new_failures.clone()));
+        assert_eq!(
+            vec![("cc-a".to_string(), false), ("cc-f".to_string(), true)],
+            comparison
+                .rebuilt
+                .iter()
+                .map(|(job, new)| (job.id.clone(), *new))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["cc-b"],
+            ids(comparison
+                .duration_changes
+                .iter()
+                .map(|(_, job)| *job)
+                .collect())
+        );
+        assert_eq!((Some(1.0 / 3.0), Some(0.0)), comparison.hit_rates);
+
+        let markdown = comparison.to_markdown();
+        assert!(
+            markdown.contains("33.3% → 0.0% (-33.3 points)"),
+            "{}",
+            markdown
+        );
+        assert!(
+            markdown.contains("- `cc-e` (cc e.c): command failed\n"),
+            "{}",
+            markdown
+        );
+        assert!(
+            markdown.contains("| `cc-b` | 1.0s | 3.0s | +2.0s |"),
+            "{}",
+            markdown
+        );
+    }
+
+    #[test]
+    fn serializes_for_dashboards() {
+        let report = Report {
+            profile: None,
+            jobs: vec![JobReport {
+                id: "false-abc".to_string(),
+                key: "abc".to_string(),
+                command: "false".to_string(),
+                outcome: Outcome::Failed,
+                duration_ms: 12,
+                store_path: None,
+                output_types: BTreeMap::new(),
+                unused_inputs: Vec::new(),
+                error: Some("command failed with the exit code 1".to_string()),
+            }],
+        };
+
+        assert_eq!(
+            serde_json::json!({
+                "jobs": [{
+                    "id": "false-abc",
+                    "key": "abc",
+                    "command": "false",
+                    "outcome": "failed",
+                    "durationMs": 12,
+                    "storePath": null,
+                    "error": "command failed with the exit code 1",
+                }]
+            }),
+            serde_json::to_value(&report).unwrap()
+        );
+    }
+}
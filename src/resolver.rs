@@ -0,0 +1,648 @@
+use crate::store::Store;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// A request for files from some system rbt doesn't know about natively (an
+/// internal blob store, Perforce, etc.) Both fields come straight from the
+/// Roc side: `resolver` picks the plugin and `spec` is JSON that only the
+/// plugin needs to understand. See `docs/adrs/012-input-resolvers.md` for the
+/// protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Spec {
+    pub resolver: String,
+    pub spec: String,
+}
+
+/// The one resolver rbt handles itself, for the common case of fetching an
+/// archive, checking it against a known hash, and unpacking it. The spec is
+/// `{"url": ..., "sha256": ...}`.
+pub const ARCHIVE: &str = "archive";
+
+impl Spec {
+    /// Run the resolver plugin for this spec and move whatever it produces
+    /// into the resolved-inputs cache below `root`. Jobs get these files
+    /// through links, just like store items, so they're read-only once
+    /// they're in the cache.
+    pub async fn resolve(&self, root: &Path) -> Result<Resolved> {
+        if self.resolver.is_empty()
+            || !self
+                .resolver
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!(
+                "`{}` is not a valid resolver name. Resolver names can only contain ASCII letters, numbers, `-`, and `_`.",
+                self.resolver
+            )
+        }
+
+        let resolved = if self.resolver == ARCHIVE {
+            self.resolve_archive(root).await?
+        } else {
+            self.resolve_with(format!("rbt-resolver-{}", self.resolver).as_ref(), root)
+                .await?
+        };
+
+        resolved.mark_used();
+        Ok(resolved)
+    }
+
+    async fn resolve_with(&self, program: &OsStr, root: &Path) -> Result<Resolved> {
+        let spec: serde_json::Value = serde_json::from_str(&self.spec)
+            .with_context(|| format!("the spec for {} was not valid JSON", self))?;
+
+        let cache_dir = root.join(&self.resolver);
+        std::fs::create_dir_all(&cache_dir)
+            .context("could not create resolved input cache directory")?;
+
+        // if the plugin fails, dropping this cleans up whatever it left behind
+        let staging = tempfile::Builder::new()
+            .prefix("tmp-")
+            .tempdir_in(root)
+            .context("could not create staging directory")?;
+
+        let request = serde_json::json!({
+            "spec": spec,
+            "staging_dir": staging.path(),
+            "cache_dir": cache_dir,
+        });
+
+        tracing::debug!("resolving {}", self);
+        let mut child = Command::new(program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| {
+                format!(
+                    "could not run `{}`. Is it installed and on your PATH?",
+                    program.to_string_lossy()
+                )
+            })?;
+
+        let mut stdin = child.stdin.take().context("could not get resolver stdin")?;
+        stdin
+            .write_all(request.to_string().as_bytes())
+            .await
+            .context("could not send request to resolver")?;
+        drop(stdin);
+
+        let output = child
+            .wait_with_output()
+            .await
+            .context("could not wait for resolver to finish")?;
+
+        if !output.status.success() {
+            anyhow::bail!("resolver for {} failed with {}", self, output.status)
+        }
+
+        let response: Response = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("could not understand the response to {}", self))?;
+
+        // the hash becomes a directory name, so it has to be safe to use as one
+        if response.hash.is_empty()
+            || response.hash.len() > 128
+            || !response
+                .hash
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!(
+                "resolver for {} reported `{}` as the content hash, but hashes must be 1 to 128 ASCII letters, numbers, `-`, or `_`",
+                self,
+                response.hash,
+            )
+        }
+
+        let path = cache_dir.join(&response.hash);
+        if path.exists() {
+            tracing::debug!(
+                "already had {} for {}, so I'm discarding the new copy",
+                response.hash,
+                self
+            );
+        } else {
+            std::fs::rename(staging.into_path(), &path).with_context(|| {
+                format!("could not move resolved files into `{}`", path.display())
+            })?;
+
+            Store::make_readonly(&path)
+                .await
+                .context("could not make resolved files read-only")?;
+        }
+
+        Ok(Resolved {
+            hash: response.hash,
+            path,
+        })
+    }
+}
+
+impl Spec {
+    /// The spec for fetching `url` with the built-in archive resolver. We
+    /// build the JSON here instead of in Roc so URLs with quotes or
+    /// backslashes in them can't change what the spec says.
+    pub fn archive(url: &str, sha256: &str) -> Self {
+        Spec {
+            resolver: ARCHIVE.into(),
+            spec: serde_json::to_string(&ArchiveSpec {
+                url: url.to_string(),
+                sha256: sha256.to_string(),
+            })
+            .expect("a struct of strings always serializes"),
+        }
+    }
+
+    async fn resolve_archive(&self, root: &Path) -> Result<Resolved> {
+        let spec: ArchiveSpec = serde_json::from_str(&self.spec).with_context(|| {
+            format!(
+                "{} needs to be a JSON object with `url` and `sha256` fields",
+                self
+            )
+        })?;
+
+        let sha256 = spec.sha256.to_ascii_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!(
+                "`{}` is not a SHA-256 hash (it should be 64 hex digits) in {}",
+                spec.sha256,
+                self
+            )
+        }
+
+        // the URL is only needed the first time: once we've unpacked an
+        // archive with this hash, we never look at the network again.
+        let path = root.join(ARCHIVE).join(&sha256);
+        if path.exists() {
+            return Ok(Resolved { hash: sha256, path });
+        }
+
+        let format = ArchiveFormat::from_url(&spec.url)?;
+
+        std::fs::create_dir_all(root.join(ARCHIVE))
+            .context("could not create resolved input cache directory")?;
+
+        // downloading and unpacking both block, and can take a while
+        let staging = {
+            let root = root.to_path_buf();
+            let sha256 = sha256.clone();
+            tokio::task::spawn_blocking(move || fetch_and_unpack(&spec, &sha256, format, &root))
+                .await
+                .context("could not join archive fetching task")??
+        };
+
+        std::fs::rename(staging.into_path(), &path)
+            .with_context(|| format!("could not move unpacked files into `{}`", path.display()))?;
+
+        Store::make_readonly(&path)
+            .await
+            .context("could not make unpacked files read-only")?;
+
+        Ok(Resolved { hash: sha256, path })
+    }
+}
+
+/// Download the archive `spec` points to into a staging directory below
+/// `root`, check that it has the hash we expect, and unpack it. If anything
+/// goes wrong, dropping the staging directory cleans up after us.
+fn fetch_and_unpack(
+    spec: &ArchiveSpec,
+    sha256: &str,
+    format: ArchiveFormat,
+    root: &Path,
+) -> Result<tempfile::TempDir> {
+    tracing::info!("fetching {}", spec.url);
+    let mut download = tempfile::NamedTempFile::new_in(root)
+        .context("could not create a temporary file to download into")?;
+    let actual = fetch(&spec.url, download.as_file_mut())?;
+    if actual != sha256 {
+        anyhow::bail!(
+            "`{}` has the SHA-256 hash {}, but I expected {}. If the new archive is the one you want, update the hash.",
+            spec.url,
+            actual,
+            sha256
+        )
+    }
+
+    // if unpacking fails, dropping this cleans up whatever got unpacked
+    let staging = tempfile::Builder::new()
+        .prefix("tmp-")
+        .tempdir_in(root)
+        .context("could not create staging directory")?;
+
+    let mut file = download.reopen().context("could not reopen download")?;
+    file.rewind().context("could not rewind download")?;
+    format
+        .unpack(file, staging.path())
+        .with_context(|| format!("could not unpack `{}`", spec.url))?;
+
+    Ok(staging)
+}
+
+/// Remove the resolved inputs below `root` that no build has used in
+/// `max_age`. Resolving marks what it hands out as used (see
+/// `Resolved::mark_used`), whether it was already here or not, so these
+/// are the ones no current build definition asks for any more. Returns how
+/// many we removed.
+pub fn collect_garbage(root: &Path, max_age: Duration) -> Result<usize> {
+    let resolvers = match std::fs::read_dir(root) {
+        Ok(resolvers) => resolvers,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => {
+            return Err(err).with_context(|| format!("could not read `{}`", root.display()))
+        }
+    };
+
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for resolver in resolvers {
+        let resolver = resolver.with_context(|| format!("could not read `{}`", root.display()))?;
+
+        // staging directories live next to the resolvers' caches, and
+        // `rbt --daemon` prunes the ones killed builds leave behind.
+        let name = resolver.file_name();
+        if name
+            .to_string_lossy()
+            .starts_with(crate::store::TEMP_PREFIX)
+            || !resolver.path().is_dir()
+        {
+            continue;
+        }
+
+        for entry in std::fs::read_dir(resolver.path())
+            .with_context(|| format!("could not read `{}`", resolver.path().display()))?
+        {
+            let entry = entry.context("could not read resolved input")?;
+            let meta = entry.metadata().with_context(|| {
+                format!("could not get metadata for `{}`", entry.path().display())
+            })?;
+
+            // a clock that went backwards makes everything look new, which
+            // only means we collect it later than we could have.
+            let unused_for = meta
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+            if unused_for < max_age {
+                continue;
+            }
+
+            Store::remove_item(&entry.path())?;
+            tracing::debug!("removed resolved input `{}`", entry.path().display());
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+impl Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} input {}", self.resolver, self.spec)
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Response {
+    hash: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ArchiveSpec {
+    url: String,
+    sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    fn from_url(url: &str) -> Result<Self> {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+
+        if path.ends_with(".tar") {
+            Ok(ArchiveFormat::Tar)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar.zst") || path.ends_with(".tzst") {
+            Ok(ArchiveFormat::TarZst)
+        } else {
+            anyhow::bail!(
+                "I don't know how to unpack `{}`. I can unpack .tar, .tar.gz, .tgz, and .tar.zst archives.",
+                url
+            )
+        }
+    }
+
+    fn unpack(self, file: File, dest: &Path) -> Result<()> {
+        match self {
+            ArchiveFormat::Tar => tar::Archive::new(file).unpack(dest)?,
+            ArchiveFormat::TarGz => {
+                tar::Archive::new(flate2::read::GzDecoder::new(file)).unpack(dest)?
+            }
+            ArchiveFormat::TarZst => tar::Archive::new(
+                zstd::Decoder::new(file).context("could not start decompressing")?,
+            )
+            .unpack(dest)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Copy whatever is at `url` into `dest`, returning its SHA-256 hash. Plain
+/// paths in `file://` URLs work too, for archives on a shared drive.
+fn fetch(url: &str, dest: &mut File) -> Result<String> {
+    let mut source: Box<dyn Read> = match url.strip_prefix("file://") {
+        Some(path) => {
+            Box::new(File::open(path).with_context(|| format!("could not open `{}`", path))?)
+        }
+        None => ureq::get(url)
+            .call()
+            .with_context(|| format!("could not get `{}`", url))?
+            .into_reader(),
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = source
+            .read(&mut buf)
+            .with_context(|| format!("could not download `{}`", url))?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..read]);
+        dest.write_all(&buf[..read])
+            .context("could not write download to disk")?;
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Files a resolver produced, along with the hash it reported for them.
+#[derive(Debug)]
+pub struct Resolved {
+    hash: String,
+    path: PathBuf,
+}
+
+impl Resolved {
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Record that a build used these files, so `collect_garbage` keeps
+    /// them. We use the directory's modification time for this: nothing
+    /// else changes it, since everything in the cache is read-only.
+    fn mark_used(&self) {
+        let marked = File::open(&self.path).and_then(|dir| dir.set_modified(SystemTime::now()));
+        if let Err(problem) = marked {
+            // the worst that happens is GC removes them and we resolve
+            // them again next time
+            tracing::debug!(
+                "could not mark `{}` as used: {}",
+                self.path.display(),
+                problem
+            );
+        }
+    }
+}
+
+impl std::ops::Deref for Resolved {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &Self::Target {
+        &self.path
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    /// Write a resolver that pulls the staging dir out of its request (with
+    /// sed, to avoid depending on jq) and then runs `body`.
+    fn plugin(temp: &TempDir, body: &str) -> PathBuf {
+        let path = temp.path().join("rbt-resolver-test");
+        std::fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\nSTAGING=\"$(sed -E 's/.*\"staging_dir\":\"([^\"]*)\".*/\\1/')\"\n{}\n",
+                body
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        path
+    }
+
+    fn spec() -> Spec {
+        Spec {
+            resolver: "test".into(),
+            spec: "{\"version\": 1}".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn moves_files_into_cache_by_reported_hash() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("resolved");
+        let program = plugin(
+            &temp,
+            "echo hello > \"$STAGING/greeting\"\necho '{\"hash\": \"abc123\"}'",
+        );
+
+        let resolved = spec().resolve_with(program.as_ref(), &root).await.unwrap();
+
+        assert_eq!(resolved.hash(), "abc123");
+        assert_eq!(*resolved, root.join("test/abc123"));
+        assert_eq!(
+            std::fs::read_to_string(resolved.join("greeting")).unwrap(),
+            "hello\n"
+        );
+
+        // jobs link these into their workspaces, so they must not be able
+        // to change them
+        assert!(std::fs::metadata(resolved.join("greeting"))
+            .unwrap()
+            .permissions()
+            .readonly());
+        assert!(std::fs::metadata(&*resolved)
+            .unwrap()
+            .permissions()
+            .readonly());
+
+        // clean up, since TempDir can't remove read-only directories
+        Store::remove_item(&resolved).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_existing_files_for_known_hash() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("resolved");
+        std::fs::create_dir_all(root.join("test/abc123")).unwrap();
+        std::fs::write(root.join("test/abc123/greeting"), "first").unwrap();
+
+        let program = plugin(
+            &temp,
+            "echo second > \"$STAGING/greeting\"\necho '{\"hash\": \"abc123\"}'",
+        );
+
+        let resolved = spec().resolve_with(program.as_ref(), &root).await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(resolved.join("greeting")).unwrap(),
+            "first"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unsafe_hashes() {
+        let temp = TempDir::new().unwrap();
+        let program = plugin(&temp, "echo '{\"hash\": \"../escape\"}'");
+
+        assert!(spec()
+            .resolve_with(program.as_ref(), &temp.path().join("resolved"))
+            .await
+            .is_err());
+    }
+
+    /// Write a gzipped tarball holding `lib/greeting` and return its path
+    /// and SHA-256 hash.
+    fn tarball(temp: &TempDir) -> (PathBuf, String) {
+        let path = temp.path().join("greeting-1.0.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "lib/greeting", "hello\n".as_bytes())
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let hash = format!("{:x}", Sha256::digest(std::fs::read(&path).unwrap()));
+        (path, hash)
+    }
+
+    fn archive_spec(path: &Path, sha256: &str) -> Spec {
+        Spec {
+            resolver: ARCHIVE.into(),
+            spec: serde_json::json!({
+                "url": format!("file://{}", path.display()),
+                "sha256": sha256,
+            })
+            .to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn unpacks_archives_by_hash() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("resolved");
+        let (path, hash) = tarball(&temp);
+
+        let resolved = archive_spec(&path, &hash).resolve(&root).await.unwrap();
+
+        assert_eq!(resolved.hash(), hash);
+        assert_eq!(*resolved, root.join("archive").join(&hash));
+        assert_eq!(
+            std::fs::read_to_string(resolved.join("lib/greeting")).unwrap(),
+            "hello\n"
+        );
+
+        // once unpacked, we don't need the archive any more
+        std::fs::remove_file(&path).unwrap();
+        let again = archive_spec(&path, &hash).resolve(&root).await.unwrap();
+        assert_eq!(*again, *resolved);
+
+        assert!(std::fs::metadata(resolved.join("lib/greeting"))
+            .unwrap()
+            .permissions()
+            .readonly());
+
+        // clean up, since TempDir can't remove read-only directories
+        Store::remove_item(&resolved).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_archives_that_do_not_match_the_hash() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("resolved");
+        let (path, _) = tarball(&temp);
+        let wrong = "0".repeat(64);
+
+        assert!(archive_spec(&path, &wrong).resolve(&root).await.is_err());
+        assert!(!root.join("archive").join(&wrong).exists());
+    }
+
+    #[tokio::test]
+    async fn collects_resolved_inputs_nobody_used_recently() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("resolved");
+        let program = plugin(
+            &temp,
+            "echo hello > \"$STAGING/greeting\"\necho '{\"hash\": \"abc123\"}'",
+        );
+        let used = spec().resolve_with(program.as_ref(), &root).await.unwrap();
+
+        let old = root.join("test/old");
+        std::fs::create_dir_all(&old).unwrap();
+        File::open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60 * 60))
+            .unwrap();
+
+        // builds in progress stage their files here; pruning those is
+        // someone else's job
+        let staging = root.join("tmp-abc");
+        std::fs::create_dir_all(&staging).unwrap();
+        File::open(&staging)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60 * 60))
+            .unwrap();
+
+        assert_eq!(collect_garbage(&root, Duration::from_secs(60)).unwrap(), 1);
+        assert!(!old.exists());
+        assert!(staging.exists());
+        assert!(used.exists());
+
+        // clean up, since TempDir can't remove read-only directories
+        Store::remove_item(&used).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_when_plugin_fails() {
+        let temp = TempDir::new().unwrap();
+        let program = plugin(&temp, "exit 1");
+
+        assert!(spec()
+            .resolve_with(program.as_ref(), &temp.path().join("resolved"))
+            .await
+            .is_err());
+    }
+}
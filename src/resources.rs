@@ -0,0 +1,320 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// A system resource declared on the command line, like `kvm=4` (four
+/// interchangeable slots, numbered 0 through 3) or `port=8000-8009` (ten
+/// ports, each handed out by number.)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    name: String,
+    identities: Vec<String>,
+}
+
+impl FromStr for Declaration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, capacity) = s
+            .split_once('=')
+            .context("resources should look like `NAME=COUNT` or `NAME=START-END`")?;
+
+        if name.is_empty() {
+            anyhow::bail!("resource names can't be empty")
+        }
+
+        let identities: Vec<String> = match capacity.split_once('-') {
+            Some((start, end)) => {
+                let start: u64 = start
+                    .parse()
+                    .with_context(|| format!("`{}` is not a number", start))?;
+                let end: u64 = end
+                    .parse()
+                    .with_context(|| format!("`{}` is not a number", end))?;
+
+                (start..=end).map(|id| id.to_string()).collect()
+            }
+            None => {
+                let count: u64 = capacity
+                    .parse()
+                    .with_context(|| format!("`{}` is not a number", capacity))?;
+
+                (0..count).map(|id| id.to_string()).collect()
+            }
+        };
+
+        if identities.is_empty() {
+            anyhow::bail!("`{}` needs a capacity of at least one", name)
+        }
+
+        Ok(Declaration {
+            name: name.to_string(),
+            identities,
+        })
+    }
+}
+
+/// Tracks which units of each system resource are free. Jobs that ask for a
+/// resource nobody declared get exclusive access to it, so `port:5432` works
+/// as a lock without any setup.
+#[derive(Debug, Clone, Default)]
+pub struct Resources {
+    free: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    capacities: HashMap<String, usize>,
+}
+
+impl Resources {
+    pub fn new(declarations: &[Declaration]) -> Self {
+        let mut free = HashMap::with_capacity(declarations.len());
+        let mut capacities = HashMap::with_capacity(declarations.len());
+
+        for declaration in declarations {
+            // we hand out identities from the end, so reverse to start with
+            // the lowest (for ports, that's the one people expect first.)
+            let mut identities = declaration.identities.clone();
+            identities.reverse();
+
+            capacities.insert(declaration.name.clone(), identities.len());
+            free.insert(declaration.name.clone(), identities);
+        }
+
+        Resources {
+            free: Arc::new(Mutex::new(free)),
+            capacities,
+        }
+    }
+
+    /// Try to get one unit of each requested resource. We only acquire
+    /// resources all at once (or not at all) so that two jobs can't each hold
+    /// half of what the other needs.
+    ///
+    /// Returns `Ok(None)` if the resources are busy right now, and an error
+    /// if they never could be free.
+    pub fn try_acquire(&self, requests: &[String]) -> Result<Option<Allocation>> {
+        let mut wanted: HashMap<&str, usize> = HashMap::with_capacity(requests.len());
+        for request in requests {
+            *wanted.entry(request).or_default() += 1;
+        }
+
+        for (name, count) in &wanted {
+            let capacity = self.capacities.get(*name).copied().unwrap_or(1);
+            if *count > capacity {
+                anyhow::bail!(
+                    "a job needs {} units of `{}`, but there are only {}",
+                    count,
+                    name,
+                    capacity
+                )
+            }
+        }
+
+        let mut free = self
+            .free
+            .lock()
+            .map_err(|_| anyhow::anyhow!("resource lock was poisoned"))?;
+
+        for (name, count) in &wanted {
+            if self.available(&free, name) < *count {
+                return Ok(None);
+            }
+        }
+
+        let mut held = Vec::with_capacity(requests.len());
+        for request in requests {
+            let identities = free
+                .entry(request.clone())
+                .or_insert_with(|| vec!["0".to_string()]);
+
+            let identity = identities
+                .pop()
+                .context("resource was free a moment ago but isn't now. This is a bug in rbt, please file it!")?;
+
+            held.push((request.clone(), identity));
+        }
+
+        Ok(Some(Allocation {
+            free: self.free.clone(),
+            held,
+        }))
+    }
+
+    /// Which of `requests` don't have enough free units right now, for
+    /// telling people why a job is waiting.
+    pub fn busy(&self, requests: &[String]) -> Result<Vec<String>> {
+        let mut wanted: BTreeMap<&str, usize> = BTreeMap::new();
+        for request in requests {
+            *wanted.entry(request).or_default() += 1;
+        }
+
+        let free = self
+            .free
+            .lock()
+            .map_err(|_| anyhow::anyhow!("resource lock was poisoned"))?;
+
+        Ok(wanted
+            .into_iter()
+            .filter(|(name, count)| self.available(&free, name) < *count)
+            .map(|(name, _)| name.to_string())
+            .collect())
+    }
+
+    fn available(&self, free: &HashMap<String, Vec<String>>, name: &str) -> usize {
+        match free.get(name) {
+            Some(identities) => identities.len(),
+            None if self.capacities.contains_key(name) => 0,
+            None => 1,
+        }
+    }
+}
+
+/// Resources held by a running job. They go back to the pool when this is
+/// dropped, whether or not the job succeeded.
+#[derive(Debug)]
+pub struct Allocation {
+    free: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    held: Vec<(String, String)>,
+}
+
+impl Allocation {
+    /// Environment variables telling the job which units it got, like
+    /// `RBT_RESOURCE_PORT=8003`. If a job asks for the same resource more
+    /// than once, the identities are separated by commas.
+    pub fn env(&self) -> HashMap<String, String> {
+        let mut env: HashMap<String, String> = HashMap::with_capacity(self.held.len());
+
+        for (name, identity) in &self.held {
+            let var = format!(
+                "RBT_RESOURCE_{}",
+                name.chars()
+                    .map(|c| if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    })
+                    .collect::<String>()
+            );
+
+            env.entry(var)
+                .and_modify(|existing| {
+                    existing.push(',');
+                    existing.push_str(identity)
+                })
+                .or_insert_with(|| identity.clone());
+        }
+
+        env
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        match self.free.lock() {
+            Ok(mut free) => {
+                for (name, identity) in self.held.drain(..) {
+                    free.entry(name).or_default().push(identity);
+                }
+            }
+            Err(_) => tracing::error!("could not release resources because the lock was poisoned"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn requests(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_declarations() {
+        assert_eq!(
+            Declaration::from_str("kvm=2").unwrap().identities,
+            vec!["0", "1"]
+        );
+        assert_eq!(
+            Declaration::from_str("port=8000-8002").unwrap().identities,
+            vec!["8000", "8001", "8002"]
+        );
+        assert!(Declaration::from_str("kvm").is_err());
+        assert!(Declaration::from_str("kvm=0").is_err());
+    }
+
+    #[test]
+    fn undeclared_resources_are_exclusive() {
+        let resources = Resources::default();
+
+        let first = resources.try_acquire(&requests(&["port:5432"])).unwrap();
+        assert!(first.is_some());
+        assert!(resources
+            .try_acquire(&requests(&["port:5432"]))
+            .unwrap()
+            .is_none());
+
+        drop(first);
+        assert!(resources
+            .try_acquire(&requests(&["port:5432"]))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn reports_busy_resources() {
+        let resources = Resources::new(&[Declaration::from_str("kvm=1").unwrap()]);
+
+        let _held = resources.try_acquire(&requests(&["kvm"])).unwrap();
+
+        assert_eq!(
+            vec!["kvm".to_string()],
+            resources.busy(&requests(&["kvm", "port:5432"])).unwrap()
+        );
+    }
+
+    #[test]
+    fn hands_out_identities_from_ranges() {
+        let resources = Resources::new(&[Declaration::from_str("port=8000-8001").unwrap()]);
+
+        let first = resources
+            .try_acquire(&requests(&["port"]))
+            .unwrap()
+            .unwrap();
+        let second = resources
+            .try_acquire(&requests(&["port"]))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(first.env().get("RBT_RESOURCE_PORT").unwrap(), "8000");
+        assert_eq!(second.env().get("RBT_RESOURCE_PORT").unwrap(), "8001");
+        assert!(resources
+            .try_acquire(&requests(&["port"]))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn acquires_all_or_nothing() {
+        let resources = Resources::new(&[Declaration::from_str("kvm=1").unwrap()]);
+
+        let _kvm = resources.try_acquire(&requests(&["kvm"])).unwrap().unwrap();
+
+        assert!(resources
+            .try_acquire(&requests(&["gpu", "kvm"]))
+            .unwrap()
+            .is_none());
+
+        // we shouldn't have kept the gpu when we couldn't get kvm
+        assert!(resources
+            .try_acquire(&requests(&["gpu"]))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_requests_over_capacity() {
+        let resources = Resources::new(&[Declaration::from_str("kvm=1").unwrap()]);
+
+        assert!(resources.try_acquire(&requests(&["kvm", "kvm"])).is_err());
+    }
+}
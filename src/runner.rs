@@ -1,68 +1,952 @@
+use crate::diagnostics::Capture;
+use crate::events::{Bus, JobInfo};
+use crate::file_trace;
 use crate::job::{self, Job};
+use crate::limits;
+use crate::logs::{self, JobLog, Logs};
+use crate::network;
+use crate::output_filter::Filter;
+use crate::progress;
+use crate::resolver;
+use crate::resources::Allocation;
+use crate::snapshot::Snapshot;
+use crate::status::Status;
 use crate::store;
-use crate::workspace::Workspace;
+use crate::tools;
+use crate::worker;
+use crate::workspace::{self, Workspace};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use path_absolutize::Absolutize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::process::Command;
 
+/// Once we're starting at least this many jobs per second, it's worth it to
+/// start recycling workspaces instead of creating new ones for every job.
+/// `workspace::tests::pooling_benchmark` measured a fresh workspace costing
+/// about 250-290µs of each job's start and finish, against under 20µs for a
+/// pooled one. At this rate, that's the pool saving 1% of every second. Below
+/// it, the saving isn't worth keeping idle workspaces and a clearing thread
+/// around.
+const POOL_THRESHOLD_JOBS_PER_SECOND: usize = 40;
+
 #[derive(Debug)]
 pub struct RunnerBuilder {
     workspace_root: PathBuf,
+    incremental_root: PathBuf,
+    checkpoint_root: PathBuf,
+    max_local_jobs: usize,
+
+    // when we started recent jobs, for deciding when to switch to the pool
+    recent_starts: VecDeque<Instant>,
+    pool: Option<workspace::Pool>,
+
+    children: Children,
+    diagnostics: Option<Capture>,
+    workers: worker::Pool,
+    logs: Logs,
+    events: Bus,
+    status: Option<Status>,
+    strict_outputs: bool,
+    check_inputs: bool,
+    warn_unused_inputs: bool,
+    stable_paths: bool,
+    pins: tools::Pins,
 }
 
 impl RunnerBuilder {
-    pub fn new(workspace_root: PathBuf) -> Self {
-        Self { workspace_root }
+    pub fn new(
+        root_dir: &Path,
+        max_local_jobs: usize,
+        diagnostics: Option<Capture>,
+        worker_timeout: Duration,
+        logs: Logs,
+        events: Bus,
+    ) -> Self {
+        Self {
+            workspace_root: root_dir.join("workspaces"),
+            incremental_root: root_dir.join("incremental"),
+            checkpoint_root: root_dir.join("checkpoints"),
+            max_local_jobs,
+            recent_starts: VecDeque::with_capacity(POOL_THRESHOLD_JOBS_PER_SECOND),
+            pool: None,
+            children: Children::default(),
+            diagnostics,
+            workers: worker::Pool::new(worker_timeout),
+            logs,
+            events,
+            status: None,
+            strict_outputs: false,
+            check_inputs: false,
+            warn_unused_inputs: false,
+            stable_paths: false,
+            pins: tools::Pins::default(),
+        }
+    }
+
+    /// Fail jobs that leave files in their workspace that aren't in their
+    /// outputs, instead of warning about them.
+    pub fn strict_outputs(&mut self, strict_outputs: bool) {
+        self.strict_outputs = strict_outputs;
+    }
+
+    /// Trace which files each command reads, and warn about the ones that
+    /// should have been inputs. Linux-only, and slow.
+    pub fn check_inputs(&mut self, check_inputs: bool) {
+        self.check_inputs = check_inputs;
+    }
+
+    /// Trace which files each command reads, and warn about inputs it never
+    /// read. Linux-only, and slow, like `check_inputs`.
+    pub fn warn_unused_inputs(&mut self, warn_unused_inputs: bool) {
+        self.warn_unused_inputs = warn_unused_inputs;
+    }
+
+    /// Run every job in a pooled workspace, whose paths are all the same
+    /// length and get reused from job to job, instead of one named after
+    /// the job's key.
+    pub fn stable_paths(&mut self, stable_paths: bool) {
+        self.stable_paths = stable_paths;
+    }
+
+    /// Give stamped jobs this build's status.
+    pub fn stamp_with(&mut self, status: Status) {
+        self.status = Some(status);
+    }
+
+    pub fn children(&self) -> &Children {
+        &self.children
+    }
+
+    /// Remove the checkpoints of the run with `final_key`, if it left any.
+    pub async fn discard_checkpoints(&self, final_key: &job::Key<job::Final>) -> Result<()> {
+        let checkpoints = self.checkpoint_root.join(final_key.to_string());
+        match tokio::fs::remove_dir_all(&checkpoints).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => {
+                Err(err).with_context(|| format!("could not remove `{}`", checkpoints.display()))
+            }
+        }
     }
 }
 
 impl RunnerBuilder {
     pub async fn build(
-        &self,
+        &mut self,
         job: &Job,
+        final_key: &job::Key<job::Final>,
         job_to_content_hash: &HashMap<job::Key<job::Base>, store::Item>,
+        spec_to_resolved: &HashMap<resolver::Spec, resolver::Resolved>,
+        snapshot: Option<&Snapshot>,
+        allocation: Allocation,
     ) -> Result<Runner> {
-        let workspace = Workspace::create(&self.workspace_root, &job.base_key)
+        let workspace = self
+            .workspace(job)
             .await
             .with_context(|| format!("could not create workspace for {}", job))?;
 
         workspace
-            .set_up_files(job, job_to_content_hash)
+            .set_up_files(job, job_to_content_hash, spec_to_resolved, snapshot)
             .await
             .with_context(|| format!("could not set up workspace files for {}", job))?;
 
-        let mut command = Command::from(&job.command);
-        command.current_dir(&workspace);
-        command.env("HOME", workspace.home_dir());
+        workspace
+            .set_up_incremental_state(job, &self.incremental_root)
+            .await
+            .with_context(|| format!("could not set up incremental state for {}", job))?;
+
+        let checkpoints = workspace
+            .set_up_checkpoints(job, final_key, &self.checkpoint_root)
+            .await
+            .with_context(|| format!("could not set up checkpoints for {}", job))?;
+
+        // these change from run to run, so persistent workers get them with
+        // each request instead of when they start.
+        let mut run_env = self.run_env(job, &workspace)?;
+        run_env.extend(allocation.env());
+
+        let build_root = workspace
+            .as_ref()
+            .absolutize()
+            .context("could not get absolute path to workspace")?
+            .into_owned();
+
+        let progress = workspace
+            .home_dir()
+            .join(progress::FILE_NAME)
+            .absolutize()
+            .context("could not get absolute path to progress file")?
+            .into_owned();
+        run_env.insert(
+            progress::ENV_VAR.to_string(),
+            progress.display().to_string(),
+        );
+
+        let response_file_arg = write_response_file(job, &workspace).await?;
+
+        // the command only writes the file's contents, so the directory it's
+        // in has to be there already.
+        if let Some(parent) = job
+            .output_from_stdout
+            .as_ref()
+            .and_then(|output| output.parent())
+        {
+            tokio::fs::create_dir_all(workspace.join_build(parent))
+                .await
+                .with_context(|| format!("could not create `{}`", parent.display()))?;
+        }
+
+        limits::check(&job.limits)
+            .with_context(|| format!("could not apply resource limits for {}", job))?;
+        network::check(job.network, &job.names)
+            .with_context(|| format!("could not keep {} off the network", job))?;
+        let names = job
+            .names
+            .write(workspace.tmp_dir())
+            .await
+            .with_context(|| format!("could not write hosts and DNS servers for {}", job))?;
+
+        // persistent workers outlive the job, so there's no telling which
+        // reads were for which request.
+        let file_trace = if (self.check_inputs || self.warn_unused_inputs) && !job.persistent_worker
+        {
+            let state_root = self.workspace_root.parent().unwrap_or(&self.workspace_root);
+            Some(
+                file_trace::Trace::new(state_root)
+                    .with_context(|| format!("could not trace file reads for {}", job))?,
+            )
+        } else {
+            None
+        };
+
+        let mut commands = Vec::with_capacity(1 + job.then_run.len());
+        let mut missing_tools = Vec::with_capacity(1 + job.then_run.len());
+        let mut wrong_tool = None;
+        let mut first = None;
+        for (index, job_command) in job.commands().enumerate() {
+            let job_command = job_command
+                .in_store(job_to_content_hash)
+                .with_context(|| format!("could not find the tool for {}", job))?;
+
+            let command_env = command_env(&job_command, &run_env)?;
+            missing_tools.push(tools::check(job, &job_command, &command_env).err());
+            if wrong_tool.is_none() {
+                wrong_tool = self.pins.check(&job_command).err();
+            }
+
+            let mut command = job_command.to_process(job.network, &names, file_trace.as_ref());
+            if index == 0 {
+                command.args(&response_file_arg);
+            }
+            command.current_dir(&workspace);
+            command.envs(&command_env);
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
 
-        Ok(Runner { command, workspace })
+            // if the build stops early because another job failed, we drop
+            // this job's future, and the process shouldn't outlive it.
+            command.kill_on_drop(true);
+            limits::apply(&mut command, &job.limits);
+
+            commands.push(command);
+            if index == 0 {
+                first = Some((job_command, command_env));
+            }
+        }
+
+        // workers outlive the workspace we wrote the job's names into, so
+        // jobs with their own names start a fresh process every time.
+        let worker = match first.filter(|_| job.persistent_worker && job.names.is_empty()) {
+            Some((job_command, command_env)) => Some(worker::Assignment {
+                pool: self.workers.clone(),
+                spec: worker::Spec {
+                    tool: job_command.tool().to_string(),
+                    env: job_command.env().clone(),
+                    limits: job.limits.clone(),
+                    network: job.network,
+                },
+                request: worker::Request {
+                    arguments: job_command
+                        .args()
+                        .iter()
+                        .cloned()
+                        .chain(response_file_arg)
+                        .collect(),
+                    cwd: build_root,
+                    env: command_env,
+                },
+            }),
+            None => None,
+        };
+
+        let log = self
+            .logs
+            .for_job(final_key, job.base_key.to_string())
+            .await
+            .with_context(|| format!("could not set up logs for {}", job))?;
+
+        Ok(Runner {
+            name: job.base_key.to_string(),
+            commands,
+            missing_tools,
+            wrong_tool,
+            worker,
+            log,
+            workspace,
+            expect_failure: job.expect_failure,
+            output_from_stdout: job.output_from_stdout.clone(),
+            progress: (progress, job.into(), self.events.clone()),
+            output_filters: job.output_filters.clone(),
+            expected_paths: job.expected_paths(),
+            strict_outputs: self.strict_outputs,
+            checkpoints,
+            file_trace,
+            check_inputs: self.check_inputs,
+            inputs: if self.warn_unused_inputs {
+                job.input_dests()
+            } else {
+                BTreeSet::new()
+            },
+            allocation,
+            children: self.children.clone(),
+            diagnostics: self.diagnostics.clone(),
+        })
     }
 }
 
+impl RunnerBuilder {
+    /// Set up `job`'s workspace the way its command would see it, without
+    /// running anything, for `rbt shell`. Along with the workspace, we
+    /// return the environment its first command would get. Resources
+    /// aren't allocated and incremental state isn't set up, so poking
+    /// around can't change what later builds see.
+    pub async fn shell(
+        &mut self,
+        job: &Job,
+        job_to_content_hash: &HashMap<job::Key<job::Base>, store::Item>,
+        spec_to_resolved: &HashMap<resolver::Spec, resolver::Resolved>,
+        snapshot: Option<&Snapshot>,
+    ) -> Result<(Workspace, BTreeMap<String, String>)> {
+        let workspace = self
+            .workspace(job)
+            .await
+            .with_context(|| format!("could not create workspace for {}", job))?;
+
+        workspace
+            .set_up_files(job, job_to_content_hash, spec_to_resolved, snapshot)
+            .await
+            .with_context(|| format!("could not set up workspace files for {}", job))?;
+
+        write_response_file(job, &workspace).await?;
+
+        let first = job
+            .command
+            .in_store(job_to_content_hash)
+            .with_context(|| format!("could not find the tool for {}", job))?;
+        let mut env = first.env().clone();
+        env.extend(command_env(&first, &self.run_env(job, &workspace)?)?);
+
+        Ok((workspace, env))
+    }
+
+    /// What every command in `job` gets on top of its own environment.
+    /// Passed-through variables come first, so rbt's own win over them.
+    fn run_env(&self, job: &Job, workspace: &Workspace) -> Result<BTreeMap<String, String>> {
+        let mut run_env: BTreeMap<String, String> = BTreeMap::new();
+        for name in &job.passthrough_env {
+            match std::env::var(name) {
+                Ok(value) => {
+                    run_env.insert(name.clone(), value);
+                }
+                Err(std::env::VarError::NotPresent) => (),
+                Err(std::env::VarError::NotUnicode(_)) => tracing::warn!(
+                    "not passing `{}` through to {} since its value isn't valid unicode",
+                    name,
+                    job
+                ),
+            }
+        }
+        run_env.insert(
+            "HOME".to_string(),
+            workspace.home_dir().display().to_string(),
+        );
+
+        let tmp_dir = workspace
+            .tmp_dir()
+            .absolutize()
+            .context("could not get absolute path to temporary directory")?
+            .into_owned();
+        run_env.insert("TMPDIR".to_string(), tmp_dir.display().to_string());
+
+        // the environment is cleared, so shells (and tools that ask them)
+        // would otherwise make up their own idea of where they are.
+        let build_root = workspace
+            .as_ref()
+            .absolutize()
+            .context("could not get absolute path to workspace")?
+            .into_owned();
+        run_env.insert("PWD".to_string(), build_root.display().to_string());
+
+        if job.stamp {
+            let status = self.status.as_ref().context("did not have build status for a stamped job. This is a bug in rbt's coordinator. Please file it!")?;
+            run_env.extend(status.env());
+        }
+
+        run_env.extend(job.variant.clone());
+
+        if let Some(shard) = job.shard {
+            run_env.insert("RBT_SHARD_INDEX".to_string(), shard.index.to_string());
+            run_env.insert("RBT_SHARD_TOTAL".to_string(), shard.total.to_string());
+        }
+
+        Ok(run_env)
+    }
+
+    async fn workspace(&mut self, job: &Job) -> Result<Workspace> {
+        if self.pool.is_none() && (self.stable_paths || self.starting_quickly()) {
+            if !self.stable_paths {
+                tracing::debug!(
+                    "starting more than {} jobs per second, so I'm switching to pooled workspaces",
+                    POOL_THRESHOLD_JOBS_PER_SECOND
+                );
+            }
+
+            // twice as many as can run at once, so jobs can start in fresh
+            // slots while the ones that just finished get cleared out.
+            let pool = workspace::Pool::new(self.workspace_root.clone());
+            let prewarming = pool.clone();
+            let count = self.max_local_jobs * 2;
+            tokio::task::spawn_blocking(move || prewarming.prewarm(count))
+                .await
+                .context("could not join pool prewarming task")?
+                .context("could not create pooled workspaces")?;
+            self.pool = Some(pool);
+        }
+
+        match &self.pool {
+            Some(pool) => pool.acquire().await,
+            None => Workspace::create(&self.workspace_root, &job.base_key).await,
+        }
+    }
+
+    /// Record that we're starting a job and check if we've been starting them
+    /// faster than the pool threshold over the last second.
+    fn starting_quickly(&mut self) -> bool {
+        let now = Instant::now();
+        self.recent_starts.push_back(now);
+
+        while let Some(start) = self.recent_starts.front() {
+            if now.duration_since(*start) <= Duration::from_secs(1) {
+                break;
+            }
+            self.recent_starts.pop_front();
+        }
+
+        self.recent_starts.len() >= POOL_THRESHOLD_JOBS_PER_SECOND
+    }
+}
+
+/// Jobs that write a lot of stray files (like a whole build directory) would
+/// otherwise drown the log in paths.
+const MAX_UNDECLARED_LISTED: usize = 10;
+
+/// What a job that worked leaves behind.
+pub struct Finished {
+    pub workspace: Workspace,
+
+    /// Inputs the command never read, if we were looking for them.
+    pub unused_inputs: Vec<PathBuf>,
+}
+
 pub struct Runner {
-    command: Command,
+    name: String,
+
+    /// The job's command, then any `thenRun` commands.
+    commands: Vec<Command>,
+
+    /// For each command, the system tool we couldn't find for it, if any.
+    /// We look again when it's that command's turn, since the ones before
+    /// it might be what installs the tool.
+    missing_tools: Vec<Option<tools::NotFound>>,
+
+    /// Why a pinned tool can't be used, if one can't. Unlike a missing
+    /// tool, nothing the job does could fix it, so the job fails before
+    /// running anything.
+    wrong_tool: Option<anyhow::Error>,
+    worker: Option<worker::Assignment>,
+    log: JobLog,
     workspace: Workspace,
+    expect_failure: bool,
+    output_from_stdout: Option<PathBuf>,
+
+    /// Where the job writes progress, and what we need to announce it.
+    progress: (PathBuf, JobInfo, Bus),
+    output_filters: Vec<(PathBuf, Filter)>,
+
+    /// What should be in the workspace when the command is done, and
+    /// whether finding anything else fails the job.
+    expected_paths: BTreeSet<PathBuf>,
+    strict_outputs: bool,
+
+    /// Where the job's checkpoints really are, if it has any.
+    checkpoints: Option<PathBuf>,
+
+    /// Files the command read, if we're checking inputs or looking for
+    /// unused ones.
+    file_trace: Option<file_trace::Trace>,
+    check_inputs: bool,
+
+    /// Where the job's inputs are in the workspace, if we're looking for
+    /// unused ones.
+    inputs: BTreeSet<PathBuf>,
+    allocation: Allocation,
+    children: Children,
+    diagnostics: Option<Capture>,
 }
 
 impl Runner {
-    pub async fn run(mut self) -> Result<Workspace> {
-        // TODO: send stdout, stderr, etc to The Log Zone(tm)
-        // TODO: rearrange this so we can stream logs
-        let status = self
-            .command
+    /// Jobs that are expected to fail usually want to check what the
+    /// failure looked like, so they get a copy of their output in the
+    /// workspace to list in `outputs`.
+    fn captured(&self, name: &str) -> Option<PathBuf> {
+        self.expect_failure.then(|| self.workspace.join_build(name))
+    }
+
+    /// Where stdout goes besides the log: the job's output, if it asked for
+    /// one, or a copy for a job expecting failure.
+    fn captured_stdout(&self) -> Option<PathBuf> {
+        match &self.output_from_stdout {
+            Some(output) => Some(self.workspace.join_build(output)),
+            None => self.captured("stdout"),
+        }
+    }
+
+    pub async fn run(mut self) -> Result<Finished> {
+        if let Some(problem) = self.wrong_tool.take() {
+            return Err(problem);
+        }
+
+        let (path, job, events) = self.progress.clone();
+        let progress = tokio::spawn(progress::watch(path, job, events));
+
+        // which command the exit code is from
+        let mut last = 0;
+
+        let code = match &self.worker {
+            Some(assignment) => {
+                if let Some(missing) = self.missing_tools[0].take() {
+                    return Err(missing.into());
+                }
+
+                let response = assignment.run(&self.children).await?;
+
+                // workers send their output back in the response instead of
+                // writing it somewhere we can see, so we log it all at once
+                // as if the command had written it.
+                self.log
+                    .stream(
+                        logs::Stream::Stdout,
+                        std::io::Cursor::new(response.output.into_bytes()),
+                        self.captured_stdout(),
+                    )
+                    .await
+                    .context("could not join log task")?
+                    .context("could not log worker output")?;
+
+                Some(response.exit_code)
+            }
+            None => {
+                let mut code = None;
+                for index in 0..self.commands.len() {
+                    last = index;
+                    code = self.run_command(index).await?;
+
+                    if code != Some(0) {
+                        break;
+                    }
+                }
+
+                code
+            }
+        };
+
+        progress.abort();
+
+        // give resources back as soon as possible so that jobs waiting on
+        // them can start.
+        drop(self.allocation);
+
+        let which = if self.commands.len() > 1 {
+            format!("command {} of {}", last + 1, self.commands.len())
+        } else {
+            "command".to_string()
+        };
+
+        let problem = match (code, self.expect_failure) {
+            (Some(0), false) => None,
+            (Some(code), false) => {
+                Some(anyhow::anyhow!("{which} failed with the exit code {code}"))
+            }
+            (Some(0), true) => Some(anyhow::anyhow!(
+                "command succeeded, but the job expected it to fail"
+            )),
+            (Some(code), true) => {
+                tracing::debug!("command failed as expected with the exit code {code}");
+                None
+            }
+            (None, _) => Some(anyhow::anyhow!(
+                "{which} failed with no exit code (maybe it was killed?)"
+            )),
+        };
+
+        match problem {
+            None => {
+                for (output, filter) in &self.output_filters {
+                    filter
+                        .apply(&self.workspace.join_build(output))
+                        .with_context(|| {
+                            format!("could not apply {:?} to `{}`", filter, output.display())
+                        })?;
+                }
+
+                check_undeclared_outputs(
+                    &self.workspace,
+                    &self.expected_paths,
+                    self.strict_outputs,
+                    &self.name,
+                )?;
+
+                let mut unused_inputs = Vec::new();
+                if let Some(trace) = &self.file_trace {
+                    if self.check_inputs {
+                        check_undeclared_inputs(
+                            trace,
+                            &self.workspace,
+                            &self.expected_paths,
+                            &self.name,
+                        )?;
+                    }
+
+                    if !self.inputs.is_empty() {
+                        unused_inputs =
+                            check_unused_inputs(trace, &self.workspace, &self.inputs, &self.name)?;
+                    }
+                }
+
+                // the job is done, so there's nothing left to resume
+                if let Some(checkpoints) = &self.checkpoints {
+                    tokio::fs::remove_dir_all(checkpoints)
+                        .await
+                        .with_context(|| {
+                            format!("could not remove checkpoints for {}", self.name)
+                        })?;
+                }
+
+                Ok(Finished {
+                    workspace: self.workspace,
+                    unused_inputs,
+                })
+            }
+            Some(problem) => match &self.diagnostics {
+                Some(capture) => Err(capture.attach(
+                    problem,
+                    &self.name,
+                    self.commands[last].as_std(),
+                    &self.workspace,
+                )),
+                None => Err(problem),
+            },
+        }
+    }
+}
+
+impl Runner {
+    /// Run one of the job's commands to completion, logging its output,
+    /// and return its exit code.
+    async fn run_command(&mut self, index: usize) -> Result<Option<i32>> {
+        if let Some(missing) = self.missing_tools[index].take() {
+            if missing.is_missing() {
+                return Err(missing.into());
+            }
+        }
+
+        let mut child = self.commands[index]
             .spawn()
-            .context("could not run command")?
-            .wait()
+            .context("could not run command")?;
+
+        let stdout = self.log.stream(
+            logs::Stream::Stdout,
+            child.stdout.take().context("command had no stdout")?,
+            self.captured_stdout(),
+        );
+        let stderr = self.log.stream(
+            logs::Stream::Stderr,
+            child.stderr.take().context("command had no stderr")?,
+            self.captured("stderr"),
+        );
+
+        let pid = child.id();
+        if let Some(pid) = pid {
+            self.children.insert(pid);
+        }
+
+        let status = child.wait().await;
+
+        if let Some(pid) = pid {
+            self.children.remove(pid);
+        }
+
+        for logged in [stdout, stderr] {
+            logged
+                .await
+                .context("could not join log task")?
+                .context("could not log command output")?;
+        }
+
+        Ok(status.context("command wasn't running")?.code())
+    }
+}
+
+/// `run_env` for one of a job's commands. Tools from other jobs come with
+/// their own directory on the PATH, in front of whatever the job would have
+/// had.
+fn command_env(
+    command: &job::Command,
+    run_env: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>> {
+    let mut env = run_env.clone();
+
+    let path = env
+        .get("PATH")
+        .or_else(|| command.env().get("PATH"))
+        .map(String::as_str);
+    if let Some(search_path) = command.search_path(path)? {
+        env.insert("PATH".to_string(), search_path);
+    }
+
+    Ok(env)
+}
+
+/// Write the job's response file into its workspace, if it has one, and
+/// return the argument that points its first command at it.
+async fn write_response_file(job: &Job, workspace: &Workspace) -> Result<Option<String>> {
+    match &job.response_file {
+        Some(response_file) => {
+            tokio::fs::write(
+                workspace.join_build(response_file),
+                job.response_file_contents(),
+            )
             .await
-            .context("command wasn't running")?;
+            .with_context(|| format!("could not write response file for {}", job))?;
 
-        match status.code() {
-            Some(0) => (),
-            Some(code) => anyhow::bail!("command failed with the exit code {code}"),
-            None => anyhow::bail!("command failed with no exit code (maybe it was killed?)"),
+            Ok(Some(format!("@{}", response_file.display())))
         }
+        None => Ok(None),
+    }
+}
+
+/// Look for files the command wrote but the job didn't list in its
+/// outputs. They don't make it into the store, so anything that needs
+/// them only works by accident (or not at all once the job is cached.)
+fn check_undeclared_outputs(
+    workspace: &Workspace,
+    expected_paths: &BTreeSet<PathBuf>,
+    strict_outputs: bool,
+    name: &str,
+) -> Result<()> {
+    let undeclared = workspace
+        .undeclared_files(expected_paths)
+        .context("could not check for undeclared outputs")?;
+    if undeclared.is_empty() {
+        return Ok(());
+    }
+
+    let mut listed = undeclared
+        .iter()
+        .take(MAX_UNDECLARED_LISTED)
+        .map(|path| format!("`{}`", path.display()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    if undeclared.len() > MAX_UNDECLARED_LISTED {
+        listed.push_str(&format!(
+            ", and {} more",
+            undeclared.len() - MAX_UNDECLARED_LISTED
+        ));
+    }
+
+    if strict_outputs {
+        anyhow::bail!(
+            "the command wrote files that aren't in the job's outputs: {}. Add them to `outputs`, or have the command write them somewhere else.",
+            listed
+        )
+    }
+
+    tracing::warn!(
+        "{} wrote files that aren't in its outputs, so they won't be kept: {}",
+        name,
+        listed
+    );
+
+    Ok(())
+}
+
+/// Look for files the command read that weren't in the job's inputs. The
+/// job only sees them because of where it happened to run, so it'll break
+/// (or silently go stale in the cache) when they change.
+fn check_undeclared_inputs(
+    trace: &file_trace::Trace,
+    workspace: &Workspace,
+    expected_paths: &BTreeSet<PathBuf>,
+    name: &str,
+) -> Result<()> {
+    let problems = trace
+        .undeclared_reads(workspace.as_ref(), expected_paths)
+        .context("could not check for undeclared inputs")?;
+
+    for problem in problems.iter().take(MAX_UNDECLARED_LISTED) {
+        tracing::warn!("{} {}", name, problem);
+    }
+    if problems.len() > MAX_UNDECLARED_LISTED {
+        tracing::warn!(
+            "{} had {} more undeclared inputs",
+            name,
+            problems.len() - MAX_UNDECLARED_LISTED
+        );
+    }
+
+    Ok(())
+}
+
+/// Look for inputs the command never read. Every input is part of the
+/// job's key, so these make it run again when they change without it
+/// having any reason to. Returns them, so they can go in the report too.
+fn check_unused_inputs(
+    trace: &file_trace::Trace,
+    workspace: &Workspace,
+    inputs: &BTreeSet<PathBuf>,
+    name: &str,
+) -> Result<Vec<PathBuf>> {
+    let unused = trace
+        .unread_inputs(workspace.as_ref(), inputs)
+        .context("could not check for unused inputs")?;
+    if unused.is_empty() {
+        return Ok(unused);
+    }
 
-        Ok(self.workspace)
+    let mut listed = unused
+        .iter()
+        .take(MAX_UNDECLARED_LISTED)
+        .map(|path| format!("`{}`", path.display()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    if unused.len() > MAX_UNDECLARED_LISTED {
+        listed.push_str(&format!(
+            ", and {} more",
+            unused.len() - MAX_UNDECLARED_LISTED
+        ));
+    }
+
+    tracing::warn!(
+        "{} never read some of its inputs, so changing them makes it run again for nothing: {}",
+        name,
+        listed
+    );
+
+    Ok(unused)
+}
+
+/// Process IDs for the jobs we're running right now, so that we can stop
+/// and continue them when someone pauses the build.
+#[derive(Debug, Clone, Default)]
+pub struct Children(Arc<Mutex<HashSet<u32>>>);
+
+impl Children {
+    pub fn insert(&self, pid: u32) {
+        match self.0.lock() {
+            Ok(mut pids) => {
+                pids.insert(pid);
+            }
+            Err(_) => {
+                tracing::error!("could not track child process because the lock was poisoned")
+            }
+        }
+    }
+
+    pub fn remove(&self, pid: u32) {
+        match self.0.lock() {
+            Ok(mut pids) => {
+                pids.remove(&pid);
+            }
+            Err(_) => {
+                tracing::error!("could not untrack child process because the lock was poisoned")
+            }
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    pub fn signal(&self, signal: libc::c_int) {
+        let pids = match self.0.lock() {
+            Ok(pids) => pids,
+            Err(_) => {
+                tracing::error!("could not signal child processes because the lock was poisoned");
+                return;
+            }
+        };
+
+        for pid in pids.iter() {
+            // a child may have exited since we last looked, in which case
+            // there's nothing to signal and that's fine.
+            if unsafe { libc::kill(*pid as libc::pid_t, signal) } != 0 {
+                tracing::debug!("could not send signal {} to {}", signal, pid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::glue;
+
+    #[tokio::test]
+    async fn switches_to_pooled_workspaces_when_starting_jobs_quickly() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let mut builder = RunnerBuilder::new(
+            temp.path(),
+            1,
+            None,
+            Duration::from_secs(1),
+            Logs::new(temp.path().join("logs"), false),
+            Bus::new(),
+        );
+        let glue_job = glue::Job::Job(glue::R1::for_test(job::system_command("true", &[])));
+        let job = Job::from_glue(&glue_job, &HashMap::new()).unwrap();
+        let named = temp
+            .path()
+            .join("workspaces")
+            .join(job.base_key.to_string());
+
+        for _ in 1..POOL_THRESHOLD_JOBS_PER_SECOND {
+            let workspace = builder.workspace(&job).await.unwrap();
+            assert!(workspace.as_ref().starts_with(&named));
+        }
+        assert!(builder.pool.is_none());
+
+        // more jobs than the pool has slots, so some of them have to wait
+        // for a used one to be cleared
+        let mut pooled = Vec::new();
+        for _ in 0..4 {
+            let workspace = builder.workspace(&job).await.unwrap();
+            assert!(!workspace.as_ref().starts_with(&named));
+            pooled.push(workspace.as_ref().to_path_buf());
+        }
+        assert!(builder.pool.is_some());
+        pooled.sort();
+        pooled.dedup();
+        assert_eq!(2, pooled.len(), "{:?}", pooled);
     }
 }
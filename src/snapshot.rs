@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A read-only copy of every project file a build declared as an input. When
+/// we build from a snapshot, jobs only ever see these copies, so edits that
+/// happen while the build is running can't bleed into it.
+///
+/// Copies are content-addressed (like the store) so taking a snapshot of a
+/// mostly-unchanged project is cheap on disk, and `std::fs::copy` will use
+/// reflinks on filesystems that support them.
+#[derive(Debug)]
+pub struct Snapshot {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, blake3::Hash>,
+    id: blake3::Hash,
+}
+
+impl Snapshot {
+    pub fn take<'a>(root: &Path, paths: impl IntoIterator<Item = &'a PathBuf>) -> Result<Self> {
+        std::fs::create_dir_all(root).context("could not create snapshot directory")?;
+
+        let mut files = BTreeMap::new();
+        let mut hasher = blake3::Hasher::new();
+
+        for path in paths {
+            // we hash the copy rather than the original: the original could
+            // change while we're reading it, but the copy is what jobs will
+            // actually see.
+            let temp = root.join(format!("tmp-{}", rand::random::<u64>()));
+            std::fs::copy(path, &temp)
+                .with_context(|| format!("could not copy `{}` into snapshot", path.display()))?;
+
+            let hash = Self::hash_file(&temp, &mut hasher)
+                .with_context(|| format!("could not hash snapshot of `{}`", path.display()))?;
+
+            let final_path = root.join(hash.to_hex().to_string());
+            if final_path.exists() {
+                std::fs::remove_file(&temp).context("could not remove duplicate snapshot file")?;
+            } else {
+                let mut perms = std::fs::metadata(&temp)
+                    .context("could not get snapshot file metadata")?
+                    .permissions();
+                perms.set_readonly(true);
+                std::fs::set_permissions(&temp, perms)
+                    .context("could not make snapshot file read-only")?;
+
+                std::fs::rename(&temp, &final_path).context("could not move file into snapshot")?;
+            }
+
+            tracing::trace!("snapshotted `{}` as {}", path.display(), hash);
+            files.insert(path.clone(), hash);
+        }
+
+        hasher.reset();
+        for (path, hash) in &files {
+            let path_str = path.to_string_lossy();
+            hasher.update(&(path_str.len() as u64).to_le_bytes());
+            hasher.update(path_str.as_bytes());
+            hasher.update(hash.as_bytes());
+        }
+
+        Ok(Snapshot {
+            root: root.to_path_buf(),
+            files,
+            id: hasher.finalize(),
+        })
+    }
+
+    fn hash_file(path: &Path, hasher: &mut blake3::Hasher) -> Result<blake3::Hash> {
+        let mut file = File::open(path).context("could not open file")?;
+        hasher.reset();
+
+        // The docs for Blake3 say that a 16 KiB buffer is the most
+        // efficient (for SIMD reasons)
+        let mut buf = [0; 16 * 1024];
+        loop {
+            let bytes = file.read(&mut buf).context("could not read file")?;
+            if bytes == 0 {
+                break;
+            }
+            hasher.update(&buf[0..bytes]);
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// Identifies exactly which files (and which contents) went into this
+    /// snapshot.
+    pub fn id(&self) -> blake3::Hash {
+        self.id
+    }
+
+    pub fn files(&self) -> &BTreeMap<PathBuf, blake3::Hash> {
+        &self.files
+    }
+
+    /// Where to read a project file from instead of the project itself.
+    pub fn path_for(&self, source: &Path) -> Option<PathBuf> {
+        self.files
+            .get(source)
+            .map(|hash| self.root.join(hash.to_hex().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn later_edits_do_not_show_up() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("input");
+        std::fs::write(&source, "before").unwrap();
+
+        let snapshot = Snapshot::take(&temp.path().join("snapshots"), [&source]).unwrap();
+        std::fs::write(&source, "after").unwrap();
+
+        assert_eq!(
+            "before",
+            std::fs::read_to_string(snapshot.path_for(&source).unwrap()).unwrap()
+        );
+        assert_eq!(blake3::hash(b"before"), snapshot.files()[&source]);
+    }
+
+    #[test]
+    fn id_depends_on_contents() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("input");
+        let root = temp.path().join("snapshots");
+
+        std::fs::write(&source, "one").unwrap();
+        let first = Snapshot::take(&root, [&source]).unwrap().id();
+        let again = Snapshot::take(&root, [&source]).unwrap().id();
+
+        std::fs::write(&source, "two").unwrap();
+        let changed = Snapshot::take(&root, [&source]).unwrap().id();
+
+        assert_eq!(first, again);
+        assert_ne!(first, changed);
+    }
+}
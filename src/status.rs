@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Stamped jobs find the status files through these. See
+/// `docs/adrs/015-build-status.md`.
+pub const STABLE_ENV_VAR: &str = "RBT_STABLE_STATUS";
+pub const VOLATILE_ENV_VAR: &str = "RBT_VOLATILE_STATUS";
+
+/// Keys starting with this are stable, and everything else is volatile.
+/// This is the same convention Bazel uses, so its status scripts work as-is.
+const STABLE_PREFIX: &str = "STABLE_";
+
+/// Facts about a build that change from build to build (when it happened,
+/// the git SHA, who ran it) for jobs that stamp them into their outputs.
+/// Changes to stable keys re-run stamped jobs. Changes to volatile keys
+/// don't, so a stamped job's cached output keeps whatever it saw last time.
+#[derive(Debug, Clone)]
+pub struct Status {
+    stable: PathBuf,
+    volatile: PathBuf,
+    stable_hash: String,
+}
+
+impl Status {
+    /// Run `command` (if we have one) and write what it prints, plus the
+    /// status we always provide, to the status files in `dir`.
+    pub fn collect(command: Option<&Path>, dir: &Path) -> Result<Status> {
+        let mut stable = BTreeMap::new();
+        let mut volatile = BTreeMap::new();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("the system clock is set before 1970")?;
+        volatile.insert("BUILD_TIMESTAMP".to_string(), now.as_secs().to_string());
+
+        if let Some(command) = command {
+            tracing::debug!("running status command `{}`", command.display());
+            let output = Command::new(command)
+                .stdin(Stdio::null())
+                .stderr(Stdio::inherit())
+                .output()
+                .with_context(|| format!("could not run `{}`", command.display()))?;
+
+            if !output.status.success() {
+                anyhow::bail!(
+                    "the status command `{}` failed with {}",
+                    command.display(),
+                    output.status
+                )
+            }
+
+            let stdout = String::from_utf8(output.stdout).with_context(|| {
+                format!(
+                    "`{}` printed something that wasn't UTF-8",
+                    command.display()
+                )
+            })?;
+
+            for (key, value) in parse(&stdout)? {
+                if key.starts_with(STABLE_PREFIX) {
+                    stable.insert(key, value);
+                } else {
+                    volatile.insert(key, value);
+                }
+            }
+        }
+
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("could not create `{}`", dir.display()))?;
+
+        let stable_contents = render(&stable);
+        let status = Status {
+            stable: dir.join("stable-status.txt"),
+            volatile: dir.join("volatile-status.txt"),
+            stable_hash: blake3::hash(stable_contents.as_bytes())
+                .to_hex()
+                .to_string(),
+        };
+
+        for (path, contents) in [
+            (&status.stable, stable_contents),
+            (&status.volatile, render(&volatile)),
+        ] {
+            std::fs::write(path, contents)
+                .with_context(|| format!("could not write `{}`", path.display()))?;
+        }
+
+        Ok(status)
+    }
+
+    /// Goes into stamped jobs' final keys, so they run again when a stable
+    /// key changes.
+    pub fn stable_hash(&self) -> &str {
+        &self.stable_hash
+    }
+
+    /// Environment variables pointing stamped jobs at the status files.
+    pub fn env(&self) -> [(String, String); 2] {
+        [
+            (
+                STABLE_ENV_VAR.to_string(),
+                self.stable.display().to_string(),
+            ),
+            (
+                VOLATILE_ENV_VAR.to_string(),
+                self.volatile.display().to_string(),
+            ),
+        ]
+    }
+}
+
+/// Status commands print `KEY value` lines. Values can have spaces in them,
+/// but keys can't.
+fn parse(output: &str) -> Result<Vec<(String, String)>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            if key.is_empty() {
+                anyhow::bail!(
+                    "status lines should look like `KEY value`, but got `{}`",
+                    line
+                )
+            }
+
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn render(status: &BTreeMap<String, String>) -> String {
+    status
+        .iter()
+        .map(|(key, value)| format!("{} {}\n", key, value))
+        .collect()
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn status_command(temp: &tempfile::TempDir, body: &str) -> PathBuf {
+        let path = temp.path().join("status.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn splits_stable_and_volatile_keys() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let command = status_command(
+            &temp,
+            "echo 'STABLE_GIT_BRANCH main'\necho 'GIT_SHA 3fa2c81e'\necho 'BUILD_USER some one'",
+        );
+        let dir = temp.path().join("status");
+
+        let status = Status::collect(Some(&command), &dir).unwrap();
+
+        assert_eq!(
+            "STABLE_GIT_BRANCH main\n",
+            std::fs::read_to_string(dir.join("stable-status.txt")).unwrap()
+        );
+        let volatile = std::fs::read_to_string(dir.join("volatile-status.txt")).unwrap();
+        assert!(volatile.contains("BUILD_TIMESTAMP "), "{}", volatile);
+        assert!(volatile.contains("BUILD_USER some one\n"), "{}", volatile);
+        assert!(volatile.contains("GIT_SHA 3fa2c81e\n"), "{}", volatile);
+
+        // only stable keys count
+        let command = status_command(
+            &temp,
+            "echo 'STABLE_GIT_BRANCH main'\necho 'GIT_SHA 0d1e2f3a'",
+        );
+        let again = Status::collect(Some(&command), &dir).unwrap();
+        assert_eq!(status.stable_hash(), again.stable_hash());
+    }
+
+    #[test]
+    fn fails_when_the_command_fails() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let command = status_command(&temp, "exit 1");
+
+        assert!(Status::collect(Some(&command), &temp.path().join("status")).is_err());
+    }
+}
@@ -1,8 +1,10 @@
+use crate::file_type::{self, FileType, FileTypes};
+use crate::glue;
 use crate::job::{self, Job};
+use crate::remote_cache::RemoteCache;
 use crate::workspace::Workspace;
 use anyhow::{Context, Result};
-use itertools::Itertools;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::fmt::{self, Display};
 use std::path::{Path, PathBuf};
 use tokio::fs::{self, File};
@@ -14,16 +16,314 @@ use tokio::io::AsyncReadExt;
 pub struct Store {
     root: PathBuf,
     db: sled::Tree,
+
+    // when each item was last used, in days since the epoch. We track this
+    // ourselves because store items are read-only (so mtimes don't move) and
+    // lots of systems mount with `noatime`.
+    access: sled::Tree,
+
+    // how long jobs asked for their items to be kept, for the ones that
+    // asked.
+    retention: sled::Tree,
+
+    // items we're partway through storing, so we can finish or undo them
+    // if we get interrupted (see `recover`.)
+    intents: sled::Tree,
+
+    // what kind of file each file in an item is (see `FileType`.)
+    file_types: sled::Tree,
+
+    remote: Option<RemoteCache>,
+}
+
+/// How long GC should keep an item after it was last used, when a job wants
+/// something other than the default from `rbt gc --unused-for-days`. Release
+/// builds might want to stick around for a long time, while scratch test
+/// outputs can go at the first opportunity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    Days(u64),
+    Forever,
+
+    /// Remove the item the next time GC runs, unless it was used today.
+    Ephemeral,
+}
+
+impl Retention {
+    /// Get the retention a job asked for, or `None` if it wants the default.
+    pub fn from_glue(retention: &glue::R4) -> Option<Self> {
+        match retention.kind {
+            glue::RetentionKind::Default => None,
+            glue::RetentionKind::Days => Some(Retention::Days(retention.days.into())),
+            glue::RetentionKind::Forever => Some(Retention::Forever),
+            glue::RetentionKind::Ephemeral => Some(Retention::Ephemeral),
+        }
+    }
+
+    fn days(self) -> u64 {
+        match self {
+            Retention::Days(days) => days,
+            Retention::Forever => u64::MAX,
+            Retention::Ephemeral => 0,
+        }
+    }
+}
+
+/// Where a job's items can go besides this machine. Some outputs can't
+/// leave it (a licensed SDK, or a secret baked in), and others can be shared
+/// with a team's cache but not handed to the world. Ordered from least to
+/// most visible.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Visibility {
+    /// Never uploaded to a remote cache or exported.
+    Local,
+
+    /// Uploaded to remote caches, but not exported.
+    Team,
+
+    #[default]
+    Public,
 }
 
+impl Visibility {
+    pub fn from_glue(visibility: glue::Visibility) -> Self {
+        match visibility {
+            glue::Visibility::Local => Visibility::Local,
+            glue::Visibility::Team => Visibility::Team,
+            glue::Visibility::Public => Visibility::Public,
+        }
+    }
+
+    pub fn allows_upload(self) -> bool {
+        self >= Visibility::Team
+    }
+
+    pub fn allows_export(self) -> bool {
+        self == Visibility::Public
+    }
+}
+
+impl Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Visibility::Local => "local",
+            Visibility::Team => "team",
+            Visibility::Public => "public",
+        })
+    }
+}
+
+/// How items are named and laid out below the store root. If you change how
+/// `ItemBuilder` hashes outputs or where it puts them, bump this: items from
+/// before would be named after hashes we can't reproduce any more, so
+/// `--verify-store` would quarantine every one of them.
+const LAYOUT_VERSION: u64 = 1;
+
+/// The file in the store root that says which layout the items in it use.
+/// GC only looks at things named like items, so it leaves this alone.
+const LAYOUT_FILE: &str = "layout";
+
+/// What temporary directories in the store root start with. Outputs go in
+/// one of these while we hash them, then get renamed to the item's name.
+pub const TEMP_PREFIX: &str = "tmp-";
+
+/// We only record access by the day. That's all GC needs, and it means we
+/// write to the database at most once per item per day instead of on every
+/// cache hit.
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
 impl Store {
-    pub fn new(db: sled::Tree, root: PathBuf) -> Result<Self> {
+    pub fn new(
+        db: sled::Tree,
+        access: sled::Tree,
+        retention: sled::Tree,
+        intents: sled::Tree,
+        file_types: sled::Tree,
+        root: PathBuf,
+    ) -> Result<Self> {
         if !root.exists() {
-            log::info!("creating store root at {}", &root.display());
+            tracing::info!("creating store root at {}", &root.display());
             std::fs::create_dir_all(&root).context("could not create specified root")?;
         }
 
-        Ok(Store { root, db })
+        Self::check_layout(&root)?;
+
+        let store = Store {
+            root,
+            db,
+            access,
+            retention,
+            intents,
+            file_types,
+            remote: None,
+        };
+        store
+            .recover()
+            .context("could not recover from an interrupted build")?;
+
+        Ok(store)
+    }
+
+    /// Finish or undo the store writes that an earlier build didn't get to
+    /// finish (because it was killed, or the machine went down), and remove
+    /// the temporary directories it left behind. Items appear in the store
+    /// in one `rename` once they're complete, so an item that's there is
+    /// safe to record, and one that isn't was never there as far as anyone
+    /// else could tell. Only one process can have the database open, so
+    /// nobody else can be partway through a write while we do this.
+    fn recover(&self) -> Result<()> {
+        let (mut finished, mut undone) = (0, 0);
+        for entry in self.intents.iter() {
+            let (key, hex) = entry.context("could not read store write intent")?;
+            let item = Item::from_hex(&self.root, &hex)?;
+
+            if item.exists() {
+                self.db
+                    .insert(&key, hex)
+                    .context("failed to write job and content-hash pair")?;
+                self.touch(&item)
+                    .context("could not record store item access")?;
+                finished += 1;
+            } else {
+                undone += 1;
+            }
+
+            self.intents
+                .remove(&key)
+                .context("could not remove store write intent")?;
+        }
+
+        let mut removed = 0;
+        for entry in std::fs::read_dir(&self.root).context("could not read store root")? {
+            let entry = entry.context("could not read store root")?;
+            if entry
+                .file_name()
+                .as_encoded_bytes()
+                .starts_with(TEMP_PREFIX.as_bytes())
+            {
+                Self::remove_item(&entry.path())?;
+                removed += 1;
+            }
+        }
+
+        if finished + undone + removed > 0 {
+            tracing::info!(
+                "an earlier build was interrupted while storing outputs, so I finished {} store writes, undid {}, and removed {} temporary directories",
+                finished,
+                undone,
+                removed
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Make sure the items below `root` are laid out the way we expect,
+    /// writing down our layout if nobody has yet.
+    fn check_layout(root: &Path) -> Result<()> {
+        let path = root.join(LAYOUT_FILE);
+
+        let stored = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .trim()
+                .parse()
+                .with_context(|| format!("could not parse `{}`", path.display()))?,
+
+            // stores from before we wrote the layout down have the first
+            // one. Empty stores can have ours.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let has_items = std::fs::read_dir(root)
+                    .context("could not read store root")?
+                    .filter_map(|entry| entry.ok())
+                    .any(|entry| {
+                        Item::from_hex(root, entry.file_name().as_encoded_bytes()).is_ok()
+                    });
+
+                let stored = if has_items { 1 } else { LAYOUT_VERSION };
+                if stored == LAYOUT_VERSION {
+                    std::fs::write(&path, format!("{}\n", LAYOUT_VERSION))
+                        .with_context(|| format!("could not write `{}`", path.display()))?;
+                }
+
+                stored
+            }
+
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not read `{}`", path.display()))
+            }
+        };
+
+        if stored > LAYOUT_VERSION {
+            anyhow::bail!(
+                "the store in `{}` was written by a newer version of rbt (layout {}, but I only understand layout {}.) Please upgrade rbt.",
+                root.display(),
+                stored,
+                LAYOUT_VERSION,
+            )
+        }
+
+        // items are named after their contents, so there's no converting
+        // them. Their jobs have to run again either way.
+        if stored < LAYOUT_VERSION {
+            anyhow::bail!(
+                "the store in `{}` was written by an older version of rbt (layout {}, but I use layout {}.) I can't convert its items, so please use the older version, or remove the store and rbt's database to start over.",
+                root.display(),
+                stored,
+                LAYOUT_VERSION,
+            )
+        }
+
+        Ok(())
+    }
+
+    /// Check `remote` for items we don't have locally (see `fetch_remote`)
+    /// and maybe upload the ones we build.
+    pub fn use_remote(&mut self, remote: RemoteCache) {
+        self.remote = Some(remote);
+    }
+
+    /// Try to get the output of `job` from the remote cache, if we have one,
+    /// and put it in the local store. Problems talking to the remote are
+    /// only warnings, since we can always run the job instead.
+    pub async fn fetch_remote(
+        &mut self,
+        key: job::Key<job::Final>,
+        job: &Job,
+    ) -> Result<Option<Item>> {
+        let remote = match &self.remote {
+            Some(remote) => remote.clone(),
+            None => return Ok(None),
+        };
+
+        // items are stored from a workspace, so downloads go in one too.
+        // Nothing in the store root is named like this, so GC leaves these
+        // alone while we're using them.
+        let workspace = Workspace::create(&self.root.join("remote"), &key)
+            .await
+            .context("could not create a workspace for the download")?;
+
+        let dest = workspace.as_ref().to_path_buf();
+        let downloaded = tokio::task::spawn_blocking(move || remote.download(&key, &dest))
+            .await
+            .context("could not join download task")?;
+
+        match downloaded {
+            Ok(true) => (),
+            Ok(false) => return Ok(None),
+            Err(err) => {
+                tracing::warn!(
+                    "could not get {} from the remote cache, so I'll run it: {:?}",
+                    job,
+                    err
+                );
+                return Ok(None);
+            }
+        }
+
+        self.store_locally(key, job, workspace)
+            .await
+            .map(Some)
+            .context("could not store item from the remote cache")
     }
 
     pub fn item_for_job(&self, key: &job::Key<job::Final>) -> Result<Option<Item>> {
@@ -33,10 +333,178 @@ impl Store {
             .context("could not read from store DB")?
         {
             None => Ok(None),
-            Some(hash) => Item::from_hex(&self.root, hash.as_ref()).map(Some),
+            Some(hash) => {
+                let item = Item::from_hex(&self.root, hash.as_ref())?;
+                self.touch(&item)
+                    .context("could not record store item access")?;
+
+                Ok(Some(item))
+            }
+        }
+    }
+
+    /// Record that `item` was used today, for GC.
+    pub fn touch(&self, item: &Item) -> Result<()> {
+        self.touch_on(item, today()?)
+    }
+
+    fn touch_on(&self, item: &Item, day: u64) -> Result<()> {
+        if let Some(last) = self.last_used(item)? {
+            if last >= day {
+                return Ok(());
+            }
+        }
+
+        self.access
+            .insert(item.hash.as_bytes(), &day.to_le_bytes())
+            .context("could not write store item access")?;
+
+        Ok(())
+    }
+
+    fn last_used(&self, item: &Item) -> Result<Option<u64>> {
+        match self
+            .access
+            .get(item.hash.as_bytes())
+            .context("could not read store item access")?
+        {
+            Some(bytes) => Ok(Some(u64::from_le_bytes(
+                bytes
+                    .as_ref()
+                    .try_into()
+                    .context("store item access was not exactly 8 bytes")?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// What kind of file each file in `item` is, by its path in the item.
+    /// Items that were stored before we sniffed types, or that an
+    /// interrupted build stored (see `recover`), have none.
+    pub fn file_types(&self, item: &Item) -> Result<FileTypes> {
+        match self
+            .file_types
+            .get(item.hash.as_bytes())
+            .context("could not read store item file types")?
+        {
+            Some(bytes) => {
+                serde_json::from_slice(&bytes).context("could not parse store item file types")
+            }
+            None => Ok(FileTypes::new()),
+        }
+    }
+
+    /// Remember how long the job that most recently produced or used `item`
+    /// wants it kept. Jobs that don't say get the default, even if an
+    /// earlier build asked for something else, so taking a hint out of the
+    /// build definition takes effect on the next build.
+    pub fn retain(&self, item: &Item, retention: Option<Retention>) -> Result<()> {
+        match retention {
+            Some(retention) => self
+                .retention
+                .insert(item.hash.as_bytes(), &retention.days().to_le_bytes())
+                .map(|_| ()),
+            None => self.retention.remove(item.hash.as_bytes()).map(|_| ()),
+        }
+        .context("could not write store item retention")
+    }
+
+    /// How many days after its last use GC should keep `item`, if a job
+    /// asked for something other than the default.
+    fn retention_days(&self, item: &Item) -> Result<Option<u64>> {
+        match self
+            .retention
+            .get(item.hash.as_bytes())
+            .context("could not read store item retention")?
+        {
+            Some(bytes) => Ok(Some(u64::from_le_bytes(
+                bytes
+                    .as_ref()
+                    .try_into()
+                    .context("store item retention was not exactly 8 bytes")?,
+            ))),
+            None => Ok(None),
         }
     }
 
+    /// Remove items that nobody has used in `unused_for_days` days (or
+    /// however long the jobs that made them asked for, unless
+    /// `ignore_retention` is set) along with the cache associations pointing
+    /// at them. Returns how many items we removed.
+    pub fn collect_garbage(
+        &mut self,
+        unused_for_days: u64,
+        ignore_retention: bool,
+    ) -> Result<usize> {
+        self.collect_garbage_on(today()?, unused_for_days, ignore_retention)
+    }
+
+    fn collect_garbage_on(
+        &mut self,
+        today: u64,
+        unused_for_days: u64,
+        ignore_retention: bool,
+    ) -> Result<usize> {
+        let mut removed = HashSet::new();
+
+        for entry in std::fs::read_dir(&self.root).context("could not read store root")? {
+            let entry = entry.context("could not read store entry")?;
+
+            // anything that isn't named like an item is something else (like
+            // a build that's moving an item in right now) so we leave it be.
+            let item = match Item::from_hex(&self.root, entry.file_name().as_encoded_bytes()) {
+                Ok(item) => item,
+                Err(_) => continue,
+            };
+
+            let keep_for_days = match self.retention_days(&item)? {
+                Some(days) if !ignore_retention => days,
+                _ => unused_for_days,
+            };
+
+            match self.last_used(&item)? {
+                Some(last) if last.saturating_add(keep_for_days) < today => {
+                    tracing::debug!("removing {}, which was last used on day {}", item, last);
+                    Self::remove_item(&item)?;
+                    self.access
+                        .remove(item.hash.as_bytes())
+                        .context("could not remove store item access")?;
+                    self.retention
+                        .remove(item.hash.as_bytes())
+                        .context("could not remove store item retention")?;
+                    self.file_types
+                        .remove(item.hash.as_bytes())
+                        .context("could not remove store item file types")?;
+                    removed.insert(item.to_string());
+                }
+                Some(_) => (),
+
+                // items from before we tracked access count as used today,
+                // so they get a full grace period instead of all vanishing
+                // the first time someone runs GC.
+                None => self.touch_on(&item, today)?,
+            }
+        }
+
+        for pair in self.db.iter() {
+            let (key, hash) = pair.context("could not read from store DB")?;
+
+            if removed.contains(String::from_utf8_lossy(&hash).as_ref()) {
+                self.db
+                    .remove(key)
+                    .context("failed to remove job and content-hash pair")?;
+            }
+        }
+
+        Ok(removed.len())
+    }
+
+    /// Look up several jobs at once. The results are in the same order as
+    /// `keys`.
+    pub fn items_for_jobs(&self, keys: &[job::Key<job::Final>]) -> Result<Vec<Option<Item>>> {
+        keys.iter().map(|key| self.item_for_job(key)).collect()
+    }
+
     /// Figure out if we need to make a new content-addressable item from the
     /// job's output, then store it if necessary. After running this function,
     /// `to_job` should return the correct store path.
@@ -56,22 +524,208 @@ impl Store {
         key: job::Key<job::Final>,
         job: &Job,
         workspace: Workspace,
+    ) -> Result<Item> {
+        let item = self.store_locally(key, job, workspace).await?;
+
+        if let Some(remote) = self.remote.clone().filter(|remote| remote.uploads()) {
+            if !job.visibility.allows_upload() {
+                tracing::debug!("not uploading {}, since it's {}", job, job.visibility);
+                return Ok(item);
+            }
+
+            let path = item.path().clone();
+            let uploaded = tokio::task::spawn_blocking(move || remote.upload(&key, &path))
+                .await
+                .context("could not join upload task")?;
+
+            if let Err(err) = uploaded {
+                tracing::warn!("could not upload {} to the remote cache: {:?}", job, err);
+            }
+        }
+
+        Ok(item)
+    }
+
+    async fn store_locally(
+        &mut self,
+        key: job::Key<job::Final>,
+        job: &Job,
+        workspace: Workspace,
     ) -> Result<Item> {
         let item_builder = ItemBuilder::load(&self.root, job, workspace)
             .await
             .context("could get content addressed path from job")?;
+        let file_types = serde_json::to_vec(&item_builder.file_types)
+            .context("could not serialize store item file types")?;
+
+        // this has to be on disk before we start, so that whatever happens
+        // to us partway through, `recover` knows what to finish or undo.
+        self.intents
+            .insert(key.to_db_key(), item_builder.item.to_string().as_bytes())
+            .context("could not record store write intent")?;
+        self.intents
+            .flush_async()
+            .await
+            .context("could not save store write intent")?;
 
         let item = item_builder
             .move_into_checked(&self.root)
             .await
             .context("could not move item into the store")?;
 
+        self.file_types
+            .insert(item.hash.as_bytes(), file_types)
+            .context("could not write store item file types")?;
         self.associate_job_with_hash(key, &item.to_string())
             .context("could not associate job with hash")?;
+        self.touch(&item)
+            .context("could not record store item access")?;
+
+        self.intents
+            .remove(key.to_db_key())
+            .context("could not remove store write intent")?;
 
         Ok(item)
     }
 
+    /// Forget that `key` produced `item`. We use this when an item has been
+    /// damaged (for example, someone deleted files inside it by hand) so that
+    /// the job producing it will run again instead of handing broken outputs
+    /// to downstream jobs.
+    ///
+    /// The item itself stays where it is: other keys can point at the same
+    /// content hash, and jobs running right now may have it linked into their
+    /// workspaces. When the job runs again, storing its output puts back
+    /// whatever's missing (see `ItemBuilder::move_into_checked`), and GC
+    /// removes the item if nothing does.
+    pub fn invalidate(&mut self, key: &job::Key<job::Final>) -> Result<()> {
+        self.db
+            .remove(key.to_db_key())
+            .context("failed to remove job and content-hash pair")?;
+
+        Ok(())
+    }
+
+    /// Hash `item` again and check it still matches its name. Items are
+    /// read-only, but disk corruption or someone editing files by hand can
+    /// still change them, and then every job that uses them builds on bad
+    /// outputs. If it doesn't match, we move it to the quarantine directory
+    /// (so someone can look at what happened) and forget that `key`
+    /// produced it, so the job runs again.
+    pub async fn verify(
+        &mut self,
+        key: &job::Key<job::Final>,
+        job: &Job,
+        item: Item,
+    ) -> Result<Option<Item>> {
+        match ItemBuilder::hash_outputs(&job.outputs, item.path()).await {
+            Ok((hash, _)) if hash == item.hash => return Ok(Some(item)),
+            Ok((hash, _)) => tracing::warn!(
+                "the output of {} has changed since we stored it (it hashes to {} instead of {}), so I'm going to quarantine it and run the job again",
+                job,
+                hash,
+                item
+            ),
+            Err(err) => tracing::warn!(
+                "could not hash the output of {} again, so I'm going to quarantine {} and run the job again: {:#}",
+                job,
+                item,
+                err
+            ),
+        }
+
+        self.quarantine(key, &item)
+            .await
+            .with_context(|| format!("could not quarantine store item {}", item))?;
+
+        Ok(None)
+    }
+
+    /// Forget that `key` produced `item` and move the item out of the way,
+    /// below `quarantine` in the store root. GC only looks at things named
+    /// like items, so it leaves quarantined ones for people to look at and
+    /// remove themselves.
+    async fn quarantine(&mut self, key: &job::Key<job::Final>, item: &Item) -> Result<()> {
+        self.db
+            .remove(key.to_db_key())
+            .context("failed to remove job and content-hash pair")?;
+        self.access
+            .remove(item.hash.as_bytes())
+            .context("could not remove store item access")?;
+        self.retention
+            .remove(item.hash.as_bytes())
+            .context("could not remove store item retention")?;
+        self.file_types
+            .remove(item.hash.as_bytes())
+            .context("could not remove store item file types")?;
+
+        // other keys can point at the same item, in which case the first
+        // one to notice already moved it.
+        if !item.exists() {
+            return Ok(());
+        }
+
+        let quarantine = self.root.join("quarantine");
+        fs::create_dir_all(&quarantine)
+            .await
+            .context("could not create the quarantine directory")?;
+
+        // moving a directory to a new parent changes its `..`, which needs
+        // write permission on the directory itself.
+        let mut perms = fs::metadata(item.path())
+            .await
+            .context("could not get store item metadata")?
+            .permissions();
+
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+
+        fs::set_permissions(item.path(), perms)
+            .await
+            .context("could not make store item writable")?;
+
+        let dest = quarantine.join(format!("{}-{}", item, rand::random::<u32>()));
+        fs::rename(item.path(), &dest)
+            .await
+            .with_context(|| format!("could not move store item to `{}`", dest.display()))?;
+
+        ItemBuilder::make_readonly(&dest)
+            .await
+            .context("could not make quarantined item read-only")
+    }
+
+    /// Make `path`, and everything in it, read-only the way items are. This
+    /// is for other caches that jobs get files from through links, so they
+    /// can't change what the next job sees either.
+    pub async fn make_readonly(path: &Path) -> Result<()> {
+        ItemBuilder::make_tree_readonly(path).await
+    }
+
+    pub fn remove_item(path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+
+        // everything in the store is read-only, so we have to make it
+        // writable again before we can remove anything.
+        for entry in walkdir::WalkDir::new(path) {
+            let entry = entry.context("could not walk store item")?;
+            let mut perms = entry
+                .metadata()
+                .context("could not get store item metadata")?
+                .permissions();
+
+            #[allow(clippy::permissions_set_readonly_false)]
+            perms.set_readonly(false);
+
+            std::fs::set_permissions(entry.path(), perms)
+                .with_context(|| format!("could not make `{}` writable", entry.path().display()))?;
+        }
+
+        std::fs::remove_dir_all(path)
+            .with_context(|| format!("could not remove `{}`", path.display()))
+    }
+
     fn associate_job_with_hash(&mut self, key: job::Key<job::Final>, hash: &str) -> Result<String> {
         self.db
             .insert(key.to_db_key(), hash)
@@ -81,6 +735,14 @@ impl Store {
     }
 }
 
+fn today() -> Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .context("system clock is before the epoch")?
+        .as_secs()
+        / SECONDS_PER_DAY)
+}
+
 /// ContentAddressedItem is responsible for hashing the outputs of a job inside
 /// a workspace and (maybe) moving those outputs into the store.
 #[derive(Debug)]
@@ -88,58 +750,194 @@ struct ItemBuilder<'job> {
     workspace: Workspace,
     job: &'job Job,
     item: Item,
+    file_types: FileTypes,
 }
 
 impl<'job> ItemBuilder<'job> {
     /// Load all the outputs from a job and workspace combo, creating a hash
     /// as we go.
     async fn load(root: &Path, job: &'job Job, workspace: Workspace) -> Result<ItemBuilder<'job>> {
+        let (hash, file_types) = Self::hash_outputs(&job.outputs, workspace.build_root()).await?;
+
+        Ok(Self {
+            workspace,
+            job,
+            item: Item::from_hash(root, hash),
+            file_types,
+        })
+    }
+
+    /// Hash `outputs` below `dir`, sniffing the type of each file as we
+    /// read it. That's a workspace when we're storing them and a store item
+    /// when we're checking one hasn't changed, which is why this doesn't
+    /// need a whole `ItemBuilder`.
+    async fn hash_outputs(
+        outputs: &BTreeSet<PathBuf>,
+        dir: &Path,
+    ) -> Result<(blake3::Hash, FileTypes)> {
         let mut hasher = blake3::Hasher::new();
+        let mut file_types = FileTypes::new();
 
-        for path in job.outputs.iter().sorted() {
+        for path in outputs {
             match path.to_str() {
                 Some(str) => hasher.update(str.as_bytes()),
                 None => anyhow::bail!("got a non-unicode path `{}`, but Roc should never have produced a Str with invalid unicode.", path.display()),
             };
 
-            let mut file = File::open(&workspace.join_build(path))
+            // inputs are symlinks, and a job that replaced an output with a
+            // link would have us move (and make read-only!) whatever it
+            // points to.
+            let meta = fs::symlink_metadata(dir.join(path))
                 .await
                 .with_context(|| {
                     format!(
-                        "couldn't open `{}` for hashing. Did the build produce it?",
+                        "couldn't find `{}` to store it. Did the build produce it?",
                         path.display()
                     )
                 })?;
+            if meta.file_type().is_symlink() {
+                anyhow::bail!(
+                    "`{}` is a symlink, but outputs have to be files the job wrote",
+                    path.display()
+                )
+            }
 
-            // Blake3 is designed to take advantage of SIMD instructions when
-            // buffer size is 16KiB or more
-            let mut buffer = [0; 16 * 1024];
-            loop {
-                let bytes = file.read(&mut buffer).await.with_context(|| {
-                    format!("could not read `{}` to calculate hash", path.display())
-                })?;
-                if bytes == 0 {
-                    break;
+            // the same goes for the directories the output is in: if the job
+            // replaced `a` with a link to `/etc`, `a/passwd` looks like a
+            // plain file.
+            for parent in path.ancestors().skip(1) {
+                if parent.as_os_str().is_empty() {
+                    continue;
                 }
-                hasher.update(&buffer[0..bytes]);
+
+                let meta = fs::symlink_metadata(dir.join(parent))
+                    .await
+                    .with_context(|| format!("could not look at `{}`", parent.display()))?;
+                if meta.file_type().is_symlink() {
+                    anyhow::bail!(
+                        "`{}` is in `{}`, which is a symlink, but outputs have to be files the job wrote",
+                        path.display(),
+                        parent.display()
+                    )
+                }
+            }
+
+            if meta.is_dir() {
+                Self::hash_dir(&mut hasher, &mut file_types, dir, path)
+                    .await
+                    .with_context(|| format!("could not hash directory `{}`", path.display()))?;
+            } else {
+                let file_type = Self::hash_file(&mut hasher, &dir.join(path))
+                    .await
+                    .with_context(|| format!("could not hash `{}`", path.display()))?;
+                file_types.insert(path.clone(), file_type);
             }
         }
 
-        Ok(Self {
-            workspace,
-            job,
-            item: Item::from_hash(root, hasher.finalize()),
+        Ok((hasher.finalize(), file_types))
+    }
+
+    /// Hash a file, returning its type.
+    async fn hash_file(hasher: &mut blake3::Hasher, path: &Path) -> Result<FileType> {
+        let mut file = File::open(path).await.context("could not open file")?;
+
+        // Blake3 is designed to take advantage of SIMD instructions when
+        // buffer size is 16KiB or more
+        let mut buffer = [0; 16 * 1024];
+        let mut file_type = None;
+        loop {
+            let bytes = file
+                .read(&mut buffer)
+                .await
+                .context("could not read file")?;
+
+            // the first read is plenty to go on
+            let file_type = file_type
+                .get_or_insert_with(|| FileType::sniff(&buffer[..bytes.min(file_type::SNIFF_LEN)]));
+            if bytes == 0 {
+                return Ok(*file_type);
+            }
+            hasher.update(&buffer[0..bytes]);
+        }
+    }
+
+    /// Hash everything in a directory output, in a stable order. The
+    /// structure is part of the hash (including empty directories) so that
+    /// moving a file around inside the output makes a different item.
+    async fn hash_dir(
+        hasher: &mut blake3::Hasher,
+        file_types: &mut FileTypes,
+        root: &Path,
+        output: &Path,
+    ) -> Result<()> {
+        let dir = root.join(output);
+
+        // walkdir only blocks, so we list everything on a blocking thread
+        // and come back here to read the files.
+        let walk_dir = dir.clone();
+        let entries = tokio::task::spawn_blocking(move || {
+            walkdir::WalkDir::new(walk_dir)
+                .min_depth(1)
+                .sort_by_file_name()
+                .into_iter()
+                .map(|entry| {
+                    let entry = entry.context("could not walk directory")?;
+                    let len = if entry.file_type().is_file() {
+                        Some(entry.metadata().context("could not get file size")?.len())
+                    } else {
+                        None
+                    };
+
+                    Ok((entry, len))
+                })
+                .collect::<Result<Vec<(walkdir::DirEntry, Option<u64>)>>>()
         })
+        .await
+        .context("could not join directory walk")??;
+
+        for (entry, len) in entries {
+            let relative = entry
+                .path()
+                .strip_prefix(&dir)
+                .context("walked outside the directory")?;
+
+            // unlike the outputs themselves, these names came from whatever
+            // the job wrote, so they might not be unicode.
+            hasher.update(b"\0");
+            hasher.update(relative.as_os_str().as_encoded_bytes());
+
+            let file_type = entry.file_type();
+            if file_type.is_dir() {
+                hasher.update(b"\0dir");
+            } else if let (true, Some(len)) = (file_type.is_file(), len) {
+                hasher.update(b"\0file");
+                hasher.update(&len.to_le_bytes());
+
+                let file_type = Self::hash_file(hasher, entry.path())
+                    .await
+                    .with_context(|| format!("could not hash `{}`", relative.display()))?;
+                file_types.insert(output.join(relative), file_type);
+            } else {
+                anyhow::bail!(
+                    "`{}` is a symlink, but outputs have to be files the job wrote",
+                    relative.display()
+                )
+            }
+        }
+
+        Ok(())
     }
 
     // like `move_into`, but checks that the store path exists first
     async fn move_into_checked(self, root: &Path) -> Result<Item> {
         if self.item.exists() {
-            log::debug!("we have already stored {}, so I'm skipping the move!", self,);
+            tracing::debug!("we have already stored {}, so I'm skipping the move!", self,);
 
-            Ok(self.item)
+            self.repair()
+                .await
+                .context("could not put missing outputs back into the store")
         } else {
-            log::debug!("moving {} into store", self);
+            tracing::debug!("moving {} into store", self);
 
             self.move_into(root)
                 .await
@@ -154,7 +952,7 @@ impl<'job> ItemBuilder<'job> {
     async fn move_into(self, root: &Path) -> Result<Item> {
         let final_path = self.item.path();
 
-        let temp = root.join(format!("tmp-{}", rand::random::<u64>()));
+        let temp = root.join(format!("{}{}", TEMP_PREFIX, rand::random::<u64>()));
         fs::create_dir(&temp)
             .await
             .context("couldn't create temporary directory for hashing")?;
@@ -166,7 +964,7 @@ impl<'job> ItemBuilder<'job> {
         // necessary!
         let mut created_dirs: HashSet<PathBuf> = HashSet::new();
 
-        for output in self.job.outputs.iter().sorted() {
+        for output in &self.job.outputs {
             // Before we can move the file into the store, we want to make
             // sure any parent paths exist. Luckily for us, `Path.ancestors`
             // exists. Unluckily for us, it puts stuff we don't care about on
@@ -192,7 +990,7 @@ impl<'job> ItemBuilder<'job> {
                     continue;
                 }
 
-                log::trace!(
+                tracing::trace!(
                     "creating parent directory {} in {}",
                     &ancestor.display(),
                     &temp.display()
@@ -214,7 +1012,7 @@ impl<'job> ItemBuilder<'job> {
             // it. We no longer need the workspace around for debugging since
             // we only move things into the store if the job succeeded, so
             // we'll be removing everything in it shortly anyway!
-            log::trace!("moving `{}` into store path", &output.display());
+            tracing::trace!("moving `{}` into store path", &output.display());
             let out = temp.join(output);
             fs::rename(self.workspace.join_build(output), &out)
                 .await
@@ -225,7 +1023,7 @@ impl<'job> ItemBuilder<'job> {
                     )
                 })?;
 
-            Self::make_readonly(&out).await.with_context(|| {
+            Self::make_tree_readonly(&out).await.with_context(|| {
                 format!(
                     "could not make `{}` read-only after moving into store",
                     out.display()
@@ -257,6 +1055,84 @@ impl<'job> ItemBuilder<'job> {
         Ok(self.item)
     }
 
+    /// Put back any outputs that have gone missing from an item we already
+    /// have (see `Store::invalidate`), from a run with the same content hash.
+    /// Everything that's still there stays put, since other jobs may be
+    /// using it right now.
+    async fn repair(self) -> Result<Item> {
+        for output in &self.job.outputs {
+            let dest = self.item.join(output);
+            if fs::symlink_metadata(&dest).await.is_ok() {
+                continue;
+            }
+
+            tracing::warn!("putting `{}` back into {}", output.display(), self);
+
+            // the item and every directory between it and the output, item
+            // first, since we have to be able to write to them for the move.
+            let mut dirs: Vec<PathBuf> = output
+                .ancestors()
+                .skip(1)
+                .map(|ancestor| self.item.join(ancestor))
+                .collect();
+            dirs.reverse();
+
+            for dir in &dirs {
+                if !dir.exists() {
+                    fs::create_dir(dir)
+                        .await
+                        .with_context(|| format!("could not create `{}`", dir.display()))?;
+                }
+                Self::make_writable(dir).await?;
+            }
+
+            fs::rename(self.workspace.join_build(output), &dest)
+                .await
+                .with_context(|| {
+                    format!(
+                        "could not move `{}` from workspace to store",
+                        output.display()
+                    )
+                })?;
+            Self::make_tree_readonly(&dest).await?;
+
+            for dir in dirs.iter().rev() {
+                Self::make_readonly(dir).await?;
+            }
+        }
+
+        Ok(self.item)
+    }
+
+    async fn make_writable(path: &Path) -> Result<()> {
+        let mut perms = fs::metadata(&path)
+            .await
+            .context("could not get file metadata")?
+            .permissions();
+
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+
+        fs::set_permissions(&path, perms)
+            .await
+            .context("could not set permissions")
+    }
+
+    /// Make a file, or a directory and everything in it, read-only. We do
+    /// directories after their contents, since we couldn't change anything
+    /// in them afterwards.
+    async fn make_tree_readonly(path: &Path) -> Result<()> {
+        for entry in walkdir::WalkDir::new(path).contents_first(true) {
+            let entry = entry.context("could not walk output")?;
+
+            Self::make_readonly(entry.path()).await.with_context(|| {
+                format!("could not make `{}` read-only", entry.path().display())
+            })?;
+        }
+
+        Ok(())
+    }
+
     async fn make_readonly(path: &Path) -> Result<()> {
         let mut perms = fs::metadata(&path)
             .await
@@ -320,3 +1196,420 @@ impl std::ops::Deref for Item {
         &self.path
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{Db, Tree};
+    use tempfile::TempDir;
+
+    fn store(temp: &TempDir) -> Store {
+        let db = Db::temporary();
+
+        Store::new(
+            db.tree(Tree::Store).unwrap(),
+            db.tree(Tree::StoreAccess).unwrap(),
+            db.tree(Tree::StoreRetention).unwrap(),
+            db.tree(Tree::StoreIntents).unwrap(),
+            db.tree(Tree::StoreFileTypes).unwrap(),
+            temp.path().join("store"),
+        )
+        .unwrap()
+    }
+
+    /// Make a read-only item with a nested file in it, like a job would.
+    async fn readonly_item(store: &Store, contents: &str) -> Item {
+        let item = Item::from_hash(&store.root, blake3::hash(contents.as_bytes()));
+        std::fs::create_dir_all(item.join("nested")).unwrap();
+        std::fs::write(item.join("nested/out"), contents).unwrap();
+        ItemBuilder::make_readonly(&item.join("nested/out"))
+            .await
+            .unwrap();
+        ItemBuilder::make_readonly(&item.join("nested"))
+            .await
+            .unwrap();
+        ItemBuilder::make_readonly(&item).await.unwrap();
+
+        item
+    }
+
+    #[test]
+    fn refuses_stores_with_other_layouts() {
+        let temp = TempDir::new().unwrap();
+        store(&temp);
+
+        let layout = temp.path().join("store").join(LAYOUT_FILE);
+        assert_eq!(
+            LAYOUT_VERSION.to_string(),
+            std::fs::read_to_string(&layout).unwrap().trim()
+        );
+
+        std::fs::write(&layout, format!("{}\n", LAYOUT_VERSION + 1)).unwrap();
+        assert!(Store::check_layout(&temp.path().join("store")).is_err());
+    }
+
+    #[tokio::test]
+    async fn recovers_from_interrupted_writes() {
+        let temp = TempDir::new().unwrap();
+        let db = Db::temporary();
+        let open = || {
+            Store::new(
+                db.tree(Tree::Store).unwrap(),
+                db.tree(Tree::StoreAccess).unwrap(),
+                db.tree(Tree::StoreRetention).unwrap(),
+                db.tree(Tree::StoreIntents).unwrap(),
+                db.tree(Tree::StoreFileTypes).unwrap(),
+                temp.path().join("store"),
+            )
+            .unwrap()
+        };
+        let store = open();
+
+        // one build got as far as moving its item into place, and another
+        // got killed while its outputs were still in a temporary directory
+        let moved = readonly_item(&store, "moved").await;
+        let unmoved = blake3::hash(b"unmoved");
+        store
+            .intents
+            .insert(b"moved", moved.to_string().as_bytes())
+            .unwrap();
+        store
+            .intents
+            .insert(b"unmoved", unmoved.to_hex().as_bytes())
+            .unwrap();
+
+        let temp_dir = store.root.join(format!("{}123", TEMP_PREFIX));
+        std::fs::create_dir(&temp_dir).unwrap();
+        std::fs::write(temp_dir.join("out"), "unmoved").unwrap();
+        ItemBuilder::make_readonly(&temp_dir.join("out"))
+            .await
+            .unwrap();
+        ItemBuilder::make_readonly(&temp_dir).await.unwrap();
+
+        let store = open();
+
+        assert_eq!(
+            Some(moved.to_string().as_bytes()),
+            store.db.get(b"moved").unwrap().as_deref()
+        );
+        assert!(store.db.get(b"unmoved").unwrap().is_none());
+        assert!(store.intents.is_empty());
+        assert!(!temp_dir.exists());
+        assert!(moved.exists());
+    }
+
+    #[tokio::test]
+    async fn invalidate_removes_association_but_not_item() {
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+
+        let item = readonly_item(&store, "hello").await;
+
+        let key = job::Key::default();
+        store
+            .associate_job_with_hash(key, &item.to_string())
+            .unwrap();
+        assert!(store.item_for_job(&key).unwrap().is_some());
+
+        store.invalidate(&key).unwrap();
+
+        assert!(store.item_for_job(&key).unwrap().is_none());
+        assert!(item.exists());
+    }
+
+    #[tokio::test]
+    async fn gc_removes_items_unused_for_too_long() {
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+
+        let old = readonly_item(&store, "old").await;
+        let recent = readonly_item(&store, "recent").await;
+        let untracked = readonly_item(&store, "untracked").await;
+
+        let old_key = job::Key::default();
+        store
+            .associate_job_with_hash(old_key, &old.to_string())
+            .unwrap();
+
+        store.touch_on(&old, 10).unwrap();
+        store.touch_on(&recent, 90).unwrap();
+
+        assert_eq!(1, store.collect_garbage_on(100, 30, false).unwrap());
+
+        assert!(!old.exists());
+        assert!(store.db.get(old_key.to_db_key()).unwrap().is_none());
+        assert!(recent.exists());
+        assert!(untracked.exists());
+        assert_eq!(Some(100), store.last_used(&untracked).unwrap());
+    }
+
+    #[tokio::test]
+    async fn gc_respects_retention() {
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+
+        let release = readonly_item(&store, "release").await;
+        let pinned = readonly_item(&store, "pinned").await;
+        let scratch = readonly_item(&store, "scratch").await;
+
+        for item in [&release, &pinned, &scratch] {
+            store.touch_on(item, 50).unwrap();
+        }
+        store.retain(&release, Some(Retention::Days(90))).unwrap();
+        store.retain(&pinned, Some(Retention::Forever)).unwrap();
+        store.retain(&scratch, Some(Retention::Ephemeral)).unwrap();
+
+        // the default would keep scratch and remove the others
+        assert_eq!(1, store.collect_garbage_on(60, 30, false).unwrap());
+        assert!(release.exists());
+        assert!(pinned.exists());
+        assert!(!scratch.exists());
+
+        // operators can fall back to the default for everything
+        assert_eq!(2, store.collect_garbage_on(100, 30, true).unwrap());
+        assert!(!release.exists());
+        assert!(!pinned.exists());
+    }
+
+    /// A job that only has outputs, since that's all the store looks at.
+    fn job_with_outputs(outputs: &[&str]) -> Job {
+        use crate::{glue, job};
+        use roc_std::RocStr;
+        use std::collections::HashMap;
+
+        let glue_job = glue::Job::Job(glue::R1 {
+            outputs: outputs.iter().map(|output| RocStr::from(*output)).collect(),
+            ..glue::R1::for_test(job::system_command("webpack", &[]))
+        });
+        Job::from_glue(&glue_job, &HashMap::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn stores_directory_outputs() {
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+        let job = job_with_outputs(&["dist"]);
+
+        let workspace = Workspace::create(&temp.path().join("workspaces"), &job::Key::default())
+            .await
+            .unwrap();
+        std::fs::create_dir_all(workspace.join_build("dist/assets")).unwrap();
+        std::fs::create_dir_all(workspace.join_build("dist/empty")).unwrap();
+        std::fs::write(workspace.join_build("dist/index.html"), "<html>").unwrap();
+        std::fs::write(workspace.join_build("dist/assets/app.js"), "app").unwrap();
+
+        let item = store
+            .store_from_workspace(job::Key::default(), &job, workspace)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            "app",
+            std::fs::read_to_string(item.join("dist/assets/app.js")).unwrap()
+        );
+        assert!(item.join("dist/empty").is_dir());
+        assert!(std::fs::metadata(item.join("dist/assets/app.js"))
+            .unwrap()
+            .permissions()
+            .readonly());
+        assert!(std::fs::metadata(item.join("dist/assets"))
+            .unwrap()
+            .permissions()
+            .readonly());
+
+        // clean up, since TempDir can't remove read-only directories
+        Store::remove_item(&item).unwrap();
+    }
+
+    /// Storing a big item does a lot of filesystem work, but the scheduler
+    /// shares its thread with other jobs' bookkeeping, so none of it may
+    /// hold the executor for long. This runs on a single-threaded runtime
+    /// (the worst case) and checks how long a task that wants to wake up
+    /// every millisecond has to wait while we store an item.
+    #[tokio::test]
+    async fn storing_large_items_keeps_the_executor_responsive() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+        let job = job_with_outputs(&["dist", "bundle.bin"]);
+
+        let workspace = Workspace::create(&temp.path().join("workspaces"), &job::Key::default())
+            .await
+            .unwrap();
+        std::fs::create_dir_all(workspace.join_build("dist")).unwrap();
+        for i in 0..2_000 {
+            std::fs::write(
+                workspace.join_build(format!("dist/{}.js", i)),
+                i.to_string().repeat(1_000),
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            workspace.join_build("bundle.bin"),
+            vec![7u8; 64 * 1024 * 1024],
+        )
+        .unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker = tokio::spawn({
+            let done = done.clone();
+            async move {
+                let mut longest = Duration::ZERO;
+                let mut last = Instant::now();
+                while !done.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    longest = longest.max(last.elapsed());
+                    last = Instant::now();
+                }
+                longest
+            }
+        });
+
+        let started = Instant::now();
+        let item = store
+            .store_from_workspace(job::Key::default(), &job, workspace)
+            .await
+            .unwrap();
+        let took = started.elapsed();
+        done.store(true, Ordering::Relaxed);
+        let longest = ticker.await.unwrap();
+
+        // storing this takes around 300ms in a debug build, and the longest
+        // the ticker has had to wait was about 5ms. Other tests share the
+        // machine, so leave plenty of room.
+        assert!(
+            longest < Duration::from_millis(100),
+            "the executor was stuck for {:?} while storing took {:?}",
+            longest,
+            took,
+        );
+
+        // clean up, since TempDir can't remove read-only directories
+        Store::remove_item(&item).unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_output_file_types() {
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+        let job = job_with_outputs(&["app.wasm", "docs"]);
+
+        let workspace = Workspace::create(&temp.path().join("workspaces"), &job::Key::default())
+            .await
+            .unwrap();
+        std::fs::write(workspace.join_build("app.wasm"), b"\0asm\x01\0\0\0").unwrap();
+        std::fs::create_dir(workspace.join_build("docs")).unwrap();
+        std::fs::write(workspace.join_build("docs/README.md"), "# app").unwrap();
+
+        let item = store
+            .store_from_workspace(job::Key::default(), &job, workspace)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            FileTypes::from([
+                (PathBuf::from("app.wasm"), FileType::Wasm),
+                (PathBuf::from("docs/README.md"), FileType::Text),
+            ]),
+            store.file_types(&item).unwrap()
+        );
+
+        Store::remove_item(&item).unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_quarantines_items_that_changed() {
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+        let job = job_with_outputs(&["out"]);
+        let key = job::Key::default();
+
+        let workspace = Workspace::create(&temp.path().join("workspaces"), &key)
+            .await
+            .unwrap();
+        std::fs::write(workspace.join_build("out"), "good").unwrap();
+        let item = store
+            .store_from_workspace(key, &job, workspace)
+            .await
+            .unwrap();
+
+        let item = store.verify(&key, &job, item).await.unwrap().unwrap();
+        assert!(store.item_for_job(&key).unwrap().is_some());
+
+        // someone "fixes" the output by hand
+        let mut perms = std::fs::metadata(item.join("out")).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        std::fs::set_permissions(item.join("out"), perms).unwrap();
+        std::fs::write(item.join("out"), "bad").unwrap();
+
+        let path = item.path().clone();
+        assert!(store.verify(&key, &job, item).await.unwrap().is_none());
+        assert!(store.item_for_job(&key).unwrap().is_none());
+        assert!(!path.exists());
+
+        let quarantined: Vec<PathBuf> = std::fs::read_dir(temp.path().join("store/quarantine"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(1, quarantined.len());
+        assert_eq!(
+            "bad",
+            std::fs::read_to_string(quarantined[0].join("out")).unwrap()
+        );
+
+        // clean up, since TempDir can't remove read-only directories
+        Store::remove_item(&quarantined[0]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_outputs_that_are_or_go_through_symlinks() {
+        let temp = TempDir::new().unwrap();
+        let mut store = store(&temp);
+
+        // something outside the workspace a job shouldn't be able to take
+        let secret = temp.path().join("secret");
+        std::fs::create_dir(&secret).unwrap();
+        std::fs::write(secret.join("passwd"), "root").unwrap();
+
+        for (output, link, target, expected) in [
+            ("out", "out", secret.join("passwd"), "`out` is a symlink"),
+            ("etc/passwd", "etc", secret.clone(), "which is a symlink"),
+            (
+                "dist",
+                "dist/passwd",
+                secret.join("passwd"),
+                "`passwd` is a symlink",
+            ),
+        ] {
+            let job = job_with_outputs(&[output]);
+            let workspace =
+                Workspace::create(&temp.path().join("workspaces"), &job::Key::default())
+                    .await
+                    .unwrap();
+
+            let link = workspace.join_build(link);
+            std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+            std::os::unix::fs::symlink(target, link).unwrap();
+
+            let err = store
+                .store_from_workspace(job::Key::default(), &job, workspace)
+                .await
+                .unwrap_err();
+            assert!(format!("{:#}", err).contains(expected), "{:#}", err);
+        }
+
+        // and nothing was moved or made read-only
+        assert_eq!(
+            "root",
+            std::fs::read_to_string(secret.join("passwd")).unwrap()
+        );
+        assert!(!std::fs::metadata(secret.join("passwd"))
+            .unwrap()
+            .permissions()
+            .readonly());
+    }
+}
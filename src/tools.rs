@@ -0,0 +1,281 @@
+use crate::job::{self, Job};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Where `execvp` looks when there's no `PATH` at all.
+const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// A system tool a job needs that isn't anywhere the job will look for it.
+/// Left alone, the job fails with "No such file or directory" and no hint
+/// of which file, so we check first and say what we searched and what to
+/// do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotFound {
+    tool: String,
+    job: String,
+
+    /// The directories we looked in, in order.
+    searched: Vec<PathBuf>,
+
+    /// Whether the `PATH` was the job's own, instead of ours.
+    from_job: bool,
+}
+
+/// Check that `command`'s tool can be found on the `PATH` it'll run with:
+/// the one in `env` (what the runner adds, like a passthrough value) or
+/// the command's own env, or ours if neither has one, since that's where
+/// the process gets looked up then. Tools from other jobs and tools given
+/// as paths don't get looked up at all.
+pub fn check(
+    job: &Job,
+    command: &job::Command,
+    env: &BTreeMap<String, String>,
+) -> Result<(), NotFound> {
+    if command.tool_job().is_some() || command.tool().contains('/') {
+        return Ok(());
+    }
+
+    let job_path = env.get("PATH").or_else(|| command.env().get("PATH"));
+    let path = match job_path {
+        Some(path) => path.clone(),
+        None => std::env::var("PATH").unwrap_or_else(|_| DEFAULT_PATH.to_string()),
+    };
+
+    let missing = NotFound {
+        tool: command.tool().to_string(),
+        job: job.to_string(),
+        searched: std::env::split_paths(&path).collect(),
+        from_job: job_path.is_some(),
+    };
+
+    if missing.is_missing() {
+        Err(missing)
+    } else {
+        Ok(())
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        #[cfg(unix)]
+        Ok(meta) => {
+            use std::os::unix::fs::PermissionsExt;
+
+            meta.is_file() && meta.permissions().mode() & 0o111 != 0
+        }
+
+        #[cfg(not(unix))]
+        Ok(meta) => meta.is_file(),
+
+        Err(_) => false,
+    }
+}
+
+impl NotFound {
+    /// Look again, in case something (like an earlier command in the same
+    /// job) put the tool there since.
+    pub fn is_missing(&self) -> bool {
+        !self
+            .searched
+            .iter()
+            .any(|dir| is_executable(&dir.join(&self.tool)))
+    }
+
+    /// What someone could do about it, most likely first.
+    fn hints(&self) -> Vec<String> {
+        let mut hints = vec![if self.from_job {
+            format!(
+                "install `{}`, and add the directory it's in to the `PATH` in the job's env",
+                self.tool
+            )
+        } else {
+            format!(
+                "install `{}` somewhere on your `PATH` (jobs without a `PATH` of their own look for tools on rbt's)",
+                self.tool
+            )
+        }];
+
+        hints.push(format!(
+            "build `{}` with another job and use `tool` instead of `systemTool`, so the build doesn't depend on what's installed",
+            self.tool
+        ));
+        hints.push(
+            "get it from a toolchain manager (like a job that runs `nix-shell` or a container) the same way; see \"Tools\" in docs/adrs/006-jobs.md".to_string(),
+        );
+
+        hints
+    }
+}
+
+impl Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} needs `{}`, but it isn't on the PATH {} runs with",
+            self.job,
+            self.tool,
+            if self.from_job { "the job" } else { "it" }
+        )?;
+
+        if self.searched.is_empty() {
+            write!(f, " (which is empty)")?;
+        } else {
+            let searched: Vec<String> = self
+                .searched
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect();
+            write!(f, " (I looked in {})", searched.join(", "))?;
+        }
+
+        write!(f, ". You could:")?;
+        for hint in self.hints() {
+            write!(f, "\n  - {}", hint)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// The hashes of the pinned tools we've read, so a toolchain a thousand
+/// jobs use gets hashed once instead of a thousand times. We hash it again
+/// if its size or modification time changes.
+#[derive(Debug, Default)]
+pub struct Pins {
+    hashed: HashMap<PathBuf, (SystemTime, u64, String)>,
+}
+
+impl Pins {
+    /// Make sure `command`'s tool has the hash it's pinned to, if it's
+    /// pinned to one.
+    pub fn check(&mut self, command: &job::Command) -> Result<()> {
+        let expected = match command.pinned_sha256() {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let path = Path::new(command.tool());
+        let meta = std::fs::metadata(path)
+            .with_context(|| format!("could not find the pinned tool `{}`", path.display()))?;
+        let modified = meta
+            .modified()
+            .context("mtime is not supported on this system")?;
+
+        let actual = match self.hashed.get(path) {
+            Some((was_modified, len, hash)) if *was_modified == modified && *len == meta.len() => {
+                hash.clone()
+            }
+            _ => {
+                let hash = sha256(path)?;
+                self.hashed
+                    .insert(path.to_path_buf(), (modified, meta.len(), hash.clone()));
+                hash
+            }
+        };
+
+        if actual != expected {
+            anyhow::bail!(
+                "`{}` has the SHA-256 hash {}, but I expected {}. If the new binary is the one you want, update the hash.",
+                path.display(),
+                actual,
+                expected
+            )
+        }
+
+        Ok(())
+    }
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("could not open `{}`", path.display()))?;
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("could not read `{}`", path.display()))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::glue;
+    use roc_std::{RocList, RocStr};
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A job that runs `tool`, since that's all we look at. roc_std can't
+    /// build a `RocDict` with items in it yet, so tests give the `PATH` to
+    /// `check` instead of the job.
+    fn job_with_command(tool: &str) -> Job {
+        job_with_tool(glue::Tool::SystemTool(glue::SystemToolPayload {
+            name: RocStr::from(tool),
+        }))
+    }
+
+    fn job_with_tool(tool: glue::Tool) -> Job {
+        use std::collections::HashMap;
+
+        let glue_job = glue::Job::Job(glue::R1::for_test(glue::Command {
+            tool,
+            args: RocList::empty(),
+        }));
+        Job::from_glue(&glue_job, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn finds_executables_on_the_path() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (empty, bin) = (temp.path().join("empty"), temp.path().join("bin"));
+        std::fs::create_dir(&empty).unwrap();
+        std::fs::create_dir(&bin).unwrap();
+
+        std::fs::write(bin.join("cc"), "").unwrap();
+        std::fs::set_permissions(bin.join("cc"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(bin.join("notes"), "").unwrap();
+
+        let path = std::env::join_paths([&empty, &bin])
+            .unwrap()
+            .into_string()
+            .unwrap();
+        let env = BTreeMap::from([("PATH".to_string(), path)]);
+
+        let job = job_with_command("cc");
+        assert_eq!(Ok(()), check(&job, &job.command, &env));
+
+        // not executable, so exec wouldn't take it either
+        let job = job_with_command("notes");
+        let missing = check(&job, &job.command, &env).unwrap_err();
+        assert_eq!(vec![empty, bin], missing.searched);
+        assert!(missing.from_job);
+        assert!(missing.to_string().contains("needs `notes`"), "{}", missing);
+    }
+
+    #[test]
+    fn pinned_tools_have_to_match_their_hash() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let cc = temp.path().join("cc");
+        std::fs::write(&cc, "hello").unwrap();
+
+        let job = job_with_tool(glue::Tool::PinnedTool(glue::PinnedToolPayload {
+            path: RocStr::from(cc.to_str().unwrap()),
+            sha256: RocStr::from(
+                "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824",
+            ),
+        }));
+
+        let mut pins = Pins::default();
+        pins.check(&job.command).unwrap();
+
+        std::fs::write(&cc, "goodbye").unwrap();
+        let problem = pins.check(&job.command).unwrap_err().to_string();
+        assert!(problem.contains("but I expected 2cf24dba"), "{}", problem);
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{self, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Set up recording every span to `path` in Chrome's trace event format,
+/// which `chrome://tracing` and Perfetto can load to show where a build
+/// spent its time. Nothing gets written until `ChromeTrace::finish`.
+pub fn chrome(path: PathBuf) -> (ChromeLayer, ChromeTrace) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    (
+        ChromeLayer {
+            started: Instant::now(),
+            events: events.clone(),
+        },
+        ChromeTrace { path, events },
+    )
+}
+
+/// Records a complete event for each span when it closes.
+pub struct ChromeLayer {
+    started: Instant,
+    events: Arc<Mutex<Vec<Value>>>,
+}
+
+/// The other end of a `ChromeLayer`, to write out what it recorded.
+pub struct ChromeTrace {
+    path: PathBuf,
+    events: Arc<Mutex<Vec<Value>>>,
+}
+
+impl ChromeTrace {
+    /// Write the trace file. Spans that are still open (there shouldn't be
+    /// any once the build is over) are left out.
+    pub fn finish(self) -> Result<()> {
+        let events = match self.events.lock() {
+            Ok(mut events) => std::mem::take(&mut *events),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+
+        let file = std::fs::File::create(&self.path)
+            .with_context(|| format!("could not create `{}`", self.path.display()))?;
+
+        serde_json::to_writer(
+            std::io::BufWriter::new(file),
+            &serde_json::json!({
+                "traceEvents": events,
+                "displayTimeUnit": "ms",
+            }),
+        )
+        .with_context(|| format!("could not write trace to `{}`", self.path.display()))
+    }
+}
+
+/// What we know about a span before it closes.
+struct Timing {
+    start: Instant,
+    args: Map<String, Value>,
+}
+
+impl<S> Layer<S> for ChromeLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let mut args = Map::new();
+        attrs.record(&mut Args(&mut args));
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Timing {
+                start: Instant::now(),
+                args,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                values.record(&mut Args(&mut timing.args));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: layer::Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let timing = match span.extensions_mut().remove::<Timing>() {
+            Some(timing) => timing,
+            None => return,
+        };
+
+        // each top-level span (a build phase, or a job) gets its own row,
+        // with the spans inside it nested underneath. Jobs run concurrently,
+        // so putting them on rows by thread would overlap them.
+        let row = span
+            .scope()
+            .from_root()
+            .next()
+            .map_or(id.into_u64(), |root| root.id().into_u64());
+
+        let event = serde_json::json!({
+            "name": span.name(),
+            "cat": span.metadata().target(),
+            "ph": "X",
+            "ts": micros(timing.start.saturating_duration_since(self.started)),
+            "dur": micros(timing.start.elapsed()),
+            "pid": std::process::id(),
+            "tid": row,
+            "args": timing.args,
+        });
+
+        match self.events.lock() {
+            Ok(mut events) => events.push(event),
+            Err(poisoned) => poisoned.into_inner().push(event),
+        }
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+/// Collects span fields (like job keys) into the event's `args`.
+struct Args<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Args<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn writes_nested_spans_as_complete_events() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("trace.json");
+        let (layer, trace) = chrome(path.clone());
+
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            let job = tracing::info_span!("job", key = "abc123");
+            let _job = job.enter();
+            tracing::info_span!("workspace setup").in_scope(|| ());
+        });
+        trace.finish().unwrap();
+
+        let written: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        let events = written["traceEvents"].as_array().unwrap();
+        assert_eq!(2, events.len());
+
+        // inner spans close first
+        assert_eq!("workspace setup", events[0]["name"]);
+        assert_eq!("job", events[1]["name"]);
+        assert_eq!("abc123", events[1]["args"]["key"]);
+        assert_eq!("X", events[1]["ph"]);
+        assert_eq!(events[0]["tid"], events[1]["tid"]);
+    }
+}
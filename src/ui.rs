@@ -0,0 +1,307 @@
+use crate::events::{Event, JobInfo};
+use crate::progress::Progress;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// How to show a build while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// `fancy` on a terminal, `plain` everywhere else (like CI logs.)
+    Auto,
+
+    /// Job output and log messages, one line after another.
+    Plain,
+
+    /// A status block that updates in place, showing running jobs and how
+    /// much is left. Job output only goes to the logs directory.
+    Fancy,
+}
+
+impl Mode {
+    /// Decide between plain and fancy for real: `true` means fancy.
+    pub fn is_fancy(self) -> bool {
+        match self {
+            Mode::Auto => {
+                std::io::stderr().is_terminal()
+                    && std::env::var("TERM").map_or(true, |term| term != "dumb")
+            }
+            Mode::Plain => false,
+            Mode::Fancy => true,
+        }
+    }
+}
+
+/// How often we redraw even if nothing happened, so elapsed times tick.
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many running jobs we list before summarizing the rest.
+const MAX_RUNNING_LINES: usize = 8;
+
+/// What the status block knows about the build so far.
+#[derive(Debug)]
+struct Status {
+    started: Instant,
+    running: BTreeMap<String, Running>,
+    queued: usize,
+    cached: usize,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+#[derive(Debug)]
+struct Running {
+    job: JobInfo,
+    started: Instant,
+    progress: Option<Progress>,
+}
+
+impl Status {
+    fn new() -> Self {
+        Status {
+            started: Instant::now(),
+            running: BTreeMap::new(),
+            queued: 0,
+            cached: 0,
+            succeeded: 0,
+            failed: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Update for `event`, returning a line to print above the block for
+    /// things people should see even after the build is over.
+    fn handle(&mut self, event: &Event) -> Option<String> {
+        match event {
+            Event::JobStarted { job } => {
+                self.running.insert(
+                    job.key.clone(),
+                    Running {
+                        job: job.clone(),
+                        started: Instant::now(),
+                        progress: None,
+                    },
+                );
+                None
+            }
+            Event::JobProgress { job, progress } => {
+                if let Some(running) = self.running.get_mut(&job.key) {
+                    running.progress = Some(progress.clone());
+                }
+                None
+            }
+            Event::JobCached { .. } => {
+                self.cached += 1;
+                None
+            }
+            Event::JobSucceeded { job, duration, .. } => {
+                self.running.remove(&job.key);
+                self.succeeded += 1;
+                Some(format!("done   {} in {}", job.id, elapsed(*duration)))
+            }
+            Event::JobFailed {
+                job,
+                duration,
+                error,
+            } => {
+                self.running.remove(&job.key);
+                self.failed += 1;
+                Some(format!(
+                    "FAILED {} after {}: {}",
+                    job.id,
+                    elapsed(*duration),
+                    error
+                ))
+            }
+            Event::JobCutShort { job, duration } => {
+                self.running.remove(&job.key);
+                self.skipped += 1;
+                Some(format!("CUT SHORT {} after {}", job.id, elapsed(*duration)))
+            }
+            Event::JobSkipped { job } => {
+                self.running.remove(&job.key);
+                self.skipped += 1;
+                None
+            }
+            Event::QueueChanged {
+                ready,
+                waiting,
+                blocked,
+                ..
+            } => {
+                self.queued = ready + waiting + blocked;
+                None
+            }
+            Event::BuildStarted { .. } | Event::BuildFinished { .. } => None,
+        }
+    }
+
+    fn render(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut lines = Vec::with_capacity(MAX_RUNNING_LINES + 2);
+
+        for running in self.running.values().take(MAX_RUNNING_LINES) {
+            let mut line = format!("  {:>6} {}", elapsed(now - running.started), running.job.id);
+            if let Some(progress) = &running.progress {
+                line.push_str(&format!(" [{}]", progress));
+            }
+            lines.push(line);
+        }
+        if self.running.len() > MAX_RUNNING_LINES {
+            lines.push(format!(
+                "  ...and {} more",
+                self.running.len() - MAX_RUNNING_LINES
+            ));
+        }
+
+        let mut summary = format!(
+            "[{}] {} running, {} queued, {} done, {} cached",
+            elapsed(now - self.started),
+            self.running.len(),
+            self.queued,
+            self.succeeded,
+            self.cached
+        );
+        if self.failed > 0 {
+            summary.push_str(&format!(", {} failed", self.failed));
+        }
+        if self.skipped > 0 {
+            summary.push_str(&format!(", {} skipped", self.skipped));
+        }
+        lines.push(summary);
+
+        lines
+    }
+}
+
+/// Show the build as a status block on stderr until it finishes. Each
+/// redraw moves back up over the last block and clears it, so anything
+/// else writing to the terminal should be kept to a minimum meanwhile (the
+/// CLI turns job output and most log messages off in fancy mode.)
+pub async fn fancy(mut events: broadcast::Receiver<Event>) {
+    let mut status = Status::new();
+    let mut drawn = 0;
+    let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+
+    loop {
+        let mut permanent = None;
+        let mut finished = false;
+
+        tokio::select! {
+            received = events.recv() => match received {
+                Ok(event) => {
+                    permanent = status.handle(&event);
+                    finished = matches!(event, Event::BuildFinished { .. });
+                }
+                Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => finished = true,
+            },
+            _ = redraw.tick() => (),
+        }
+
+        let mut out = std::io::stderr().lock();
+        let mut frame = String::new();
+        if drawn > 0 {
+            // to the start of the first line we drew, then clear to the end
+            frame.push_str(&format!("\x1b[{}F\x1b[J", drawn));
+        }
+        if let Some(line) = permanent {
+            frame.push_str(&line);
+            frame.push('\n');
+        }
+
+        let lines = status.render();
+        for line in &lines {
+            frame.push_str(line);
+            frame.push('\n');
+        }
+        drawn = lines.len();
+
+        // nothing useful to do if the terminal went away
+        let _ = out.write_all(frame.as_bytes());
+        let _ = out.flush();
+
+        if finished {
+            return;
+        }
+    }
+}
+
+pub fn elapsed(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    if secs < 60 {
+        format!("{}.{}s", secs, duration.subsec_millis() / 100)
+    } else if secs < 60 * 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 60 / 60, secs / 60 % 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summarizes_the_build() {
+        let job = |id: &str| JobInfo {
+            id: id.to_string(),
+            key: id.to_string(),
+            command: "cc".to_string(),
+        };
+
+        let mut status = Status::new();
+        for event in [
+            Event::JobStarted { job: job("cc-a") },
+            Event::JobStarted { job: job("cc-b") },
+            Event::JobCached {
+                job: job("cc-c"),
+                store_path: "/store/c".into(),
+                output_types: BTreeMap::new(),
+            },
+            Event::JobProgress {
+                job: job("cc-b"),
+                progress: Progress {
+                    percent: Some(50),
+                    stage: None,
+                },
+            },
+            Event::QueueChanged {
+                ready: 1,
+                waiting: 0,
+                blocked: 2,
+                running: 2,
+            },
+        ] {
+            assert_eq!(None, status.handle(&event));
+        }
+
+        let done = status.handle(&Event::JobSucceeded {
+            job: job("cc-a"),
+            duration: Duration::from_millis(1500),
+            store_path: "/store/a".into(),
+            output_types: BTreeMap::new(),
+            unused_inputs: Vec::new(),
+        });
+        assert_eq!(Some("done   cc-a in 1.5s".to_string()), done);
+
+        let lines = status.render();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].ends_with("cc-b [50%]"), "{}", lines[0]);
+        assert!(
+            lines[1].ends_with("1 running, 3 queued, 1 done, 1 cached"),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn formats_elapsed_time() {
+        assert_eq!("0.2s", elapsed(Duration::from_millis(250)));
+        assert_eq!("2m05s", elapsed(Duration::from_secs(125)));
+        assert_eq!("1h01m", elapsed(Duration::from_secs(3660)));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::limits::{self, Limits};
+use crate::network::{self, NameFiles, Network};
+use crate::runner::Children;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+
+/// Long-lived tool processes for jobs that opt in with
+/// `Rbt.withPersistentWorker`. Tools like the JVM take much longer to start
+/// than to do a small piece of work, so instead of starting a new process
+/// for every job we keep some around and send them work over stdin. See
+/// docs/adrs/013-persistent-workers.md for the protocol.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    idle: Arc<Mutex<HashMap<Spec, Vec<Worker>>>>,
+    timeout: Duration,
+}
+
+/// Workers can only be shared between jobs that would have started the
+/// same process: the same tool with the same environment, limits, and
+/// network access.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Spec {
+    pub tool: String,
+    pub env: BTreeMap<String, String>,
+    pub limits: Limits,
+    pub network: Network,
+}
+
+/// One job's worth of work.
+#[derive(Debug, serde::Serialize)]
+pub struct Request {
+    pub arguments: Vec<String>,
+    pub cwd: PathBuf,
+
+    /// Variables that change from job to job (like `HOME`) and so couldn't
+    /// be set when the worker started.
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub exit_code: i32,
+
+    #[serde(default)]
+    pub output: String,
+}
+
+/// Everything a runner needs to hand a job to a worker.
+#[derive(Debug)]
+pub struct Assignment {
+    pub pool: Pool,
+    pub spec: Spec,
+    pub request: Request,
+}
+
+impl Pool {
+    pub fn new(timeout: Duration) -> Self {
+        Pool {
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            timeout,
+        }
+    }
+
+    /// Send a request to an idle worker (or a new one, if they're all busy)
+    /// and wait for the response. Workers that time out or misbehave are
+    /// killed instead of going back in the pool, since we can't know what
+    /// state they're in.
+    pub async fn run(
+        &self,
+        spec: &Spec,
+        request: &Request,
+        children: &Children,
+    ) -> Result<Response> {
+        let mut worker = match self.take(spec)? {
+            Some(worker) => worker,
+            None => Worker::spawn(spec).with_context(|| {
+                format!("could not start a persistent worker for `{}`", spec.tool)
+            })?,
+        };
+
+        let pid = worker.child.id();
+        if let Some(pid) = pid {
+            children.insert(pid);
+        }
+
+        let result = tokio::time::timeout(self.timeout, worker.request(request)).await;
+
+        if let Some(pid) = pid {
+            children.remove(pid);
+        }
+
+        match result {
+            Ok(Ok(response)) => {
+                self.give_back(spec, worker)?;
+                Ok(response)
+            }
+            Ok(Err(err)) => Err(err.context("persistent worker failed")),
+            Err(_) => anyhow::bail!(
+                "persistent worker didn't respond within {:?}, so I stopped it",
+                self.timeout
+            ),
+        }
+    }
+
+    fn take(&self, spec: &Spec) -> Result<Option<Worker>> {
+        let mut idle = self
+            .idle
+            .lock()
+            .map_err(|_| anyhow::anyhow!("worker pool lock was poisoned"))?;
+
+        Ok(idle.get_mut(spec).and_then(|workers| workers.pop()))
+    }
+
+    fn give_back(&self, spec: &Spec, worker: Worker) -> Result<()> {
+        let mut idle = self
+            .idle
+            .lock()
+            .map_err(|_| anyhow::anyhow!("worker pool lock was poisoned"))?;
+
+        idle.entry(spec.clone()).or_default().push(worker);
+
+        Ok(())
+    }
+}
+
+impl Assignment {
+    pub async fn run(&self, children: &Children) -> Result<Response> {
+        self.pool.run(&self.spec, &self.request, children).await
+    }
+}
+
+#[derive(Debug)]
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Worker {
+    fn spawn(spec: &Spec) -> Result<Self> {
+        let mut command = network::command(&spec.tool, spec.network, &NameFiles::default());
+        command
+            .arg("--persistent-worker")
+            .env_clear()
+            .envs(&spec.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+        limits::apply(&mut command, &spec.limits);
+
+        let mut child = command.spawn().context("could not run command")?;
+
+        let stdin = child.stdin.take().context("worker had no stdin")?;
+        let stdout = child.stdout.take().context("worker had no stdout")?;
+
+        Ok(Worker {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    async fn request(&mut self, request: &Request) -> Result<Response> {
+        let mut line = serde_json::to_vec(request).context("could not serialize work request")?;
+        line.push(b'\n');
+
+        self.stdin
+            .write_all(&line)
+            .await
+            .context("could not send work request")?;
+        self.stdin
+            .flush()
+            .await
+            .context("could not send work request")?;
+
+        let response = self
+            .stdout
+            .next_line()
+            .await
+            .context("could not read work response")?
+            .context("worker exited without responding")?;
+
+        serde_json::from_str(&response)
+            .with_context(|| format!("could not parse work response `{}`", response))
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod test {
+    use super::*;
+
+    /// A worker that answers every request with its own PID, so we can tell
+    /// whether requests went to the same process.
+    const ECHO_PID: &str = r#"
+        while read -r line; do
+            printf '{"exitCode": 0, "output": "%s"}\n' "$$"
+        done
+    "#;
+
+    fn spec(dir: &tempfile::TempDir, script: &str) -> Spec {
+        let tool = dir.path().join("worker");
+        std::fs::write(&tool, format!("#!/bin/sh\n{}", script)).unwrap();
+
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        Spec {
+            tool: tool.display().to_string(),
+            env: BTreeMap::new(),
+            limits: Limits::new(),
+            network: Network::Allowed,
+        }
+    }
+
+    fn request() -> Request {
+        Request {
+            arguments: vec!["compile".to_string()],
+            cwd: PathBuf::from("/"),
+            env: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reuses_workers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let spec = spec(&dir, ECHO_PID);
+        let pool = Pool::new(Duration::from_secs(10));
+        let children = Children::default();
+
+        let first = pool.run(&spec, &request(), &children).await.unwrap();
+        let second = pool.run(&spec, &request(), &children).await.unwrap();
+
+        assert_eq!(0, first.exit_code);
+        assert_eq!(first.output, second.output);
+    }
+
+    #[tokio::test]
+    async fn times_out_and_discards_stuck_workers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let spec = spec(&dir, "sleep 60");
+        let pool = Pool::new(Duration::from_millis(100));
+
+        assert!(pool
+            .run(&spec, &request(), &Children::default())
+            .await
+            .is_err());
+        assert!(pool.take(&spec).unwrap().is_none());
+    }
+}
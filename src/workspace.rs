@@ -1,28 +1,28 @@
-use crate::{job, store};
+use crate::snapshot::Snapshot;
+use crate::{glue, job, resolver, store};
 use anyhow::{Context, Result};
 use path_absolutize::Absolutize;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use tokio::fs;
 
-#[cfg(target_family = "windows")]
-use std::os::windows::fs::symlink_file;
-
 #[derive(Debug)]
 pub struct Workspace {
     root: PathBuf,
     build_root: PathBuf,
     home_dir: PathBuf,
+    tmp_dir: PathBuf,
+
+    // if we got this workspace from a pool, we give it back when we're done
+    // instead of removing it.
+    pool: Option<Pool>,
 }
 
 impl Workspace {
     pub async fn create<Finality>(root: &Path, key: &job::Key<Finality>) -> Result<Self> {
-        let root = root.join(key.to_string());
-        let workspace = Workspace {
-            build_root: root.join("build"),
-            home_dir: root.join("home"),
-            root,
-        };
+        let workspace = Self::skeleton(root.join(key.to_string()));
 
         std::fs::create_dir_all(&workspace.build_root)
             .context("could not create workspace build directory")?;
@@ -30,16 +30,55 @@ impl Workspace {
         std::fs::create_dir(&workspace.home_dir)
             .context("could not create workspace home directory")?;
 
+        std::fs::create_dir(&workspace.tmp_dir)
+            .context("could not create workspace temporary directory")?;
+
         Ok(workspace)
     }
 
+    fn skeleton(root: PathBuf) -> Self {
+        let [build_root, home_dir, tmp_dir] = Self::dirs(&root);
+
+        Workspace {
+            build_root,
+            home_dir,
+            tmp_dir,
+            root,
+            pool: None,
+        }
+    }
+
+    /// The directories a workspace rooted at `root` has: the build root,
+    /// home directory, and temporary directory.
+    fn dirs(root: &Path) -> [PathBuf; 3] {
+        [root.join("build"), root.join("home"), root.join("tmp")]
+    }
+
     pub async fn set_up_files(
         &self,
         job: &job::Job,
         job_to_store_path: &HashMap<job::Key<job::Base>, store::Item>,
+        spec_to_resolved: &HashMap<resolver::Spec, resolver::Resolved>,
+        snapshot: Option<&Snapshot>,
     ) -> Result<()> {
         for file in &job.input_files {
-            self.set_up_path(&file.source, &file.dest).await?
+            match snapshot {
+                Some(snapshot) => {
+                    let copy = snapshot.path_for(&file.source).with_context(|| {
+                        format!(
+                            "`{}` wasn't in the input snapshot. This is a bug in rbt, please file it!",
+                            file.source.display()
+                        )
+                    })?;
+
+                    self.set_up_path(&copy, &file.dest, job.input_strategy, false)
+                        .await?
+                }
+                None => {
+                    self.set_up_path(&file.source, &file.dest, job.input_strategy, false)
+                        .await?
+                }
+            }
         }
 
         for (key, files) in &job.input_jobs {
@@ -51,16 +90,141 @@ impl Workspace {
             // but creating parent directories in parallel may cause contention
             // issues.
             for file in files {
-                self.set_up_path(&store_item.join(&file.source), &file.dest)
-                    .await?
+                self.set_up_path(
+                    &store_item.join(&file.source),
+                    &file.dest,
+                    job.input_strategy,
+                    job.writable_inputs.contains(&file.dest),
+                )
+                .await?
+            }
+        }
+
+        for (spec, files) in &job.input_resolvers {
+            let resolved = spec_to_resolved
+                .get(spec)
+                .with_context(|| format!("could not find resolved files for {}", spec))?;
+
+            for file in files {
+                self.set_up_path(
+                    &resolved.join(&file.source),
+                    &file.dest,
+                    job.input_strategy,
+                    false,
+                )
+                .await?
             }
         }
 
         Ok(())
     }
 
-    async fn set_up_path(&self, src: &Path, local_dest: &Path) -> Result<()> {
-        log::trace!("symlinking {} to {}", src.display(), local_dest.display());
+    /// Link each of the job's incremental state directories into the
+    /// workspace. These live below `root`, keyed by the job's base key, so
+    /// that the next run of the same job sees whatever the last one left.
+    pub async fn set_up_incremental_state(&self, job: &job::Job, root: &Path) -> Result<()> {
+        for dir in &job.incremental_state {
+            let state = root.join(job.base_key.to_string()).join(dir);
+            fs::create_dir_all(&state).await.with_context(|| {
+                format!(
+                    "could not create incremental state directory `{}`",
+                    state.display()
+                )
+            })?;
+
+            tracing::trace!("linking incremental state {}", dir.display());
+            self.link_dir(&state, dir).await.with_context(|| {
+                format!(
+                    "could not link incremental state `{}` into workspace",
+                    dir.display()
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Link the job's checkpoint directory into the workspace, if it has
+    /// one, and return where it really is. It lives below `root`, keyed by
+    /// the job's final key, so a run that gets interrupted leaves its
+    /// checkpoints for the next run of the same job with the same inputs,
+    /// but a change to the job or its inputs starts over. The job writes
+    /// straight into it, so even a run that gets killed keeps everything it
+    /// finished writing.
+    pub async fn set_up_checkpoints(
+        &self,
+        job: &job::Job,
+        final_key: &job::Key<job::Final>,
+        root: &Path,
+    ) -> Result<Option<PathBuf>> {
+        let dir = match &job.checkpoint_dir {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+
+        let checkpoints = root.join(final_key.to_string());
+        let resuming = checkpoints.exists();
+        fs::create_dir_all(&checkpoints).await.with_context(|| {
+            format!(
+                "could not create checkpoint directory `{}`",
+                checkpoints.display()
+            )
+        })?;
+
+        if resuming {
+            tracing::info!("resuming {} from its checkpoints", job);
+        }
+
+        self.link_dir(&checkpoints, dir).await.with_context(|| {
+            format!(
+                "could not link checkpoint directory `{}` into workspace",
+                dir.display()
+            )
+        })?;
+
+        Ok(Some(checkpoints))
+    }
+
+    /// Put a symlink to `target` (a directory outside the workspace) at
+    /// `dir` in the workspace.
+    async fn link_dir(&self, target: &Path, dir: &Path) -> Result<()> {
+        if let Some(parent_base) = dir.parent() {
+            fs::create_dir_all(self.join_build(parent_base))
+                .await
+                .with_context(|| format!("could not create parent for `{}`", dir.display()))?;
+        }
+
+        let absolute_target = target.absolutize().with_context(|| {
+            format!(
+                "could not convert `{}` to an absolute path",
+                target.display()
+            )
+        })?;
+
+        #[cfg(target_family = "unix")]
+        fs::symlink(absolute_target, self.join_build(dir))
+            .await
+            .context("could not create symlink")?;
+
+        #[cfg(target_family = "windows")]
+        fs::symlink_dir(absolute_target, self.join_build(dir))
+            .await
+            .context("could not create symlink")?;
+
+        Ok(())
+    }
+
+    /// Put `src` at `local_dest` in the workspace the way the job's
+    /// `strategy` asks. If it has to be `writable`, it's always a copy, so
+    /// the job can't change the original.
+    async fn set_up_path(
+        &self,
+        src: &Path,
+        local_dest: &Path,
+        strategy: InputStrategy,
+        writable: bool,
+    ) -> Result<()> {
+        tracing::trace!("setting up {} at {}", src.display(), local_dest.display());
 
         // validate that the path exists and is a file
         let meta = fs::metadata(src)
@@ -76,7 +240,7 @@ impl Workspace {
 
         if let Some(parent_base) = local_dest.parent() {
             let parent = self.join_build(parent_base);
-            log::trace!("making parent {parent:?}");
+            tracing::trace!("making parent {parent:?}");
 
             if !parent.exists() {
                 fs::create_dir_all(parent).await.with_context(|| {
@@ -90,51 +254,513 @@ impl Workspace {
         })?;
 
         let final_dest = self.join_build(local_dest);
-        log::trace!("symlinking to {final_dest:?}");
 
-        #[cfg(target_family = "unix")]
-        fs::symlink(absolute_src, &final_dest)
-            .await
-            .with_context(|| {
-                format!(
-                    "could not symlink `{}` into workspace",
-                    final_dest.display()
-                )
-            })?;
+        let strategies: &[Materialize] = if writable {
+            &[Materialize::WritableCopy]
+        } else {
+            strategy.materializations()
+        };
 
-        #[cfg(target_family = "windows")]
-        fs::symlink_file(absolute_src, &final_dest)
-            .await
-            .with_context(|| {
-                format!(
-                    "could not symlink `{}` into workspace",
-                    final_file.display()
-                )
-            })?;
+        let mut problems = Vec::with_capacity(strategies.len());
+        for strategy in strategies {
+            match strategy
+                .materialize(&absolute_src, &final_dest, &meta)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    tracing::debug!(
+                        "could not {strategy:?} `{}`, trying the next way: {err:?}",
+                        final_dest.display()
+                    );
+                    problems.push(format!("{strategy:?}: {err:#}"));
+                }
+            }
+        }
 
-        Ok(())
+        anyhow::bail!(
+            "could not put `{}` into the workspace: {}",
+            final_dest.display(),
+            problems.join("; ")
+        )
     }
 
     pub fn join_build<P: AsRef<Path>>(&self, other: P) -> PathBuf {
         self.build_root.join(other)
     }
 
+    /// Where the job runs, and where it writes its outputs.
+    pub fn build_root(&self) -> &Path {
+        &self.build_root
+    }
+
     pub fn home_dir(&self) -> &Path {
         &self.home_dir
     }
+
+    /// Where the job's `TMPDIR` points. It's outside the build directory so
+    /// scratch files don't look like undeclared outputs, and we throw it
+    /// away with the rest of the workspace without looking at it.
+    pub fn tmp_dir(&self) -> &Path {
+        &self.tmp_dir
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Files in the workspace that aren't in `expected` (see
+    /// `Job::expected_paths`) or inside a directory that is. We don't look
+    /// inside symlinked directories, since they're incremental state.
+    pub fn undeclared_files(&self, expected: &BTreeSet<PathBuf>) -> Result<Vec<PathBuf>> {
+        let mut undeclared = Vec::new();
+
+        let walker = walkdir::WalkDir::new(&self.build_root)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| match entry.path().strip_prefix(&self.build_root) {
+                Ok(relative) => !expected.contains(relative),
+                Err(_) => true,
+            });
+
+        for entry in walker {
+            let entry = entry.context("could not look through workspace")?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            undeclared.push(
+                entry
+                    .path()
+                    .strip_prefix(&self.build_root)
+                    .context("workspace file was outside the workspace")?
+                    .to_path_buf(),
+            );
+        }
+
+        Ok(undeclared)
+    }
+}
+
+/// How a job wants its inputs put into its workspace. Symlinks are cheapest,
+/// but some tools (certain compilers and file watchers) resolve them and end
+/// up looking outside the workspace, or don't follow them at all. Those jobs
+/// can ask for hardlinks or copies instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InputStrategy {
+    #[default]
+    Symlink,
+    Hardlink,
+    Copy,
+}
+
+impl InputStrategy {
+    pub fn from_glue(strategy: glue::InputStrategy) -> Self {
+        match strategy {
+            glue::InputStrategy::Symlink => InputStrategy::Symlink,
+            glue::InputStrategy::Hardlink => InputStrategy::Hardlink,
+            glue::InputStrategy::Copy => InputStrategy::Copy,
+        }
+    }
+
+    /// What we call this in job keys, matching the Roc tag.
+    pub fn name(self) -> &'static str {
+        match self {
+            InputStrategy::Symlink => "Symlink",
+            InputStrategy::Hardlink => "Hardlink",
+            InputStrategy::Copy => "Copy",
+        }
+    }
+
+    /// The ways to try, in order. Hardlinks can't cross filesystems, so we
+    /// still fall back to copying, but never to a symlink: the job asked
+    /// not to get one.
+    fn materializations(self) -> &'static [Materialize] {
+        match self {
+            InputStrategy::Symlink => &Materialize::ALL,
+            InputStrategy::Hardlink => &[Materialize::Hardlink, Materialize::Copy],
+            InputStrategy::Copy => &[Materialize::Copy],
+        }
+    }
+}
+
+/// Ways of putting an input file into a workspace, from cheapest to most
+/// expensive. We use the first one that works: symlinks need privileges on
+/// Windows, and hardlinks can't cross filesystems, but copying always works.
+///
+/// Whichever one we use, the file has to keep its mode so that scripts
+/// declared as inputs can still be run from inside the workspace. Symlinks and
+/// hardlinks get that for free since they point at the same file, but copies
+/// have to be given it on purpose.
+///
+/// `WritableCopy` isn't one of the choices: it's only for inputs that asked
+/// for it with `withWritableOutput`, since the store's read-only files have to
+/// stay that way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Materialize {
+    Symlink,
+    Hardlink,
+    Copy,
+    WritableCopy,
+}
+
+impl Materialize {
+    const ALL: [Materialize; 3] = [
+        Materialize::Symlink,
+        Materialize::Hardlink,
+        Materialize::Copy,
+    ];
+
+    async fn materialize(self, src: &Path, dest: &Path, meta: &std::fs::Metadata) -> Result<()> {
+        match self {
+            Materialize::Symlink => {
+                #[cfg(target_family = "unix")]
+                fs::symlink(src, dest).await.context("could not symlink")?;
+
+                #[cfg(target_family = "windows")]
+                fs::symlink_file(src, dest)
+                    .await
+                    .context("could not symlink")?;
+            }
+            Materialize::Hardlink => {
+                fs::hard_link(src, dest)
+                    .await
+                    .context("could not hardlink")?;
+            }
+            Materialize::Copy => {
+                fs::copy(src, dest).await.context("could not copy")?;
+
+                // `copy` usually carries permissions over already, but not on
+                // every platform, and this is what keeps scripts runnable.
+                fs::set_permissions(dest, meta.permissions())
+                    .await
+                    .context("could not set permissions on copy")?;
+            }
+            Materialize::WritableCopy => {
+                fs::copy(src, dest).await.context("could not copy")?;
+
+                let mut permissions = meta.permissions();
+
+                #[cfg(target_family = "unix")]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    permissions.set_mode(permissions.mode() | 0o200);
+                }
+
+                #[cfg(not(target_family = "unix"))]
+                #[allow(clippy::permissions_set_readonly_false)]
+                permissions.set_readonly(false);
+
+                fs::set_permissions(dest, permissions)
+                    .await
+                    .context("could not set permissions on copy")?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for Workspace {
-    // TODO: measure and see if blocking on these drops is affecting
-    // performance, and consider moving this to a cleanup function that we call
-    // by hand.
+    // TODO: measure and see if blocking on removing unpooled workspaces is
+    // affecting performance, and consider handing them to a background
+    // thread like the pool does.
     fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.recycle(std::mem::take(&mut self.root));
+            return;
+        }
+
         if let Err(problem) = std::fs::remove_dir_all(&self.root) {
-            log::warn!("problem removing workspace dir: {}", problem);
+            tracing::warn!("problem removing workspace dir: {}", problem);
         };
     }
 }
 
+/// A pool of empty workspace skeletons. When we're running lots of tiny jobs,
+/// creating and removing the same three directories for every job starts to
+/// add up, so instead we keep workspaces around after they're used, clear out
+/// their contents, and hand them to the next job. `--stable-paths` always
+/// uses one, since its slots' paths are all the same length.
+///
+/// Clearing a workspace out happens on a background thread, so a job that
+/// just finished doesn't hold up the next one. A workspace only goes back on
+/// the free list once it's empty, so jobs can't see each other's files.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+/// The slot numbers every pool in this process is using. The daemon runs
+/// builds side by side, each with its own pool in the same directory, so
+/// they can't all start counting from zero.
+static SLOT_NUMBERS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
+#[derive(Debug)]
+struct PoolInner {
+    root: PathBuf,
+    slots: Arc<Slots>,
+
+    /// Which of `SLOT_NUMBERS` are ours, to give back when we're dropped.
+    numbers: Mutex<Vec<usize>>,
+
+    /// How many slots we'd like to have. Below this, we make a new slot
+    /// instead of waiting for one to be cleared.
+    capacity: AtomicUsize,
+
+    /// Where to send used workspaces to be cleared. Only `None` once we're
+    /// being dropped.
+    to_clear: Option<mpsc::Sender<PathBuf>>,
+    clearer: Option<std::thread::JoinHandle<()>>,
+}
+
+/// The slots we have, shared with the thread that clears them.
+#[derive(Debug, Default)]
+struct Slots {
+    state: Mutex<SlotState>,
+    cleared: tokio::sync::Notify,
+}
+
+#[derive(Debug, Default)]
+struct SlotState {
+    free: Vec<PathBuf>,
+    clearing: usize,
+    total: usize,
+}
+
+impl Pool {
+    pub fn new(root: PathBuf) -> Self {
+        let slots = Arc::new(Slots::default());
+        let (to_clear, used) = mpsc::channel();
+
+        let clearer = {
+            let slots = slots.clone();
+            std::thread::spawn(move || Self::clear_all(used, &slots))
+        };
+
+        Pool {
+            inner: Arc::new(PoolInner {
+                root,
+                slots,
+                numbers: Mutex::new(Vec::new()),
+                capacity: AtomicUsize::new(0),
+                to_clear: Some(to_clear),
+                clearer: Some(clearer),
+            }),
+        }
+    }
+
+    /// Create `count` empty workspaces ahead of time so that the jobs that
+    /// use them don't have to wait.
+    pub fn prewarm(&self, count: usize) -> Result<()> {
+        self.inner.capacity.fetch_add(count, Ordering::Relaxed);
+
+        for _ in 0..count {
+            let root = self.create_slot()?;
+
+            let mut state = self.inner.slots.lock();
+            state.free.push(root);
+            state.total += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Get an empty workspace, either by recycling one we've used before or
+    /// by making a new one. If we already have as many slots as we'd like
+    /// and some are being cleared, we wait for one of those.
+    pub async fn acquire(&self) -> Result<Workspace> {
+        loop {
+            // we ask to be told about cleared slots before looking, so one
+            // that gets cleared in between still wakes us up.
+            let cleared = self.inner.slots.cleared.notified();
+
+            let create = {
+                let mut state = self.inner.slots.lock();
+                if let Some(root) = state.free.pop() {
+                    return Ok(self.lend(root));
+                }
+
+                let create = state.clearing == 0
+                    || state.total < self.inner.capacity.load(Ordering::Relaxed);
+                if create {
+                    state.total += 1;
+                }
+                create
+            };
+
+            if create {
+                let pool = self.clone();
+                let created = tokio::task::spawn_blocking(move || pool.create_slot())
+                    .await
+                    .context("could not join slot creation task")
+                    .and_then(|created| created);
+
+                return match created {
+                    Ok(root) => Ok(self.lend(root)),
+                    Err(problem) => {
+                        self.inner.slots.lock().total -= 1;
+                        Err(problem)
+                    }
+                };
+            }
+
+            cleared.await;
+        }
+    }
+
+    /// Hand out the slot at `root` as a workspace that comes back to us
+    /// when it's dropped.
+    fn lend(&self, root: PathBuf) -> Workspace {
+        let mut workspace = Workspace::skeleton(root);
+        workspace.pool = Some(self.clone());
+
+        workspace
+    }
+
+    /// Make a new, empty slot, returning its root.
+    fn create_slot(&self) -> Result<PathBuf> {
+        let slot = {
+            let mut taken = SLOT_NUMBERS.lock().expect("slot numbers lock was poisoned");
+            let slot = (0..)
+                .find(|slot| !taken.contains(slot))
+                .expect("ran out of slot numbers");
+            taken.insert(slot);
+            slot
+        };
+        self.inner
+            .numbers
+            .lock()
+            .expect("slot numbers lock was poisoned")
+            .push(slot);
+
+        // padded so that every slot's path is the same length, for tools
+        // that behave differently depending on how long paths are.
+        let root = self.inner.root.join(format!("pool-{:04}", slot));
+
+        // a previous build may have left this slot behind if it crashed, so
+        // we start over from scratch to keep the isolation guarantees.
+        if root.exists() {
+            std::fs::remove_dir_all(&root)
+                .with_context(|| format!("could not remove stale `{}`", root.display()))?;
+        }
+
+        let [build_root, home_dir, tmp_dir] = Workspace::dirs(&root);
+
+        std::fs::create_dir_all(build_root)
+            .context("could not create workspace build directory")?;
+
+        std::fs::create_dir(home_dir).context("could not create workspace home directory")?;
+
+        std::fs::create_dir(tmp_dir).context("could not create workspace temporary directory")?;
+
+        Ok(root)
+    }
+
+    /// Take back a workspace that a job is done with, to be cleared out and
+    /// used again.
+    fn recycle(&self, root: PathBuf) {
+        self.inner.slots.lock().clearing += 1;
+
+        // the clearer runs until we're dropped, which can't have happened
+        // yet since the workspace was holding on to us.
+        self.inner
+            .to_clear
+            .as_ref()
+            .expect("workspace pool was already dropped")
+            .send(root)
+            .expect("workspace pool clearer stopped early");
+    }
+
+    /// Clear out used workspaces as they come in, until the pool is
+    /// dropped. Workspaces we can't clear get removed instead.
+    fn clear_all(used: mpsc::Receiver<PathBuf>, slots: &Slots) {
+        for root in used {
+            let cleared = Self::clear(&root);
+            if let Err(problem) = &cleared {
+                tracing::warn!(
+                    "problem cleaning workspace dir for reuse, so I'm removing it instead: {:?}",
+                    problem
+                );
+
+                if let Err(problem) = std::fs::remove_dir_all(&root) {
+                    tracing::warn!("problem removing workspace dir: {}", problem);
+                }
+            }
+
+            let mut state = slots.lock();
+            state.clearing -= 1;
+            match cleared {
+                Ok(()) => state.free.push(root),
+                Err(_) => state.total -= 1,
+            }
+            slots.cleared.notify_one();
+        }
+    }
+
+    /// Remove everything inside the workspace's directories, leaving the
+    /// directories themselves in place.
+    fn clear(root: &Path) -> Result<()> {
+        for dir in Workspace::dirs(root) {
+            for entry in std::fs::read_dir(&dir)
+                .with_context(|| format!("could not read `{}`", dir.display()))?
+            {
+                let entry = entry.context("could not read entry")?;
+
+                if entry
+                    .file_type()
+                    .context("could not get file type")?
+                    .is_dir()
+                {
+                    std::fs::remove_dir_all(entry.path())
+                } else {
+                    std::fs::remove_file(entry.path())
+                }
+                .with_context(|| format!("could not remove `{}`", entry.path().display()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Slots {
+    fn lock(&self) -> std::sync::MutexGuard<'_, SlotState> {
+        self.state.lock().expect("workspace pool lock was poisoned")
+    }
+}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        // every workspace holds a reference to the pool, so by the time we
+        // get here everything has been sent back to us. Once the clearer
+        // has caught up, everything is on the free list.
+        drop(self.to_clear.take());
+        if let Some(clearer) = self.clearer.take() {
+            if clearer.join().is_err() {
+                tracing::warn!("workspace pool clearer panicked");
+            }
+        }
+
+        for root in self.slots.lock().free.drain(..) {
+            if let Err(problem) = std::fs::remove_dir_all(&root) {
+                tracing::warn!("problem removing pooled workspace dir: {}", problem);
+            }
+        }
+
+        let mut taken = SLOT_NUMBERS.lock().expect("slot numbers lock was poisoned");
+        for slot in self
+            .numbers
+            .get_mut()
+            .expect("slot numbers lock was poisoned")
+            .drain(..)
+        {
+            taken.remove(&slot);
+        }
+    }
+}
+
 impl AsRef<Path> for Workspace {
     fn as_ref(&self) -> &Path {
         &self.build_root
@@ -145,8 +771,11 @@ impl AsRef<Path> for Workspace {
 mod tests {
     use super::*;
     use crate::glue;
-    use roc_std::{RocDict, RocList, RocStr};
-    use std::{collections::HashMap, path::PathBuf};
+    use roc_std::{RocList, RocStr};
+    use std::{
+        collections::{HashMap, HashSet},
+        path::PathBuf,
+    };
     use tempfile::TempDir;
 
     fn key() -> job::Key<job::Final> {
@@ -154,24 +783,26 @@ mod tests {
     }
 
     fn glue_job_with_files(files: &[&str]) -> glue::Job {
+        let mappings: Vec<(&str, &str)> = files.iter().map(|name| (*name, *name)).collect();
+        glue_job(&mappings, &[])
+    }
+
+    fn glue_job(files: &[(&str, &str)], incremental_state: &[&str]) -> glue::Job {
         glue::Job::Job(glue::R1 {
-            command: glue::Command {
-                tool: glue::Tool::SystemTool(glue::SystemToolPayload {
-                    name: RocStr::from("bash"),
-                }),
-                args: RocList::empty(),
-            },
             inputs: RocList::from_slice(&[glue::U1::FromProjectSource(
                 files
                     .iter()
-                    .map(|name| glue::FileMapping {
-                        source: (*name).into(),
-                        dest: (*name).into(),
+                    .map(|(source, dest)| glue::FileMapping {
+                        source: (*source).into(),
+                        dest: (*dest).into(),
                     })
                     .collect(),
             )]),
-            outputs: RocList::empty(),
-            env: RocDict::with_capacity(0),
+            incrementalState: incremental_state
+                .iter()
+                .map(|dir| RocStr::from(*dir))
+                .collect(),
+            ..glue::R1::for_test(job::system_command("bash", &[]))
         })
     }
 
@@ -191,6 +822,119 @@ mod tests {
         assert!(!path.exists());
     }
 
+    #[tokio::test]
+    async fn pool_recycles_cleaned_workspaces() {
+        let temp = TempDir::new().unwrap();
+        let pool = Pool::new(temp.path().to_path_buf());
+
+        let workspace = pool.acquire().await.expect("could not acquire workspace");
+        let path = workspace.as_ref().to_path_buf();
+        std::fs::create_dir(workspace.join_build("leftover-dir")).unwrap();
+        std::fs::write(workspace.join_build("leftover-dir/file"), "hi").unwrap();
+        std::fs::write(workspace.home_dir().join(".bashrc"), "hi").unwrap();
+        std::fs::write(workspace.tmp_dir().join("scratch"), "hi").unwrap();
+        drop(workspace);
+
+        let recycled = pool.acquire().await.expect("could not acquire workspace");
+        assert_eq!(path, recycled.as_ref());
+        assert_eq!(0, std::fs::read_dir(&path).unwrap().count());
+        assert_eq!(0, std::fs::read_dir(recycled.home_dir()).unwrap().count());
+        assert_eq!(0, std::fs::read_dir(recycled.tmp_dir()).unwrap().count());
+
+        drop(recycled);
+        drop(pool);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn pool_slots_have_paths_of_the_same_length() {
+        let temp = TempDir::new().unwrap();
+        let pool = Pool::new(temp.path().to_path_buf());
+        pool.prewarm(11).unwrap();
+
+        let lengths: HashSet<usize> = pool
+            .inner
+            .slots
+            .lock()
+            .free
+            .iter()
+            .map(|root| root.as_os_str().len())
+            .collect();
+        assert_eq!(1, lengths.len());
+    }
+
+    #[test]
+    fn pools_in_the_same_process_use_different_slots() {
+        let temp = TempDir::new().unwrap();
+        let first = Pool::new(temp.path().to_path_buf());
+        let second = Pool::new(temp.path().to_path_buf());
+        first.prewarm(2).unwrap();
+        second.prewarm(2).unwrap();
+
+        let slots = |pool: &Pool| -> HashSet<PathBuf> {
+            pool.inner.slots.lock().free.iter().cloned().collect()
+        };
+        assert!(slots(&first).is_disjoint(&slots(&second)));
+
+        // and the second pool didn't clear away the first one's slots
+        assert_eq!(4, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+
+    /// How long jobs spend waiting to get a workspace and give it back, with
+    /// and without the pool. This is where `POOL_THRESHOLD_JOBS_PER_SECOND`
+    /// in runner.rs comes from. It only means anything in a release build,
+    /// so it doesn't run by default:
+    ///
+    ///     cargo test --release --lib pooling_benchmark -- --ignored --nocapture
+    #[cfg(target_family = "unix")]
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn pooling_benchmark() {
+        use std::time::{Duration, Instant};
+
+        const JOBS: u32 = 5_000;
+
+        let temp = TempDir::new().unwrap();
+        let fresh_root = temp.path().join("fresh");
+        let pool = Pool::new(temp.path().join("pooled"));
+        pool.prewarm(2).unwrap();
+
+        // like a tiny compile step: an input, an output, and some scratch
+        fn run(workspace: &Workspace) {
+            std::os::unix::fs::symlink(file!(), workspace.join_build("input.rs")).unwrap();
+            std::fs::write(workspace.join_build("output.o"), [0; 4096]).unwrap();
+            std::fs::create_dir(workspace.home_dir().join(".cache")).unwrap();
+            std::fs::write(workspace.tmp_dir().join("scratch"), "hi").unwrap();
+        }
+
+        let (mut fresh, mut pooled) = (Duration::ZERO, Duration::ZERO);
+        for _ in 0..JOBS {
+            let started = Instant::now();
+            let workspace = Workspace::create(&fresh_root, &key()).await.unwrap();
+            fresh += started.elapsed();
+            run(&workspace);
+            let started = Instant::now();
+            drop(workspace);
+            fresh += started.elapsed();
+
+            let started = Instant::now();
+            let workspace = pool.acquire().await.unwrap();
+            pooled += started.elapsed();
+            run(&workspace);
+            let started = Instant::now();
+            drop(workspace);
+            pooled += started.elapsed();
+        }
+
+        let (fresh, pooled) = (fresh / JOBS, pooled / JOBS);
+        println!("fresh workspaces:  {:?} per job", fresh);
+        println!("pooled workspaces: {:?} per job", pooled);
+        println!(
+            "pooling saves 1% of a second at {:.0} jobs per second",
+            0.01 / fresh.saturating_sub(pooled).as_secs_f64()
+        );
+    }
+
     #[tokio::test]
     async fn test_sets_up_file() {
         let temp = TempDir::new().unwrap();
@@ -201,7 +945,7 @@ mod tests {
         let glue_job = glue_job_with_files(&[file!()]);
         let job = job::Job::from_glue(&glue_job, &HashMap::new()).unwrap();
         workspace
-            .set_up_files(&job, &HashMap::new())
+            .set_up_files(&job, &HashMap::new(), &HashMap::new(), None)
             .await
             .expect("failed to set up files");
 
@@ -214,6 +958,180 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn sets_up_renamed_files() {
+        let temp = TempDir::new().unwrap();
+        let workspace = Workspace::create(temp.path(), &key())
+            .await
+            .expect("could not create workspace");
+
+        let glue_job = glue_job(&[(file!(), "vendor/renamed.rs")], &[]);
+        let job = job::Job::from_glue(&glue_job, &HashMap::new()).unwrap();
+        workspace
+            .set_up_files(&job, &HashMap::new(), &HashMap::new(), None)
+            .await
+            .expect("failed to set up files");
+
+        let path = workspace.join_build("vendor/renamed.rs");
+        assert_eq!(
+            PathBuf::from(file!()).absolutize().unwrap(),
+            path.read_link().unwrap()
+        );
+
+        // only the new name shows up in the workspace
+        assert!(!workspace.join_build(file!()).exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn scripts_stay_executable_however_they_are_materialized() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new().unwrap();
+        let script = temp.path().join("script.sh");
+        std::fs::write(&script, "#!/bin/sh\necho hi\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let meta = std::fs::metadata(&script).unwrap();
+
+        let workspace = Workspace::create(temp.path(), &key())
+            .await
+            .expect("could not create workspace");
+
+        for strategy in Materialize::ALL {
+            let dest = workspace.join_build(format!("{strategy:?}.sh"));
+            strategy
+                .materialize(&script, &dest, &meta)
+                .await
+                .unwrap_or_else(|err| panic!("could not {strategy:?}: {err:?}"));
+
+            let output = std::process::Command::new(&dest)
+                .current_dir(workspace.join_build(""))
+                .output()
+                .unwrap_or_else(|err| panic!("could not run {strategy:?} script: {err:?}"));
+            assert_eq!("hi\n", String::from_utf8_lossy(&output.stdout));
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn writable_inputs_are_copies() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new().unwrap();
+        let original = temp.path().join("fixture.db");
+        std::fs::write(&original, "tables").unwrap();
+        std::fs::set_permissions(&original, std::fs::Permissions::from_mode(0o444)).unwrap();
+
+        let workspace = Workspace::create(temp.path(), &key())
+            .await
+            .expect("could not create workspace");
+        workspace
+            .set_up_path(
+                &original,
+                Path::new("test.db"),
+                InputStrategy::Symlink,
+                true,
+            )
+            .await
+            .unwrap();
+
+        let copy = workspace.join_build("test.db");
+        assert!(!copy.is_symlink());
+        std::fs::write(&copy, "changed").unwrap();
+
+        assert_eq!("tables", std::fs::read_to_string(&original).unwrap());
+        assert!(std::fs::metadata(&original)
+            .unwrap()
+            .permissions()
+            .readonly());
+    }
+
+    #[tokio::test]
+    async fn input_strategies_avoid_symlinks_when_asked() {
+        let temp = TempDir::new().unwrap();
+        let original = temp.path().join("index.ts");
+        std::fs::write(&original, "export {}").unwrap();
+
+        let workspace = Workspace::create(temp.path(), &key())
+            .await
+            .expect("could not create workspace");
+
+        for strategy in [
+            InputStrategy::Symlink,
+            InputStrategy::Hardlink,
+            InputStrategy::Copy,
+        ] {
+            let dest = PathBuf::from(strategy.name()).join("index.ts");
+            workspace
+                .set_up_path(&original, &dest, strategy, false)
+                .await
+                .unwrap();
+
+            let dest = workspace.join_build(&dest);
+            assert_eq!(strategy == InputStrategy::Symlink, dest.is_symlink());
+            assert_eq!("export {}", std::fs::read_to_string(&dest).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_undeclared_files() {
+        let temp = TempDir::new().unwrap();
+        let workspace = Workspace::create(temp.path(), &key())
+            .await
+            .expect("could not create workspace");
+
+        for file in [
+            "main.c",
+            "out/app",
+            "out/app.d",
+            "dist/docs/index.html",
+            "core",
+        ] {
+            let path = workspace.join_build(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let expected = ["main.c", "out/app", "dist"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+
+        assert_eq!(
+            vec![PathBuf::from("core"), PathBuf::from("out/app.d")],
+            workspace.undeclared_files(&expected).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn incremental_state_outlives_workspace() {
+        let temp = TempDir::new().unwrap();
+        let state_root = temp.path().join("incremental");
+
+        let glue_job = glue_job(&[], &["cache/tsc"]);
+        let job = job::Job::from_glue(&glue_job, &HashMap::new()).unwrap();
+
+        let workspace = Workspace::create(temp.path(), &key())
+            .await
+            .expect("could not create workspace");
+        workspace
+            .set_up_incremental_state(&job, &state_root)
+            .await
+            .expect("failed to set up incremental state");
+        std::fs::write(workspace.join_build("cache/tsc/buildinfo"), "hi").unwrap();
+        drop(workspace);
+
+        assert_eq!(
+            "hi",
+            std::fs::read_to_string(
+                state_root
+                    .join(job.base_key.to_string())
+                    .join("cache/tsc/buildinfo")
+            )
+            .unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn test_rejects_missing_file() {
         let temp = TempDir::new().unwrap();
@@ -227,7 +1145,7 @@ mod tests {
         assert_eq!(
             String::from("`does-not-exist` does not exist"),
             workspace
-                .set_up_files(&job, &HashMap::new())
+                .set_up_files(&job, &HashMap::new(), &HashMap::new(), None)
                 .await
                 .unwrap_err()
                 .to_string(),
@@ -255,7 +1173,7 @@ mod tests {
                 parent.display()
             ),
             workspace
-                .set_up_files(&job, &HashMap::new())
+                .set_up_files(&job, &HashMap::new(), &HashMap::new(), None)
                 .await
                 .unwrap_err()
                 .to_string()
@@ -4,6 +4,14 @@ use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 
 fn output_of_default_job(root: &TempDir, rbt_dot_roc: &Path) -> Result<PathBuf> {
+    output_of_default_job_with_args(root, rbt_dot_roc, &[])
+}
+
+fn output_of_default_job_with_args(
+    root: &TempDir,
+    rbt_dot_roc: &Path,
+    args: &[&str],
+) -> Result<PathBuf> {
     let current_dir = rbt_dot_roc
         .parent()
         .context("failed to get parent of the target rbt.roc")?;
@@ -18,6 +26,7 @@ fn output_of_default_job(root: &TempDir, rbt_dot_roc: &Path) -> Result<PathBuf>
         .arg("--root-dir")
         .arg(root.path().display().to_string())
         .arg("--print-root-output-paths")
+        .args(args)
         .current_dir(current_dir.display().to_string())
         .output()
         .context("failed to spawn `roc run`")?;
@@ -63,6 +72,33 @@ fn test_file_inputs_renaming() {
     assert_eq!(String::from("Hello, World!\n"), greeting)
 }
 
+#[test]
+fn test_then_run() {
+    let root = TempDir::new().unwrap();
+
+    let store_path =
+        output_of_default_job(&root, &PathBuf::from("tests/end_to_end/then_run/rbt.roc")).unwrap();
+
+    let greeting = std::fs::read_to_string(store_path.join("out")).unwrap();
+
+    assert_eq!(String::from("Hello, World!\n"), greeting)
+}
+
+#[test]
+fn test_tool_from_job() {
+    let root = TempDir::new().unwrap();
+
+    let store_path = output_of_default_job(
+        &root,
+        &PathBuf::from("tests/end_to_end/tool_from_job/rbt.roc"),
+    )
+    .unwrap();
+
+    let greeting = std::fs::read_to_string(store_path.join("out")).unwrap();
+
+    assert_eq!(String::from("Hello, World!\n"), greeting)
+}
+
 #[test]
 fn test_env() {
     let root = TempDir::new().unwrap();
@@ -117,6 +153,36 @@ fn test_job_inputs() {
     assert_eq!(String::from("Hello, World!\n"), greeting)
 }
 
+#[test]
+fn test_job_inputs_renaming() {
+    let root = TempDir::new().unwrap();
+
+    let store_path = output_of_default_job(
+        &root,
+        &PathBuf::from("tests/end_to_end/job_inputs_renaming/rbt.roc"),
+    )
+    .unwrap();
+
+    let greeting = std::fs::read_to_string(store_path.join("out")).unwrap();
+
+    assert_eq!(String::from("Hello, World!\n"), greeting)
+}
+
+#[test]
+fn test_job_inputs_subpath() {
+    let root = TempDir::new().unwrap();
+
+    let store_path = output_of_default_job(
+        &root,
+        &PathBuf::from("tests/end_to_end/job_inputs_subpath/rbt.roc"),
+    )
+    .unwrap();
+
+    let greeting = std::fs::read_to_string(store_path.join("out")).unwrap();
+
+    assert_eq!(String::from("Hello, World!\n"), greeting)
+}
+
 #[test]
 fn test_job_inputs_branching() {
     let root = TempDir::new().unwrap();
@@ -131,3 +197,111 @@ fn test_job_inputs_branching() {
 
     assert_eq!(String::from("Hello, World!\n"), greeting)
 }
+
+#[test]
+fn test_expect_failure() {
+    let root = TempDir::new().unwrap();
+
+    let store_path = output_of_default_job(
+        &root,
+        &PathBuf::from("tests/end_to_end/expect_failure/rbt.roc"),
+    )
+    .unwrap();
+
+    let stderr = std::fs::read_to_string(store_path.join("stderr")).unwrap();
+
+    assert_eq!(String::from("Hello, World!\n"), stderr)
+}
+
+#[test]
+fn test_define() {
+    let root = TempDir::new().unwrap();
+    let rbt_dot_roc = PathBuf::from("tests/end_to_end/define/rbt.roc");
+
+    let default_path = output_of_default_job(&root, &rbt_dot_roc).unwrap();
+    let defined_path =
+        output_of_default_job_with_args(&root, &rbt_dot_roc, &["--define", "greeting=Howdy"])
+            .unwrap();
+
+    assert_eq!(
+        String::from("Hello, World!\n"),
+        std::fs::read_to_string(default_path.join("out")).unwrap()
+    );
+    assert_eq!(
+        String::from("Howdy, World!\n"),
+        std::fs::read_to_string(defined_path.join("out")).unwrap()
+    );
+}
+
+#[test]
+fn test_targets() {
+    let root = TempDir::new().unwrap();
+    let rbt_dot_roc = PathBuf::from("tests/end_to_end/targets/rbt.roc");
+
+    let default_path = output_of_default_job(&root, &rbt_dot_roc).unwrap();
+    let target_path =
+        output_of_default_job_with_args(&root, &rbt_dot_roc, &["build", "howdy"]).unwrap();
+
+    assert_eq!(
+        String::from("Hello, World!\n"),
+        std::fs::read_to_string(default_path.join("out")).unwrap()
+    );
+    assert_eq!(
+        String::from("Howdy, World!\n"),
+        std::fs::read_to_string(target_path.join("out")).unwrap()
+    );
+    assert!(output_of_default_job_with_args(&root, &rbt_dot_roc, &["build", "hwody"]).is_err());
+}
+
+#[test]
+fn test_compose() {
+    let root = TempDir::new().unwrap();
+    let rbt_dot_roc = PathBuf::from("tests/end_to_end/compose/rbt.roc");
+
+    let package_path =
+        output_of_default_job_with_args(&root, &rbt_dot_roc, &["build", "texan"]).unwrap();
+    let english_formal =
+        output_of_default_job_with_args(&root, &rbt_dot_roc, &["build", "english/formal"]).unwrap();
+    let texan_formal =
+        output_of_default_job_with_args(&root, &rbt_dot_roc, &["build", "texan/formal"]).unwrap();
+
+    assert_eq!(
+        String::from("Howdy, World!\n"),
+        std::fs::read_to_string(package_path.join("out")).unwrap()
+    );
+    assert_eq!(
+        String::from("Good day, World!\n"),
+        std::fs::read_to_string(english_formal.join("out")).unwrap()
+    );
+
+    // the same job in two packages is one job
+    assert_eq!(english_formal, texan_formal);
+}
+
+#[test]
+fn test_tests() {
+    let root = TempDir::new().unwrap();
+
+    let run_tests = || {
+        let output = std::process::Command::new("roc")
+            .arg("run")
+            .arg("rbt.roc")
+            .arg("--")
+            .arg("--root-dir")
+            .arg(root.path().display().to_string())
+            .arg("test")
+            .current_dir("tests/end_to_end/tests")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{:#?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let first = run_tests();
+    assert!(first.contains("1 passed (0 cached)"), "{}", first);
+
+    let second = run_tests();
+    assert!(second.contains("cached pass"), "{}", second);
+    assert!(second.contains("1 passed (1 cached)"), "{}", second);
+}
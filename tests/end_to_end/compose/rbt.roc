@@ -0,0 +1,42 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.compose {
+        default: greet "Hello",
+        packages: Dict.empty
+        |> Dict.insert "english" english
+        |> Dict.insert "texan" texan,
+    }
+
+english : Rbt
+english =
+    Rbt.init {
+        default: greet "Hello",
+        targets: Dict.empty
+        |> Dict.insert "formal" (greet "Good day"),
+    }
+
+texan : Rbt
+texan =
+    Rbt.init {
+        default: greet "Howdy",
+        targets: Dict.empty
+        |> Dict.insert "formal" (greet "Good day"),
+    }
+
+greet : Str -> Job
+greet = \greeting ->
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "echo \"$GREETING, World!\" > out",
+        ],
+        inputs: [],
+        outputs: ["out"],
+        env: Dict.empty
+        |> Dict.insert "GREETING" greeting,
+    }
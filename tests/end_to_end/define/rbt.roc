@@ -0,0 +1,21 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, define, systemTool, Job, job, exec }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \config ->
+    Rbt.init { default: hello config }
+
+hello : Config -> Job
+hello = \config ->
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "echo \"$GREETING, World!\" > out",
+        ],
+        inputs: [],
+        outputs: ["out"],
+        env: Dict.empty
+        |> Dict.insert "GREETING" (define config "greeting" "Hello"),
+    }
@@ -0,0 +1,21 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec, expectFailure }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.init { default: rejected }
+
+rejected : Job
+rejected =
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "echo 'Hello, World!' >&2; exit 1",
+        ],
+        inputs: [],
+        outputs: ["stderr"],
+        env: Dict.empty,
+    }
+    |> expectFailure
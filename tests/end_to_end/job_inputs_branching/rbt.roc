@@ -1,10 +1,10 @@
 app "build"
     packages { pf: "../../../Package-Config.roc" }
-    imports [pf.Rbt.{ Rbt, systemTool, Job, job, exec, sourceFile, fromJob }]
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec, sourceFile, fromJob }]
     provides [init] to pf
 
-init : Rbt
-init =
+init : Config -> Rbt
+init = \_ ->
     Rbt.init { default: helloWorld }
 
 helloWorld : Job
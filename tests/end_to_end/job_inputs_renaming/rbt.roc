@@ -0,0 +1,54 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec, sourceFile, withFilename, fromJob }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.init { default: helloWorld }
+
+helloWorld : Job
+helloWorld =
+    job {
+        command: exec (systemTool "bash") [
+            "-euo",
+            "pipefail",
+            "-c",
+            
+            """
+            WHAT="$(cat words/what)"
+            WHO="$(cat words/who)"
+            printf '%s, %s!\n' "$WHAT" "$WHO" > out
+            """,
+        ],
+        inputs: [
+            fromJob greeting [sourceFile "out" |> withFilename "words/what"],
+            fromJob subject [sourceFile "out" |> withFilename "words/who"],
+        ],
+        outputs: ["out"],
+        env: Dict.empty,
+    }
+
+greeting : Job
+greeting =
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "printf Hello > out",
+        ],
+        inputs: [],
+        outputs: ["out"],
+        env: Dict.empty,
+    }
+
+subject : Job
+subject =
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "printf World > out",
+        ],
+        inputs: [],
+        outputs: ["out"],
+        env: Dict.empty,
+    }
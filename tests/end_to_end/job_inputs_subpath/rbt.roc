@@ -0,0 +1,40 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec, sourceFile, withFilename, fromJob }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.init { default: app }
+
+app : Job
+app =
+    job {
+        command: exec (systemTool "bash") [
+            "-euo",
+            "pipefail",
+            "-c",
+            
+            """
+            if test -e bundle.map; then exit 1; fi
+            cp bundle.js out
+            """,
+        ],
+        inputs: [
+            fromJob bundle [sourceFile "dist/bundle.js" |> withFilename "bundle.js"],
+        ],
+        outputs: ["out"],
+        env: Dict.empty,
+    }
+
+bundle : Job
+bundle =
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "mkdir dist && printf 'Hello, World!\\n' > dist/bundle.js && printf '{}' > dist/bundle.map",
+        ],
+        inputs: [],
+        outputs: ["dist"],
+        env: Dict.empty,
+    }
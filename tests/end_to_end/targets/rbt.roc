@@ -0,0 +1,25 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.init {
+        default: greet "Hello",
+        targets: Dict.empty
+        |> Dict.insert "howdy" (greet "Howdy"),
+    }
+
+greet : Str -> Job
+greet = \greeting ->
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "echo \"$GREETING, World!\" > out",
+        ],
+        inputs: [],
+        outputs: ["out"],
+        env: Dict.empty
+        |> Dict.insert "GREETING" greeting,
+    }
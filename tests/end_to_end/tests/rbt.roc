@@ -0,0 +1,33 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec, withKind, fromJob, sourceFile }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.init { default: checkGreeting }
+
+greeting : Job
+greeting =
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "echo 'Hello, World!' > out",
+        ],
+        inputs: [],
+        outputs: ["out"],
+        env: Dict.empty,
+    }
+
+checkGreeting : Job
+checkGreeting =
+    job {
+        command: exec (systemTool "bash") [
+            "-c",
+            "grep -q Hello out && touch ok",
+        ],
+        inputs: [fromJob greeting [sourceFile "out"]],
+        outputs: ["ok"],
+        env: Dict.empty,
+    }
+    |> withKind Test
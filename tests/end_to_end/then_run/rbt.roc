@@ -0,0 +1,22 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, Job, job, exec, thenRun, projectFiles, sourceFile }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.init { default: hello }
+
+hello : Job
+hello =
+    job {
+        command: exec (systemTool "mkdir") ["copies"],
+        inputs: [
+            projectFiles [sourceFile "greeting"],
+        ],
+        outputs: ["out"],
+        env: Dict.empty,
+    }
+    |> thenRun (exec (systemTool "cp") ["greeting", "copies/greeting"])
+    |> thenRun (exec (systemTool "mv") ["copies/greeting", "out"])
+    |> thenRun (exec (systemTool "rmdir") ["copies"])
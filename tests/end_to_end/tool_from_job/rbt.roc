@@ -0,0 +1,27 @@
+app "build"
+    packages { pf: "../../../Package-Config.roc" }
+    imports [pf.Rbt.{ Rbt, Config, systemTool, tool, Job, job, exec, withOutputFromStdout }]
+    provides [init] to pf
+
+init : Config -> Rbt
+init = \_ ->
+    Rbt.init { default: hello }
+
+greeter : Job
+greeter =
+    job {
+        command: exec (systemTool "bash") ["-c", "mkdir bin && printf '#!/bin/sh\\necho \"Hello, $1!\"\\n' > bin/greet && chmod +x bin/greet"],
+        inputs: [],
+        outputs: ["bin"],
+        env: Dict.empty,
+    }
+
+hello : Job
+hello =
+    job {
+        command: exec (tool greeter "bin/greet") ["World"],
+        inputs: [],
+        outputs: [],
+        env: Dict.empty,
+    }
+    |> withOutputFromStdout "out"